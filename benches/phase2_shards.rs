@@ -0,0 +1,75 @@
+//! What sharding a leader's Phase 2 tallies costs the counting of P2bs.
+//!
+//! Counts the same runs of P2bs, every acceptor of a 5-acceptor cluster
+//! accepting each slot in turn, with the tallies in 1, 2, 4 and 8 shards,
+//! and prints for each:
+//!
+//! - P2bs counted per second of wall-clock time;
+//! - the speedup over a single shard.
+//!
+//! The shards are counted one after another on one thread: this is the
+//! baseline any way of counting them in parallel has to beat.
+//!
+//! `cargo bench --bench phase2_shards`, or add a number of slots per run to
+//! override `SLOTS`: `cargo bench --bench phase2_shards -- 100000`.
+use std::time::{Duration, Instant};
+
+use multifaustus::collections::HashSet;
+use multifaustus::nodes::phase2::Phase2Tallies;
+use multifaustus::nodes::quorum::Quorum;
+use multifaustus::types::{AcceptorId, Slot};
+
+const ACCEPTORS: u64 = 5;
+const SLOTS: u64 = 50_000;
+const RUNS: u64 = 20;
+
+fn run(shards: usize, slots: u64) -> anyhow::Result<Duration> {
+    let acceptors: HashSet<AcceptorId> = (1..=ACCEPTORS).map(AcceptorId::new).collect();
+    let quorum = Quorum::majority(acceptors);
+    let mut tallies = Phase2Tallies::new(shards);
+    let mut decided = 0;
+    let started = Instant::now();
+    for run in 0..RUNS {
+        let first = run * slots + 1;
+        let p2bs = (1..=ACCEPTORS).flat_map(|acceptor| {
+            (first..first + slots).map(move |slot| (Slot(slot), AcceptorId::new(acceptor)))
+        });
+        decided += tallies.count(p2bs, || quorum.clone()).len() as u64;
+        // As a leader does once the slots are decided
+        tallies.retain(|slot, _| *slot >= Slot(first + slots));
+    }
+    let wall = started.elapsed();
+    if decided != RUNS * slots || tallies.counted() != RUNS * slots * ACCEPTORS {
+        anyhow::bail!("{} shards decided {} slots", shards, decided);
+    }
+    Ok(wall)
+}
+
+fn main() -> anyhow::Result<()> {
+    // `cargo bench` passes `--bench`; the first number is the slots per run
+    let slots = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(SLOTS);
+    let p2bs = (RUNS * slots * ACCEPTORS) as f64;
+    println!(
+        "{} cores available",
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    println!("{:<8}{:>14}{:>10}", "SHARDS", "P2BS/SEC", "SPEEDUP");
+    let single = run(1, slots)?;
+    for shards in [1, 2, 4, 8] {
+        let wall = if shards == 1 {
+            single
+        } else {
+            run(shards, slots)?
+        };
+        println!(
+            "{:<8}{:>14.0}{:>9.2}x",
+            shards,
+            p2bs / wall.as_secs_f64(),
+            single.as_secs_f64() / wall.as_secs_f64(),
+        );
+    }
+    Ok(())
+}
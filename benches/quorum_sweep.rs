@@ -0,0 +1,162 @@
+//! How acceptor count and quorum policy affect the cost of a command.
+//!
+//! Runs the same workload through a simulated cluster of 3, 5 and 7
+//! acceptors, once with every acceptor storing values and once with as many
+//! witnesses as the configuration allows, and prints per committed command:
+//!
+//! - latency in simulated time, from the request reaching a replica to the
+//!   client getting its response, at the 50th and 99th percentile;
+//! - messages sent by all nodes;
+//! - wall-clock time spent simulating it, a rough measure of CPU cost.
+//!
+//! Simulated time advances in `TICK` steps and every step delivers one hop,
+//! so latencies count round trips rather than network delay.
+//!
+//! `cargo bench --bench quorum_sweep`, or add a number of commands to
+//! override `COMMANDS`: `cargo bench --bench quorum_sweep -- 5000`.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use multifaustus::messages::{Consistency, Message, RequestMessage, SendableMessage};
+use multifaustus::sim::{cluster_config, Simulation};
+use multifaustus::types::{Address, Command, CommandId, CommandType, Config, NodeId};
+
+const TICK: Duration = Duration::from_millis(1);
+const COMMANDS: u64 = 1000;
+// Commands injected per tick, spread over the replicas
+const PER_TICK: u64 = 4;
+
+#[derive(Clone, Copy, Debug)]
+enum Policy {
+    // Every acceptor stores accepted values
+    Full,
+    // As many acceptors as possible only vote
+    Witnesses,
+}
+
+impl Policy {
+    fn apply(self, config: Config) -> Config {
+        match self {
+            Policy::Full => config,
+            Policy::Witnesses => {
+                let quorum = config.acceptors.len() / 2 + 1;
+                let mut acceptors: Vec<_> = config.acceptors.iter().copied().collect();
+                acceptors.sort_by_key(|id| NodeId::from(*id));
+                config.with_witnesses(acceptors.into_iter().take(quorum - 1))
+            }
+        }
+    }
+}
+
+struct Measured {
+    committed: usize,
+    p50: Duration,
+    p99: Duration,
+    messages_per_command: f64,
+    wall_per_command: Duration,
+}
+
+fn run(acceptors: u64, policy: Policy, commands: u64) -> anyhow::Result<Measured> {
+    let config = policy.apply(cluster_config(acceptors, 1, 3));
+    config.check_witnesses()?;
+    let mut sim: Simulation = Simulation::new();
+    sim.add_cluster(&config)?;
+    // Let a leader take over before measuring
+    sim.run_for(Duration::from_secs(2), TICK);
+    let warmed_up = sim.sent().len();
+
+    let client = Address::new("client".to_string(), 1);
+    let mut replicas: Vec<_> = config.replicas.iter().copied().collect();
+    replicas.sort_by_key(|id| NodeId::from(*id));
+    let mut requested: HashMap<CommandId, Instant> = HashMap::new();
+    let mut latencies = Vec::new();
+    let started = Instant::now();
+    let mut request_id = 0;
+    let mut idle_ticks = 0;
+    while latencies.len() < commands as usize && idle_ticks < 10_000 {
+        for _ in 0..PER_TICK.min(commands - request_id) {
+            request_id += 1;
+            let replica = replicas[request_id as usize % replicas.len()];
+            let command = Command {
+                client_id: NodeId::new(999),
+                request_id,
+                op: CommandType::Op(request_id.to_le_bytes().to_vec()),
+            };
+            requested.insert(command.id(), sim.now());
+            sim.inject(SendableMessage {
+                src: client.clone(),
+                dst: config
+                    .get_address(replica.as_ref())
+                    .cloned()
+                    .ok_or(anyhow::anyhow!("{} has no address", replica))?,
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Request(RequestMessage {
+                    src: client.clone(),
+                    command,
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }),
+            });
+        }
+        sim.run_for(TICK, TICK);
+        let before = latencies.len();
+        for msg in sim.take_external() {
+            if let Message::Response(response) = msg.message {
+                // Every replica answers; the first answer counts
+                if let Some(at) = requested.remove(&response.command_id) {
+                    latencies.push(sim.now() - at);
+                }
+            }
+        }
+        idle_ticks = if latencies.len() == before {
+            idle_ticks + 1
+        } else {
+            0
+        };
+    }
+    let wall = started.elapsed();
+
+    latencies.sort();
+    let committed = latencies.len();
+    if committed == 0 {
+        anyhow::bail!("no command was committed");
+    }
+    let percentile = |p: usize| latencies[(committed * p / 100).min(committed - 1)];
+    Ok(Measured {
+        committed,
+        p50: percentile(50),
+        p99: percentile(99),
+        messages_per_command: (sim.sent().len() - warmed_up) as f64 / committed as f64,
+        wall_per_command: wall / committed as u32,
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    // `cargo bench` passes `--bench`; the first number is the command count
+    let commands = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(COMMANDS);
+    println!(
+        "{:<10}{:<11}{:>10}{:>10}{:>10}{:>14}{:>14}",
+        "ACCEPTORS", "POLICY", "COMMITTED", "P50", "P99", "MSGS/CMD", "WALL/CMD"
+    );
+    for acceptors in [3, 5, 7] {
+        for policy in [Policy::Full, Policy::Witnesses] {
+            let measured = run(acceptors, policy, commands)?;
+            println!(
+                "{:<10}{:<11}{:>10}{:>10}{:>10}{:>14.1}{:>14}",
+                acceptors,
+                format!("{:?}", policy).to_lowercase(),
+                measured.committed,
+                format!("{:?}", measured.p50),
+                format!("{:?}", measured.p99),
+                measured.messages_per_command,
+                format!("{:.1?}", measured.wall_per_command),
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! A three-acceptor, two-leader, two-replica cluster talking over TCP on
+//! localhost, replicating a key-value store.
+//!
+//! Every node runs in its own task behind a `NodeRunner`. A client submits
+//! commands to both replicas and checks that they answer identically, then
+//! reads back with `Sequential` consistency, which replicas that have caught
+//! up with the client answer without going through consensus.
+//!
+//!     cargo run --example tcp_cluster
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+
+use multifaustus::client::Client;
+use multifaustus::collections::{BTreeMap, HashMap, HashSet};
+use multifaustus::messages::{Consistency, Message, SendableMessage};
+use multifaustus::nodes::acceptor::Acceptor;
+use multifaustus::nodes::clock::SystemClock;
+use multifaustus::nodes::leader::Leader;
+use multifaustus::nodes::mailbox::Mailbox;
+use multifaustus::nodes::replica::Replica;
+use multifaustus::runtime::NodeRunner;
+use multifaustus::state_machine::{KvCommand, KvStore};
+use multifaustus::transport::tcp::{TcpSender, TcpServer};
+use multifaustus::transport::Transport;
+use multifaustus::types::{AcceptorId, Address, CommandId, Config, LeaderId, NodeId, ReplicaId};
+
+type Inbound = mpsc::UnboundedReceiver<SendableMessage<KvCommand>>;
+
+/// Bind a server on an ephemeral port and start accepting connections.
+async fn listen() -> anyhow::Result<(Address, Inbound)> {
+    let (server, inbound) = TcpServer::bind("127.0.0.1:0".parse::<SocketAddr>()?).await?;
+    let port = server.local_addr()?.port();
+    tokio::spawn(server.run());
+    Ok((Address::new("127.0.0.1".to_string(), port as u64), inbound))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::WARN)
+        .init();
+
+    let acceptors: Vec<AcceptorId> = (1..=3).map(AcceptorId::new).collect();
+    let leaders: Vec<LeaderId> = (11..=12).map(LeaderId::new).collect();
+    let replicas: Vec<ReplicaId> = (21..=22).map(ReplicaId::new).collect();
+
+    // Bind every node first so the config can name real ports
+    let mut id_address_map = BTreeMap::new();
+    let mut inbound: HashMap<NodeId, Inbound> = HashMap::new();
+    let ids = acceptors
+        .iter()
+        .map(|a| NodeId::from(*a))
+        .chain(leaders.iter().map(|l| NodeId::from(*l)))
+        .chain(replicas.iter().map(|r| NodeId::from(*r)));
+    for id in ids {
+        let (address, receiver) = listen().await?;
+        id_address_map.insert(id, address);
+        inbound.insert(id, receiver);
+    }
+    let config = Config::new(
+        HashSet::from_iter(replicas.iter().copied()),
+        HashSet::from_iter(acceptors.iter().copied()),
+        HashSet::from_iter(leaders.iter().copied()),
+        id_address_map,
+        None,
+    );
+
+    for id in &acceptors {
+        let mut acceptor = Acceptor::new(
+            *id,
+            config.clone(),
+            Mailbox::new(),
+            Box::new(SystemClock::new()),
+        )?;
+        acceptor.start_periodic_checks()?;
+        let rx = inbound.remove(id.as_ref()).unwrap();
+        tokio::spawn(NodeRunner::new(acceptor, rx, Box::new(TcpSender::spawn())).run());
+    }
+    for id in &leaders {
+        let leader = Leader::new(
+            *id,
+            config.clone(),
+            Mailbox::new(),
+            Box::new(SystemClock::new()),
+        )?;
+        let rx = inbound.remove(id.as_ref()).unwrap();
+        tokio::spawn(NodeRunner::new(leader, rx, Box::new(TcpSender::spawn())).run());
+    }
+    for id in &replicas {
+        let mut replica = Replica::new(
+            *id,
+            config.clone(),
+            Mailbox::new(),
+            Box::new(SystemClock::new()),
+        )?;
+        replica.set_state_machine(Box::new(KvStore::new()));
+        replica.start_periodic_checks()?;
+        let rx = inbound.remove(id.as_ref()).unwrap();
+        tokio::spawn(NodeRunner::new(replica, rx, Box::new(TcpSender::spawn())).run());
+    }
+
+    let (client_address, mut responses) = listen().await?;
+    let mut client = Client::new(NodeId::new(100), client_address);
+    let sender: TcpSender<KvCommand> = TcpSender::spawn();
+
+    let put = |key: &str, value: &[u8]| KvCommand::Put {
+        key: key.to_string(),
+        value: value.to_vec(),
+    };
+    let get = |key: &str| KvCommand::Get {
+        key: key.to_string(),
+    };
+    let workload = [
+        (put("a", b"1"), b"".to_vec(), false),
+        (put("b", b"2"), b"".to_vec(), false),
+        (get("a"), b"1".to_vec(), false),
+        (
+            KvCommand::Delete {
+                key: "b".to_string(),
+            },
+            b"2".to_vec(),
+            false,
+        ),
+        (get("b"), b"".to_vec(), false),
+        // Read back at the session's consistency rather than through consensus
+        (get("a"), b"1".to_vec(), true),
+    ];
+
+    for (op, expected, sequential) in workload {
+        let consistency = if sequential {
+            client.sequential()
+        } else {
+            Consistency::Linearizable
+        };
+        let command = client.command(op);
+        let command_id = command.id();
+        // Clients broadcast to every replica; each one answers once it has performed the command
+        for replica in &replicas {
+            let dst = config.get_address(replica.as_ref()).unwrap();
+            sender.send(&client.request(dst, &command, consistency))?;
+        }
+        let answers = tokio::time::timeout(
+            Duration::from_secs(10),
+            collect_responses(&mut client, &mut responses, command_id, replicas.len()),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out waiting for {}", command_id))?;
+        for (replica, result) in &answers {
+            anyhow::ensure!(
+                *result == expected,
+                "{} answered {:?} to {}, expected {:?}",
+                replica,
+                result,
+                command_id,
+                expected
+            );
+        }
+        println!(
+            "{}: {} replicas agree on {:?}",
+            command_id,
+            answers.len(),
+            String::from_utf8_lossy(&expected)
+        );
+    }
+    println!("ok");
+    Ok(())
+}
+
+/// Wait until `count` distinct replicas have answered `command_id`.
+async fn collect_responses(
+    client: &mut Client,
+    responses: &mut Inbound,
+    command_id: CommandId,
+    count: usize,
+) -> Vec<(ReplicaId, Vec<u8>)> {
+    let mut answers: Vec<(ReplicaId, Vec<u8>)> = Vec::new();
+    while answers.len() < count {
+        let Some(msg) = responses.recv().await else {
+            break;
+        };
+        if let Message::Response(response) = msg.message {
+            let result = client.receive(&response);
+            if response.command_id == command_id && !answers.iter().any(|(r, _)| *r == response.src)
+            {
+                // Unavailable answers are left to the timeout
+                answers.extend(
+                    result
+                        .and_then(Result::ok)
+                        .map(|result| (response.src, result)),
+                );
+            }
+        }
+    }
+    answers
+}
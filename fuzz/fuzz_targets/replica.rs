@@ -0,0 +1,9 @@
+//! Arbitrary messages, well-formed or not, through the codec into a replica.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use multifaustus_fuzz::{Input, Role};
+
+fuzz_target!(|input: Input| {
+    input.run(Role::Replica);
+});
@@ -0,0 +1,413 @@
+//! Shared harness for the fuzz targets.
+//!
+//! Each target runs one node of a simulated cluster on its own and feeds it
+//! a sequence of `Step`s: raw bytes, or messages built from arbitrary fields,
+//! both decoded by `JsonCodec` and delivered to the node, and ticks of the
+//! clock. The steps before the last leave the node in whatever state they
+//! lead to, so the fuzzer explores internal states by exploring prefixes.
+//! After every step the harness checks that handling the input did not
+//! panic and that the node's invariants still hold.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use arbitrary::Arbitrary;
+use multifaustus::messages::*;
+use multifaustus::nodes::acceptor::Acceptor;
+use multifaustus::nodes::leader::Leader;
+use multifaustus::nodes::mailbox::Mailbox;
+use multifaustus::nodes::replica::Replica;
+use multifaustus::sim::{cluster_config, Simulation};
+use multifaustus::transport::codec::{Codec, JsonCodec};
+use multifaustus::types::{
+    AcceptorId, Address, BallotNumber, Command, CommandId, CommandType, Config, LeaderId, NodeId,
+    PValue, ReplicaId, Round, Slot,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    Acceptor,
+    Leader,
+    Replica,
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct Input {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Step {
+    /// Bytes for the codec to decode, delivered to the node if they do.
+    Bytes(Vec<u8>),
+    /// A message from a peer or client, encoded and decoded again.
+    Message(Fuzzed),
+    /// Let the node's clock run on by this many 10ms ticks.
+    Tick(u8),
+}
+
+/// A command from one of a few clients, so that ids collide.
+#[derive(Arbitrary, Debug)]
+pub struct FuzzedCommand {
+    client: u8,
+    request: u8,
+    op: Vec<u8>,
+}
+
+/// Messages with ids drawn from the cluster and small ballots and slots, so
+/// they are mostly ones the node takes up rather than turns away.
+#[derive(Arbitrary, Debug)]
+pub enum Fuzzed {
+    P1a {
+        leader: u8,
+        round: u8,
+    },
+    PreP1a {
+        leader: u8,
+        round: u8,
+    },
+    P2a {
+        leader: u8,
+        round: u8,
+        slot: u8,
+        command: FuzzedCommand,
+    },
+    P1b {
+        acceptor: u8,
+        leader: u8,
+        round: u8,
+        accepted: Vec<(u8, u8, FuzzedCommand)>,
+    },
+    P2b {
+        acceptor: u8,
+        leader: u8,
+        round: u8,
+        slot: u8,
+    },
+    Preempted {
+        leader: u8,
+        round: u8,
+    },
+    Propose {
+        replica: u8,
+        slot: u8,
+        command: FuzzedCommand,
+    },
+    Decision {
+        leader: u8,
+        slot: u8,
+        command: FuzzedCommand,
+    },
+    Request {
+        command: FuzzedCommand,
+    },
+    DecisionFetch {
+        replica: u8,
+        slots: Vec<u8>,
+    },
+    DecisionFetchReply {
+        replica: u8,
+        decisions: Vec<(u8, FuzzedCommand)>,
+    },
+}
+
+const TICK: Duration = Duration::from_millis(10);
+
+fn acceptor(n: u8) -> AcceptorId {
+    AcceptorId::new(1 + u64::from(n % 3))
+}
+
+fn leader(n: u8) -> LeaderId {
+    LeaderId::new(101 + u64::from(n % 2))
+}
+
+fn replica(n: u8) -> ReplicaId {
+    ReplicaId::new(201 + u64::from(n % 2))
+}
+
+fn ballot(round: u8, by: u8) -> BallotNumber {
+    BallotNumber {
+        round: Round(u64::from(round)),
+        leader: leader(by),
+        incarnation: 0,
+    }
+}
+
+fn client() -> Address {
+    Address::new("client".to_string(), 1)
+}
+
+impl FuzzedCommand {
+    fn command(self) -> Command {
+        Command {
+            client_id: NodeId::new(900 + u64::from(self.client % 4)),
+            request_id: u64::from(self.request),
+            op: CommandType::Op(self.op),
+        }
+    }
+}
+
+impl Fuzzed {
+    /// The message and the node it comes from, if any.
+    fn message(self) -> (Message, Option<NodeId>) {
+        match self {
+            Fuzzed::P1a { leader: by, round } => (
+                Message::P1a(P1aMessage {
+                    src: leader(by),
+                    ballot_number: ballot(round, by),
+                    decided_below: Slot(0),
+                }),
+                Some(leader(by).into()),
+            ),
+            Fuzzed::PreP1a { leader: by, round } => (
+                Message::PreP1a(PreP1aMessage {
+                    src: leader(by),
+                    ballot_number: ballot(round, by),
+                }),
+                Some(leader(by).into()),
+            ),
+            Fuzzed::P2a {
+                leader: by,
+                round,
+                slot,
+                command,
+            } => (
+                Message::P2a(P2aMessage {
+                    src: leader(by),
+                    ballot_number: ballot(round, by),
+                    slot_number: Slot(u64::from(slot)),
+                    command: command.command(),
+                    gc_below: Slot(0),
+                }),
+                Some(leader(by).into()),
+            ),
+            Fuzzed::P1b {
+                acceptor: from,
+                leader: by,
+                round,
+                accepted,
+            } => (
+                Message::P1b(P1bMessage {
+                    src: acceptor(from),
+                    ballot_number: ballot(round, by),
+                    accepted: accepted
+                        .into_iter()
+                        .map(|(slot, round, command)| PValue {
+                            ballot_number: ballot(round, by),
+                            slot: Slot(u64::from(slot)),
+                            command: command.command(),
+                        })
+                        .collect(),
+                    gc_below: Slot(0),
+                    witnessed: Vec::new(),
+                    continues_from: None,
+                    more_from: None,
+                }),
+                Some(acceptor(from).into()),
+            ),
+            Fuzzed::P2b {
+                acceptor: from,
+                leader: by,
+                round,
+                slot,
+            } => (
+                Message::P2b(P2bMessage {
+                    src: acceptor(from),
+                    ballot_number: ballot(round, by),
+                    slot_number: Slot(u64::from(slot)),
+                }),
+                Some(acceptor(from).into()),
+            ),
+            Fuzzed::Preempted { leader: by, round } => (
+                Message::Preempted(PreemptedMessage {
+                    src: leader(by),
+                    ballot_number: ballot(round, by),
+                }),
+                Some(leader(by).into()),
+            ),
+            Fuzzed::Propose {
+                replica: from,
+                slot,
+                command,
+            } => (
+                Message::Propose(ProposeMessage {
+                    src: replica(from),
+                    slot_number: Slot(u64::from(slot)),
+                    command: command.command(),
+                }),
+                Some(replica(from).into()),
+            ),
+            Fuzzed::Decision {
+                leader: by,
+                slot,
+                command,
+            } => (
+                Message::Decision(DecisionMessage {
+                    src: leader(by),
+                    slot_number: Slot(u64::from(slot)),
+                    command: command.command(),
+                    certificate: None,
+                }),
+                Some(leader(by).into()),
+            ),
+            Fuzzed::Request { command } => (
+                Message::Request(RequestMessage {
+                    src: client(),
+                    command: command.command(),
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }),
+                None,
+            ),
+            Fuzzed::DecisionFetch {
+                replica: from,
+                slots,
+            } => (
+                Message::DecisionFetch(DecisionFetchMessage {
+                    src: replica(from),
+                    slots: slots
+                        .into_iter()
+                        .map(|slot| Slot(u64::from(slot)))
+                        .collect(),
+                }),
+                Some(replica(from).into()),
+            ),
+            Fuzzed::DecisionFetchReply {
+                replica: from,
+                decisions,
+            } => (
+                Message::DecisionFetchReply(DecisionFetchReplyMessage {
+                    src: replica(from),
+                    decisions: decisions
+                        .into_iter()
+                        .map(|(slot, command)| (Slot(u64::from(slot)), command.command()))
+                        .collect(),
+                    certificates: vec![],
+                }),
+                Some(replica(from).into()),
+            ),
+        }
+    }
+}
+
+/// What the node has done so far that later steps must not contradict.
+#[derive(Default)]
+struct Invariants {
+    ballot: Option<BallotNumber>,
+    frontier: Option<Slot>,
+    // Slots a replica answered each command in, and the command in each slot
+    answered: HashMap<CommandId, Slot>,
+    performed: HashMap<Slot, CommandId>,
+}
+
+impl Invariants {
+    fn check(&mut self, sim: &mut Simulation, id: NodeId) {
+        let progress = sim
+            .node(&id)
+            .expect("the node is in the simulation")
+            .progress();
+        assert!(
+            progress.ballot >= self.ballot,
+            "ballot went back from {:?} to {:?}",
+            self.ballot,
+            progress.ballot
+        );
+        assert!(
+            progress.frontier >= self.frontier,
+            "frontier went back from {:?} to {:?}",
+            self.frontier,
+            progress.frontier
+        );
+        self.ballot = progress.ballot;
+        self.frontier = progress.frontier;
+
+        for msg in sim.take_external() {
+            if let Message::Response(response) = msg.message {
+                let slot = *self
+                    .answered
+                    .entry(response.command_id)
+                    .or_insert(response.slot);
+                assert_eq!(slot, response.slot, "answered in two slots");
+                let command = *self
+                    .performed
+                    .entry(response.slot)
+                    .or_insert(response.command_id);
+                assert_eq!(command, response.command_id, "two commands in one slot");
+            }
+        }
+    }
+}
+
+fn address(config: &Config, id: NodeId) -> Address {
+    config
+        .get_address(&id)
+        .cloned()
+        .expect("cluster nodes have addresses")
+}
+
+impl Input {
+    /// Run the steps against a node in `role` and check its invariants after
+    /// each of them. Panics if the node panics or breaks an invariant.
+    pub fn run(self, role: Role) {
+        let config = cluster_config(3, 2, 2);
+        let mut sim: Simulation = Simulation::new();
+        let id: NodeId = match role {
+            Role::Acceptor => acceptor(0).into(),
+            Role::Leader => leader(0).into(),
+            Role::Replica => replica(0).into(),
+        };
+        let dst = address(&config, id);
+        match role {
+            Role::Acceptor => {
+                let mut node =
+                    Acceptor::new(acceptor(0), config.clone(), Mailbox::new(), sim.clock())
+                        .expect("valid config");
+                node.start_periodic_checks().expect("timers start");
+                sim.add_node(id, &dst, Box::new(node));
+            }
+            Role::Leader => {
+                let node = Leader::new(leader(0), config.clone(), Mailbox::new(), sim.clock())
+                    .expect("valid config");
+                sim.add_node(id, &dst, Box::new(node));
+            }
+            Role::Replica => {
+                let mut node =
+                    Replica::new(replica(0), config.clone(), Mailbox::new(), sim.clock())
+                        .expect("valid config");
+                node.start_periodic_checks().expect("timers start");
+                sim.add_node(id, &dst, Box::new(node));
+            }
+        }
+
+        let codec = JsonCodec;
+        let mut invariants = Invariants::default();
+        for step in self.steps {
+            let bytes = match step {
+                Step::Bytes(bytes) => bytes,
+                Step::Message(fuzzed) => {
+                    let (message, from) = fuzzed.message();
+                    let src = from.map_or_else(client, |from| address(&config, from));
+                    let msg = SendableMessage {
+                        src,
+                        dst: dst.clone(),
+                        seq: None,
+                        lamport: None,
+                        epoch: None,
+                        message,
+                    };
+                    codec.encode(&msg).expect("messages encode")
+                }
+                Step::Tick(ticks) => {
+                    sim.run_for(TICK * u32::from(ticks), TICK);
+                    invariants.check(&mut sim, id);
+                    continue;
+                }
+            };
+            if let Ok(mut msg) = Codec::<Vec<u8>>::decode(&codec, &bytes) {
+                msg.dst = dst.clone();
+                sim.inject(msg);
+                sim.step();
+            }
+            invariants.check(&mut sim, id);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Minimal HTTP admin surface for orchestrators and load balancers.
+//!
+//! `GET /health` answers `200 OK` while a node is `Ready` or `Degraded` and
+//! `503 Service Unavailable` when it is `NotReady`; the body is the `Health`
+//! report as JSON. Nodes are not shared across threads, so the server reads
+//! health through a `HealthSource` closure supplied by the embedder.
+//!
+//! With a `ReloadHandle` attached, `PUT /config` takes a `RuntimeConfig` as
+//! JSON and answers `202 Accepted` once it is queued for the node's runner.
+//!
+//! With a `StatusSource` attached, `GET /status` answers with a
+//! `StatusReport`: the node's `Progress` and its health, which
+//! `multifaustus status` collects from every node of a cluster. With a
+//! `LinkSource` attached as well, the report includes the health of each of
+//! the node's transport links.
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::warn;
+
+use crate::collections::BTreeMap;
+use crate::nodes::health::Health;
+use crate::nodes::node::Progress;
+use crate::runtime::{ReloadHandle, RuntimeConfig};
+use crate::transport::LinkHealth;
+
+/// Requests larger than this are refused.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+
+/// Produces the current health report, e.g. by locking a node and calling `Node::health`.
+pub type HealthSource = Arc<dyn Fn() -> Health + Send + Sync>;
+
+/// Produces the node's current `Progress`, e.g. `NodeRunner::status_source`.
+pub type StatusSource = Arc<dyn Fn() -> Progress + Send + Sync>;
+
+/// Produces the health of the node's links by destination, e.g. `NodeRunner::link_source`.
+pub type LinkSource = Arc<dyn Fn() -> BTreeMap<String, LinkHealth> + Send + Sync>;
+
+/// The body of a `GET /status` response.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusReport {
+    #[serde(flatten)]
+    pub progress: Progress,
+    pub health: Health,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, LinkHealth>,
+}
+
+pub struct AdminServer {
+    listener: TcpListener,
+    health: HealthSource,
+    reload: Option<ReloadHandle>,
+    status: Option<StatusSource>,
+    links: Option<LinkSource>,
+}
+
+impl AdminServer {
+    pub async fn bind(addr: SocketAddr, health: HealthSource) -> anyhow::Result<AdminServer> {
+        let listener = TcpListener::bind(addr).await?;
+        Ok(AdminServer {
+            listener,
+            health,
+            reload: None,
+            status: None,
+            links: None,
+        })
+    }
+
+    /// Serve `GET /status`, reporting the progress `status` produces.
+    pub fn with_status(mut self, status: StatusSource) -> AdminServer {
+        self.status = Some(status);
+        self
+    }
+
+    /// Report the link health `links` produces in `GET /status`.
+    pub fn with_links(mut self, links: LinkSource) -> AdminServer {
+        self.links = Some(links);
+        self
+    }
+
+    /// Serve `PUT /config`, forwarding runtime config updates to `reload`.
+    pub fn with_reload(mut self, reload: ReloadHandle) -> AdminServer {
+        self.reload = Some(reload);
+        self
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serve requests until the listener fails.
+    pub async fn run(self) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            let health = self.health.clone();
+            let reload = self.reload.clone();
+            let status = self.status.clone();
+            let links = self.links.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, health, reload, status, links).await {
+                    warn!("admin: request from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// Read the request head and, if it declares a `Content-Length`, the body.
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buf.len() > MAX_REQUEST_LEN {
+            anyhow::bail!("request head too large");
+        }
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            // Clients may close after sending a bare request line
+            break buf.len();
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST_LEN {
+        anyhow::bail!("request body too large");
+    }
+    let mut body = buf.split_off((head_end + 4).min(buf.len()));
+    while body.len() < content_length {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            anyhow::bail!("request body truncated");
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(content_length);
+    Ok((head, body))
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    health: HealthSource,
+    reload: Option<ReloadHandle>,
+    status: Option<StatusSource>,
+    links: Option<LinkSource>,
+) -> anyhow::Result<()> {
+    let (request, request_body) = read_request(&mut stream).await?;
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/health")) => {
+            let report = health();
+            let status = if report.is_ready() {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, serde_json::to_string(&report)?)
+        }
+        (Some("GET"), Some("/status")) => match &status {
+            Some(status) => {
+                let report = StatusReport {
+                    progress: status(),
+                    health: health(),
+                    links: links.as_ref().map(|links| links()).unwrap_or_default(),
+                };
+                ("200 OK", serde_json::to_string(&report)?)
+            }
+            None => ("404 Not Found", String::new()),
+        },
+        (Some("PUT"), Some("/config")) => match &reload {
+            Some(reload) => {
+                let applied = serde_json::from_slice::<RuntimeConfig>(&request_body)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|config| reload.reload(config));
+                match applied {
+                    Ok(()) => ("202 Accepted", String::new()),
+                    Err(e) => ("400 Bad Request", serde_json::to_string(&e.to_string())?),
+                }
+            }
+            None => ("404 Not Found", String::new()),
+        },
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn put(addr: SocketAddr, path: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "PUT {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+                    path,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn admin_server_reports_readiness() {
+        let current = Arc::new(Mutex::new(Health::Ready));
+        let source = current.clone();
+        let server = AdminServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(move || source.lock().unwrap().clone()),
+        )
+        .await
+        .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        assert!(get(addr, "/health").await.starts_with("HTTP/1.1 200 OK"));
+
+        *current.lock().unwrap() = Health::NotReady {
+            reasons: vec!["no quorum".to_string()],
+        };
+        let response = get(addr, "/health").await;
+        assert!(response.starts_with("HTTP/1.1 503"));
+        assert!(response.contains("no quorum"));
+
+        assert!(get(addr, "/metrics").await.starts_with("HTTP/1.1 404"));
+    }
+
+    #[tokio::test]
+    async fn admin_server_forwards_config_reloads() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let server = AdminServer::bind("127.0.0.1:0".parse().unwrap(), Arc::new(|| Health::Ready))
+            .await
+            .unwrap()
+            .with_reload(ReloadHandle(tx));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        let response = put(
+            addr,
+            "/config",
+            r#"{"log_level": "debug", "max_requests_per_sec": 10}"#,
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 202"));
+        let config = rx.recv().await.unwrap();
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert_eq!(config.max_requests_per_sec, Some(10));
+        assert_eq!(config.timeouts, None);
+
+        let response = put(addr, "/config", r#"{"log_level": "loud"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Structured event journal for operator forensics.
+//!
+//! Nodes record protocol-level events (ballot changes, leadership transitions,
+//! reconfigurations) as `AuditEvent`s, which the embedder drains and appends to
+//! an `AuditLog`: a dedicated append-only file of timestamped JSON lines, kept
+//! separate from tracing output so it survives log-level changes and rotation.
+//! The runtime's `Supervisor` appends node crashes and restarts to the same log.
+//!
+//! Nodes stamp each event with their Lamport time, as a `Stamped` event, so
+//! the journals of several nodes can be merged in an order consistent with
+//! the messages that passed between them.
+use alloc::boxed::Box;
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+
+use crate::time::Duration;
+use crate::types;
+
+/// A protocol-level event worth keeping for after-the-fact investigation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    /// A leader moved to a new ballot.
+    BallotChanged {
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+    },
+    /// A leader's ballot was adopted by a quorum of acceptors.
+    LeadershipAcquired {
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+    },
+    /// An active leader was preempted by a higher ballot.
+    LeadershipLost {
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+        preempted_by: types::BallotNumber,
+    },
+    /// A replica switched to the configuration decided at `slot`.
+    ReconfigApplied {
+        replica: types::ReplicaId,
+        slot: types::Slot,
+        config: Box<types::Config>,
+    },
+    /// A supervised node stopped unexpectedly; it is restarted after `restart_in`.
+    NodeCrashed {
+        node: types::NodeId,
+        reason: String,
+        restart_in: Duration,
+    },
+    /// A supervised node was started again after crashing `restarts` times.
+    NodeRestarted { node: types::NodeId, restarts: u32 },
+}
+
+/// An `AuditEvent` with the Lamport time of the node that recorded it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stamped {
+    pub lamport: u64,
+    pub event: AuditEvent,
+}
+
+impl AuditEvent {
+    /// The node that recorded the event.
+    pub fn node(&self) -> types::NodeId {
+        match self {
+            AuditEvent::BallotChanged { leader, .. }
+            | AuditEvent::LeadershipAcquired { leader, .. }
+            | AuditEvent::LeadershipLost { leader, .. } => (*leader).into(),
+            AuditEvent::ReconfigApplied { replica, .. } => (*replica).into(),
+            AuditEvent::NodeCrashed { node, .. } | AuditEvent::NodeRestarted { node, .. } => *node,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::log::{AuditLog, AuditRecord};
+
+#[cfg(feature = "std")]
+mod log {
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufRead, BufReader, Write};
+    use std::path::{Path, PathBuf};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{AuditEvent, Stamped};
+
+    /// An event as stored in the journal.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct AuditRecord {
+        /// Wall-clock time the event was appended, in milliseconds since the Unix epoch.
+        pub timestamp_ms: u64,
+        /// The Lamport time of the node that recorded the event, if stamped.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub lamport: Option<u64>,
+        pub event: AuditEvent,
+    }
+
+    /// Append-only journal of `AuditEvent`s, one JSON record per line.
+    pub struct AuditLog {
+        path: PathBuf,
+        file: File,
+    }
+
+    impl AuditLog {
+        /// Open (or create) the journal at `path` for appending.
+        pub fn open(path: impl AsRef<Path>) -> anyhow::Result<AuditLog> {
+            let path = path.as_ref().to_path_buf();
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            Ok(AuditLog { path, file })
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        /// Append an event, stamped with the current wall-clock time.
+        pub fn append(&mut self, event: AuditEvent) -> anyhow::Result<()> {
+            self.write(event, None)
+        }
+
+        /// Append an event a node stamped with its Lamport time.
+        pub fn append_stamped(&mut self, stamped: Stamped) -> anyhow::Result<()> {
+            self.write(stamped.event, Some(stamped.lamport))
+        }
+
+        fn write(&mut self, event: AuditEvent, lamport: Option<u64>) -> anyhow::Result<()> {
+            let timestamp_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default();
+            let mut line = serde_json::to_vec(&AuditRecord {
+                timestamp_ms,
+                lamport,
+                event,
+            })?;
+            line.push(b'\n');
+            self.file.write_all(&line)?;
+            self.file.flush()?;
+            Ok(())
+        }
+
+        /// Append every event drained from a node.
+        pub fn append_all(
+            &mut self,
+            events: impl IntoIterator<Item = AuditEvent>,
+        ) -> anyhow::Result<()> {
+            for event in events {
+                self.append(event)?;
+            }
+            Ok(())
+        }
+
+        /// Read back the records in this journal that match `filter`.
+        pub fn query<F>(&self, filter: F) -> anyhow::Result<Vec<AuditRecord>>
+        where
+            F: Fn(&AuditRecord) -> bool,
+        {
+            AuditLog::query_path(&self.path, filter)
+        }
+
+        /// Read the records in the journal at `path` that match `filter`.
+        ///
+        /// A torn final line (e.g. from a crash mid-append) is skipped.
+        pub fn query_path<F>(path: impl AsRef<Path>, filter: F) -> anyhow::Result<Vec<AuditRecord>>
+        where
+            F: Fn(&AuditRecord) -> bool,
+        {
+            let reader = BufReader::new(File::open(path)?);
+            let mut records = Vec::new();
+            for line in reader.lines() {
+                let line = line?;
+                if let Ok(record) = serde_json::from_str::<AuditRecord>(&line) {
+                    if filter(&record) {
+                        records.push(record);
+                    }
+                }
+            }
+            Ok(records)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    #[test]
+    fn audit_log_appends_and_queries_events() {
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-audit-{}-{}.jsonl",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let leader = LeaderId::new(1);
+        let ballot = BallotNumber::new(leader);
+
+        let mut log = AuditLog::open(&path).unwrap();
+        log.append_all([
+            AuditEvent::BallotChanged {
+                leader,
+                ballot: ballot.clone(),
+            },
+            AuditEvent::LeadershipAcquired {
+                leader,
+                ballot: ballot.clone(),
+            },
+        ])
+        .unwrap();
+        drop(log);
+
+        // Reopening appends rather than truncating
+        let mut log = AuditLog::open(&path).unwrap();
+        log.append_stamped(Stamped {
+            lamport: 12,
+            event: AuditEvent::BallotChanged {
+                leader: LeaderId::new(2),
+                ballot: BallotNumber::new(LeaderId::new(2)),
+            },
+        })
+        .unwrap();
+
+        let all = log.query(|_| true).unwrap();
+        assert_eq!(all.len(), 3);
+        let lamports: Vec<_> = all.iter().map(|record| record.lamport).collect();
+        assert_eq!(lamports, vec![None, None, Some(12)]);
+        let acquired = log
+            .query(|r| matches!(r.event, AuditEvent::LeadershipAcquired { .. }))
+            .unwrap();
+        assert_eq!(acquired.len(), 1);
+        let from_leader1 = log.query(|r| r.event.node() == leader.into()).unwrap();
+        assert_eq!(from_leader1.len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
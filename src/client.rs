@@ -0,0 +1,703 @@
+//! Sans-IO client sessions.
+//!
+//! A `Client` numbers its commands, builds the requests that carry them and
+//! remembers the latest slot any response has reflected, so that its
+//! `Sequential` reads never observe state older than what it has already
+//! seen. Sending the requests and feeding back the responses is up to the
+//! caller's transport.
+//!
+//! `barrier` asks a replica to answer once it has performed every slot up to
+//! one; once it has, the session's `Sequential` reads reflect that slot.
+//!
+//! A `Batcher` submits a session's operations in batches instead: it holds
+//! them for a short linger window, sends them as one `CommandType::Batch`,
+//! and splits the batch's response back into each operation's result. It
+//! turns operations away while too many batches await a response.
+//!
+//! A `SlotRead` reads what was chosen in one slot straight from the
+//! acceptors, without a leader or replica: tooling and external verifiers can
+//! check a slot's outcome against what the replicas report.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::collections::{BTreeMap, HashMap};
+use crate::constants::{BATCH_MAX_OPS, BATCH_MAX_OUTSTANDING};
+use crate::messages::{
+    AcceptedReplyMessage, BarrierMessage, CommittedMessage, Consistency, Message,
+    QueryAcceptedMessage, RequestMessage, ResponseMessage, ResponseStatus, SendableMessage,
+};
+use crate::nodes::quorum::Quorum;
+use crate::state_machine::decode_batch_results;
+use crate::time::{Duration, Instant};
+use crate::types::{
+    AcceptorId, Address, BallotNumber, Command, CommandType, Config, NodeId, RefusalReason, Slot,
+};
+
+/// Why a replica turned a command away. The command was not performed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestError {
+    /// No leader could reach a quorum of acceptors; send it again later.
+    Unavailable,
+    /// The command encodes to `size` bytes, more than the replica accepts.
+    TooLarge { size: u64, limit: u64 },
+    /// A leader refused the command; sending it again will not help.
+    Refused(RefusalReason),
+}
+
+impl core::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RequestError::Unavailable => write!(f, "no leader can reach a quorum"),
+            RequestError::TooLarge { size, limit } => write!(
+                f,
+                "command of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
+            RequestError::Refused(reason) => write!(f, "refused: {}", reason),
+        }
+    }
+}
+
+impl core::error::Error for RequestError {}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    client_id: NodeId,
+    address: Address,
+    next_request_id: u64,
+    // Highest slot reflected in a response this session has received
+    session_slot: Slot,
+}
+
+impl Client {
+    /// A session for `client_id`, whose responses are sent to `address`.
+    pub fn new(client_id: NodeId, address: Address) -> Client {
+        Client {
+            client_id,
+            address,
+            next_request_id: 0,
+            session_slot: Slot(0),
+        }
+    }
+
+    pub fn client_id(&self) -> NodeId {
+        self.client_id
+    }
+
+    /// The latest slot this session has observed.
+    pub fn session_slot(&self) -> Slot {
+        self.session_slot
+    }
+
+    /// A new command carrying `op`, to pass to `request` for each replica it should go to.
+    pub fn command<T>(&mut self, op: T) -> Command<T> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        Command {
+            client_id: self.client_id,
+            request_id,
+            op: CommandType::Op(op),
+        }
+    }
+
+    /// `Sequential` consistency for this session: answers must reflect at
+    /// least everything it has observed so far.
+    pub fn sequential(&self) -> Consistency {
+        Consistency::Sequential {
+            after_slot: self.session_slot,
+        }
+    }
+
+    /// A request asking the replica at `replica` to execute `command`.
+    pub fn request<T: Clone>(
+        &self,
+        replica: &Address,
+        command: &Command<T>,
+        consistency: Consistency,
+    ) -> SendableMessage<T> {
+        SendableMessage {
+            src: self.address.clone(),
+            dst: replica.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Request(RequestMessage {
+                src: self.address.clone(),
+                command: command.clone(),
+                consistency,
+                proxied_by: None,
+            }),
+        }
+    }
+
+    /// A barrier asking the replica at `replica` to send a `CommittedMessage`
+    /// once it has performed every slot up to `slot`.
+    pub fn barrier<T>(&mut self, replica: &Address, slot: Slot) -> SendableMessage<T> {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        SendableMessage {
+            src: self.address.clone(),
+            dst: replica.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Barrier(BarrierMessage {
+                src: self.address.clone(),
+                id,
+                slot,
+            }),
+        }
+    }
+
+    /// Record a replica's answer to a barrier, returning the last slot it
+    /// has performed. A replica with too many barriers waiting answers early,
+    /// so the slot can fall short of the one asked about.
+    pub fn committed(&mut self, committed: &CommittedMessage) -> Slot {
+        let slot = committed.commit_index.0;
+        self.session_slot = self.session_slot.max(slot);
+        slot
+    }
+
+    /// Record a response from a replica, returning its result if it answers
+    /// one of this session's commands.
+    pub fn receive(&mut self, response: &ResponseMessage) -> Option<Result<Vec<u8>, RequestError>> {
+        if response.command_id.client_id != self.client_id {
+            return None;
+        }
+        match response.status {
+            ResponseStatus::Performed => {}
+            ResponseStatus::Unavailable => return Some(Err(RequestError::Unavailable)),
+            ResponseStatus::TooLarge { size, limit } => {
+                return Some(Err(RequestError::TooLarge { size, limit }))
+            }
+            ResponseStatus::Refused(reason) => return Some(Err(RequestError::Refused(reason))),
+        }
+        self.session_slot = self.session_slot.max(response.slot);
+        Some(Ok(response.result.clone()))
+    }
+}
+
+/// Where an operation submitted to a `Batcher` went: the request id of its
+/// batch and its place in the batch.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BatchedOp {
+    pub request_id: u64,
+    pub index: usize,
+}
+
+/// A `Batcher` turned an operation away: its next batch is full and as many
+/// batches as it allows are awaiting a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backpressure {
+    pub outstanding: usize,
+}
+
+impl core::fmt::Display for Backpressure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} batches are awaiting a response", self.outstanding)
+    }
+}
+
+impl core::error::Error for Backpressure {}
+
+// What one operation in a batch came to
+type OpResult = Result<Vec<u8>, RequestError>;
+
+// The batch operations are gathered in until it is sent
+#[derive(Clone, Debug)]
+struct OpenBatch<T> {
+    request_id: u64,
+    since: Instant,
+    ops: Vec<T>,
+}
+
+/// A client session that submits its operations in batches.
+#[derive(Clone, Debug)]
+pub struct Batcher<T = Vec<u8>> {
+    client: Client,
+    linger: Duration,
+    max_ops: usize,
+    max_outstanding: usize,
+    open: Option<OpenBatch<T>>,
+    // Batches sent and not yet answered, by request id, and how many ops each holds
+    outstanding: BTreeMap<u64, usize>,
+}
+
+impl<T> Batcher<T> {
+    /// Batches for `client`'s session, each sent once its first operation
+    /// has waited `linger` or it holds `BATCH_MAX_OPS` operations, with at
+    /// most `BATCH_MAX_OUTSTANDING` awaiting a response.
+    pub fn new(client: Client, linger: Duration) -> Batcher<T> {
+        Batcher {
+            client,
+            linger,
+            max_ops: BATCH_MAX_OPS,
+            max_outstanding: BATCH_MAX_OUTSTANDING,
+            open: None,
+            outstanding: BTreeMap::new(),
+        }
+    }
+
+    /// Hold batches to `max_ops` operations, and `max_outstanding` of them
+    /// awaiting a response.
+    pub fn with_limits(mut self, max_ops: usize, max_outstanding: usize) -> Batcher<T> {
+        self.max_ops = max_ops.max(1);
+        self.max_outstanding = max_outstanding.max(1);
+        self
+    }
+
+    /// The session the batches are sent in, e.g. for `Client::request`.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn client_mut(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Batches sent and not yet answered.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Add `op` to the next batch, returning where it went. Operations are
+    /// only turned away while that batch is full and cannot be sent yet.
+    pub fn submit(&mut self, op: T, now: Instant) -> Result<BatchedOp, Backpressure> {
+        if self
+            .open
+            .as_ref()
+            .is_some_and(|open| open.ops.len() >= self.max_ops)
+        {
+            return Err(Backpressure {
+                outstanding: self.outstanding.len(),
+            });
+        }
+        let client = &mut self.client;
+        let open = self.open.get_or_insert_with(|| {
+            let request_id = client.next_request_id;
+            client.next_request_id += 1;
+            OpenBatch {
+                request_id,
+                since: now,
+                ops: Vec::new(),
+            }
+        });
+        open.ops.push(op);
+        Ok(BatchedOp {
+            request_id: open.request_id,
+            index: open.ops.len() - 1,
+        })
+    }
+
+    /// The next batch, if it is full or has lingered long enough, and fewer
+    /// than the allowed batches await a response. Pass it to
+    /// `Client::request` for each replica it should go to.
+    pub fn poll(&mut self, now: Instant) -> Option<Command<T>> {
+        let open = self.open.as_ref()?;
+        let due = open.ops.len() >= self.max_ops || now.duration_since(open.since) >= self.linger;
+        if !due {
+            return None;
+        }
+        self.flush()
+    }
+
+    /// The next batch, however long it has lingered, unless the allowed
+    /// batches already await a response.
+    pub fn flush(&mut self) -> Option<Command<T>> {
+        if self.outstanding.len() >= self.max_outstanding {
+            return None;
+        }
+        let open = self.open.take()?;
+        self.outstanding.insert(open.request_id, open.ops.len());
+        Some(Command {
+            client_id: self.client.client_id,
+            request_id: open.request_id,
+            op: CommandType::Batch(open.ops),
+        })
+    }
+
+    /// How long until the next batch is due to be sent, if there is one.
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let open = self.open.as_ref()?;
+        Some(self.linger.saturating_sub(now.duration_since(open.since)))
+    }
+
+    /// Record a response, returning each operation's result if it answers
+    /// one of the batches awaiting one. A batch a replica turned away
+    /// returns the same error for each of its operations, which can be
+    /// submitted again.
+    pub fn receive(&mut self, response: &ResponseMessage) -> Option<Vec<(BatchedOp, OpResult)>> {
+        let request_id = response.command_id.request_id;
+        let ops = *self.outstanding.get(&request_id)?;
+        let results: Vec<OpResult> = match self.client.receive(response)? {
+            Ok(packed) => decode_batch_results(&packed)
+                .filter(|results| results.len() == ops)?
+                .into_iter()
+                .map(Ok)
+                .collect(),
+            Err(e) => (0..ops).map(|_| Err(e)).collect(),
+        };
+        self.outstanding.remove(&request_id);
+        Some(
+            results
+                .into_iter()
+                .enumerate()
+                .map(|(index, result)| (BatchedOp { request_id, index }, result))
+                .collect(),
+        )
+    }
+}
+
+/// What a `SlotRead` found in its slot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SlotReading<T = Vec<u8>> {
+    /// A quorum of acceptors accepted this command at one ballot.
+    Chosen(Box<Command<T>>),
+    /// An acceptor has already forgotten the slot, so it was decided long
+    /// ago; a replica's answers reflect it.
+    Forgotten,
+    /// Every acceptor answered and no quorum agreed on a ballot. The slot may
+    /// not be chosen yet, or its acceptors have moved on to a later ballot
+    /// than some of them have seen; read it again later.
+    Unsettled,
+}
+
+// The ballot an acceptor accepted a slot at, and the command unless it is a witness
+type Accepted<T> = (BallotNumber, Option<Command<T>>);
+
+/// A read of the command chosen in one slot from a quorum of acceptors.
+#[derive(Clone, Debug)]
+pub struct SlotRead<T = Vec<u8>> {
+    slot: Slot,
+    address: Address,
+    quorum: Quorum,
+    // What each acceptor that answered had accepted in the slot, if anything
+    answers: HashMap<AcceptorId, Option<Accepted<T>>>,
+}
+
+impl<T: Clone> SlotRead<T> {
+    /// A read of `slot` from `config`'s acceptors, answered at `address`.
+    pub fn new(slot: Slot, config: &Config, address: Address) -> SlotRead<T> {
+        SlotRead {
+            slot,
+            address,
+            quorum: Quorum::of(config),
+            answers: HashMap::new(),
+        }
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// A `QueryAccepted` for each of the acceptors in `config` that has an
+    /// address, asking what it accepted in the slot.
+    pub fn queries(&self, config: &Config) -> Vec<SendableMessage<T>> {
+        self.quorum
+            .acceptors()
+            .filter_map(|acceptor| config.id_address_map.get(&(*acceptor).into()))
+            .map(|dst| SendableMessage {
+                src: self.address.clone(),
+                dst: dst.clone(),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::QueryAccepted(QueryAcceptedMessage {
+                    src: self.address.clone(),
+                    slots: alloc::vec![self.slot],
+                }),
+            })
+            .collect()
+    }
+
+    /// Record an acceptor's `AcceptedReply`, returning what the read found
+    /// once the answers so far settle it. Witnesses count towards a quorum,
+    /// but an acceptor holding the command must have answered to say what it
+    /// was. Replies from acceptors outside the read's quorum are ignored.
+    pub fn receive(&mut self, reply: &AcceptedReplyMessage<T>) -> Option<SlotReading<T>> {
+        if !self.quorum.contains(&reply.src) {
+            return None;
+        }
+        if reply.gc_below > self.slot {
+            return Some(SlotReading::Forgotten);
+        }
+        let accepted = reply
+            .accepted
+            .iter()
+            .find(|pvalue| pvalue.slot == self.slot)
+            .map(|pvalue| (pvalue.ballot_number.clone(), Some(pvalue.command.clone())))
+            .or_else(|| {
+                reply
+                    .witnessed
+                    .iter()
+                    .find(|(slot, _)| *slot == self.slot)
+                    .map(|(_, ballot)| (ballot.clone(), None))
+            });
+        self.answers.insert(reply.src, accepted);
+        if let Some(command) = self.chosen() {
+            return Some(SlotReading::Chosen(Box::new(command)));
+        }
+        (self.answers.len() == self.quorum.acceptors().count()).then_some(SlotReading::Unsettled)
+    }
+
+    fn chosen(&self) -> Option<Command<T>> {
+        let accepted: Vec<&Accepted<T>> = self.answers.values().flatten().collect();
+        accepted.iter().find_map(|(ballot, _)| {
+            let same: Vec<_> = accepted.iter().filter(|(b, _)| b == ballot).collect();
+            let command = same.iter().find_map(|(_, command)| command.clone())?;
+            (same.len() >= self.quorum.size()).then_some(command)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::types::{CommandId, LeaderId, PValue, ReplicaId, Round};
+
+    #[test]
+    fn client_carries_its_session_into_sequential_reads() {
+        let mut client = Client::new(NodeId::new(9), Address::new("c".to_string(), 1));
+        let replica = Address::new("r".to_string(), 1);
+        let first = client.command(vec![1u8]);
+        let second = client.command(vec![2u8]);
+        assert_eq!((first.request_id, second.request_id), (0, 1));
+
+        let answer = client.receive(&ResponseMessage {
+            src: ReplicaId::new(1),
+            command_id: first.id(),
+            result: vec![7],
+            slot: Slot(12),
+            status: ResponseStatus::Performed,
+        });
+        assert_eq!(answer, Some(Ok(vec![7])));
+        // Responses to other clients are not ours to observe
+        let mut other = client.command(vec![3u8]).id();
+        other.client_id = NodeId::new(10);
+        let ignored = client.receive(&ResponseMessage {
+            src: ReplicaId::new(1),
+            command_id: other,
+            result: vec![],
+            slot: Slot(40),
+            status: ResponseStatus::Performed,
+        });
+        assert_eq!(ignored, None);
+        assert_eq!(client.session_slot(), Slot(12));
+
+        // Turned away: nothing was performed, so the session does not move on
+        let retry = client.receive(&ResponseMessage {
+            src: ReplicaId::new(1),
+            command_id: second.id(),
+            result: vec![],
+            slot: Slot(30),
+            status: ResponseStatus::Unavailable,
+        });
+        assert_eq!(retry, Some(Err(RequestError::Unavailable)));
+        assert_eq!(client.session_slot(), Slot(12));
+
+        assert_eq!(
+            client.sequential(),
+            Consistency::Sequential {
+                after_slot: Slot(12)
+            }
+        );
+        match client
+            .request(&replica, &second, client.sequential())
+            .message
+        {
+            Message::Request(request) => {
+                assert_eq!(request.command.id(), second.id());
+                assert_eq!(
+                    request.consistency,
+                    Consistency::Sequential {
+                        after_slot: Slot(12)
+                    }
+                );
+            }
+            other => panic!("expected a request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batcher_lingers_demultiplexes_and_pushes_back() {
+        use crate::nodes::clock::{ClockProvider, MockClock};
+        use crate::state_machine::encode_batch_results;
+
+        let client = Client::new(NodeId::new(9), Address::new("c".to_string(), 1));
+        let linger = Duration::from_millis(5);
+        let mut batcher: Batcher = Batcher::new(client, linger).with_limits(2, 1);
+        let now = MockClock::new().now();
+        let answer = |request_id, status, result| ResponseMessage {
+            src: ReplicaId::new(1),
+            command_id: CommandId {
+                client_id: NodeId::new(9),
+                request_id,
+            },
+            result,
+            slot: Slot(4),
+            status,
+        };
+
+        // A full batch goes at once
+        assert_eq!(
+            batcher.submit(vec![1], now),
+            Ok(BatchedOp {
+                request_id: 0,
+                index: 0
+            })
+        );
+        assert_eq!(
+            batcher.submit(vec![2], now),
+            Ok(BatchedOp {
+                request_id: 0,
+                index: 1
+            })
+        );
+        let first = batcher.poll(now).unwrap();
+        assert_eq!(first.op, CommandType::Batch(vec![vec![1], vec![2]]));
+
+        // The next one lingers, then waits for the first to be answered
+        batcher.submit(vec![3], now).unwrap();
+        assert!(batcher.poll(now).is_none());
+        assert_eq!(batcher.next_timeout(now), Some(linger));
+        batcher.submit(vec![4], now).unwrap();
+        assert_eq!(
+            batcher.submit(vec![5], now),
+            Err(Backpressure { outstanding: 1 })
+        );
+        assert!(batcher.poll(now + linger).is_none());
+
+        let results = encode_batch_results(&[vec![10], vec![]]);
+        let answered = batcher.receive(&answer(0, ResponseStatus::Performed, results));
+        assert_eq!(
+            answered,
+            Some(vec![
+                (
+                    BatchedOp {
+                        request_id: 0,
+                        index: 0
+                    },
+                    Ok(vec![10])
+                ),
+                (
+                    BatchedOp {
+                        request_id: 0,
+                        index: 1
+                    },
+                    Ok(vec![])
+                ),
+            ])
+        );
+        assert_eq!(batcher.client().session_slot(), Slot(4));
+
+        // A batch turned away fails each of its ops
+        let second = batcher.poll(now + linger).unwrap();
+        assert_eq!(second.request_id, 1);
+        let refused = batcher
+            .receive(&answer(1, ResponseStatus::Unavailable, vec![]))
+            .unwrap();
+        assert!(refused
+            .iter()
+            .all(|(_, result)| *result == Err(RequestError::Unavailable)));
+        assert_eq!(batcher.outstanding(), 0);
+        assert!(batcher
+            .receive(&answer(1, ResponseStatus::Performed, vec![]))
+            .is_none());
+    }
+
+    #[test]
+    fn slot_read_finds_what_a_quorum_of_acceptors_chose() {
+        let acceptors: Vec<AcceptorId> = (1..=3).map(AcceptorId::new).collect();
+        let config = Config::new(
+            HashSet::from([ReplicaId::new(4)]),
+            acceptors.iter().copied().collect(),
+            HashSet::from([LeaderId::new(5)]),
+            acceptors
+                .iter()
+                .zip(1..)
+                .map(|(a, port)| ((*a).into(), Address::new("a".to_string(), port)))
+                .collect::<BTreeMap<_, _>>(),
+            None,
+        );
+        let mut read: SlotRead = SlotRead::new(Slot(7), &config, Address::new("v".to_string(), 1));
+        let queries = read.queries(&config);
+        assert_eq!(queries.len(), 3);
+        assert!(queries.iter().all(|query| matches!(
+            &query.message,
+            Message::QueryAccepted(q) if q.slots == [Slot(7)]
+        )));
+
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1u8]),
+        };
+        let ballot = BallotNumber {
+            round: Round(4),
+            leader: LeaderId::new(5),
+            incarnation: 0,
+        };
+        let reply =
+            |acceptor: u64, accepted: Vec<PValue>, witnessed, gc_below| AcceptedReplyMessage {
+                src: AcceptorId::new(acceptor),
+                accepted,
+                witnessed,
+                gc_below: Slot(gc_below),
+            };
+        let pvalue = |ballot: &BallotNumber| PValue {
+            ballot_number: ballot.clone(),
+            slot: Slot(7),
+            command: command.clone(),
+        };
+        let lower = BallotNumber {
+            round: Round(3),
+            ..ballot.clone()
+        };
+
+        assert_eq!(
+            read.receive(&reply(1, vec![pvalue(&ballot)], vec![], 0)),
+            None
+        );
+        // Accepted at another ballot, so no quorum yet
+        assert_eq!(
+            read.receive(&reply(2, vec![pvalue(&lower)], vec![], 0)),
+            None
+        );
+        // Outside the configuration
+        assert_eq!(
+            read.receive(&reply(8, vec![pvalue(&ballot)], vec![], 0)),
+            None
+        );
+        // A witness completes the quorum; acceptor 1 said what was chosen
+        assert_eq!(
+            read.receive(&reply(3, vec![], vec![(Slot(7), ballot.clone())], 0)),
+            Some(SlotReading::Chosen(Box::new(command.clone())))
+        );
+
+        // Nothing accepted by enough of them at any one ballot
+        let mut read: SlotRead = SlotRead::new(Slot(7), &config, Address::new("v".to_string(), 1));
+        assert_eq!(
+            read.receive(&reply(1, vec![pvalue(&ballot)], vec![], 0)),
+            None
+        );
+        assert_eq!(
+            read.receive(&reply(2, vec![pvalue(&lower)], vec![], 0)),
+            None
+        );
+        assert_eq!(
+            read.receive(&reply(3, vec![], vec![], 0)),
+            Some(SlotReading::Unsettled)
+        );
+
+        // Forgotten by an acceptor that has moved past it
+        let mut read: SlotRead = SlotRead::new(Slot(7), &config, Address::new("v".to_string(), 1));
+        assert_eq!(
+            read.receive(&reply(2, vec![], vec![], 10)),
+            Some(SlotReading::Forgotten)
+        );
+    }
+}
@@ -0,0 +1,10 @@
+//! Collection types for the protocol core.
+//!
+//! With `std` these are the standard library collections; without it the
+//! hash-based ones come from `hashbrown`, which has the same API.
+pub use alloc::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
+#[cfg(feature = "std")]
+pub use std::collections::{hash_map, HashMap, HashSet};
+
+#[cfg(not(feature = "std"))]
+pub use hashbrown::{hash_map, HashMap, HashSet};
@@ -1,3 +1,5 @@
+use crate::types::Slot;
+
 //Number of slots that can have proposals pending
 pub const WINDOW: u64 = 5;
 
@@ -6,3 +8,73 @@ pub const TIMEOUT_MULTIPLY: f32 = 1.2;
 
 // Additive decrease amount for liveness timeouts
 pub const TIMEOUT_SUBTRACT: f32 = 0.03;
+
+// Highest slot a received message may name; far beyond any real log, and low
+// enough that adding a window or a retention to it cannot overflow
+pub const MAX_SLOT: Slot = Slot(1 << 62);
+
+// Slots an active leader may have awaiting a P2b quorum before it throttles proposals
+pub const MAX_OUTSTANDING_SLOTS: usize = 64;
+
+// Ballots a leader remembers giving up, and why, for telling late responses apart
+pub const RETIRED_BALLOTS: usize = 16;
+
+// Inbox depth beyond which a node reports itself Degraded
+pub const INBOX_BACKPRESSURE: usize = 1024;
+
+// Missing slots a replica fetches from one peer at a time when catching up
+pub const CATCH_UP_CHUNK: usize = 64;
+
+// Chunks of missing slots a catching-up replica may have outstanding at once
+pub const CATCH_UP_MAX_WINDOW: usize = 8;
+
+// Inbox depth at which a catching-up replica stops opening its fetch window
+pub const CATCH_UP_BACKLOG: usize = 64;
+
+// Accepted slots an acceptor reports per P1b; leaders ask for the rest a page at a time
+pub const P1B_PAGE: usize = 256;
+
+// Slots a replica may trail the highest decision it has seen before it reports itself Degraded
+pub const LAG_ALERT_SLOTS: u64 = 256;
+
+// Slots past slot_out a replica buffers decisions for; later ones are dropped and fetched again
+pub const MAX_BUFFERED_DECISIONS: u64 = 4096;
+
+// Times a replica reproposes one slot before suspecting the active leader
+pub const MAX_PROPOSAL_RETRIES: u32 = 5;
+
+// Most repropose checks a replica lets pass between reproposals of one slot
+pub const MAX_REPROPOSE_BACKOFF: u32 = 8;
+
+// Results of performed commands a replica keeps for answering retried requests
+pub const RESULT_CACHE_CAPACITY: usize = 4096;
+
+// Sequence numbers per peer remembered for dropping duplicate deliveries
+pub const DEDUP_WINDOW: u64 = 1024;
+
+// Storage fsync latency (smoothed) beyond which a node reports itself Degraded
+pub const SLOW_FSYNC_MS: u64 = 100;
+
+// Storage fsync latency (smoothed) beyond which a node reports itself NotReady
+pub const STALLED_FSYNC_MS: u64 = 1000;
+
+// Slots past its own watermark a node takes a slot from a peer; later ones are refused as out of range
+pub const MAX_SLOTS_AHEAD: u64 = 1 << 20;
+
+// Operations a client's `Batcher` puts in one batch, unless told otherwise
+pub const BATCH_MAX_OPS: usize = 64;
+
+// Batches a client's `Batcher` has awaiting a response before it turns operations away
+pub const BATCH_MAX_OUTSTANDING: usize = 4;
+
+// Peers a node in discovery mode gossips to each heartbeat interval
+pub const GOSSIP_FANOUT: usize = 3;
+
+// Decisions a group-committing decision log buffers before syncing them in one write
+pub const GROUP_COMMIT_MAX_BATCH: usize = 64;
+
+// Longest a group-committing decision log holds a decision back before syncing it
+pub const GROUP_COMMIT_INTERVAL_MS: u64 = 10;
+
+// Barriers a replica holds waiting for the commit index before it answers new ones straight away
+pub const MAX_PENDING_BARRIERS: usize = 1024;
@@ -0,0 +1,224 @@
+//! High-level protocol events for subscribers outside the protocol code.
+//!
+//! Nodes publish an `Event` to their `EventSink` when something an operator
+//! or another subsystem cares about happens: a leader is adopted or
+//! preempted, a slot is decided, a replica switches configuration or falls
+//! too far behind the decisions it has seen. Nodes
+//! publish to `NoEvents` unless given a sink with `set_event_sink`.
+//!
+//! With `std`, an `EventBus` is a sink that can be shared between every node
+//! in a process and fans each event out to its subscribers, so metrics, the
+//! audit log, an admin API or a test can follow the cluster without the
+//! nodes knowing about any of them.
+use alloc::boxed::Box;
+
+use crate::audit::AuditEvent;
+use crate::types;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A leader's ballot was adopted by a quorum of acceptors.
+    LeadershipAcquired {
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+    },
+    /// An active leader stepped down for a higher ballot.
+    Preempted {
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+        preempted_by: types::BallotNumber,
+    },
+    /// A leader saw a quorum of acceptors accept `command` in `slot`.
+    SlotDecided {
+        leader: types::LeaderId,
+        slot: types::Slot,
+        command: types::CommandId,
+    },
+    /// A replica switched to the configuration decided at `slot`.
+    ReconfigApplied {
+        replica: types::ReplicaId,
+        slot: types::Slot,
+        config: Box<types::Config>,
+    },
+    /// A replica's lag, the slots between the last it performed and the
+    /// highest it has seen decided, went over its threshold.
+    ReplicaLagging {
+        replica: types::ReplicaId,
+        lag: u64,
+        threshold: u64,
+    },
+    /// A lagging replica's lag is back within its threshold.
+    ReplicaCaughtUp { replica: types::ReplicaId, lag: u64 },
+    /// A replica reproposed `slot` `attempts` times without it being
+    /// decided, and proposes to leaders other than `leader` from now on.
+    LeaderSuspected {
+        replica: types::ReplicaId,
+        leader: types::LeaderId,
+        slot: types::Slot,
+        attempts: u32,
+    },
+}
+
+impl Event {
+    /// The node that published the event.
+    pub fn node(&self) -> types::NodeId {
+        match self {
+            Event::LeadershipAcquired { leader, .. }
+            | Event::Preempted { leader, .. }
+            | Event::SlotDecided { leader, .. } => (*leader).into(),
+            Event::ReconfigApplied { replica, .. }
+            | Event::ReplicaLagging { replica, .. }
+            | Event::ReplicaCaughtUp { replica, .. }
+            | Event::LeaderSuspected { replica, .. } => (*replica).into(),
+        }
+    }
+
+    /// The audit journal entry for the event, for those the journal keeps.
+    pub fn audit(&self) -> Option<AuditEvent> {
+        match self.clone() {
+            Event::LeadershipAcquired { leader, ballot } => {
+                Some(AuditEvent::LeadershipAcquired { leader, ballot })
+            }
+            Event::Preempted {
+                leader,
+                ballot,
+                preempted_by,
+            } => Some(AuditEvent::LeadershipLost {
+                leader,
+                ballot,
+                preempted_by,
+            }),
+            Event::ReconfigApplied {
+                replica,
+                slot,
+                config,
+            } => Some(AuditEvent::ReconfigApplied {
+                replica,
+                slot,
+                config,
+            }),
+            Event::SlotDecided { .. }
+            | Event::ReplicaLagging { .. }
+            | Event::ReplicaCaughtUp { .. }
+            | Event::LeaderSuspected { .. } => None,
+        }
+    }
+}
+
+/// Where a node publishes its events.
+pub trait EventSink {
+    fn publish(&mut self, event: Event);
+}
+
+/// The default sink: events are dropped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoEvents;
+
+impl EventSink for NoEvents {
+    fn publish(&mut self, _event: Event) {}
+}
+
+#[cfg(feature = "std")]
+pub use self::bus::EventBus;
+
+#[cfg(feature = "std")]
+mod bus {
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+
+    use super::{Event, EventSink};
+
+    enum Subscriber {
+        Callback(Box<dyn FnMut(&Event) + Send>),
+        Channel(Sender<Event>),
+    }
+
+    /// An `EventSink` that hands every event to each of its subscribers, in
+    /// the order they subscribed. Clones share the same subscribers, so one
+    /// bus can be given to every node in a process.
+    #[derive(Clone, Default)]
+    pub struct EventBus {
+        subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    }
+
+    impl EventBus {
+        pub fn new() -> EventBus {
+            EventBus::default()
+        }
+
+        /// Call `callback` with every event published from now on. It runs
+        /// on the publishing node's thread, so it should be quick.
+        pub fn subscribe(&self, callback: impl FnMut(&Event) + Send + 'static) {
+            self.lock().push(Subscriber::Callback(Box::new(callback)));
+        }
+
+        /// Receive every event published from now on. The subscription ends
+        /// when the receiver is dropped.
+        pub fn subscribe_channel(&self) -> Receiver<Event> {
+            let (sender, receiver) = mpsc::channel();
+            self.lock().push(Subscriber::Channel(sender));
+            receiver
+        }
+
+        pub fn subscribers(&self) -> usize {
+            self.lock().len()
+        }
+
+        fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Subscriber>> {
+            // A subscriber that panicked leaves the list itself intact
+            self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+        }
+    }
+
+    impl EventSink for EventBus {
+        fn publish(&mut self, event: Event) {
+            self.lock().retain_mut(|subscriber| match subscriber {
+                Subscriber::Callback(callback) => {
+                    callback(&event);
+                    true
+                }
+                Subscriber::Channel(sender) => sender.send(event.clone()).is_ok(),
+            });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::types::{BallotNumber, LeaderId, Round};
+
+    #[test]
+    fn event_bus_fans_events_out_to_every_subscriber() {
+        let bus = EventBus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let counted = seen.clone();
+        bus.subscribe(move |event| counted.lock().unwrap().push(event.node()));
+        let receiver = bus.subscribe_channel();
+        let dropped = bus.subscribe_channel();
+        drop(dropped);
+
+        // Every clone publishes to the same subscribers
+        let leader = LeaderId::new(1);
+        let ballot = BallotNumber {
+            round: Round(2),
+            leader,
+            incarnation: 0,
+        };
+        let mut sink = bus.clone();
+        sink.publish(Event::LeadershipAcquired {
+            leader,
+            ballot: ballot.clone(),
+        });
+        assert_eq!(*seen.lock().unwrap(), vec![leader.into()]);
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            event.audit(),
+            Some(AuditEvent::LeadershipAcquired { leader, ballot })
+        );
+        // The dropped receiver was unsubscribed
+        assert_eq!(bus.subscribers(), 2);
+    }
+}
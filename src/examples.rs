@@ -0,0 +1,176 @@
+//! Runnable examples of driving the nodes by hand.
+//!
+//! The nodes are sans-IO: they take messages in through `accept_message`,
+//! handle them in `work_on_message`, and hand back what they want sent from
+//! `deliver_sent`. Moving messages between them, and moving their clocks on,
+//! is up to the embedder. The examples below do both in a few lines, with
+//! one node of each role on `MockClock`s, so they double as tests that the
+//! public API still fits together this way.
+//!
+//! # Committing a command
+//!
+//! A leader scouts as soon as it is created. Handing its P1as to the
+//! acceptor and the P1b back gets it adopted, and from then on a request
+//! sent to the replica is proposed, accepted, decided and answered.
+//!
+//! ```
+//! use multifaustus::client::Client;
+//! use multifaustus::collections::{BTreeMap, HashSet};
+//! use multifaustus::messages::{Consistency, Message, SendableMessage};
+//! use multifaustus::nodes::acceptor::Acceptor;
+//! use multifaustus::nodes::clock::MockClock;
+//! use multifaustus::nodes::leader::Leader;
+//! use multifaustus::nodes::mailbox::Mailbox;
+//! use multifaustus::nodes::node::Node;
+//! use multifaustus::nodes::replica::Replica;
+//! use multifaustus::types::{AcceptorId, Address, Config, LeaderId, NodeId, ReplicaId, Slot};
+//!
+//! let address = |port| Address::new("127.0.0.1".to_string(), port);
+//! let (acc, ldr, rep) = (AcceptorId::new(1), LeaderId::new(101), ReplicaId::new(201));
+//! let config = Config::new(
+//!     HashSet::from([rep]),
+//!     HashSet::from([acc]),
+//!     HashSet::from([ldr]),
+//!     BTreeMap::from([
+//!         (acc.into(), address(8001)),
+//!         (ldr.into(), address(8101)),
+//!         (rep.into(), address(8201)),
+//!     ]),
+//!     None,
+//! );
+//! let clock = || Box::new(MockClock::new());
+//! let mut acceptor: Acceptor = Acceptor::new(acc, config.clone(), Mailbox::new(), clock())?;
+//! let mut leader: Leader = Leader::new(ldr, config.clone(), Mailbox::new(), clock())?;
+//! let mut replica: Replica = Replica::new(rep, config.clone(), Mailbox::new(), clock())?;
+//!
+//! // Let every node work, and deliver what it sent, until nothing is in
+//! // flight; return the messages for anyone outside the cluster
+//! fn route(nodes: &mut [(Address, &mut dyn Node)]) -> Vec<SendableMessage> {
+//!     let mut external = Vec::new();
+//!     loop {
+//!         let mut in_flight = Vec::new();
+//!         for (_, node) in nodes.iter_mut() {
+//!             while node.work_on_message() {}
+//!             while let Some(msg) = node.deliver_sent() {
+//!                 in_flight.push(msg);
+//!             }
+//!         }
+//!         if in_flight.is_empty() {
+//!             return external;
+//!         }
+//!         for msg in in_flight {
+//!             match nodes.iter_mut().find(|(address, _)| *address == msg.dst) {
+//!                 Some((_, node)) => node.accept_message(msg),
+//!                 None => external.push(msg),
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! route(&mut [
+//!     (address(8001), &mut acceptor),
+//!     (address(8101), &mut leader),
+//!     (address(8201), &mut replica),
+//! ]);
+//! assert!(leader.progress().leading);
+//!
+//! // A client's request, answered once it is decided in the first slot
+//! let mut client = Client::new(NodeId::new(9), address(9000));
+//! let command = client.command(b"hello".to_vec());
+//! replica.accept_message(client.request(&address(8201), &command, Consistency::Linearizable));
+//! let sent = route(&mut [
+//!     (address(8001), &mut acceptor),
+//!     (address(8101), &mut leader),
+//!     (address(8201), &mut replica),
+//! ]);
+//! let response = sent
+//!     .iter()
+//!     .find_map(|msg| match &msg.message {
+//!         Message::Response(response) if msg.dst == address(9000) => Some(response),
+//!         _ => None,
+//!     })
+//!     .expect("the client is answered");
+//! assert_eq!(response.slot, Slot(1));
+//! assert_eq!(client.receive(response), Some(Ok(Vec::new())));
+//! assert_eq!(replica.progress().frontier, Some(Slot(2)));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! # Applying commands to a state machine
+//!
+//! A replica applies each decided operation to its `StateMachine` in slot
+//! order and answers the client with the result. The state machine is
+//! plugged in with `set_state_machine`, and the replica can be driven one
+//! decision at a time, here without a leader, by handing it `Decision`s.
+//!
+//! ```
+//! use multifaustus::collections::{BTreeMap, HashSet};
+//! use multifaustus::messages::{DecisionMessage, Message, SendableMessage};
+//! use multifaustus::nodes::clock::MockClock;
+//! use multifaustus::nodes::mailbox::Mailbox;
+//! use multifaustus::nodes::node::Node;
+//! use multifaustus::nodes::replica::Replica;
+//! use multifaustus::state_machine::StateMachine;
+//! use multifaustus::types::{
+//!     AcceptorId, Address, Command, CommandType, Config, LeaderId, NodeId, ReplicaId, Slot,
+//! };
+//!
+//! /// Adds each operation to a running total and answers with the total.
+//! #[derive(Default)]
+//! struct Counter(u64);
+//!
+//! impl StateMachine<u64> for Counter {
+//!     fn apply(&mut self, op: &u64) -> Vec<u8> {
+//!         self.0 += op;
+//!         self.0.to_be_bytes().to_vec()
+//!     }
+//! }
+//!
+//! let address = |port| Address::new("127.0.0.1".to_string(), port);
+//! let (acc, ldr, rep) = (AcceptorId::new(1), LeaderId::new(101), ReplicaId::new(201));
+//! let client = NodeId::new(9);
+//! let config = Config::new(
+//!     HashSet::from([rep]),
+//!     HashSet::from([acc]),
+//!     HashSet::from([ldr]),
+//!     BTreeMap::from([
+//!         (acc.into(), address(8001)),
+//!         (ldr.into(), address(8101)),
+//!         (rep.into(), address(8201)),
+//!         (client, address(9000)),
+//!     ]),
+//!     None,
+//! );
+//! let mut replica: Replica<u64> =
+//!     Replica::new(rep, config, Mailbox::new(), Box::new(MockClock::new()))?;
+//! replica.set_state_machine(Box::new(Counter::default()));
+//!
+//! // Decided out of order: slot 2 waits for slot 1
+//! for (slot, op) in [(2, 5), (1, 3)] {
+//!     replica.accept_message(SendableMessage {
+//!         src: address(8101),
+//!         dst: address(8201),
+//!         seq: None,
+//!         lamport: None,
+//!         epoch: None,
+//!         message: Message::Decision(DecisionMessage {
+//!             src: ldr,
+//!             slot_number: Slot(slot),
+//!             command: Command { client_id: client, request_id: slot, op: CommandType::Op(op) },
+//!             certificate: None,
+//!         }),
+//!     });
+//!     while replica.work_on_message() {}
+//! }
+//! let mut totals = Vec::new();
+//! while let Some(msg) = replica.deliver_sent() {
+//!     if let Message::Response(response) = msg.message {
+//!         totals.push((response.slot, response.result));
+//!     }
+//! }
+//! assert_eq!(
+//!     totals,
+//!     vec![(Slot(1), 3u64.to_be_bytes().to_vec()), (Slot(2), 8u64.to_be_bytes().to_vec())]
+//! );
+//! # Ok::<(), anyhow::Error>(())
+//! ```
@@ -1,5 +1,31 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod admin;
+pub mod audit;
+pub mod client;
+pub mod collections;
 pub mod constants;
+pub mod events;
+pub mod examples;
+pub mod membership;
 pub mod messages;
 pub mod nodes;
+pub mod persistence;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod runtime;
+#[cfg(feature = "std")]
+pub mod sim;
+pub mod state_machine;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod status;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod time;
+#[cfg(feature = "std")]
 pub mod transport;
 pub mod types;
+#[cfg(feature = "std")]
+pub mod workload;
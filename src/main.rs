@@ -1,3 +1,91 @@
-fn main() {
-    println!("Hello, world!");
+//! Operator commands for a multifaustus cluster.
+//!
+//! `multifaustus status --config cluster.toml` asks every node listed in
+//! the config for its status and prints them as one table. It exits with 1
+//! if the cluster is degraded and 2 if it could not run, so scripts can
+//! check a cluster without parsing the table.
+//!
+//! `multifaustus verify-log 201=r201.jsonl 202=r202.jsonl ...` compares the
+//! decision logs of replicas 201, 202 and so on, and reports the first slot
+//! they disagree on and which replicas hold which command there. It exits
+//! with 1 if they disagree.
+use std::process::ExitCode;
+use std::time::Duration;
+
+use multifaustus::persistence::file::FileDecisionLog;
+use multifaustus::persistence::verify::verify_decision_logs;
+use multifaustus::status::{ClusterStatus, StatusConfig};
+use multifaustus::types::NodeId;
+
+/// How long to wait for each node's admin server.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+const USAGE: &str = "usage: multifaustus status --config <cluster.toml>
+       multifaustus verify-log <replica id>=<decision log> ...";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["status", "--config", path] => status(path),
+        ["verify-log", ref logs @ ..] if logs.len() >= 2 => verify_log(logs),
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn status(path: &str) -> ExitCode {
+    let config = match StatusConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("multifaustus: cannot read {}: {}", path, e);
+            return ExitCode::from(2);
+        }
+    };
+    let status = ClusterStatus::collect(&config, STATUS_TIMEOUT);
+    print!("{}", status);
+    if status.is_degraded() {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn verify_log(logs: &[&str]) -> ExitCode {
+    let mut decisions = Vec::new();
+    for arg in logs {
+        let Some((id, path)) = arg.split_once('=') else {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        };
+        let Ok(id) = id.parse() else {
+            eprintln!("multifaustus: {} is not a replica id", id);
+            return ExitCode::from(2);
+        };
+        match FileDecisionLog::read::<Vec<u8>>(path) {
+            Ok(log) => decisions.push((NodeId::new(id), log)),
+            Err(e) => {
+                eprintln!("multifaustus: cannot read {}: {}", path, e);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    match verify_decision_logs::<Vec<u8>>(&decisions) {
+        Ok(slots) => {
+            println!("{} logs agree on {} slots", decisions.len(), slots);
+            ExitCode::SUCCESS
+        }
+        Err(divergence) => {
+            println!("{}", divergence);
+            let offenders: Vec<String> = divergence
+                .offenders()
+                .iter()
+                .map(NodeId::to_string)
+                .collect();
+            println!("offending nodes: {}", offenders.join(", "));
+            ExitCode::from(1)
+        }
+    }
 }
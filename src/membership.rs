@@ -0,0 +1,324 @@
+//! Validating and applying membership changes.
+//!
+//! A `CommandType::Reconfig` replaces the whole configuration, so an operator
+//! proposing one must know every other member. A `CommandType::Membership`
+//! command names one node to add or remove instead. Like a reconfiguration,
+//! it takes effect `WINDOW` slots after the slot it is decided in. The change
+//! is applied to whichever configuration is current by then, so changes
+//! decided close together compose rather than overwrite each other.
+//!
+//! A `CommandType::ConfigDelta` carries several membership changes, and new
+//! addresses for existing members, to merge into the current configuration
+//! at once. It applies as a whole or not at all, and leaves the
+//! configuration's timeouts and witnesses as they are, so a delta written
+//! without them cannot reset a cluster's operational settings.
+//!
+//! Replicas check a change when it is requested, and again when it takes
+//! effect: a change that was valid when requested may no longer be once the
+//! changes decided before it have applied. Every replica applies the same
+//! changes to the same configurations in slot order, so they all skip the
+//! same invalid changes.
+use core::fmt;
+use core::hash::Hash;
+
+use crate::collections::HashSet;
+use crate::types::{Address, Config, ConfigDelta, MembershipChange, NodeId};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MembershipError {
+    /// The node being added already has a role in the configuration.
+    AlreadyMember(NodeId),
+    /// The node being removed has no such role in the configuration.
+    NotMember(NodeId),
+    /// Another node already listens at the address of the node being added.
+    AddressInUse(Address),
+    /// Removing the node would leave its role with no members.
+    LastMember(NodeId),
+    /// Removing the acceptor would let witnesses form a quorum on their own.
+    WitnessQuorum(NodeId),
+}
+
+impl fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MembershipError::AlreadyMember(id) => write!(f, "{} is already a member", id),
+            MembershipError::NotMember(id) => write!(f, "{} is not a member", id),
+            MembershipError::AddressInUse(address) => {
+                write!(f, "another node is already at {}", address)
+            }
+            MembershipError::LastMember(id) => {
+                write!(f, "{} is the last member of its role", id)
+            }
+            MembershipError::WitnessQuorum(id) => write!(
+                f,
+                "without {} the witnesses could form a quorum on their own",
+                id
+            ),
+        }
+    }
+}
+
+impl core::error::Error for MembershipError {}
+
+/// Checks membership changes against a configuration and applies them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MembershipManager;
+
+impl MembershipManager {
+    /// Whether `change` can be applied to `config`.
+    pub fn validate(config: &Config, change: &MembershipChange) -> Result<(), MembershipError> {
+        MembershipManager::apply(config, change).map(|_| ())
+    }
+
+    /// The configuration `change` turns `config` into.
+    pub fn apply(config: &Config, change: &MembershipChange) -> Result<Config, MembershipError> {
+        let mut next = config.clone();
+        match change {
+            MembershipChange::AddReplica { id, address } => {
+                add(&mut next, (*id).into(), address)?;
+                next.replicas.insert(*id);
+            }
+            MembershipChange::AddLeader { id, address } => {
+                add(&mut next, (*id).into(), address)?;
+                next.leaders.insert(*id);
+            }
+            MembershipChange::AddAcceptor { id, address } => {
+                add(&mut next, (*id).into(), address)?;
+                next.acceptors.insert(*id);
+            }
+            MembershipChange::RemoveReplica { id } => {
+                remove(&mut next.replicas, id, (*id).into())?;
+                next.id_address_map.remove(id.as_ref());
+            }
+            MembershipChange::RemoveLeader { id } => {
+                remove(&mut next.leaders, id, (*id).into())?;
+                next.id_address_map.remove(id.as_ref());
+            }
+            MembershipChange::RemoveAcceptor { id } => {
+                remove(&mut next.acceptors, id, (*id).into())?;
+                next.id_address_map.remove(id.as_ref());
+                next.witnesses.remove(id);
+                if next.check_witnesses().is_err() {
+                    return Err(MembershipError::WitnessQuorum((*id).into()));
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    /// The configuration `delta` turns `config` into: its membership changes
+    /// applied in order, then its addresses, which may only name members.
+    pub fn merge(config: &Config, delta: &ConfigDelta) -> Result<Config, MembershipError> {
+        let mut next = delta
+            .membership
+            .iter()
+            .try_fold(config.clone(), |next, change| {
+                MembershipManager::apply(&next, change)
+            })?;
+        for (id, address) in &delta.addresses {
+            if !next.id_address_map.contains_key(id) {
+                return Err(MembershipError::NotMember(*id));
+            }
+            if next
+                .id_address_map
+                .iter()
+                .any(|(other, used)| other != id && used == address)
+            {
+                return Err(MembershipError::AddressInUse(address.clone()));
+            }
+            next.id_address_map.insert(*id, address.clone());
+        }
+        Ok(next)
+    }
+}
+
+fn add(config: &mut Config, id: NodeId, address: &Address) -> Result<(), MembershipError> {
+    if config.id_address_map.contains_key(&id) {
+        return Err(MembershipError::AlreadyMember(id));
+    }
+    if config.id_address_map.values().any(|a| a == address) {
+        return Err(MembershipError::AddressInUse(address.clone()));
+    }
+    config.id_address_map.insert(id, address.clone());
+    Ok(())
+}
+
+fn remove<I: Hash + Eq>(
+    members: &mut HashSet<I>,
+    member: &I,
+    id: NodeId,
+) -> Result<(), MembershipError> {
+    if !members.contains(member) {
+        return Err(MembershipError::NotMember(id));
+    }
+    if members.len() == 1 {
+        return Err(MembershipError::LastMember(id));
+    }
+    members.remove(member);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use alloc::vec;
+
+    use crate::collections::BTreeMap;
+    use crate::time::Duration;
+    use crate::types::{AcceptorId, LeaderId, ReplicaId};
+
+    fn config() -> Config {
+        let address = |port: u64| Address::new("127.0.0.1".to_string(), port);
+        Config::new(
+            HashSet::from([ReplicaId::new(201)]),
+            HashSet::from([AcceptorId::new(1), AcceptorId::new(2), AcceptorId::new(3)]),
+            HashSet::from([LeaderId::new(101)]),
+            BTreeMap::from([
+                (NodeId::new(201), address(8201)),
+                (NodeId::new(1), address(8001)),
+                (NodeId::new(2), address(8002)),
+                (NodeId::new(3), address(8003)),
+                (NodeId::new(101), address(8101)),
+            ]),
+            None,
+        )
+        .with_witnesses([AcceptorId::new(3)])
+    }
+
+    #[test]
+    fn membership_changes_touch_one_role_at_a_time() {
+        let config = config();
+        let address = Address::new("127.0.0.1".to_string(), 8102);
+        let added = MembershipManager::apply(
+            &config,
+            &MembershipChange::AddLeader {
+                id: LeaderId::new(102),
+                address: address.clone(),
+            },
+        )
+        .unwrap();
+        assert_eq!(added.leaders.len(), 2);
+        assert_eq!(added.get_address(&NodeId::new(102)), Some(&address));
+        assert_eq!(added.replicas, config.replicas);
+        assert_eq!(added.acceptors, config.acceptors);
+
+        let removed = MembershipManager::apply(
+            &added,
+            &MembershipChange::RemoveLeader {
+                id: LeaderId::new(101),
+            },
+        )
+        .unwrap();
+        assert_eq!(removed.leaders, HashSet::from([LeaderId::new(102)]));
+        assert_eq!(removed.get_address(&NodeId::new(101)), None);
+    }
+
+    #[test]
+    fn membership_manager_refuses_invalid_changes() {
+        let config = config();
+        let check = |change: MembershipChange| MembershipManager::validate(&config, &change);
+        let taken = Address::new("127.0.0.1".to_string(), 8001);
+        let free = Address::new("127.0.0.1".to_string(), 9000);
+
+        // Ids are unique across roles
+        assert_eq!(
+            check(MembershipChange::AddReplica {
+                id: ReplicaId::new(1),
+                address: free.clone(),
+            }),
+            Err(MembershipError::AlreadyMember(NodeId::new(1)))
+        );
+        assert_eq!(
+            check(MembershipChange::AddAcceptor {
+                id: AcceptorId::new(4),
+                address: taken.clone(),
+            }),
+            Err(MembershipError::AddressInUse(taken))
+        );
+        assert_eq!(
+            check(MembershipChange::RemoveLeader {
+                id: LeaderId::new(102),
+            }),
+            Err(MembershipError::NotMember(NodeId::new(102)))
+        );
+        assert_eq!(
+            check(MembershipChange::RemoveReplica {
+                id: ReplicaId::new(201),
+            }),
+            Err(MembershipError::LastMember(NodeId::new(201)))
+        );
+        // A lone witness would be a quorum of its own
+        let two = MembershipManager::apply(
+            &config,
+            &MembershipChange::RemoveAcceptor {
+                id: AcceptorId::new(1),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            MembershipManager::validate(
+                &two,
+                &MembershipChange::RemoveAcceptor {
+                    id: AcceptorId::new(2),
+                },
+            ),
+            Err(MembershipError::WitnessQuorum(NodeId::new(2)))
+        );
+        // Removing the witness itself is fine
+        let without_witness = MembershipManager::apply(
+            &config,
+            &MembershipChange::RemoveAcceptor {
+                id: AcceptorId::new(3),
+            },
+        )
+        .unwrap();
+        assert!(without_witness.witnesses.is_empty());
+    }
+
+    #[test]
+    fn config_delta_merges_into_the_current_config() {
+        let mut config = config();
+        config.timeout_config.suspect_timeout = Duration::from_secs(42);
+        let address = |port: u64| Address::new("127.0.0.1".to_string(), port);
+        let delta = ConfigDelta {
+            membership: vec![
+                MembershipChange::AddLeader {
+                    id: LeaderId::new(102),
+                    address: address(8102),
+                },
+                MembershipChange::RemoveLeader {
+                    id: LeaderId::new(101),
+                },
+            ],
+            addresses: BTreeMap::from([(NodeId::new(1), address(9001))]),
+        };
+        let merged = MembershipManager::merge(&config, &delta).unwrap();
+        assert_eq!(merged.leaders, HashSet::from([LeaderId::new(102)]));
+        assert_eq!(merged.get_address(&NodeId::new(1)), Some(&address(9001)));
+        // Everything the delta does not mention is left as it was
+        assert_eq!(merged.timeout_config, config.timeout_config);
+        assert_eq!(merged.witnesses, config.witnesses);
+        assert_eq!(merged.acceptors, config.acceptors);
+        assert_eq!(merged.replicas, config.replicas);
+
+        // A delta applies as a whole or not at all
+        let moved_away = ConfigDelta {
+            addresses: BTreeMap::from([(NodeId::new(101), address(9101))]),
+            ..delta.clone()
+        };
+        assert_eq!(
+            MembershipManager::merge(&config, &moved_away),
+            Err(MembershipError::NotMember(NodeId::new(101)))
+        );
+        let clash = ConfigDelta {
+            membership: vec![],
+            addresses: BTreeMap::from([(NodeId::new(1), address(8002))]),
+        };
+        assert_eq!(
+            MembershipManager::merge(&config, &clash),
+            Err(MembershipError::AddressInUse(address(8002)))
+        );
+    }
+}
@@ -1,108 +1,615 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::HashSet;
+use crate::nodes::quorum::Quorum;
+use crate::time::Duration;
 use crate::types;
-use std::fmt;
 
-#[derive(Clone, Debug)]
-pub struct SendableMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SendableMessage<T = Vec<u8>> {
     pub src: types::Address,
     pub dst: types::Address,
-    pub message: Message,
+    /// Per-destination sequence number stamped by the sender's mailbox, so the
+    /// receiver can drop copies a transport delivered more than once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// The sender's Lamport time when its mailbox sent the message. Every
+    /// mailbox moves its clock past the times it receives, so a message is
+    /// always stamped later than the messages its sender had handled before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lamport: Option<u64>,
+    /// The epoch of the configuration the sender was in when its mailbox
+    /// sent the message. See `types::Config::epoch`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+    pub message: Message<T>,
 }
 
 /// Enum of all protocol messages exchanged between nodes in MultiPaxos.
-#[derive(Clone, Debug)]
-pub enum Message {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Message<T = Vec<u8>> {
     /// Phase 1a: Sent by leaders to acceptors to initiate a new ballot (prepare).
     P1a(P1aMessage),
     /// Phase 1b: Sent by acceptors to leaders in response to P1a, promising not to accept lower ballots and reporting previously accepted proposals.
-    P1b(P1bMessage),
+    P1b(P1bMessage<T>),
     /// Phase 2a: Sent by leaders to acceptors to propose a value for a slot (accept).
-    P2a(P2aMessage),
+    P2a(P2aMessage<T>),
     /// Phase 2b: Sent by acceptors to leaders in response to P2a, confirming acceptance of the proposal for a slot.
     P2b(P2bMessage),
     /// Sent by acceptors or other leaders to preempt a leader with a higher ballot.
     Preempted(PreemptedMessage),
     /// Sent by leaders to replicas to inform them of a chosen command for a slot.
-    Decision(DecisionMessage),
+    Decision(DecisionMessage<T>),
     /// Sent by clients to replicas to request execution of a command.
-    Request(RequestMessage),
+    Request(RequestMessage<T>),
     /// Sent by replicas to leaders to propose a command for a slot.
-    Propose(ProposeMessage),
+    Propose(ProposeMessage<T>),
+    /// Sent by leaders to a replica whose Propose they did not take up.
+    ProposeRejected(ProposeRejectedMessage),
+    /// Sent by leaders to a replica whose Propose they took up into Phase 2.
+    ProposeAccepted(ProposeAcceptedMessage),
+    /// Sent by a stalled replica to leaders and peer replicas to fetch decisions for slots it is missing.
+    DecisionFetch(DecisionFetchMessage),
+    /// Sent by replicas in response to a DecisionFetch with the requested decisions they know.
+    DecisionFetchReply(DecisionFetchReplyMessage<T>),
+    /// Sent by replicas to the client once its command has been performed.
+    Response(ResponseMessage),
+    /// Sent periodically by acceptors and leaders so that peers can tell a quiet node from a dead one.
+    Heartbeat(HeartbeatMessage),
+    /// Pre-vote: sent by a leader to acceptors to ask whether it could win `ballot_number` before scouting with it.
+    PreP1a(PreP1aMessage),
+    /// Pre-vote: sent by acceptors in response to PreP1a, without changing their promise.
+    PreP1b(PreP1bMessage),
+    /// Sent by an active leader handing leadership over, asking the target to scout straight away.
+    TakeOver(TakeOverMessage),
+    /// Sent by leaders to an acceptor whose P1b left accepted values out, asking for the next page of them.
+    P1bMore(P1bMoreMessage),
+    /// Sent by a leader or recovery tool to acceptors to read what they accepted in some slots, without a promise.
+    QueryAccepted(QueryAcceptedMessage),
+    /// Sent by acceptors in response to QueryAccepted with what they accepted in the slots asked about.
+    AcceptedReply(AcceptedReplyMessage<T>),
+    /// Sent back by a node that was sent a message its role does not handle.
+    Misrouted(MisroutedMessage),
+    /// Sent to a leader or acceptor whose messages carry an older configuration epoch, with the sender's configuration.
+    ConfigSync(ConfigSyncMessage),
+    /// Sent by a node in discovery mode to a few peers, with its configuration and the addresses it knows.
+    Gossip(GossipMessage),
+    /// Sent by clients to a replica to be told once every slot up to one is committed.
+    Barrier(BarrierMessage),
+    /// Sent by replicas to the client in response to a Barrier, with the slots they have performed.
+    Committed(CommittedMessage),
+}
+
+impl<T> Message<T> {
+    /// The cluster node that sent this message, if it came from one (client requests do not).
+    pub fn sender(&self) -> Option<types::NodeId> {
+        match self {
+            Message::P1a(m) => Some(m.src.into()),
+            Message::P1b(m) => Some(m.src.into()),
+            Message::P2a(m) => Some(m.src.into()),
+            Message::P2b(m) => Some(m.src.into()),
+            Message::Preempted(m) => Some(m.src.into()),
+            Message::Decision(m) => Some(m.src.into()),
+            Message::Request(_) => None,
+            Message::Propose(m) => Some(m.src.into()),
+            Message::ProposeRejected(m) => Some(m.src.into()),
+            Message::ProposeAccepted(m) => Some(m.src.into()),
+            Message::DecisionFetch(m) => Some(m.src.into()),
+            Message::DecisionFetchReply(m) => Some(m.src.into()),
+            Message::Response(m) => Some(m.src.into()),
+            Message::Heartbeat(m) => Some(m.src),
+            Message::PreP1a(m) => Some(m.src.into()),
+            Message::PreP1b(m) => Some(m.src.into()),
+            Message::TakeOver(m) => Some(m.src.into()),
+            Message::P1bMore(m) => Some(m.src.into()),
+            Message::QueryAccepted(_) => None,
+            Message::AcceptedReply(m) => Some(m.src.into()),
+            Message::Misrouted(m) => Some(m.src),
+            Message::ConfigSync(m) => Some(m.src),
+            Message::Gossip(m) => Some(m.src),
+            Message::Barrier(_) => None,
+            Message::Committed(m) => Some(m.src.into()),
+        }
+    }
+
+    /// The name of the message's variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::P1a(_) => "P1a",
+            Message::P1b(_) => "P1b",
+            Message::P2a(_) => "P2a",
+            Message::P2b(_) => "P2b",
+            Message::Preempted(_) => "Preempted",
+            Message::Decision(_) => "Decision",
+            Message::Request(_) => "Request",
+            Message::Propose(_) => "Propose",
+            Message::ProposeRejected(_) => "ProposeRejected",
+            Message::ProposeAccepted(_) => "ProposeAccepted",
+            Message::DecisionFetch(_) => "DecisionFetch",
+            Message::DecisionFetchReply(_) => "DecisionFetchReply",
+            Message::Response(_) => "Response",
+            Message::Heartbeat(_) => "Heartbeat",
+            Message::PreP1a(_) => "PreP1a",
+            Message::PreP1b(_) => "PreP1b",
+            Message::TakeOver(_) => "TakeOver",
+            Message::P1bMore(_) => "P1bMore",
+            Message::QueryAccepted(_) => "QueryAccepted",
+            Message::AcceptedReply(_) => "AcceptedReply",
+            Message::Misrouted(_) => "Misrouted",
+            Message::ConfigSync(_) => "ConfigSync",
+            Message::Gossip(_) => "Gossip",
+            Message::Barrier(_) => "Barrier",
+            Message::Committed(_) => "Committed",
+        }
+    }
+
+    /// Whether a node in `role` handles this message. Responses and commit
+    /// notifications are only for clients, and any node may be gossiped to or told that it misrouted a
+    /// message.
+    pub fn handled_by(&self, role: types::Role) -> bool {
+        use types::Role::*;
+        match self {
+            Message::P1a(_)
+            | Message::P2a(_)
+            | Message::PreP1a(_)
+            | Message::P1bMore(_)
+            | Message::QueryAccepted(_) => role == Acceptor,
+            Message::P1b(_)
+            | Message::P2b(_)
+            | Message::Preempted(_)
+            | Message::Propose(_)
+            | Message::PreP1b(_)
+            | Message::TakeOver(_)
+            | Message::AcceptedReply(_) => role == Leader,
+            Message::Decision(_)
+            | Message::Request(_)
+            | Message::ProposeRejected(_)
+            | Message::ProposeAccepted(_)
+            | Message::DecisionFetchReply(_)
+            | Message::Barrier(_) => role == Replica,
+            Message::DecisionFetch(_) => role != Acceptor,
+            Message::ConfigSync(_) => role != Replica,
+            Message::Response(_) | Message::Committed(_) => false,
+            Message::Heartbeat(_) | Message::Misrouted(_) | Message::Gossip(_) => true,
+        }
+    }
+}
+
+/// The message struct carried by one `Message` variant, e.g. to pick the
+/// P2as out of a mailbox with `Mailbox::count_outbox_of::<P2aMessage>()`.
+pub trait Variant<T = Vec<u8>>: Sized {
+    /// The struct `message` carries, if it is this variant.
+    fn of(message: &Message<T>) -> Option<&Self>;
 }
 
-impl fmt::Display for SendableMessage {
+macro_rules! variants {
+    ($($variant:ident => $message:ty),* $(,)?) => {
+        $(
+            impl<T> Variant<T> for $message {
+                fn of(message: &Message<T>) -> Option<&Self> {
+                    match message {
+                        Message::$variant(m) => Some(m),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+variants! {
+    P1a => P1aMessage,
+    P1b => P1bMessage<T>,
+    P2a => P2aMessage<T>,
+    P2b => P2bMessage,
+    Preempted => PreemptedMessage,
+    Decision => DecisionMessage<T>,
+    Request => RequestMessage<T>,
+    Propose => ProposeMessage<T>,
+    ProposeRejected => ProposeRejectedMessage,
+    ProposeAccepted => ProposeAcceptedMessage,
+    DecisionFetch => DecisionFetchMessage,
+    DecisionFetchReply => DecisionFetchReplyMessage<T>,
+    Response => ResponseMessage,
+    Heartbeat => HeartbeatMessage,
+    PreP1a => PreP1aMessage,
+    PreP1b => PreP1bMessage,
+    TakeOver => TakeOverMessage,
+    P1bMore => P1bMoreMessage,
+    QueryAccepted => QueryAcceptedMessage,
+    AcceptedReply => AcceptedReplyMessage<T>,
+    Misrouted => MisroutedMessage,
+    ConfigSync => ConfigSyncMessage,
+    Gossip => GossipMessage,
+    Barrier => BarrierMessage,
+    Committed => CommittedMessage,
+}
+
+impl<T> fmt::Display for SendableMessage<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self.message {
-            Message::P1a(_) => write!(f, "P1a from {} => {}", self.src, self.dst),
-            Message::P1b(_) => write!(f, "P1b from {} => {}", self.src, self.dst),
-            Message::P2a(_) => write!(f, "P2a from {} => {}", self.src, self.dst),
-            Message::P2b(_) => write!(f, "P2b from {} => {}", self.src, self.dst),
-            Message::Preempted(_) => write!(f, "Preempted from {} => {}", self.src, self.dst),
-            Message::Decision(_) => write!(f, "Decision from {} => {}", self.src, self.dst),
-            Message::Request(_) => write!(f, "Request from {} => {}", self.src, self.dst),
-            Message::Propose(_) => write!(f, "Propose from {} => {}", self.src, self.dst),
+        write!(
+            f,
+            "{} from {} => {}",
+            self.message.kind(),
+            self.src,
+            self.dst
+        )?;
+        if let Some(lamport) = self.lamport {
+            write!(f, " at L{}", lamport)?;
+        }
+        if let Some(epoch) = self.epoch {
+            write!(f, " in E{}", epoch)?;
         }
+        Ok(())
     }
 }
 
 /// Sent by leaders (scouts) to acceptors in Phase 1 of Paxos to initiate a new ballot (prepare).
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct P1aMessage {
     pub src: types::LeaderId,
     pub ballot_number: types::BallotNumber,
+    /// Every slot below this is decided as far as the leader knows, so acceptors leave them out of their P1b.
+    #[serde(default)]
+    pub decided_below: types::Slot,
+}
+
+/// Sent by a leader to acceptors before Phase 1, asking whether they would promise `ballot_number`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreP1aMessage {
+    pub src: types::LeaderId,
+    pub ballot_number: types::BallotNumber,
+}
+
+/// An acceptor's answer to a PreP1a. It grants the pre-vote if the ballot is
+/// higher than its promise and it no longer hears from the leader it promised.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PreP1bMessage {
+    pub src: types::AcceptorId,
+    pub ballot_number: types::BallotNumber,
+    pub granted: bool,
+}
+
+/// Sent by an active leader to the leader it is handing over to. The target
+/// runs Phase 1 at once, without a pre-vote, with a ballot above `ballot_hint`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TakeOverMessage {
+    pub src: types::LeaderId,
+    pub ballot_hint: types::BallotNumber,
 }
 
 /// Sent by acceptors to leaders (scouts) in response to P1a, promising not to accept lower ballots and reporting previously accepted proposals.
-#[derive(Clone, Debug)]
-pub struct P1bMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct P1bMessage<T = Vec<u8>> {
     pub src: types::AcceptorId,
     pub ballot_number: types::BallotNumber,
-    pub accepted: Vec<types::PValue>,
+    pub accepted: Vec<types::PValue<T>>,
+    /// The acceptor's GC watermark: it no longer reports slots below this.
+    #[serde(default)]
+    pub gc_below: types::Slot,
+    /// Slots and ballots a witness acceptor accepted, without the commands.
+    #[serde(default)]
+    pub witnessed: Vec<(types::Slot, types::BallotNumber)>,
+    /// For a page after the first, the slot it starts at: the `more_from` of the page before.
+    #[serde(default)]
+    pub continues_from: Option<types::Slot>,
+    /// The slot the acceptor's next page of accepted values starts at, if it has more than fit in this one.
+    #[serde(default)]
+    pub more_from: Option<types::Slot>,
+}
+
+/// Sent by leaders to an acceptor whose P1b for `ballot_number` had more accepted values than fit, asking for
+/// those from `from_slot` on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct P1bMoreMessage {
+    pub src: types::LeaderId,
+    pub ballot_number: types::BallotNumber,
+    pub from_slot: types::Slot,
+}
+
+/// Sent to acceptors to read what they accepted in `slots`. It changes no promise, so a leader can probe for values
+/// that may have been chosen without running Phase 1, and a tool that is not a cluster member can send it too; the
+/// reply goes to `src`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueryAcceptedMessage {
+    pub src: types::Address,
+    pub slots: Vec<types::Slot>,
+}
+
+/// An acceptor's answer to a QueryAccepted: what it accepted in the slots asked about, leaving out slots it never
+/// accepted anything in or has forgotten.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcceptedReplyMessage<T = Vec<u8>> {
+    pub src: types::AcceptorId,
+    pub accepted: Vec<types::PValue<T>>,
+    /// Slots and ballots a witness acceptor accepted, without the commands.
+    pub witnessed: Vec<(types::Slot, types::BallotNumber)>,
+    /// The acceptor's GC watermark: slots below it were decided and forgotten.
+    pub gc_below: types::Slot,
 }
 
 /// Sent by leaders (commanders) to acceptors in Phase 2 of Paxos to propose a value for a slot (accept).
-#[derive(Clone, Debug)]
-pub struct P2aMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct P2aMessage<T = Vec<u8>> {
     pub src: types::LeaderId,
     pub ballot_number: types::BallotNumber,
-    pub slot_number: u64,
-    pub command: types::Command,
+    pub slot_number: types::Slot,
+    pub command: types::Command<T>,
+    /// The leader's GC watermark: every slot below it is decided.
+    #[serde(default)]
+    pub gc_below: types::Slot,
 }
 
 /// Sent by acceptors to leaders (commanders) in response to P2a, confirming acceptance of the proposal for a slot.
 /// This message is an indicator that the proposal has been Accepted/Decided by a single Acceptor.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct P2bMessage {
     pub src: types::AcceptorId,
     pub ballot_number: types::BallotNumber,
-    pub slot_number: u64,
+    pub slot_number: types::Slot,
 }
 
 /// Sent by acceptors or other leaders to preempt a leader with a higher ballot.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PreemptedMessage {
     pub src: types::LeaderId,
     pub ballot_number: types::BallotNumber,
 }
 
 /// Sent by leaders to replicas to inform them of a chosen command for a slot.
-#[derive(Clone, Debug)]
-pub struct DecisionMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecisionMessage<T = Vec<u8>> {
     pub src: types::LeaderId,
-    pub slot_number: u64,
-    pub command: types::Command,
+    pub slot_number: types::Slot,
+    pub command: types::Command<T>,
+    /// How the slot was chosen, sent when the configuration asks for
+    /// certified decisions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<DecisionCertificate>,
+}
+
+/// The ballot a slot's command was chosen at and the acceptors whose P2bs
+/// made the quorum, as the deciding leader counted them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DecisionCertificate {
+    pub ballot_number: types::BallotNumber,
+    pub acceptors: Vec<types::AcceptorId>,
+}
+
+impl DecisionCertificate {
+    /// Check that the acceptors make a quorum of `config`'s, the
+    /// configuration in effect at the certified slot.
+    pub fn verify(&self, config: &types::Config) -> anyhow::Result<()> {
+        if let Some(stray) = self
+            .acceptors
+            .iter()
+            .find(|a| !config.acceptors.contains(*a))
+        {
+            anyhow::bail!("{} is not an acceptor", stray);
+        }
+        // Naming an acceptor twice counts it once
+        let acceptors: HashSet<types::AcceptorId> = self.acceptors.iter().copied().collect();
+        let quorum = Quorum::of(config);
+        if !quorum.reached_by(&acceptors) {
+            anyhow::bail!(
+                "{} acceptors are short of a quorum of {}",
+                acceptors.len(),
+                quorum.size()
+            );
+        }
+        Ok(())
+    }
 }
 
 /// Sent by clients to replicas to request execution of a command.
-#[derive(Clone, Debug)]
-pub struct RequestMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestMessage<T = Vec<u8>> {
     pub src: types::Address,
-    pub command: types::Command,
+    pub command: types::Command<T>,
+    #[serde(default)]
+    pub consistency: Consistency,
+    /// The replica that passed the request on, having no leader to decide it.
+    /// A proxied request is not passed on again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxied_by: Option<types::ReplicaId>,
+}
+
+/// How current the answer to a request has to be.
+///
+/// Only reads the state machine can answer without changing its state are
+/// served outside consensus; everything else is ordered like a
+/// `Linearizable` request whatever level it asks for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Consistency {
+    /// Ordered through consensus with every other command.
+    #[default]
+    Linearizable,
+    /// Answered by any replica that has performed every slot up to
+    /// `after_slot`, the latest one the client's session has observed.
+    Sequential { after_slot: types::Slot },
+    /// Answered from whatever state the receiving replica has.
+    Eventual,
 }
 
 /// Sent by replicas to leaders to propose a command for a slot.
-#[derive(Clone, Debug)]
-pub struct ProposeMessage {
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProposeMessage<T = Vec<u8>> {
+    pub src: types::ReplicaId,
+    pub slot_number: types::Slot,
+    pub command: types::Command<T>,
+}
+
+/// Sent by a replica whose slot_out has stalled behind later decisions, asking for the missing slots.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecisionFetchMessage {
+    pub src: types::ReplicaId,
+    pub slots: Vec<types::Slot>,
+}
+
+/// Sent by replicas in response to a DecisionFetch: the requested (slot, command) decisions they know.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DecisionFetchReplyMessage<T = Vec<u8>> {
+    pub src: types::ReplicaId,
+    pub decisions: Vec<(types::Slot, types::Command<T>)>,
+    /// The certificates of those decisions the replica holds one for, by slot.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub certificates: Vec<(types::Slot, DecisionCertificate)>,
+}
+
+/// Why a leader did not take up a proposal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// The leader already has a different command for this slot; the replica should re-slot its command.
+    SlotOccupied,
+    /// The leader has recorded the proposal but is not active, so Phase 2 will wait for adoption.
+    NotActive,
+    /// The leader's proposal policy refused the command for now; the replica should retry later.
+    Throttled,
+    /// The leader already holds the same command, proposed by another replica, in `slot`; the replica should
+    /// wait for it to be decided there.
+    Duplicate { slot: types::Slot },
+}
+
+/// Sent by leaders to a replica whose Propose they did not take up, so it can react without waiting for a timer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProposeRejectedMessage {
+    pub src: types::LeaderId,
+    pub slot_number: types::Slot,
+    pub command_id: types::CommandId,
+    pub reason: RejectReason,
+    /// For `SlotOccupied`, the slot after the highest one the leader holds a command for.
+    #[serde(default)]
+    pub free_slot: Option<types::Slot>,
+}
+
+/// Sent by leaders to a replica whose Propose they took up, so it leaves
+/// retrying it to them instead of proposing it again on every repropose check.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProposeAcceptedMessage {
+    pub src: types::LeaderId,
+    pub slot_number: types::Slot,
+    pub command_id: types::CommandId,
+}
+
+/// Sent by replicas to the client once its command has been performed, with the state machine's result,
+/// or straight away if the command was turned away.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResponseMessage {
     pub src: types::ReplicaId,
-    pub slot_number: u64,
-    pub command: types::Command,
+    pub command_id: types::CommandId,
+    pub result: Vec<u8>,
+    /// The last slot the replica had performed when it produced `result`.
+    #[serde(default)]
+    pub slot: types::Slot,
+    #[serde(default)]
+    pub status: ResponseStatus,
+}
+
+/// Sent by a client to a replica, which answers with a Committed once it has
+/// performed every slot up to `slot`: a barrier the client's later reads can
+/// wait on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BarrierMessage {
+    pub src: types::Address,
+    /// Chosen by the client to match the Committed to this barrier.
+    pub id: u64,
+    pub slot: types::Slot,
+}
+
+/// A replica's answer to a Barrier. `commit_index` is the last slot the
+/// replica has performed, every slot up to it decided. It is sent once that
+/// covers the barrier's slot, or straight away when the replica has too many
+/// barriers waiting.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommittedMessage {
+    pub src: types::ReplicaId,
+    pub id: u64,
+    pub commit_index: types::CommitIndex,
+}
+
+/// Whether a replica took up the command a response answers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseStatus {
+    /// The command was performed, or the read answered, and `result` holds the outcome.
+    #[default]
+    Performed,
+    /// No leader can reach a quorum of acceptors, so the command was turned
+    /// away rather than queued. It is safe to send it again later.
+    Unavailable,
+    /// The command encodes to `size` bytes, more than the replica's limit of
+    /// `limit`. It was not proposed, and sending it again will not help.
+    TooLarge { size: u64, limit: u64 },
+    /// A leader refused the command, and decided the refusal in its place.
+    /// Sending it again will not help.
+    Refused(types::RefusalReason),
+}
+
+/// Sent back by a node that was sent a message its role does not handle, so
+/// the sender finds out about its routing bug instead of the message being lost.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MisroutedMessage {
+    pub src: types::NodeId,
+    /// The role of the node the message reached.
+    pub role: types::Role,
+    /// The kind of message it was, as `Message::kind` names it.
+    pub kind: String,
+}
+
+/// A node's configuration, sent to a leader or acceptor found to be in an
+/// older epoch. Configurations only move on at replicas, through decided
+/// slots; the receiver takes this one up if its epoch is newer than its own.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigSyncMessage {
+    pub src: types::NodeId,
+    pub config: types::Config,
+}
+
+/// What a node in discovery mode knows of the cluster, see
+/// `nodes::discovery`. Each address carries the version its node last
+/// advertised it at, so that the newest one wins wherever they meet.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GossipMessage {
+    pub src: types::NodeId,
+    pub config: types::Config,
+    pub peers: Vec<(types::NodeId, types::Address, u64)>,
+}
+
+/// Liveness signal from acceptors and leaders.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeartbeatMessage {
+    pub src: types::NodeId,
+    /// Set by an active leader to the ballot it leads under.
+    pub ballot: Option<types::BallotNumber>,
+    /// Set by a leader that has not reached a quorum of acceptors for longer
+    /// than `quorum_loss_timeout`.
+    #[serde(default)]
+    pub quorum_lost: bool,
+    /// Set by an acceptor to the highest ballot it has promised.
+    #[serde(default)]
+    pub promised: Option<types::BallotNumber>,
+    /// Set by an active leader to the lease it asks for, and by an acceptor
+    /// to the lease it grants, when leases are used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease: Option<Lease>,
+    /// Set by an active leader to the highest slot it has seen decided along
+    /// with every slot before it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_index: Option<types::CommitIndex>,
+}
+
+/// A lease on a leader's ballot. While an acceptor's grant runs, it promises
+/// no other leader's ballot, so the holder of a quorum's grants is the only
+/// leader that can be active.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    pub ballot: types::BallotNumber,
+    /// Ties a grant to the request it answers.
+    pub id: u64,
+    /// How long the lease runs from when it is sent: granted for, by an
+    /// acceptor, or left of a quorum's grants, by a leader.
+    pub duration: Duration,
 }
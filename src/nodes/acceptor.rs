@@ -1,52 +1,378 @@
-use std::collections::HashMap;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, error, info, warn};
 
+use crate::collections::HashMap;
+use crate::constants::{INBOX_BACKPRESSURE, P1B_PAGE};
 use crate::messages;
 use crate::nodes::clock::{ClockAction, ClockProvider};
+use crate::nodes::discovery::Discovery;
+use crate::nodes::epoch::{EpochCheck, EpochSync};
+use crate::nodes::failure_detector::{FailureDetector, FrozenFailureDetector};
+use crate::nodes::freeze::{self, age, rewind, Timers};
+use crate::nodes::health::Health;
 use crate::nodes::mailbox::Mailbox;
+use crate::nodes::node::{Node, Progress};
+use crate::nodes::router::{self, ConfigRouter, Router, WireFormat};
+use crate::nodes::slot_map::{MemoryMode, SlotMap};
+use crate::nodes::validate::{SlotBound, Validator};
+use crate::persistence::{BallotStore, VolatileBallotStore};
+use crate::time::{Duration, Instant};
 use crate::types;
 
-pub enum AcceptorMessageIn {
+// The key of the promise made for every slot, by a P1a
+const EVERY_SLOT: types::Slot = types::Slot(0);
+
+pub enum AcceptorMessageIn<T = Vec<u8>> {
     P1a(messages::P1aMessage),
-    P2a(Box<messages::P2aMessage>),
+    P2a(Box<messages::P2aMessage<T>>),
+    PreP1a(messages::PreP1aMessage),
+    P1bMore(messages::P1bMoreMessage),
+    QueryAccepted(messages::QueryAcceptedMessage),
+    ConfigSync(Box<messages::ConfigSyncMessage>),
+}
+
+/// An `Acceptor` captured by `Acceptor::freeze`. See `nodes::freeze`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrozenAcceptor<T = Vec<u8>> {
+    node_id: types::AcceptorId,
+    address: types::Address,
+    config: types::Config,
+    mailbox: Mailbox<T>,
+    promised: HashMap<types::Slot, types::BallotNumber>,
+    accepted: SlotMap<(types::BallotNumber, Option<types::Command<T>>)>,
+    witness: bool,
+    memory_mode: MemoryMode,
+    failure_detector: FrozenFailureDetector,
+    validator: Validator,
+    slot_bound: SlotBound,
+    decided_below: types::Slot,
+    timers: Timers,
+    #[serde(default)]
+    discovery: Option<Discovery>,
+    // The ballot last granted a lease, and how long ago
+    #[serde(default)]
+    lease: Option<(types::BallotNumber, Duration)>,
 }
 
-pub struct Acceptor {
+pub struct Acceptor<T = Vec<u8>> {
     node_id: types::AcceptorId,
     address: types::Address,
     config: types::Config,
-    mailbox: Mailbox,
+    mailbox: Mailbox<T>,
     // State per slot: promised ballot, accepted ballot, accepted command
-    promised: HashMap<u64, types::BallotNumber>,
-    accepted: HashMap<u64, (types::BallotNumber, types::Command)>,
+    promised: HashMap<types::Slot, types::BallotNumber>,
+    accepted: SlotMap<(types::BallotNumber, Option<types::Command<T>>)>,
+    // A witness votes like any acceptor but keeps no commands
+    witness: bool,
+    // Whether slots below the leaders' GC watermark are forgotten
+    memory_mode: MemoryMode,
     // Clock provider for periodic cleanup and heartbeat
     clock: Box<dyn ClockProvider + Send>,
+    // When each peer was last heard from
+    failure_detector: FailureDetector,
+    // Checks received messages and counts the malformed ones dropped
+    validator: Validator,
+    // Refuses P2as for slots absurdly far past the watermark
+    slot_bound: SlotBound,
+    // The highest slot a leader we promised had not seen decided
+    decided_below: types::Slot,
+    // Resolves peers to addresses at send time
+    router: Box<dyn Router + Send>,
+    // Durable copy of the global promise (promised[0])
+    ballot_store: Box<dyn BallotStore + Send>,
+    // Peers in older configuration epochs sent this one's
+    epochs: EpochSync,
+    // Peers' addresses learned by gossip, in discovery mode
+    discovery: Option<Discovery>,
+    // The ballot last granted a lease, and when
+    lease: Option<(types::BallotNumber, Instant)>,
 }
 
-impl Acceptor {
+impl<T: types::Payload> Acceptor<T> {
     pub fn new(
         acceptor_id: types::AcceptorId,
         config: types::Config,
-        mailbox: Mailbox,
+        mut mailbox: Mailbox<T>,
         clock: Box<dyn ClockProvider + Send>,
-    ) -> anyhow::Result<Acceptor> {
+    ) -> anyhow::Result<Acceptor<T>> {
         let addr = config
             .get_address(acceptor_id.as_ref())
             .ok_or(anyhow::anyhow!("Failed to get address"))?;
+        config.check_witnesses()?;
+        mailbox.set_epoch(config.epoch);
         Ok(Acceptor {
             node_id: acceptor_id,
+            witness: config.is_witness(&acceptor_id),
             address: addr.clone(),
+            failure_detector: FailureDetector::new(config.timeout_config.suspect_timeout),
+            validator: Validator::default(),
+            slot_bound: SlotBound::default(),
+            decided_below: types::Slot(0),
+            router: Box::new(ConfigRouter::new(&config)),
             config,
             mailbox,
             promised: HashMap::new(),
-            accepted: HashMap::new(),
+            accepted: SlotMap::default(),
+            memory_mode: MemoryMode::Unbounded,
             clock,
+            ballot_store: Box::new(VolatileBallotStore::default()),
+            epochs: EpochSync::new(),
+            discovery: None,
+            lease: None,
         })
     }
 
-    pub fn accept_message(&mut self, msg: messages::SendableMessage) {
-        self.mailbox.receive(msg);
+    /// Replace how peers are resolved to addresses.
+    pub fn set_router(&mut self, mut router: Box<dyn Router + Send>) {
+        router.reconfigure(&self.config);
+        if let Some(discovery) = &self.discovery {
+            discovery.route(router.as_mut());
+        }
+        self.router = router;
+    }
+
+    /// Learn peers' addresses by gossip, starting from `seeds`, and gossip
+    /// every `heartbeat_interval`. See `nodes::discovery`.
+    pub fn enable_discovery(&mut self, seeds: Vec<types::Address>) {
+        let discovery = Discovery::new(
+            self.node_id.into(),
+            self.address.clone(),
+            seeds,
+            &self.config,
+        );
+        discovery.route(self.router.as_mut());
+        self.discovery = Some(discovery);
+        self.gossip();
+    }
+
+    /// The addresses discovered so far, in discovery mode.
+    pub fn discovery(&self) -> Option<&Discovery> {
+        self.discovery.as_ref()
+    }
+
+    fn gossip(&mut self) {
+        let Some(discovery) = self.discovery.as_mut() else {
+            return;
+        };
+        for sendable in discovery.gossip(&self.config) {
+            self.mailbox.send(sendable);
+        }
+        let interval = self.config.timeout_config.heartbeat_interval;
+        self.clock.schedule(ClockAction::Gossip, interval);
+    }
+
+    fn gossiped(&mut self, from: &types::Address, gossip: &messages::GossipMessage) {
+        let Some(discovery) = self.discovery.as_mut() else {
+            return;
+        };
+        discovery.receive(from, gossip, self.router.as_mut());
+        // Leaders and acceptors take up a newer configuration gossiped to them
+        if gossip.config.epoch > self.config.epoch {
+            if let Err(e) = self.reconfigure(gossip.config.clone()) {
+                warn!("{}: ignoring gossiped configuration: {}", self.node_id, e);
+            }
+        }
+    }
+
+    /// Move to `config`, e.g. once it is sent in a `ConfigSync`. What the
+    /// acceptor promised and accepted is kept, and so is whether it is a
+    /// witness, which only a restart changes.
+    pub fn reconfigure(&mut self, config: types::Config) -> anyhow::Result<()> {
+        config.check_witnesses()?;
+        info!(
+            "{}: moving from configuration epoch {} to {}",
+            self.node_id, self.config.epoch, config.epoch
+        );
+        self.router.reconfigure(&config);
+        self.mailbox.set_epoch(config.epoch);
+        self.config = config;
+        Ok(())
+    }
+
+    /// Hold accepted slots to a window, forgetting those below the GC
+    /// watermark leaders send with their P2as.
+    pub fn set_memory_mode(&mut self, mode: MemoryMode) {
+        self.accepted.set_mode(mode);
+        self.memory_mode = mode;
+    }
+
+    /// Capture this acceptor's state, to `thaw` it elsewhere.
+    pub fn freeze(&self) -> FrozenAcceptor<T> {
+        let now = self.clock.now();
+        FrozenAcceptor {
+            node_id: self.node_id,
+            address: self.address.clone(),
+            config: self.config.clone(),
+            mailbox: self.mailbox.clone(),
+            promised: self.promised.clone(),
+            accepted: self.accepted.clone(),
+            witness: self.witness,
+            memory_mode: self.memory_mode,
+            failure_detector: self.failure_detector.freeze(now),
+            validator: self.validator.clone(),
+            slot_bound: self.slot_bound.clone(),
+            decided_below: self.decided_below,
+            timers: self.clock.pending(),
+            discovery: self.discovery.clone(),
+            lease: self
+                .lease
+                .as_ref()
+                .map(|(ballot, granted)| (ballot.clone(), age(*granted, now))),
+        }
+    }
+
+    /// Build a frozen acceptor again, its timers scheduled on `clock`.
+    pub fn thaw(
+        frozen: FrozenAcceptor<T>,
+        mut clock: Box<dyn ClockProvider + Send>,
+    ) -> anyhow::Result<Acceptor<T>> {
+        frozen.config.check_witnesses()?;
+        let now = clock.now();
+        freeze::schedule(clock.as_mut(), frozen.timers);
+        let mut mailbox = frozen.mailbox;
+        mailbox.set_epoch(frozen.config.epoch);
+        Ok(Acceptor {
+            node_id: frozen.node_id,
+            address: frozen.address,
+            failure_detector: frozen.failure_detector.thaw(now),
+            validator: frozen.validator,
+            slot_bound: frozen.slot_bound,
+            decided_below: frozen.decided_below,
+            router: Box::new(ConfigRouter::new(&frozen.config)),
+            config: frozen.config,
+            mailbox,
+            promised: frozen.promised,
+            accepted: frozen.accepted,
+            witness: frozen.witness,
+            memory_mode: frozen.memory_mode,
+            clock,
+            ballot_store: Box::new(VolatileBallotStore::default()),
+            epochs: EpochSync::new(),
+            discovery: frozen.discovery,
+            lease: frozen
+                .lease
+                .map(|(ballot, granted)| (ballot, rewind(granted, now))),
+        })
+    }
+
+    /// Persist promises to `store`, first recovering any promise it already holds.
+    ///
+    /// Call before the acceptor handles messages, so a restarted acceptor
+    /// never promises a lower ballot than it did before the crash. Nor does
+    /// it promise another leader for one lease from then: grants are not
+    /// stored, and one made before the crash may still be running.
+    pub fn set_ballot_store(
+        &mut self,
+        mut store: Box<dyn BallotStore + Send>,
+    ) -> anyhow::Result<()> {
+        if let Some(ballot) = store.load()? {
+            if self
+                .promised
+                .get(&EVERY_SLOT)
+                .is_none_or(|current| ballot > *current)
+            {
+                self.promised.insert(EVERY_SLOT, ballot);
+            }
+            // Only the leader of the promised ballot can hold a running lease:
+            // while one runs, no other leader is promised
+            self.lease = self
+                .promised
+                .get(&EVERY_SLOT)
+                .map(|promised| (promised.clone(), self.clock.now()));
+        }
+        self.ballot_store = store;
+        Ok(())
+    }
+
+    /// Raise the global promise to `ballot`, durably, if it is higher.
+    fn promise(&mut self, ballot: &types::BallotNumber) -> anyhow::Result<()> {
+        if self
+            .promised
+            .get(&EVERY_SLOT)
+            .is_none_or(|current| ballot > current)
+        {
+            // The store must succeed before the promise can be acted on
+            self.ballot_store.store(ballot)?;
+            self.promised.insert(EVERY_SLOT, ballot.clone());
+        }
+        Ok(())
+    }
+
+    /// Malformed messages received and dropped so far. See `nodes::validate`.
+    pub fn malformed(&self) -> u64 {
+        self.validator.rejected()
+    }
+
+    /// Refuse P2as for slots more than `max_ahead` past the acceptor's
+    /// watermark. Defaults to `MAX_SLOTS_AHEAD`.
+    pub fn set_max_slots_ahead(&mut self, max_ahead: u64) {
+        self.slot_bound.set_max_ahead(max_ahead);
+    }
+
+    /// P2as refused so far for slots out of range. See `nodes::validate::SlotBound`.
+    pub fn out_of_range(&self) -> u64 {
+        self.slot_bound.refused()
+    }
+
+    /// How far the log has got as far as this acceptor knows: the highest
+    /// slot it accepted, its GC watermark, or the first slot a leader it
+    /// promised had not seen decided.
+    fn watermark(&self) -> types::Slot {
+        let accepted = self.accepted.last_slot().unwrap_or(types::Slot(0));
+        accepted.max(self.accepted.floor()).max(self.decided_below)
+    }
+
+    pub fn accept_message(&mut self, msg: messages::SendableMessage<T>) {
+        if !self.validator.admit(self.node_id, &msg) {
+            return;
+        }
+        let now = self.clock.now();
+        if let Some(sender) = msg.message.sender() {
+            self.failure_detector.heard_from(sender, now);
+        }
+        if let messages::Message::Gossip(gossip) = &msg.message {
+            self.gossiped(&msg.src, gossip);
+            return;
+        }
+        if let Some(sync) = self
+            .epochs
+            .sync_for(self.node_id.into(), &self.config, &msg, now)
+        {
+            self.mailbox.send(sync);
+        }
+        if self.refuses_stale(&msg) {
+            return;
+        }
+        self.mailbox
+            .receive_as(types::Role::Acceptor, self.node_id.into(), msg);
+    }
+
+    /// Whether `msg` is a vote asked for by a leader in an older epoch, which
+    /// may be counting a quorum of acceptors that no longer make one.
+    fn refuses_stale(&self, msg: &messages::SendableMessage<T>) -> bool {
+        let vote = matches!(
+            msg.message,
+            messages::Message::P1a(_) | messages::Message::P2a(_)
+        );
+        let EpochCheck::Stale(theirs) = EpochCheck::of(msg, self.config.epoch) else {
+            return false;
+        };
+        if vote {
+            warn!(
+                monotonic_counter.paxos.messages.stale_epoch = 1u64,
+                paxos.message.kind = msg.message.kind(),
+                "{}: refused {} from epoch {}, in epoch {}",
+                self.node_id,
+                msg,
+                theirs,
+                self.config.epoch
+            );
+        }
+        vote
     }
 
     pub fn work_on_message(&mut self) -> bool {
@@ -58,6 +384,18 @@ impl Acceptor {
         let inbox_received = match received_msg.message {
             messages::Message::P1a(_msg) => AcceptorMessageIn::P1a(_msg),
             messages::Message::P2a(_msg) => AcceptorMessageIn::P2a(Box::new(_msg)),
+            messages::Message::PreP1a(_msg) => AcceptorMessageIn::PreP1a(_msg),
+            messages::Message::P1bMore(_msg) => AcceptorMessageIn::P1bMore(_msg),
+            messages::Message::QueryAccepted(_msg) => AcceptorMessageIn::QueryAccepted(_msg),
+            messages::Message::ConfigSync(_msg) => AcceptorMessageIn::ConfigSync(Box::new(_msg)),
+            // Liveness was already recorded by the failure detector on arrival
+            messages::Message::Heartbeat(heartbeat) => {
+                if let Err(e) = self.grant_lease(heartbeat) {
+                    error!("{}: Error granting a lease: {}", self.node_id, e);
+                    return false;
+                }
+                return true;
+            }
             msg => {
                 error!(
                     "{}: Leader received unexpected message in mailbox: {:?}",
@@ -74,85 +412,305 @@ impl Acceptor {
         }
     }
 
-    pub fn handle_msg(&mut self, msg: AcceptorMessageIn) -> anyhow::Result<()> {
+    pub fn handle_msg(&mut self, msg: AcceptorMessageIn<T>) -> anyhow::Result<()> {
+        let _span = debug_span!(
+            "acceptor.handle_msg",
+            paxos.node.role = "acceptor",
+            paxos.node.id = %self.node_id,
+            paxos.lamport = self.mailbox.lamport(),
+        )
+        .entered();
         match msg {
             AcceptorMessageIn::P1a(p1a_msg) => {
                 // For all slots, update promised if ballot >= promised
                 // For simplicity, treat promised as a global ballot (can be per-slot for full generality)
                 let ballot_number = p1a_msg.ballot_number.clone();
-                let mut accepted = Vec::new();
-                // Collect all accepted proposals for this ballot
-                for (&slot, (accepted_ballot, command)) in &self.accepted {
-                    if accepted_ballot == &ballot_number {
-                        accepted.push(types::PValue {
-                            ballot_number: accepted_ballot.clone(),
-                            slot,
-                            command: command.clone(),
-                        });
-                    }
-                }
                 // Update promised if ballot >= promised
                 let promised_ballot = self
                     .promised
-                    .get(&0)
+                    .get(&EVERY_SLOT)
                     .cloned()
                     .unwrap_or_else(|| types::BallotNumber::new(p1a_msg.src));
-                if ballot_number >= promised_ballot {
-                    self.promised.insert(0, ballot_number.clone()); // Update global promised
-                    self.send_p1b(p1a_msg.src, ballot_number, accepted)?;
+                if let Some(holder) = self.lease_holder(p1a_msg.src) {
+                    // The holder may be answering reads on the strength of it
+                    debug!(
+                        monotonic_counter.paxos.acceptor.lease_held_off = 1u64,
+                        "{}: not promising {:?} while {} holds a lease",
+                        self.node_id,
+                        ballot_number,
+                        holder
+                    );
+                } else if ballot_number >= promised_ballot {
+                    self.decided_below = self.decided_below.max(p1a_msg.decided_below);
+                    // Update global promised
+                    self.promise(&ballot_number)?;
+                    // Report what was accepted under any ballot in the slots
+                    // the leader has not seen decided: it must re-propose
+                    // these values to keep decisions stable
+                    self.send_p1b(p1a_msg.src, ballot_number, p1a_msg.decided_below, None)?;
+                }
+            }
+            AcceptorMessageIn::P1bMore(more_msg) => {
+                // Only the promise still held is worth reporting on
+                if self.promised.get(&EVERY_SLOT) == Some(&more_msg.ballot_number) {
+                    self.send_p1b(
+                        more_msg.src,
+                        more_msg.ballot_number,
+                        more_msg.from_slot,
+                        Some(more_msg.from_slot),
+                    )?;
                 }
             }
             AcceptorMessageIn::P2a(p2a_msg) => {
                 let ballot = p2a_msg.ballot_number.clone();
                 let slot = p2a_msg.slot_number;
-                let promised_ballot = self
-                    .promised
-                    .get(&slot)
-                    .cloned()
-                    .unwrap_or_else(|| types::BallotNumber::new(p2a_msg.src));
+                let watermark = self.watermark();
+                let in_range = [slot, p2a_msg.gc_below]
+                    .into_iter()
+                    .all(|slot| self.slot_bound.admit(self.node_id, "P2a", slot, watermark));
+                if !in_range {
+                    return Ok(());
+                }
+                if self.memory_mode != MemoryMode::Unbounded {
+                    self.collect_garbage(p2a_msg.gc_below);
+                }
+                // Respect the Phase 1 promise as well as earlier accepts for this slot
+                let promised_ballot =
+                    match (self.promised.get(&EVERY_SLOT), self.promised.get(&slot)) {
+                        (Some(global), Some(per_slot)) if per_slot > global => per_slot.clone(),
+                        (Some(global), _) => global.clone(),
+                        (None, Some(per_slot)) => per_slot.clone(),
+                        (None, None) => types::BallotNumber::new(p2a_msg.src),
+                    };
                 if ballot >= promised_ballot {
+                    self.accepted.check(slot)?;
                     // Accept the proposal
+                    debug!(
+                        monotonic_counter.paxos.acceptor.accepted = 1u64,
+                        paxos.slot = slot.0,
+                        paxos.ballot.round = ballot.round.0,
+                        "{}: accepted slot {}",
+                        self.node_id,
+                        slot
+                    );
+                    self.promise(&ballot)?;
                     self.promised.insert(slot, ballot.clone());
-                    self.accepted
-                        .insert(slot, (ballot.clone(), p2a_msg.command.clone()));
+                    let command = (!self.witness).then(|| p2a_msg.command.clone());
+                    self.accepted.insert(slot, (ballot.clone(), command))?;
                     self.send_p2b(p2a_msg.src, ballot, slot)?;
                 }
             }
+            AcceptorMessageIn::QueryAccepted(query) => {
+                self.send_accepted_reply(query)?;
+            }
+            AcceptorMessageIn::ConfigSync(sync) => {
+                if sync.config.epoch > self.config.epoch {
+                    self.reconfigure(sync.config)?;
+                }
+            }
+            AcceptorMessageIn::PreP1a(pre_p1a_msg) => {
+                // Encourage a scout only if it could win and would not
+                // displace a leader this acceptor still hears from
+                let now = self.clock.now();
+                let granted = match self.promised.get(&EVERY_SLOT) {
+                    None => true,
+                    Some(promised) => {
+                        pre_p1a_msg.ballot_number > *promised
+                            && (promised.leader == pre_p1a_msg.src
+                                || self
+                                    .failure_detector
+                                    .is_suspected(promised.leader.as_ref(), now))
+                    }
+                };
+                debug!(
+                    "{}: pre-vote for {:?}: {}",
+                    self.node_id, pre_p1a_msg.ballot_number, granted
+                );
+                self.send_pre_p1b(pre_p1a_msg.src, pre_p1a_msg.ballot_number, granted)?;
+            }
         }
         Ok(())
     }
 
+    /// Forget the slots below `watermark`, which a leader has seen decided.
+    fn collect_garbage(&mut self, watermark: types::Slot) {
+        if watermark > self.accepted.floor() {
+            self.accepted.collect_garbage(watermark);
+            self.promised
+                .retain(|slot, _| *slot == EVERY_SLOT || *slot >= watermark);
+        }
+    }
+
     /// Send a P1b (promise) message to the leader.
+    /// Send a page of at most `P1B_PAGE` accepted slots from `from_slot` on.
+    /// `continues_from` is set for pages after the first.
     pub fn send_p1b(
         &mut self,
         leader: types::LeaderId,
         ballot: types::BallotNumber,
-        accepted: Vec<types::PValue>,
+        from_slot: types::Slot,
+        continues_from: Option<types::Slot>,
     ) -> anyhow::Result<()> {
+        let mut accepted: Vec<types::PValue<T>> = Vec::new();
+        let mut witnessed = Vec::new();
+        let mut more_from = None;
+        let slots = self.accepted.iter().filter(|(slot, _)| **slot >= from_slot);
+        for (i, (&slot, (accepted_ballot, command))) in slots.enumerate() {
+            if i == P1B_PAGE {
+                more_from = Some(slot);
+                break;
+            }
+            match command {
+                Some(command) => accepted.push(types::PValue {
+                    ballot_number: accepted_ballot.clone(),
+                    slot,
+                    command: command.clone(),
+                }),
+                None => witnessed.push((slot, accepted_ballot.clone())),
+            }
+        }
         let msg = messages::P1bMessage {
             src: self.node_id,
             ballot_number: ballot,
             accepted,
+            gc_below: self.accepted.floor(),
+            witnessed,
+            continues_from,
+            more_from,
         };
         let ldr_address = self
-            .config
-            .get_address(leader.as_ref())
+            .router
+            .resolve(leader.as_ref())
             .ok_or(anyhow::anyhow!("Leader address not found"))?;
         let sendable = messages::SendableMessage {
             src: self.address.clone(),
-            dst: ldr_address.clone(),
+            dst: ldr_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
             message: messages::Message::P1b(msg),
         };
         self.mailbox.send(sendable);
         Ok(())
     }
 
+    /// Answer a leader's pre-vote without changing any promise.
+    fn send_pre_p1b(
+        &mut self,
+        leader: types::LeaderId,
+        ballot: types::BallotNumber,
+        granted: bool,
+    ) -> anyhow::Result<()> {
+        let msg = messages::PreP1bMessage {
+            src: self.node_id,
+            ballot_number: ballot,
+            granted,
+        };
+        let ldr_address = self
+            .router
+            .resolve(leader.as_ref())
+            .ok_or(anyhow::anyhow!("Leader address not found"))?;
+        let sendable = messages::SendableMessage {
+            src: self.address.clone(),
+            dst: ldr_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::PreP1b(msg),
+        };
+        self.mailbox.send(sendable);
+        Ok(())
+    }
+
+    /// The leader other than `leader` whose lease this acceptor granted and
+    /// has not yet run out, if any.
+    fn lease_holder(&self, leader: types::LeaderId) -> Option<types::LeaderId> {
+        let duration = self.config.timeout_config.lease?;
+        let (ballot, granted) = self.lease.as_ref()?;
+        let running = self.clock.now() < *granted + duration;
+        (running && ballot.leader != leader).then_some(ballot.leader)
+    }
+
+    /// Grant the lease a leader asks for in its heartbeat, if it asks under
+    /// the ballot this acceptor promised.
+    fn grant_lease(&mut self, heartbeat: messages::HeartbeatMessage) -> anyhow::Result<()> {
+        let (Some(duration), Some(ask)) = (self.config.timeout_config.lease, heartbeat.lease)
+        else {
+            return Ok(());
+        };
+        let leader = ask.ballot.leader;
+        if heartbeat.src != *leader.as_ref() || self.promised.get(&EVERY_SLOT) != Some(&ask.ballot)
+        {
+            return Ok(());
+        }
+        // A grant to the same ballot extends it
+        self.lease = Some((ask.ballot.clone(), self.clock.now()));
+        let heartbeat = messages::HeartbeatMessage {
+            src: self.node_id.into(),
+            ballot: None,
+            quorum_lost: false,
+            promised: Some(ask.ballot.clone()),
+            lease: Some(messages::Lease {
+                ballot: ask.ballot,
+                id: ask.id,
+                duration,
+            }),
+            commit_index: None,
+        };
+        let ldr_address = self
+            .router
+            .resolve(leader.as_ref())
+            .ok_or(anyhow::anyhow!("Leader address not found"))?;
+        let sendable = messages::SendableMessage {
+            src: self.address.clone(),
+            dst: ldr_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::Heartbeat(heartbeat),
+        };
+        self.mailbox.send(sendable);
+        Ok(())
+    }
+
+    /// Tell whoever sent `query` what was accepted in the slots it asked
+    /// about, at most `P1B_PAGE` of them.
+    fn send_accepted_reply(&mut self, query: messages::QueryAcceptedMessage) -> anyhow::Result<()> {
+        let mut accepted = Vec::new();
+        let mut witnessed = Vec::new();
+        for slot in query.slots.into_iter().take(P1B_PAGE) {
+            match self.accepted.get(&slot) {
+                Some((ballot, Some(command))) => accepted.push(types::PValue {
+                    ballot_number: ballot.clone(),
+                    slot,
+                    command: command.clone(),
+                }),
+                Some((ballot, None)) => witnessed.push((slot, ballot.clone())),
+                None => {}
+            }
+        }
+        self.mailbox.send(messages::SendableMessage {
+            src: self.address.clone(),
+            dst: query.src,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::AcceptedReply(messages::AcceptedReplyMessage {
+                src: self.node_id,
+                accepted,
+                witnessed,
+                gc_below: self.accepted.floor(),
+            }),
+        });
+        Ok(())
+    }
+
     /// Send a P2b (accepted) message to the leader.
     pub fn send_p2b(
         &mut self,
         leader: types::LeaderId,
         ballot: types::BallotNumber,
-        slot: u64,
+        slot: types::Slot,
     ) -> anyhow::Result<()> {
         let msg = messages::P2bMessage {
             src: self.node_id,
@@ -160,12 +718,15 @@ impl Acceptor {
             slot_number: slot,
         };
         let ldr_address = self
-            .config
-            .get_address(leader.as_ref())
+            .router
+            .resolve(leader.as_ref())
             .ok_or(anyhow::anyhow!("Leader address not found"))?;
         let sendable = messages::SendableMessage {
             src: self.address.clone(),
-            dst: ldr_address.clone(),
+            dst: ldr_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
             message: messages::Message::P2b(msg),
         };
         self.mailbox.send(sendable);
@@ -176,9 +737,11 @@ impl Acceptor {
     pub fn handle_timer(&mut self, action: ClockAction) -> anyhow::Result<()> {
         match action {
             ClockAction::AcceptorHeartbeat => {
+                self.send_heartbeats()?;
                 // Perform periodic maintenance tasks
                 self.cleanup_old_state()?;
             }
+            ClockAction::Gossip => self.gossip(),
             _ => {
                 // Ignore action types not relevant to acceptors
             }
@@ -191,16 +754,44 @@ impl Acceptor {
         // In a full implementation, this could:
         // 1. Remove promises/acceptances for very old slots
         // 2. Compact state for slots that are likely committed
-        // 3. Send heartbeat signals to other nodes
 
         // For now, just schedule the next heartbeat
         self.schedule_heartbeat()?;
         Ok(())
     }
 
+    /// Let every leader know this acceptor is alive, and the highest ballot
+    /// it has promised.
+    fn send_heartbeats(&mut self) -> anyhow::Result<()> {
+        let heartbeat = messages::Message::Heartbeat(messages::HeartbeatMessage {
+            src: self.node_id.into(),
+            ballot: None,
+            quorum_lost: false,
+            promised: self.promised.values().max().cloned(),
+            lease: None,
+            commit_index: None,
+        });
+        let leaders = self.config.leaders.iter().map(|l| (*l).into());
+        let (src, mailbox) = (&self.address, &mut self.mailbox);
+        let sent = router::broadcast(&*self.router, leaders, &heartbeat, |dst, message| {
+            mailbox.send(messages::SendableMessage {
+                src: src.clone(),
+                dst,
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message,
+            })
+        });
+        if let Err(unroutable) = sent {
+            unroutable.report(self.node_id);
+        }
+        Ok(())
+    }
+
     /// Schedule periodic heartbeat
     fn schedule_heartbeat(&mut self) -> anyhow::Result<()> {
-        let timeout = self.config.timeout_config.max_timeout;
+        let timeout = self.config.timeout_config.heartbeat_interval;
         self.clock.schedule(ClockAction::AcceptorHeartbeat, timeout);
         Ok(())
     }
@@ -228,13 +819,87 @@ impl Acceptor {
     // Add methods for sending Promise and Accepted messages
 }
 
+impl<T: types::Payload> Node<T> for Acceptor<T> {
+    fn accept_message(&mut self, msg: messages::SendableMessage<T>) {
+        Acceptor::accept_message(self, msg)
+    }
+
+    fn work_on_message(&mut self) -> bool {
+        Acceptor::work_on_message(self)
+    }
+
+    fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>> {
+        Acceptor::check_timers(self)
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.clock.next_timeout()
+    }
+
+    fn deliver_sent(&mut self) -> Option<messages::SendableMessage<T>> {
+        self.mailbox.deliver_sent()
+    }
+
+    fn pending(&self) -> usize {
+        self.mailbox.inbox.len()
+    }
+
+    /// Acceptors only respond to leaders, so they are never `NotReady` on their own.
+    fn health(&self) -> Health {
+        let now = self.clock.now();
+        let mut degraded: Vec<String> = Vec::new();
+        let leaders = self.config.leaders.iter().map(|l| (*l).into());
+        if self.failure_detector.reachable_count(leaders, now) == 0 {
+            degraded.push("no leader heard from recently".into());
+        }
+        if self.mailbox.inbox.len() > INBOX_BACKPRESSURE {
+            degraded.push(alloc::format!(
+                "inbox backed up: {} messages",
+                self.mailbox.inbox.len()
+            ));
+        }
+        Health::from_checks(Vec::new(), degraded)
+    }
+
+    fn set_timeouts(&mut self, timeouts: types::TimeoutConfig) {
+        self.failure_detector
+            .set_suspect_after(timeouts.suspect_timeout);
+        self.config.timeout_config = timeouts;
+    }
+
+    fn link_down(&mut self, address: &types::Address) {
+        self.failure_detector.suspect_address(&self.config, address);
+    }
+
+    fn link_negotiated(&mut self, address: &types::Address, format: WireFormat) {
+        self.router.set_wire_format(address, format);
+    }
+
+    fn wire_format(&self, address: &types::Address) -> Option<WireFormat> {
+        self.router.wire_format(address)
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            ballot: self.promised.get(&EVERY_SLOT).cloned(),
+            leading: false,
+            frontier: Some(
+                self.accepted
+                    .last_slot()
+                    .map_or(self.accepted.floor(), |s| s + 1),
+            ),
+            committed: None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::collections::{BTreeMap, HashSet};
     use crate::messages::*;
     use crate::nodes::mailbox::Mailbox;
     use crate::types::*;
-    use std::collections::{BTreeMap, HashSet};
 
     fn setup() -> Acceptor {
         let mailbox = Mailbox::new();
@@ -254,8 +919,7 @@ mod tests {
             None,
         );
         let clock = Box::new(crate::nodes::clock::MockClock::new());
-        let acceptor = Acceptor::new(accept, config, mailbox, clock).unwrap();
-        acceptor
+        Acceptor::new(accept, config, mailbox, clock).unwrap()
     }
 
     #[test]
@@ -267,17 +931,414 @@ mod tests {
         let p1a_msg = P1aMessage {
             src: LeaderId::new(1),
             ballot_number: ballot.clone(),
+            decided_below: Slot(0),
         };
         acceptor
             .handle_msg(AcceptorMessageIn::P1a(p1a_msg))
             .unwrap();
 
         // Assert outgoing P1b message
-        assert!(acceptor
-            .mailbox
-            .outbox
-            .iter()
-            .any(|msg| matches!(msg.message, Message::P1b(_))));
+        assert_eq!(acceptor.mailbox.count_outbox_of::<P1bMessage>(), 1);
+    }
+
+    #[test]
+    fn acceptor_reports_a_long_log_in_pages_above_the_decided_slots() {
+        let mut acceptor = setup();
+        let low = BallotNumber::new(LeaderId::new(1));
+        let last = P1B_PAGE as u64 + 20;
+        for slot in 1..=last {
+            let command = Command {
+                client_id: NodeId::new(9),
+                request_id: slot,
+                op: CommandType::Op(vec![]),
+            };
+            acceptor
+                .accepted
+                .insert(Slot(slot), (low.clone(), Some(command)))
+                .unwrap();
+        }
+        let high = BallotNumber {
+            round: Round(1),
+            leader: LeaderId::new(1),
+            incarnation: 0,
+        };
+        let p1bs = |acceptor: &mut Acceptor| {
+            let pages: Vec<P1bMessage> = acceptor.mailbox.outbox_of().cloned().collect();
+            acceptor.mailbox.clear_outbox();
+            pages
+        };
+
+        // Slots the leader has seen decided are left out, and one page is sent
+        acceptor
+            .handle_msg(AcceptorMessageIn::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: high.clone(),
+                decided_below: Slot(11),
+            }))
+            .unwrap();
+        let first = p1bs(&mut acceptor);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].accepted.len(), P1B_PAGE);
+        assert_eq!(first[0].accepted[0].slot, Slot(11));
+        assert_eq!(first[0].continues_from, None);
+        let more_from = first[0].more_from.unwrap();
+        assert_eq!(more_from, Slot(11 + P1B_PAGE as u64));
+
+        // The rest follow on request, for the ballot still promised only
+        let more = |ballot: &BallotNumber| {
+            AcceptorMessageIn::P1bMore(P1bMoreMessage {
+                src: LeaderId::new(1),
+                ballot_number: ballot.clone(),
+                from_slot: more_from,
+            })
+        };
+        acceptor.handle_msg(more(&low)).unwrap();
+        assert!(p1bs(&mut acceptor).is_empty());
+        acceptor.handle_msg(more(&high)).unwrap();
+        let rest = p1bs(&mut acceptor);
+        assert_eq!(rest[0].continues_from, Some(more_from));
+        assert_eq!(rest[0].more_from, None);
+        assert_eq!(
+            rest[0].accepted.iter().map(|p| p.slot).collect::<Vec<_>>(),
+            more_from.up_to(Slot(last + 1)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn acceptor_reports_earlier_accepts_and_keeps_its_promise() {
+        let mut acceptor = setup();
+        let low = BallotNumber::new(LeaderId::new(1));
+        let high = BallotNumber {
+            round: Round(1),
+            leader: LeaderId::new(1),
+            incarnation: 0,
+        };
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        let p2a = |ballot: &BallotNumber| {
+            AcceptorMessageIn::P2a(Box::new(P2aMessage {
+                src: ballot.leader,
+                ballot_number: ballot.clone(),
+                slot_number: Slot(1),
+                command: command.clone(),
+                gc_below: Slot(0),
+            }))
+        };
+        acceptor.handle_msg(p2a(&low)).unwrap();
+        acceptor.drain_outbox();
+
+        // A higher ballot's Phase 1 learns what the lower ballot got accepted
+        acceptor
+            .handle_msg(AcceptorMessageIn::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: high.clone(),
+                decided_below: Slot(0),
+            }))
+            .unwrap();
+        match &acceptor.mailbox.outbox[0].message {
+            Message::P1b(p1b) => {
+                assert_eq!(p1b.accepted.len(), 1);
+                assert_eq!(p1b.accepted[0].ballot_number, low);
+            }
+            other => panic!("expected P1b, got {:?}", other),
+        }
+        acceptor.drain_outbox();
+
+        // After promising the higher ballot, the lower one can no longer be accepted
+        acceptor.handle_msg(p2a(&low)).unwrap();
+        assert!(acceptor.mailbox.outbox.is_empty());
+    }
+
+    #[test]
+    fn bounded_acceptor_forgets_slots_below_the_leaders_watermark() {
+        let mut acceptor = setup();
+        let mode = MemoryMode::SlotBounded { retained: 10 };
+        acceptor.set_memory_mode(mode);
+        let ballot = BallotNumber::new(LeaderId::new(1));
+        let p2a = |slot: u64, gc_below: u64| {
+            AcceptorMessageIn::P2a(Box::new(P2aMessage {
+                src: LeaderId::new(1),
+                ballot_number: ballot.clone(),
+                slot_number: Slot(slot),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: slot,
+                    op: CommandType::Op(vec![slot as u8]),
+                },
+                gc_below: Slot(gc_below),
+            }))
+        };
+        let capacity = mode.capacity().unwrap();
+        for slot in 1..=capacity {
+            acceptor.handle_msg(p2a(slot, 1)).unwrap();
+        }
+        acceptor.drain_outbox();
+        // A full window refuses the next slot without answering
+        assert!(acceptor.handle_msg(p2a(capacity + 1, 1)).is_err());
+        assert!(acceptor.mailbox.outbox.is_empty());
+
+        // Once the leader has seen the first slots decided, the window moves on
+        acceptor.handle_msg(p2a(capacity + 1, 3)).unwrap();
+        acceptor.drain_outbox();
+        acceptor.handle_msg(p1a(1)).unwrap();
+        match &acceptor.mailbox.outbox[0].message {
+            Message::P1b(p1b) => {
+                assert_eq!(p1b.gc_below, Slot(3));
+                assert_eq!(p1b.accepted.len() as u64, capacity - 1);
+                assert!(p1b.accepted.iter().all(|pvalue| pvalue.slot >= Slot(3)));
+            }
+            other => panic!("expected P1b, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn acceptor_refuses_slots_far_past_its_watermark() {
+        let mut acceptor = setup();
+        acceptor.set_max_slots_ahead(100);
+        let ballot = BallotNumber::new(LeaderId::new(1));
+        let p2a = |slot: u64, gc_below: u64| {
+            AcceptorMessageIn::P2a(Box::new(P2aMessage {
+                src: LeaderId::new(1),
+                ballot_number: ballot.clone(),
+                slot_number: Slot(slot),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: slot,
+                    op: CommandType::Op(vec![1]),
+                },
+                gc_below: Slot(gc_below),
+            }))
+        };
+        acceptor.handle_msg(p2a(100, 0)).unwrap();
+        assert_eq!(acceptor.mailbox.outbox.len(), 1);
+        acceptor.drain_outbox();
+
+        // Neither a slot nor a watermark may leap ahead
+        acceptor.handle_msg(p2a(201, 0)).unwrap();
+        acceptor.handle_msg(p2a(101, 201)).unwrap();
+        assert!(acceptor.mailbox.outbox.is_empty());
+        assert_eq!(acceptor.out_of_range(), 2);
+        assert_eq!(acceptor.progress().frontier, Some(Slot(101)));
+
+        // A leader whose Phase 1 starts further on moves the watermark there
+        acceptor
+            .handle_msg(AcceptorMessageIn::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: ballot.clone(),
+                decided_below: Slot(1000),
+            }))
+            .unwrap();
+        acceptor.drain_outbox();
+        acceptor.handle_msg(p2a(1050, 0)).unwrap();
+        assert_eq!(acceptor.mailbox.outbox.len(), 1);
+    }
+
+    fn p1a(round: u64) -> AcceptorMessageIn {
+        AcceptorMessageIn::P1a(P1aMessage {
+            src: LeaderId::new(1),
+            ballot_number: BallotNumber {
+                round: Round(round),
+                leader: LeaderId::new(1),
+                incarnation: 0,
+            },
+            decided_below: Slot(0),
+        })
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn acceptor_keeps_its_promise_across_restarts() {
+        use crate::persistence::file::FileBallotStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-acceptor-ballot-{}-{}.json",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut acceptor = setup();
+        acceptor
+            .set_ballot_store(Box::new(FileBallotStore::new(&path)))
+            .unwrap();
+        acceptor.handle_msg(p1a(5)).unwrap();
+        assert_eq!(acceptor.mailbox.outbox.len(), 1);
+        // Crash: all in-memory state is lost
+        drop(acceptor);
+
+        let mut acceptor = setup();
+        acceptor
+            .set_ballot_store(Box::new(FileBallotStore::new(&path)))
+            .unwrap();
+        acceptor.handle_msg(p1a(4)).unwrap();
+        acceptor
+            .handle_msg(AcceptorMessageIn::P2a(Box::new(P2aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber {
+                    round: Round(4),
+                    leader: LeaderId::new(1),
+                    incarnation: 0,
+                },
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![]),
+                },
+                gc_below: Slot(0),
+            })))
+            .unwrap();
+        assert!(acceptor.mailbox.outbox.is_empty());
+
+        acceptor.handle_msg(p1a(5)).unwrap();
+        assert_eq!(acceptor.mailbox.outbox.len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn acceptor_does_not_promise_when_the_ballot_cannot_be_stored() {
+        struct Unwritable;
+        impl BallotStore for Unwritable {
+            fn load(&mut self) -> anyhow::Result<Option<BallotNumber>> {
+                Ok(None)
+            }
+            fn store(&mut self, _ballot: &BallotNumber) -> anyhow::Result<()> {
+                Err(anyhow::anyhow!("disk full"))
+            }
+        }
+
+        let mut acceptor = setup();
+        acceptor.set_ballot_store(Box::new(Unwritable)).unwrap();
+        assert!(acceptor.handle_msg(p1a(1)).is_err());
+        assert!(acceptor.mailbox.outbox.is_empty());
+        assert!(acceptor.promised.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn acceptor_keeps_a_promise_whose_sync_failed_after_a_restart() {
+        use crate::persistence::faulty::{Fault, Faults, FaultyStore};
+
+        let faults = Faults::new();
+        let mut acceptor = setup();
+        acceptor
+            .set_ballot_store(Box::new(FaultyStore::new(
+                VolatileBallotStore::default(),
+                faults.clone(),
+            )))
+            .unwrap();
+        acceptor.handle_msg(p1a(2)).unwrap();
+        acceptor.mailbox.clear_outbox();
+
+        // The ballot may be on disk, but the acceptor cannot know it is
+        faults.fail_next(Fault::FsyncError);
+        assert!(acceptor.handle_msg(p1a(5)).is_err());
+        assert!(acceptor.mailbox.outbox.is_empty());
+        assert_eq!(acceptor.promised[&EVERY_SLOT].round, Round(2));
+
+        // After a restart it honours what reached the disk
+        let store = std::mem::replace(
+            &mut acceptor.ballot_store,
+            Box::new(VolatileBallotStore::default()),
+        );
+        let mut acceptor = setup();
+        acceptor.set_ballot_store(store).unwrap();
+        assert_eq!(acceptor.promised[&EVERY_SLOT].round, Round(5));
+        acceptor.handle_msg(p1a(4)).unwrap();
+        assert!(acceptor.mailbox.outbox.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn restarted_acceptor_promises_no_other_leader_within_a_lease() {
+        use crate::nodes::clock::MockClock;
+        use crate::persistence::file::FileBallotStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-acceptor-ballot-{}-{}.json",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let lease = Duration::from_secs(4);
+        let acceptor_id = AcceptorId::new(1);
+        let (first, second) = (LeaderId::new(1), LeaderId::new(2));
+        let mut config = Config::new(
+            HashSet::new(),
+            HashSet::from([acceptor_id]),
+            HashSet::from([first, second]),
+            BTreeMap::from([
+                (
+                    acceptor_id.into(),
+                    Address::new("127.0.0.1".to_string(), 8081),
+                ),
+                (first.into(), Address::new("127.0.0.1".to_string(), 8082)),
+                (second.into(), Address::new("127.0.0.1".to_string(), 8083)),
+            ]),
+            None,
+        );
+        config.timeout_config.lease = Some(lease);
+        let start = |clock: MockClock| {
+            let mut acceptor: Acceptor =
+                Acceptor::new(acceptor_id, config.clone(), Mailbox::new(), Box::new(clock))
+                    .unwrap();
+            acceptor
+                .set_ballot_store(Box::new(FileBallotStore::new(&path)))
+                .unwrap();
+            acceptor
+        };
+        let p1a = |round: u64, leader: LeaderId| {
+            AcceptorMessageIn::P1a(P1aMessage {
+                src: leader,
+                ballot_number: BallotNumber {
+                    round: Round(round),
+                    leader,
+                    incarnation: 0,
+                },
+                decided_below: Slot(0),
+            })
+        };
+
+        let mut acceptor = start(MockClock::new());
+        acceptor.handle_msg(p1a(1, first)).unwrap();
+        acceptor
+            .grant_lease(HeartbeatMessage {
+                src: first.into(),
+                ballot: None,
+                quorum_lost: false,
+                promised: None,
+                lease: Some(Lease {
+                    ballot: acceptor.promised[&EVERY_SLOT].clone(),
+                    id: 1,
+                    duration: lease,
+                }),
+                commit_index: None,
+            })
+            .unwrap();
+        assert!(acceptor.lease.is_some());
+        let crashed_at = acceptor.clock.now();
+        // Crash: the grant is lost with the rest of the in-memory state
+        drop(acceptor);
+
+        let mut clock = MockClock::new();
+        clock.set_time(crashed_at + Duration::from_secs(1));
+        let mut acceptor = start(clock);
+        acceptor.handle_msg(p1a(2, second)).unwrap();
+        assert_eq!(acceptor.mailbox.count_outbox_of::<P1bMessage>(), 0);
+        assert_eq!(acceptor.promised[&EVERY_SLOT].leader, first);
+
+        // Once a whole lease has passed since the restart, the grant has run out
+        let mut clock = MockClock::new();
+        clock.set_time(crashed_at + Duration::from_secs(1) + lease);
+        acceptor.clock = Box::new(clock);
+        acceptor.handle_msg(p1a(2, second)).unwrap();
+        assert_eq!(acceptor.mailbox.count_outbox_of::<P1bMessage>(), 1);
+        assert_eq!(acceptor.promised[&EVERY_SLOT].leader, second);
+        let _ = std::fs::remove_file(&path);
     }
 
     // Add more tests for P2a handling, ballot rejection, etc.
@@ -291,8 +1352,278 @@ mod tests {
             .handle_timer(ClockAction::AcceptorHeartbeat)
             .unwrap();
 
-        // Should not panic or produce errors
-        // In a full implementation, this might send heartbeat messages
-        // or perform state cleanup
+        // Every leader is told the acceptor is alive
+        assert!(acceptor.mailbox.count_outbox_of::<HeartbeatMessage>() > 0);
+
+        // and, once it has promised a ballot, which one
+        acceptor.handle_msg(p1a(3)).unwrap();
+        acceptor.mailbox.clear_outbox();
+        acceptor
+            .handle_timer(ClockAction::AcceptorHeartbeat)
+            .unwrap();
+        assert!(acceptor.mailbox.outbox.iter().all(|msg| matches!(
+            &msg.message,
+            Message::Heartbeat(HeartbeatMessage { promised: Some(ballot), .. }) if ballot.round == Round(3)
+        )));
+    }
+
+    #[test]
+    fn witness_acceptor_votes_without_keeping_commands() {
+        let clock = || Box::new(crate::nodes::clock::MockClock::new());
+        // A lone witness would be a quorum on its own
+        let alone = setup().config.with_witnesses([AcceptorId::new(1)]);
+        assert!(
+            Acceptor::<Vec<u8>>::new(AcceptorId::new(1), alone, Mailbox::new(), clock()).is_err()
+        );
+
+        let witness = AcceptorId::new(3);
+        let leader = LeaderId::new(1);
+        let acceptors = [AcceptorId::new(1), AcceptorId::new(2), witness];
+        let config = Config::new(
+            HashSet::new(),
+            HashSet::from(acceptors),
+            HashSet::from([leader]),
+            acceptors
+                .iter()
+                .map(|id| NodeId::from(*id))
+                .chain([leader.into()])
+                .enumerate()
+                .map(|(i, id)| (id, Address::new("127.0.0.1".to_string(), 8081 + i as u64)))
+                .collect(),
+            None,
+        )
+        .with_witnesses([witness]);
+        let mut acceptor: Acceptor =
+            Acceptor::new(witness, config, Mailbox::new(), clock()).unwrap();
+        let ballot = BallotNumber::new(leader);
+        acceptor
+            .handle_msg(AcceptorMessageIn::P2a(Box::new(P2aMessage {
+                src: leader,
+                ballot_number: ballot.clone(),
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                gc_below: Slot(0),
+            })))
+            .unwrap();
+        assert!(matches!(
+            acceptor.mailbox.outbox.pop_back().map(|msg| msg.message),
+            Some(Message::P2b(_))
+        ));
+
+        acceptor.handle_msg(p1a(1)).unwrap();
+        match acceptor.mailbox.outbox.pop_back().map(|msg| msg.message) {
+            Some(Message::P1b(p1b)) => {
+                assert!(p1b.accepted.is_empty());
+                assert_eq!(p1b.witnessed, vec![(Slot(1), ballot)]);
+            }
+            other => panic!("expected P1b, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn acceptor_grants_pre_votes_only_against_a_silent_leader() {
+        let (first, second) = (LeaderId::new(1), LeaderId::new(2));
+        let acceptor_id = AcceptorId::new(1);
+        let config = Config::new(
+            HashSet::new(),
+            HashSet::from([acceptor_id]),
+            HashSet::from([first, second]),
+            BTreeMap::from([
+                (
+                    acceptor_id.into(),
+                    Address::new("127.0.0.1".to_string(), 8081),
+                ),
+                (first.into(), Address::new("127.0.0.1".to_string(), 8082)),
+                (second.into(), Address::new("127.0.0.1".to_string(), 8083)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut acceptor = Acceptor::new(acceptor_id, config, Mailbox::new(), clock).unwrap();
+        let ballot = |round: u64, leader: LeaderId| BallotNumber {
+            round: Round(round),
+            leader,
+            incarnation: 0,
+        };
+        let pre_vote = |acceptor: &mut Acceptor, ballot: BallotNumber| -> bool {
+            acceptor
+                .handle_msg(AcceptorMessageIn::PreP1a(PreP1aMessage {
+                    src: ballot.leader,
+                    ballot_number: ballot,
+                }))
+                .unwrap();
+            match acceptor.mailbox.outbox.pop_back().map(|msg| msg.message) {
+                Some(Message::PreP1b(reply)) => reply.granted,
+                other => panic!("expected PreP1b, got {:?}", other),
+            }
+        };
+
+        // Nothing promised yet
+        assert!(pre_vote(&mut acceptor, ballot(1, second)));
+
+        // The first leader's scout arrives over the wire, so it is heard from
+        acceptor.accept_message(SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8081),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: first,
+                ballot_number: ballot(1, first),
+                decided_below: Slot(0),
+            }),
+        });
+        assert!(acceptor.work_on_message());
+        assert!(!pre_vote(&mut acceptor, ballot(2, second)));
+        assert!(!pre_vote(&mut acceptor, ballot(0, second)));
+        // A leader may always move past its own ballot
+        assert!(pre_vote(&mut acceptor, ballot(2, first)));
+        // A pre-vote changes no promise
+        assert_eq!(acceptor.promised.get(&EVERY_SLOT), Some(&ballot(1, first)));
+
+        // A promise to a leader never heard from does not hold others back
+        acceptor
+            .handle_msg(AcceptorMessageIn::P1a(P1aMessage {
+                src: second,
+                ballot_number: ballot(3, second),
+                decided_below: Slot(0),
+            }))
+            .unwrap();
+        assert!(pre_vote(&mut acceptor, ballot(4, first)));
+    }
+
+    #[test]
+    fn acceptor_health_tracks_leader_liveness_and_backpressure() {
+        let mut acceptor = setup();
+        assert!(matches!(acceptor.health(), Health::Degraded { .. }));
+
+        let heartbeat = |src: NodeId| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8081),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src,
+                ballot: None,
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+        };
+        acceptor.accept_message(heartbeat(LeaderId::new(1).into()));
+        assert!(acceptor.work_on_message());
+        assert_eq!(acceptor.health(), Health::Ready);
+
+        for _ in 0..=INBOX_BACKPRESSURE {
+            acceptor.accept_message(heartbeat(LeaderId::new(1).into()));
+        }
+        assert_eq!(acceptor.health().reasons().len(), 1);
+        assert!(acceptor.health().is_ready());
+    }
+
+    #[test]
+    fn acceptor_answers_a_query_with_the_slots_it_accepted() {
+        let mut acceptor = setup();
+        let ballot = BallotNumber::new(LeaderId::new(1));
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![]),
+        };
+        acceptor
+            .accepted
+            .insert(Slot(2), (ballot.clone(), Some(command.clone())))
+            .unwrap();
+        acceptor
+            .accepted
+            .insert(Slot(3), (ballot.clone(), None))
+            .unwrap();
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        acceptor
+            .handle_msg(AcceptorMessageIn::QueryAccepted(QueryAcceptedMessage {
+                src: client.clone(),
+                slots: vec![Slot(1), Slot(2), Slot(3)],
+            }))
+            .unwrap();
+
+        // Slot 1 was never accepted, and the witness slot has no command
+        let reply = acceptor.mailbox.outbox.pop_front().unwrap();
+        assert_eq!(reply.dst, client);
+        let Message::AcceptedReply(reply) = reply.message else {
+            panic!("expected an AcceptedReply");
+        };
+        assert_eq!(
+            reply.accepted,
+            vec![PValue {
+                ballot_number: ballot.clone(),
+                slot: Slot(2),
+                command,
+            }]
+        );
+        assert_eq!(reply.witnessed, vec![(Slot(3), ballot)]);
+        // Queries promise nothing
+        assert!(acceptor.promised.is_empty());
+    }
+    #[test]
+    fn acceptor_refuses_votes_from_an_older_epoch_and_syncs_the_leader() {
+        let mut acceptor = setup();
+        let leader = LeaderId::new(1);
+        let mut newer = acceptor.config.clone();
+        newer.epoch = 2;
+        let sent_in = |epoch: u64, message: Message| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8081),
+            seq: None,
+            lamport: None,
+            epoch: Some(epoch),
+            message,
+        };
+        acceptor.accept_message(sent_in(
+            2,
+            Message::ConfigSync(ConfigSyncMessage {
+                src: leader.into(),
+                config: newer,
+            }),
+        ));
+        assert!(acceptor.work_on_message());
+        assert_eq!(acceptor.config.epoch, 2);
+
+        let p2a = |epoch| {
+            sent_in(
+                epoch,
+                Message::P2a(P2aMessage {
+                    src: leader,
+                    ballot_number: BallotNumber::new(leader),
+                    slot_number: Slot(1),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id: 1,
+                        op: CommandType::Op(vec![1]),
+                    },
+                    gc_below: Slot(0),
+                }),
+            )
+        };
+        acceptor.accept_message(p2a(1));
+        assert_eq!(acceptor.pending(), 0);
+        let sync = acceptor
+            .mailbox
+            .assert_sent_to(&Address::new("127.0.0.1".to_string(), 8082));
+        assert_eq!(sync.epoch, Some(2));
+        assert!(matches!(&sync.message, Message::ConfigSync(m) if m.config.epoch == 2));
+
+        // Once the leader has caught up, its vote is taken
+        acceptor.accept_message(p2a(2));
+        assert!(acceptor.work_on_message());
+        let p2b = acceptor.mailbox.outbox.pop_back().unwrap();
+        assert!(matches!(p2b.message, Message::P2b(_)));
+        assert_eq!(p2b.epoch, Some(2));
     }
 }
@@ -0,0 +1,207 @@
+//! Flow control for a replica fetching the decisions it missed.
+//!
+//! A replica that stalls on missing slots fetches them in chunks of
+//! `CATCH_UP_CHUNK` slots, each from one peer in turn, and keeps at most a
+//! window of chunks outstanding. The window grows by one chunk for each reply
+//! the replica handles while it is keeping up with its inbox, and halves when
+//! the inbox backs up or a chunk goes unanswered, so peers send decisions
+//! about as fast as the replica can apply them.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::constants::{CATCH_UP_CHUNK, CATCH_UP_MAX_WINDOW};
+use crate::nodes::freeze::{age, rewind};
+use crate::time::{Duration, Instant};
+use crate::types::Slot;
+
+#[derive(Clone, Debug)]
+pub struct CatchUp {
+    // Chunks that may be outstanding at once
+    window: usize,
+    // Outstanding chunks by first slot, with their slots and when they were requested
+    outstanding: BTreeMap<Slot, (Vec<Slot>, Instant)>,
+    // Which peer the next chunk is fetched from
+    next_peer: usize,
+}
+
+impl Default for CatchUp {
+    fn default() -> Self {
+        CatchUp {
+            window: 1,
+            outstanding: BTreeMap::new(),
+            next_peer: 0,
+        }
+    }
+}
+
+impl CatchUp {
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Split the `missing` slots no chunk is outstanding for into new chunks,
+    /// as many as the window has room for, and note them as requested.
+    pub fn next_chunks(&mut self, missing: &[Slot], now: Instant) -> Vec<Vec<Slot>> {
+        let requested = |slot: &Slot| {
+            self.outstanding
+                .values()
+                .any(|(slots, _)| slots.contains(slot))
+        };
+        let unrequested: Vec<Slot> = missing.iter().copied().filter(|s| !requested(s)).collect();
+        let room = self.window.saturating_sub(self.outstanding.len());
+        let chunks: Vec<Vec<Slot>> = unrequested
+            .chunks(CATCH_UP_CHUNK)
+            .take(room)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        for chunk in &chunks {
+            self.outstanding.insert(chunk[0], (chunk.clone(), now));
+        }
+        chunks
+    }
+
+    /// The peer, out of `peers`, to fetch the next chunk from.
+    pub fn next_peer(&mut self, peers: usize) -> usize {
+        let peer = self.next_peer % peers.max(1);
+        self.next_peer = peer + 1;
+        peer
+    }
+
+    /// Drop chunks whose slots are all below `slot_out`: they were decided
+    /// some other way, which says nothing about how the peer is doing.
+    pub fn forget_below(&mut self, slot_out: Slot) {
+        self.outstanding
+            .retain(|_, (slots, _)| slots.iter().any(|slot| *slot >= slot_out));
+    }
+
+    /// A reply with decisions for `slots` arrived. Returns whether it
+    /// answered an outstanding chunk. `backlogged` is whether the replica's
+    /// inbox is backing up.
+    pub fn answered(&mut self, slots: &[Slot], backlogged: bool) -> bool {
+        let chunks: Vec<Slot> = self
+            .outstanding
+            .iter()
+            .filter(|(_, (chunk, _))| slots.iter().any(|slot| chunk.contains(slot)))
+            .map(|(first, _)| *first)
+            .collect();
+        if chunks.is_empty() {
+            return false;
+        }
+        for first in chunks {
+            self.outstanding.remove(&first);
+        }
+        self.window = if backlogged {
+            (self.window / 2).max(1)
+        } else {
+            (self.window + 1).min(CATCH_UP_MAX_WINDOW)
+        };
+        true
+    }
+
+    /// Give up on chunks outstanding for longer than `timeout`, so they are
+    /// fetched again from another peer. Returns how many were given up on.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> usize {
+        let before = self.outstanding.len();
+        self.outstanding
+            .retain(|_, (_, sent)| now.duration_since(*sent) < timeout);
+        let expired = before - self.outstanding.len();
+        if expired > 0 {
+            self.window = (self.window / 2).max(1);
+        }
+        expired
+    }
+
+    /// Capture the window, with how long before `now` each outstanding chunk
+    /// was requested.
+    pub fn freeze(&self, now: Instant) -> FrozenCatchUp {
+        FrozenCatchUp {
+            window: self.window,
+            outstanding: self
+                .outstanding
+                .iter()
+                .map(|(first, (slots, sent))| (*first, (slots.clone(), age(*sent, now))))
+                .collect(),
+            next_peer: self.next_peer,
+        }
+    }
+}
+
+/// A `CatchUp` captured by `freeze`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrozenCatchUp {
+    window: usize,
+    outstanding: BTreeMap<Slot, (Vec<Slot>, Duration)>,
+    next_peer: usize,
+}
+
+impl FrozenCatchUp {
+    /// The window again, as of `now`.
+    pub fn thaw(self, now: Instant) -> CatchUp {
+        CatchUp {
+            window: self.window,
+            outstanding: self
+                .outstanding
+                .into_iter()
+                .map(|(first, (slots, sent))| (first, (slots, rewind(sent, now))))
+                .collect(),
+            next_peer: self.next_peer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catch_up_window_follows_how_fast_replies_are_handled() {
+        let mut catch_up = CatchUp::default();
+        let now = Instant::now();
+        let missing: Vec<Slot> = Slot(1).up_to(Slot(CATCH_UP_CHUNK as u64 * 4 + 1)).collect();
+
+        // One chunk at a time to start with
+        let first = catch_up.next_chunks(&missing, now);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].len(), CATCH_UP_CHUNK);
+        assert!(catch_up.next_chunks(&missing, now).is_empty());
+
+        // Keeping up opens the window; chunks already requested are not repeated
+        assert!(catch_up.answered(&first[0], false));
+        assert_eq!(catch_up.window(), 2);
+        let next = catch_up.next_chunks(&missing[CATCH_UP_CHUNK..], now);
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0][0], Slot(CATCH_UP_CHUNK as u64 + 1));
+        // Replies to no outstanding chunk do not count
+        assert!(!catch_up.answered(&first[0], false));
+
+        // A backed-up inbox halves the window
+        assert!(catch_up.answered(&next[0], true));
+        assert_eq!(catch_up.window(), 1);
+
+        // So does a chunk nobody answered
+        catch_up.answered(&next[1], false);
+        let lost = catch_up.next_chunks(&missing[CATCH_UP_CHUNK * 3..], now);
+        assert_eq!(lost.len(), 1);
+        let later = now + Duration::from_secs(2);
+        assert_eq!(catch_up.expire(later, Duration::from_secs(1)), 1);
+        assert_eq!(catch_up.window(), 1);
+        assert_eq!(catch_up.outstanding(), 0);
+
+        // Chunks decided some other way are dropped without shrinking the window
+        catch_up.next_chunks(&missing, later);
+        catch_up.forget_below(Slot(CATCH_UP_CHUNK as u64 + 1));
+        assert_eq!(catch_up.outstanding(), 0);
+        assert_eq!(catch_up.window(), 1);
+
+        assert_eq!(
+            (0..4).map(|_| catch_up.next_peer(3)).collect::<Vec<_>>(),
+            [0, 1, 2, 0]
+        );
+    }
+}
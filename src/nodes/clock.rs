@@ -1,8 +1,14 @@
-use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-use std::time::{Duration, Instant};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
 
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{BTreeMap, BinaryHeap};
 use crate::messages;
+use crate::time::{Duration, Instant};
+use crate::types::Slot;
 
 /// A scheduled action to be executed at a specific time.
 #[derive(Debug, Clone)]
@@ -33,30 +39,34 @@ impl Ord for TimerEvent {
 }
 
 /// Actions that can be scheduled for later execution.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ClockAction {
     // Leader actions
     SendScout { ballot: crate::types::BallotNumber },
-    RetryProposal { slot: u64 },
+    RetryProposal { slot: Slot },
     LeaderHeartbeat,
 
     // Replica actions
     ReproposePendingRequests,
     CheckSlotWindow,
+    SyncDecisionLog,
 
     // Acceptor actions
     AcceptorHeartbeat,
 
+    // Any node in discovery mode
+    Gossip,
+
     // Custom action with identifier
     Custom(String),
 }
 
 /// Events that can be processed by nodes.
 #[derive(Debug)]
-pub enum ClockEvent {
-    Message(Box<messages::SendableMessage>),
+pub enum ClockEvent<T = Vec<u8>> {
+    Message(Box<messages::SendableMessage<T>>),
     Timer(ClockAction),
-    Tick, // Regular check for timeouts
+    Tick, // Regular check for timeouts, see `Node::tick`
 }
 
 /// Trait for different clock implementations.
@@ -78,6 +88,22 @@ pub trait ClockProvider {
 
     /// Check for expired timers and return them.
     fn check_timers(&mut self) -> Vec<ClockAction>;
+
+    /// Every pending action and how long until it is due, earliest first.
+    fn pending(&self) -> Vec<(ClockAction, Duration)>;
+}
+
+/// The actions in `timers` and how long after `now` each is due, earliest first.
+pub fn pending_timers(
+    timers: impl IntoIterator<Item = TimerEvent>,
+    now: Instant,
+) -> Vec<(ClockAction, Duration)> {
+    let mut pending: Vec<_> = timers
+        .into_iter()
+        .map(|timer| (timer.action, timer.when.duration_since(now)))
+        .collect();
+    pending.sort_by_key(|(_, due_in)| *due_in);
+    pending
 }
 
 /// A concrete clock implementation that can be used in production or tests.
@@ -116,17 +142,20 @@ impl Clock {
 }
 
 /// A real-time clock provider for production use.
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub struct SystemClock {
     timers: BinaryHeap<TimerEvent>,
 }
 
+#[cfg(feature = "std")]
 impl Default for SystemClock {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "std")]
 impl SystemClock {
     pub fn new() -> Self {
         SystemClock {
@@ -135,6 +164,7 @@ impl SystemClock {
     }
 }
 
+#[cfg(feature = "std")]
 impl ClockProvider for SystemClock {
     fn now(&self) -> Instant {
         Instant::now()
@@ -188,8 +218,13 @@ impl ClockProvider for SystemClock {
 
         expired
     }
+
+    fn pending(&self) -> Vec<(ClockAction, Duration)> {
+        pending_timers(self.timers.iter().cloned(), self.now())
+    }
 }
 
+#[cfg(feature = "std")]
 impl SystemClock {
     fn actions_match(action1: &ClockAction, action2: &ClockAction) -> bool {
         use ClockAction::*;
@@ -199,6 +234,7 @@ impl SystemClock {
             (LeaderHeartbeat, LeaderHeartbeat) => true,
             (ReproposePendingRequests, ReproposePendingRequests) => true,
             (CheckSlotWindow, CheckSlotWindow) => true,
+            (SyncDecisionLog, SyncDecisionLog) => true,
             (AcceptorHeartbeat, AcceptorHeartbeat) => true,
             (Custom(s1), Custom(s2)) => s1 == s2,
             _ => false,
@@ -221,8 +257,12 @@ impl Default for MockClock {
 
 impl MockClock {
     pub fn new() -> Self {
+        #[cfg(feature = "std")]
+        let current_time = Instant::now();
+        #[cfg(not(feature = "std"))]
+        let current_time = Instant::from_origin(Duration::ZERO);
         MockClock {
-            current_time: Instant::now(),
+            current_time,
             timers: BinaryHeap::new(),
         }
     }
@@ -292,6 +332,10 @@ impl ClockProvider for MockClock {
 
         expired
     }
+
+    fn pending(&self) -> Vec<(ClockAction, Duration)> {
+        pending_timers(self.timers.iter().cloned(), self.current_time)
+    }
 }
 
 impl MockClock {
@@ -303,6 +347,7 @@ impl MockClock {
             (LeaderHeartbeat, LeaderHeartbeat) => true,
             (ReproposePendingRequests, ReproposePendingRequests) => true,
             (CheckSlotWindow, CheckSlotWindow) => true,
+            (SyncDecisionLog, SyncDecisionLog) => true,
             (AcceptorHeartbeat, AcceptorHeartbeat) => true,
             (Custom(s1), Custom(s2)) => s1 == s2,
             _ => false,
@@ -310,10 +355,213 @@ impl MockClock {
     }
 }
 
+/// A clock provider for in-browser simulations, driven by the host page.
+///
+/// The embedder reports elapsed time in milliseconds (e.g. `performance.now()`
+/// or a `requestAnimationFrame` timestamp), so a simulation can be paused,
+/// slowed down, or fast-forwarded from JavaScript.
+#[derive(Debug)]
+pub struct BrowserClock {
+    origin: Instant,
+    inner: MockClock,
+}
+
+impl Default for BrowserClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BrowserClock {
+    pub fn new() -> Self {
+        let inner = MockClock::new();
+        BrowserClock {
+            origin: inner.now(),
+            inner,
+        }
+    }
+
+    /// Report the host's elapsed time. Time never moves backwards.
+    pub fn set_elapsed_millis(&mut self, millis: f64) {
+        let when = self.origin + Duration::from_secs_f64(millis.max(0.0) / 1000.0);
+        if when > self.inner.now() {
+            self.inner.set_time(when);
+        }
+    }
+}
+
+impl ClockProvider for BrowserClock {
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    fn schedule(&mut self, action: ClockAction, delay: Duration) {
+        self.inner.schedule(action, delay);
+    }
+
+    fn schedule_at(&mut self, action: ClockAction, when: Instant) {
+        self.inner.schedule_at(action, when);
+    }
+
+    fn cancel(&mut self, action_type: &ClockAction) {
+        self.inner.cancel(action_type);
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.inner.next_timeout()
+    }
+
+    fn check_timers(&mut self) -> Vec<ClockAction> {
+        self.inner.check_timers()
+    }
+
+    fn pending(&self) -> Vec<(ClockAction, Duration)> {
+        self.inner.pending()
+    }
+}
+
+/// A number of logical clock ticks.
+pub type Ticks = u64;
+
+/// A clock that keeps time as a count of ticks of a fixed length, for the
+/// simulator and replay tooling.
+///
+/// Timers are kept by the tick they are due at rather than by `Instant`, so
+/// the clock can be serialized and carried to another process, and runs that
+/// advance by the same ticks fire the same timers in the same order.
+/// `Instant`s and `Duration`s only appear at the `ClockProvider` boundary:
+/// durations are rounded up to whole ticks, so a timer never fires early, and
+/// instants are ticks past an origin that is not serialized. A deserialized
+/// clock reads the same ticks against a new origin.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogicalClock {
+    tick: Duration,
+    now: Ticks,
+    // Actions by the tick they are due at, in the order they were scheduled
+    timers: BTreeMap<Ticks, Vec<ClockAction>>,
+    #[serde(skip, default = "origin")]
+    origin: Instant,
+}
+
+fn origin() -> Instant {
+    MockClock::new().now()
+}
+
+impl LogicalClock {
+    /// A clock at tick 0, counting ticks of `tick`, at least a nanosecond.
+    pub fn new(tick: Duration) -> Self {
+        LogicalClock::with_origin(origin(), tick)
+    }
+
+    /// A clock at tick 0 that reads as `origin`.
+    pub fn with_origin(origin: Instant, tick: Duration) -> Self {
+        LogicalClock {
+            tick: tick.max(Duration::from_nanos(1)),
+            now: 0,
+            timers: BTreeMap::new(),
+            origin,
+        }
+    }
+
+    /// The current tick.
+    pub fn ticks(&self) -> Ticks {
+        self.now
+    }
+
+    pub fn tick_length(&self) -> Duration {
+        self.tick
+    }
+
+    /// Move on by `ticks`.
+    pub fn advance(&mut self, ticks: Ticks) {
+        self.now = self.now.saturating_add(ticks);
+    }
+
+    /// Move on to tick `ticks`. Time never moves backwards.
+    pub fn set_ticks(&mut self, ticks: Ticks) {
+        self.now = self.now.max(ticks);
+    }
+
+    /// The whole ticks `duration` takes, rounded up.
+    pub fn to_ticks(&self, duration: Duration) -> Ticks {
+        let ticks = duration.as_nanos().div_ceil(self.tick.as_nanos());
+        Ticks::try_from(ticks).unwrap_or(Ticks::MAX)
+    }
+
+    /// How long `ticks` ticks last.
+    pub fn to_duration(&self, ticks: Ticks) -> Duration {
+        let nanos = self.tick.as_nanos().saturating_mul(u128::from(ticks));
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// The instant tick `ticks` reads as.
+    pub fn instant_at(&self, ticks: Ticks) -> Instant {
+        self.origin + self.to_duration(ticks)
+    }
+
+    /// The first tick at or after `when`.
+    pub fn ticks_at(&self, when: Instant) -> Ticks {
+        self.to_ticks(when.duration_since(self.origin))
+    }
+
+    /// Every pending action and the tick it is due at, earliest first.
+    pub fn timers(&self) -> impl Iterator<Item = (Ticks, &ClockAction)> + '_ {
+        self.timers
+            .iter()
+            .flat_map(|(due, actions)| actions.iter().map(move |action| (*due, action)))
+    }
+
+    fn schedule_tick(&mut self, action: ClockAction, due: Ticks) {
+        self.timers.entry(due).or_default().push(action);
+    }
+}
+
+impl ClockProvider for LogicalClock {
+    fn now(&self) -> Instant {
+        self.instant_at(self.now)
+    }
+
+    fn schedule(&mut self, action: ClockAction, delay: Duration) {
+        let due = self.now.saturating_add(self.to_ticks(delay));
+        self.schedule_tick(action, due);
+    }
+
+    fn schedule_at(&mut self, action: ClockAction, when: Instant) {
+        let due = self.ticks_at(when);
+        self.schedule_tick(action, due);
+    }
+
+    fn cancel(&mut self, action_type: &ClockAction) {
+        self.timers.retain(|_, actions| {
+            actions.retain(|action| !MockClock::actions_match(action, action_type));
+            !actions.is_empty()
+        });
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let due = *self.timers.keys().next()?;
+        Some(self.to_duration(due.saturating_sub(self.now)))
+    }
+
+    fn check_timers(&mut self) -> Vec<ClockAction> {
+        let later = self.timers.split_off(&self.now.saturating_add(1));
+        let due = core::mem::replace(&mut self.timers, later);
+        due.into_values().flatten().collect()
+    }
+
+    fn pending(&self) -> Vec<(ClockAction, Duration)> {
+        self.timers()
+            .map(|(due, action)| {
+                let due_in = self.to_duration(due.saturating_sub(self.now));
+                (action.clone(), due_in)
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::Duration;
 
     #[test]
     fn test_mock_clock_basic_functionality() {
@@ -406,6 +654,7 @@ mod tests {
         assert!(timeout > Duration::from_millis(40));
     }
 
+    #[cfg(feature = "std")]
     #[test]
     fn test_system_clock_basic() {
         let mut clock = Clock::new(Box::new(SystemClock::new()));
@@ -459,4 +708,57 @@ mod tests {
         assert_eq!(expired.len(), 1);
         matches!(expired[0], ClockAction::Custom(ref s) if s == "third");
     }
+
+    #[test]
+    fn test_browser_clock_follows_host_time() {
+        let mut clock = BrowserClock::new();
+        clock.schedule(ClockAction::AcceptorHeartbeat, Duration::from_millis(100));
+
+        clock.set_elapsed_millis(99.0);
+        assert!(clock.check_timers().is_empty());
+
+        clock.set_elapsed_millis(100.5);
+        assert_eq!(clock.check_timers().len(), 1);
+
+        // A host timestamp that goes backwards does not rewind the clock
+        let now = clock.now();
+        clock.set_elapsed_millis(10.0);
+        assert_eq!(clock.now(), now);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn logical_clock_fires_timers_by_tick_and_survives_serialization() {
+        let mut clock = LogicalClock::new(Duration::from_millis(10));
+        // Rounded up to whole ticks, so neither fires early
+        clock.schedule(ClockAction::AcceptorHeartbeat, Duration::from_millis(25));
+        clock.schedule(ClockAction::CheckSlotWindow, Duration::from_millis(30));
+        clock.schedule(ClockAction::LeaderHeartbeat, Duration::from_millis(5));
+        assert_eq!(
+            clock.timers().map(|(due, _)| due).collect::<Vec<_>>(),
+            [1, 3, 3]
+        );
+
+        clock.advance(1);
+        assert!(matches!(
+            &clock.check_timers()[..],
+            [ClockAction::LeaderHeartbeat]
+        ));
+
+        // Carried to a clock with another origin, the same ticks fire the
+        // same timers in the order they were scheduled
+        let mut thawed: LogicalClock =
+            serde_json::from_str(&serde_json::to_string(&clock).unwrap()).unwrap();
+        assert_eq!(thawed.ticks(), 1);
+        assert_eq!(thawed.next_timeout(), Some(Duration::from_millis(20)));
+        thawed.set_ticks(3);
+        assert!(matches!(
+            &thawed.check_timers()[..],
+            [ClockAction::AcceptorHeartbeat, ClockAction::CheckSlotWindow]
+        ));
+        assert_eq!(
+            thawed.now() - thawed.instant_at(0),
+            Duration::from_millis(30)
+        );
+    }
 }
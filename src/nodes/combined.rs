@@ -0,0 +1,317 @@
+//! Several protocol roles hosted as one node.
+//!
+//! A `CombinedNode` runs, say, an acceptor and a replica side by side behind
+//! one `Node`, so a runtime can drive them from a single task. Each role
+//! keeps its own address and inbox. Messages are handed to roles in weighted
+//! round-robin: a role takes up to `weight` messages in a row before the next
+//! role with messages waiting gets its turn. A flood of traffic for one role
+//! then only slows the others down in proportion to its weight, instead of
+//! starving them until its inbox is empty.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use tracing::{debug, warn};
+
+use crate::messages::SendableMessage;
+use crate::nodes::clock::ClockAction;
+use crate::nodes::health::Health;
+use crate::nodes::node::Node;
+use crate::nodes::router::WireFormat;
+use crate::time::Duration;
+pub use crate::types::Role;
+use crate::types::{Address, TimeoutConfig};
+
+struct CoLocated<T> {
+    role: Role,
+    address: Address,
+    node: Box<dyn Node<T> + Send>,
+    weight: u32,
+    // Messages handled for this role so far
+    processed: u64,
+}
+
+pub struct CombinedNode<T = Vec<u8>> {
+    roles: Vec<CoLocated<T>>,
+    // The role whose turn it is, and how many messages it has had this turn
+    turn: usize,
+    taken: u32,
+}
+
+impl<T> Default for CombinedNode<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> CombinedNode<T> {
+    pub fn new() -> CombinedNode<T> {
+        CombinedNode {
+            roles: Vec::new(),
+            turn: 0,
+            taken: 0,
+        }
+    }
+
+    /// Host `node` as `role`, receiving the messages sent to `address`, and
+    /// let it handle up to `weight` messages per turn.
+    pub fn add_role(
+        &mut self,
+        role: Role,
+        address: Address,
+        node: Box<dyn Node<T> + Send>,
+        weight: u32,
+    ) -> anyhow::Result<()> {
+        if self.roles.iter().any(|r| r.role == role) {
+            return Err(anyhow::anyhow!("a {} is already hosted here", role));
+        }
+        if self.roles.iter().any(|r| r.address == address) {
+            return Err(anyhow::anyhow!(
+                "another role already listens at {}",
+                address
+            ));
+        }
+        self.roles.push(CoLocated {
+            role,
+            address,
+            node,
+            weight: weight.max(1),
+            processed: 0,
+        });
+        Ok(())
+    }
+
+    /// Let `role` handle up to `weight` messages per turn.
+    pub fn set_weight(&mut self, role: Role, weight: u32) {
+        if let Some(hosted) = self.roles.iter_mut().find(|r| r.role == role) {
+            hosted.weight = weight.max(1);
+        }
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &Address> {
+        self.roles.iter().map(|r| &r.address)
+    }
+
+    /// How many messages `role` has handled.
+    pub fn processed(&self, role: Role) -> u64 {
+        self.roles
+            .iter()
+            .find(|r| r.role == role)
+            .map(|r| r.processed)
+            .unwrap_or_default()
+    }
+
+    /// The fraction of all messages handled here that `role` handled.
+    pub fn share(&self, role: Role) -> f64 {
+        let total: u64 = self.roles.iter().map(|r| r.processed).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        self.processed(role) as f64 / total as f64
+    }
+
+    fn next_turn(&mut self) {
+        self.turn = (self.turn + 1) % self.roles.len();
+        self.taken = 0;
+    }
+}
+
+impl<T> Node<T> for CombinedNode<T> {
+    fn accept_message(&mut self, msg: SendableMessage<T>) {
+        match self.roles.iter_mut().find(|r| r.address == msg.dst) {
+            Some(hosted) => hosted.node.accept_message(msg),
+            None => warn!("combined node: no role at {}, dropping message", msg.dst),
+        }
+    }
+
+    fn work_on_message(&mut self) -> bool {
+        if self.roles.is_empty() {
+            return false;
+        }
+        // One pass over every role, plus the rest of the current role's turn
+        for _ in 0..=self.roles.len() {
+            let hosted = &mut self.roles[self.turn];
+            if self.taken < hosted.weight && hosted.node.pending() > 0 {
+                self.taken += 1;
+                hosted.processed += 1;
+                debug!(
+                    monotonic_counter.paxos.combined.processed = 1u64,
+                    paxos.node.role = %hosted.role,
+                    "combined node: {} handling a message",
+                    hosted.role
+                );
+                return hosted.node.work_on_message();
+            }
+            self.next_turn();
+        }
+        false
+    }
+
+    fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>> {
+        let mut fired = Vec::new();
+        for hosted in self.roles.iter_mut() {
+            fired.extend(hosted.node.check_timers()?);
+        }
+        Ok(fired)
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.roles
+            .iter()
+            .filter_map(|r| r.node.next_timeout())
+            .min()
+    }
+
+    fn deliver_sent(&mut self) -> Option<SendableMessage<T>> {
+        self.roles.iter_mut().find_map(|r| r.node.deliver_sent())
+    }
+
+    fn pending(&self) -> usize {
+        self.roles.iter().map(|r| r.node.pending()).sum()
+    }
+
+    fn health(&self) -> Health {
+        self.roles
+            .iter()
+            .map(|r| r.node.health())
+            .fold(Health::Ready, Health::and)
+    }
+
+    fn set_timeouts(&mut self, timeouts: TimeoutConfig) {
+        for hosted in self.roles.iter_mut() {
+            hosted.node.set_timeouts(timeouts.clone());
+        }
+    }
+
+    fn link_down(&mut self, address: &Address) {
+        for hosted in self.roles.iter_mut() {
+            hosted.node.link_down(address);
+        }
+    }
+
+    fn link_negotiated(&mut self, address: &Address, format: WireFormat) {
+        for hosted in self.roles.iter_mut() {
+            hosted.node.link_negotiated(address, format.clone());
+        }
+    }
+
+    fn wire_format(&self, address: &Address) -> Option<WireFormat> {
+        self.roles
+            .iter()
+            .find_map(|hosted| hosted.node.wire_format(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use crate::collections::VecDeque;
+    use crate::messages::{HeartbeatMessage, Message};
+    use crate::types::NodeId;
+
+    // A role that only counts the messages it is given
+    struct Counting {
+        inbox: VecDeque<SendableMessage>,
+    }
+
+    impl Node for Counting {
+        fn accept_message(&mut self, msg: SendableMessage) {
+            self.inbox.push_back(msg);
+        }
+
+        fn work_on_message(&mut self) -> bool {
+            self.inbox.pop_front().is_some()
+        }
+
+        fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>> {
+            Ok(vec![])
+        }
+
+        fn next_timeout(&self) -> Option<Duration> {
+            None
+        }
+
+        fn deliver_sent(&mut self) -> Option<SendableMessage> {
+            None
+        }
+
+        fn pending(&self) -> usize {
+            self.inbox.len()
+        }
+
+        fn health(&self) -> Health {
+            Health::Ready
+        }
+
+        fn set_timeouts(&mut self, _timeouts: TimeoutConfig) {}
+    }
+
+    fn message(dst: &Address) -> SendableMessage {
+        SendableMessage {
+            src: Address::new("peer".to_string(), 1),
+            dst: dst.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: NodeId::new(1),
+                ballot: None,
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn combined_node_shares_work_between_roles_by_weight() {
+        let acceptor = Address::new("node".to_string(), 1);
+        let replica = Address::new("node".to_string(), 2);
+        let counting = || {
+            Box::new(Counting {
+                inbox: VecDeque::new(),
+            })
+        };
+        let mut node = CombinedNode::new();
+        node.add_role(Role::Acceptor, acceptor.clone(), counting(), 3)
+            .unwrap();
+        node.add_role(Role::Replica, replica.clone(), counting(), 1)
+            .unwrap();
+        assert!(node
+            .add_role(Role::Leader, replica.clone(), counting(), 1)
+            .is_err());
+
+        // A flood for the acceptor does not hold up the replica
+        for _ in 0..100 {
+            node.accept_message(message(&acceptor));
+        }
+        for _ in 0..10 {
+            node.accept_message(message(&replica));
+        }
+        for _ in 0..40 {
+            assert!(node.work_on_message());
+        }
+        assert_eq!(node.processed(Role::Acceptor), 30);
+        assert_eq!(node.processed(Role::Replica), 10);
+        assert_eq!(node.share(Role::Replica), 0.25);
+
+        // With nothing left for the replica, the acceptor gets every turn
+        while node.work_on_message() {}
+        assert_eq!(node.processed(Role::Acceptor), 100);
+        assert_eq!(node.pending(), 0);
+
+        node.set_weight(Role::Acceptor, 1);
+        for _ in 0..4 {
+            node.accept_message(message(&acceptor));
+            node.accept_message(message(&replica));
+        }
+        for _ in 0..4 {
+            node.work_on_message();
+        }
+        assert_eq!(node.processed(Role::Acceptor), 102);
+        assert_eq!(node.processed(Role::Replica), 12);
+    }
+}
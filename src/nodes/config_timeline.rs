@@ -0,0 +1,180 @@
+//! The configurations a replica moves through, by slot.
+//!
+//! A reconfiguration decided in slot `s` takes effect in slot `s + WINDOW`.
+//! Decisions reach a replica in any order, so a `ConfigTimeline` records each
+//! configuration change by the slot it takes effect in as soon as it is
+//! decided, instead of looking for it in one particular slot when `slot_in`
+//! gets there. Membership changes apply to the configuration current when
+//! they take effect, so they are only applied then, in slot order, and every
+//! replica arrives at the same configurations whatever order it learnt them in.
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::constants::WINDOW;
+use crate::membership::{MembershipError, MembershipManager};
+use crate::types::{CommandType, Config, ConfigDelta, MembershipChange, Slot};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum ConfigChange {
+    Reconfig(Box<Config>),
+    Membership(MembershipChange),
+    Delta(ConfigDelta),
+}
+
+/// A configuration change that took effect, or was skipped, on `advance`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Applied {
+    /// The slot the change was decided in.
+    pub decided: Slot,
+    /// The new configuration, or why the change could not be applied to the
+    /// one before it.
+    pub config: Result<Config, MembershipError>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConfigTimeline {
+    // The configuration in effect up to the first scheduled change
+    current: Config,
+    // Changes yet to take effect, by the slot they take effect in
+    scheduled: BTreeMap<Slot, ConfigChange>,
+}
+
+impl ConfigTimeline {
+    pub fn new(config: Config) -> ConfigTimeline {
+        ConfigTimeline {
+            current: config,
+            scheduled: BTreeMap::new(),
+        }
+    }
+
+    /// The configuration in effect at the slot last advanced to.
+    pub fn current(&self) -> &Config {
+        &self.current
+    }
+
+    /// Note the command decided in `slot`, scheduling it if it changes the
+    /// configuration. Deciding the same slot again changes nothing.
+    pub fn record<T>(&mut self, slot: Slot, op: &CommandType<T>) {
+        let change = match op {
+            CommandType::Reconfig(config) => ConfigChange::Reconfig(Box::new(config.clone())),
+            CommandType::Membership(change) => ConfigChange::Membership(change.clone()),
+            CommandType::ConfigDelta(delta) => ConfigChange::Delta(delta.clone()),
+            _ => return,
+        };
+        self.scheduled.insert(slot + WINDOW, change);
+    }
+
+    /// Slots in which configuration changes are still to take effect.
+    pub fn scheduled(&self) -> impl Iterator<Item = Slot> + '_ {
+        self.scheduled.keys().copied()
+    }
+
+    /// The configuration in effect at `slot`, as far as the changes recorded
+    /// so far go. It is only final once every slot up to `slot - WINDOW` has
+    /// been decided.
+    pub fn config_at(&self, slot: Slot) -> Config {
+        self.scheduled
+            .range(..=slot)
+            .fold(self.current.clone(), |config, (_, change)| {
+                apply(&config, change).unwrap_or(config)
+            })
+    }
+
+    /// Move on to `slot`, applying the changes that take effect up to it, in
+    /// slot order, and returning what each of them did.
+    pub fn advance(&mut self, slot: Slot) -> Vec<Applied> {
+        let later = self.scheduled.split_off(&slot.next());
+        let due = core::mem::replace(&mut self.scheduled, later);
+        let mut applied = Vec::new();
+        for (effective, change) in due {
+            let config = apply(&self.current, &change);
+            if let Ok(config) = &config {
+                self.current = config.clone();
+            }
+            applied.push(Applied {
+                decided: effective - WINDOW,
+                config,
+            });
+        }
+        applied
+    }
+}
+
+/// The configuration `change` leads to from `config`, an epoch later
+/// whatever epoch a `Reconfig` came with.
+fn apply(config: &Config, change: &ConfigChange) -> Result<Config, MembershipError> {
+    let mut next = match change {
+        ConfigChange::Reconfig(config) => Config::clone(config),
+        ConfigChange::Membership(change) => MembershipManager::apply(config, change)?,
+        ConfigChange::Delta(delta) => MembershipManager::merge(config, delta)?,
+    };
+    next.epoch = config.epoch + 1;
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::types::{AcceptorId, Address, LeaderId, ReplicaId};
+
+    fn add_leader(id: u64) -> CommandType {
+        CommandType::Membership(MembershipChange::AddLeader {
+            id: LeaderId::new(id),
+            address: Address::new("127.0.0.1".to_string(), 9000 + id),
+        })
+    }
+
+    fn leaders(config: &Config) -> HashSet<LeaderId> {
+        config.leaders.clone()
+    }
+
+    #[test]
+    fn config_timeline_applies_changes_in_slot_order_whatever_order_they_arrive_in() {
+        let (acc, ldr, rep) = (AcceptorId::new(1), LeaderId::new(101), ReplicaId::new(201));
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::from([acc]),
+            HashSet::from([ldr]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8201)),
+                (acc.into(), Address::new("127.0.0.1".to_string(), 8001)),
+                (ldr.into(), Address::new("127.0.0.1".to_string(), 8101)),
+            ]),
+            None,
+        );
+        let mut timeline = ConfigTimeline::new(config.clone());
+        // Decided out of order; slot 4 is a duplicate id once slot 2 applies
+        timeline.record(Slot(4), &add_leader(102));
+        timeline.record(Slot(3), &CommandType::<Vec<u8>>::Op(alloc::vec![]));
+        timeline.record(Slot(2), &add_leader(102));
+        timeline.record(Slot(2), &add_leader(102));
+        assert_eq!(
+            timeline.scheduled().collect::<Vec<_>>(),
+            [Slot(2) + WINDOW, Slot(4) + WINDOW]
+        );
+
+        // Known ahead of time, without changing the current configuration
+        assert_eq!(
+            leaders(&timeline.config_at(Slot(1) + WINDOW)),
+            leaders(&config)
+        );
+        assert_eq!(leaders(&timeline.config_at(Slot(2) + WINDOW)).len(), 2);
+
+        assert!(timeline.advance(Slot(1) + WINDOW).is_empty());
+        let applied = timeline.advance(Slot(4) + WINDOW);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].decided, Slot(2));
+        assert!(applied[0].config.is_ok());
+        assert!(applied[1].config.is_err());
+        assert_eq!(timeline.current().leaders.len(), 2);
+        // Only the change that took effect moved the epoch on
+        assert_eq!(timeline.current().epoch, config.epoch + 1);
+        assert_eq!(timeline.scheduled().count(), 0);
+    }
+}
@@ -0,0 +1,313 @@
+//! Learning peers' addresses by gossip.
+//!
+//! In discovery mode a node does not need every peer's address in its
+//! configuration. It starts from a few seed addresses and, every
+//! `heartbeat_interval`, sends a `Gossip` to `GOSSIP_FANOUT` of the peers it
+//! knows, in turn, with its configuration and every address it knows. What
+//! it hears back is routed to: a discovered address takes precedence over
+//! the configuration's, so a node whose IP changes is reached at the new one
+//! as soon as word of it spreads.
+//!
+//! Each address carries a version, and the higher one wins wherever two
+//! meet. A node's own entry is the one it advertises; when a peer hears from
+//! it at an address other than the one it knew, it bumps the version past
+//! the old one, and a node that finds an older address of its own gossiped
+//! at a higher version than it holds reasserts its own past it. A node that
+//! restarts elsewhere with a fresh table is therefore not shadowed by its
+//! old address.
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::collections::{BTreeMap, BTreeSet};
+use crate::constants::GOSSIP_FANOUT;
+use crate::messages::{GossipMessage, Message, SendableMessage};
+use crate::nodes::router::Router;
+use crate::types::{Address, Config, NodeId};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Peer {
+    address: Address,
+    version: u64,
+}
+
+/// A node's table of the addresses it has discovered.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Discovery {
+    node: NodeId,
+    seeds: Vec<Address>,
+    // Every node's address that is known, this one's included
+    peers: BTreeMap<NodeId, Peer>,
+    // Where the next round of gossip starts among the peers to gossip to
+    cursor: usize,
+}
+
+impl Discovery {
+    /// Discovery for `node`, which advertises `address`, starting from the
+    /// peers at `seeds` and the addresses in `config`.
+    pub fn new(node: NodeId, address: Address, seeds: Vec<Address>, config: &Config) -> Discovery {
+        let mut peers: BTreeMap<NodeId, Peer> = config
+            .id_address_map
+            .iter()
+            .map(|(node, address)| {
+                let peer = Peer {
+                    address: address.clone(),
+                    version: 0,
+                };
+                (*node, peer)
+            })
+            .collect();
+        peers.insert(
+            node,
+            Peer {
+                address,
+                version: 0,
+            },
+        );
+        Discovery {
+            node,
+            seeds,
+            peers,
+            cursor: 0,
+        }
+    }
+
+    /// The address known for `node`, if any.
+    pub fn address_of(&self, node: &NodeId) -> Option<&Address> {
+        self.peers.get(node).map(|peer| &peer.address)
+    }
+
+    /// Every other node's known address.
+    pub fn peers(&self) -> impl Iterator<Item = (NodeId, &Address)> + '_ {
+        self.peers
+            .iter()
+            .filter(|(node, _)| **node != self.node)
+            .map(|(node, peer)| (*node, &peer.address))
+    }
+
+    /// Route `router` to every address known, e.g. one that replaces the
+    /// node's router.
+    pub fn route(&self, router: &mut dyn Router) {
+        for (node, address) in self.peers() {
+            router.discovered(node, address.clone());
+        }
+    }
+
+    /// This round's `Gossip`, for the next `GOSSIP_FANOUT` of the seeds and
+    /// known peers.
+    pub fn gossip<T>(&mut self, config: &Config) -> Vec<SendableMessage<T>> {
+        let own = self.peers[&self.node].address.clone();
+        let mut seen = BTreeSet::from([own.to_string()]);
+        let targets: Vec<&Address> = self
+            .seeds
+            .iter()
+            .chain(self.peers.values().map(|peer| &peer.address))
+            .filter(|address| seen.insert(address.to_string()))
+            .collect();
+        if targets.is_empty() {
+            return Vec::new();
+        }
+        let start = self.cursor % targets.len();
+        let fanout = GOSSIP_FANOUT.min(targets.len());
+        let gossip = GossipMessage {
+            src: self.node,
+            config: config.clone(),
+            peers: self
+                .peers
+                .iter()
+                .map(|(node, peer)| (*node, peer.address.clone(), peer.version))
+                .collect(),
+        };
+        let sent = targets
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(fanout)
+            .map(|dst| SendableMessage {
+                src: own.clone(),
+                dst: (*dst).clone(),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Gossip(gossip.clone()),
+            })
+            .collect();
+        self.cursor = start + fanout;
+        sent
+    }
+
+    /// Merge what `gossip`, received from `from`, knows, routing `router`
+    /// to every address that changed. Returns how many did.
+    pub fn receive(
+        &mut self,
+        from: &Address,
+        gossip: &GossipMessage,
+        router: &mut dyn Router,
+    ) -> usize {
+        let mut changed = Vec::new();
+        for (node, address, version) in &gossip.peers {
+            if *node == self.node {
+                let own = self.peers.get_mut(node).expect("own entry");
+                if *version >= own.version {
+                    // Ours, perhaps from before a restart elsewhere: reassert it past that one
+                    own.version = if *address == own.address {
+                        *version
+                    } else {
+                        version + 1
+                    };
+                }
+                continue;
+            }
+            match self.peers.get_mut(node) {
+                Some(known) if known.version >= *version => {}
+                Some(known) => {
+                    if known.address != *address {
+                        changed.push((*node, address.clone()));
+                    }
+                    known.address = address.clone();
+                    known.version = *version;
+                }
+                None => {
+                    changed.push((*node, address.clone()));
+                    self.peers.insert(
+                        *node,
+                        Peer {
+                            address: address.clone(),
+                            version: *version,
+                        },
+                    );
+                }
+            }
+        }
+        // The sender is where it was just heard from
+        if gossip.src != self.node {
+            let version = self.peers.get(&gossip.src).map_or(0, |peer| peer.version);
+            match self.peers.get_mut(&gossip.src) {
+                Some(known) if known.address == *from => {}
+                _ => {
+                    changed.push((gossip.src, from.clone()));
+                    self.peers.insert(
+                        gossip.src,
+                        Peer {
+                            address: from.clone(),
+                            version: version + 1,
+                        },
+                    );
+                }
+            }
+        }
+        for (node, address) in &changed {
+            debug!("{}: discovered {} at {}", self.node, node, address);
+            router.discovered(*node, address.clone());
+        }
+        changed.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use crate::collections::HashSet;
+    use crate::nodes::router::ConfigRouter;
+    use crate::types::{AcceptorId, LeaderId, ReplicaId};
+
+    fn address(port: u64) -> Address {
+        Address::new("10.0.0.1".to_string(), port)
+    }
+
+    fn gossip_of<T>(sent: &SendableMessage<T>) -> &GossipMessage {
+        match &sent.message {
+            Message::Gossip(gossip) => gossip,
+            _ => panic!("expected gossip"),
+        }
+    }
+
+    #[test]
+    fn seeded_nodes_learn_every_address_and_follow_a_move() {
+        let (replica, acceptor, leader) = (ReplicaId::new(1), AcceptorId::new(2), LeaderId::new(3));
+        // Each node's configuration only has its own address
+        let config = |node: NodeId, port| {
+            Config::new(
+                HashSet::from([replica]),
+                HashSet::from([acceptor]),
+                HashSet::from([leader]),
+                BTreeMap::from([(node, address(port))]),
+                None,
+            )
+        };
+        let nodes = [
+            (NodeId::from(replica), 1, vec![address(3)]),
+            (NodeId::from(acceptor), 2, vec![address(3)]),
+            (NodeId::from(leader), 3, vec![]),
+        ];
+        let configs: Vec<Config> = nodes.iter().map(|(n, p, _)| config(*n, *p)).collect();
+        let mut tables: Vec<Discovery> = nodes
+            .iter()
+            .zip(&configs)
+            .map(|((node, port, seeds), config)| {
+                Discovery::new(*node, address(*port), seeds.clone(), config)
+            })
+            .collect();
+        let mut routers: Vec<ConfigRouter> = configs.iter().map(ConfigRouter::new).collect();
+        let round = |tables: &mut Vec<Discovery>, routers: &mut Vec<ConfigRouter>| {
+            let sent: Vec<SendableMessage<()>> = (0..tables.len())
+                .flat_map(|i| tables[i].gossip(&configs[i]))
+                .collect();
+            for message in &sent {
+                let to = nodes
+                    .iter()
+                    .position(|(_, port, _)| address(*port) == message.dst);
+                if let Some(to) = to {
+                    tables[to].receive(&message.src, gossip_of(message), &mut routers[to]);
+                }
+            }
+        };
+
+        // The seed learns of both, and they of each other through it
+        round(&mut tables, &mut routers);
+        round(&mut tables, &mut routers);
+        for (i, (node, _, _)) in nodes.iter().enumerate() {
+            for (other, port, _) in &nodes {
+                if other != node {
+                    assert_eq!(routers[i].resolve(other), Some(address(*port)), "{}", node);
+                }
+            }
+        }
+
+        // The acceptor restarts at a new address with a fresh table
+        let moved = config(acceptor.into(), 20);
+        tables[1] = Discovery::new(acceptor.into(), address(20), vec![address(3)], &moved);
+        let sent: Vec<SendableMessage<()>> = tables[1].gossip(&moved);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dst, address(3));
+        assert_eq!(
+            tables[2].receive(&address(20), gossip_of(&sent[0]), &mut routers[2]),
+            1
+        );
+        assert_eq!(routers[2].resolve(&acceptor.into()), Some(address(20)));
+
+        // The replica still gossips the old address, at a lower version
+        let stale = tables[0].gossip::<()>(&configs[0]);
+        let to_leader = stale.iter().find(|m| m.dst == address(3)).unwrap();
+        assert_eq!(
+            tables[2].receive(&address(1), gossip_of(to_leader), &mut routers[2]),
+            0
+        );
+        assert_eq!(routers[2].resolve(&acceptor.into()), Some(address(20)));
+
+        // The acceptor, told of its old address, reasserts the new one past it
+        let old = gossip_of(to_leader)
+            .peers
+            .iter()
+            .find(|(node, _, _)| *node == acceptor.into())
+            .unwrap()
+            .2;
+        tables[1].receive(&address(1), gossip_of(to_leader), &mut routers[1]);
+        let own = gossip_of(&tables[1].gossip::<()>(&moved)[0]).peers.clone();
+        assert!(own.contains(&(acceptor.into(), address(20), old + 1)));
+    }
+}
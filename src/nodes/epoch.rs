@@ -0,0 +1,215 @@
+//! Configuration epochs across nodes.
+//!
+//! Every message carries the epoch of its sender's configuration, see
+//! `types::Config::epoch`. A node that hears from a leader or acceptor in an
+//! older epoch than its own sends it its configuration in a `ConfigSync`, so
+//! a node that missed a reconfiguration catches up from the first peer that
+//! did not. Only the Synod messages tell a leader or acceptor apart from a
+//! replica with the same id, so those are what a sync answers. Replicas are
+//! never sent one: their configurations only move on through the slots they
+//! perform.
+//!
+//! Acceptors also refuse P1as and P2as from a leader in an older epoch: it
+//! may be counting a quorum of acceptors that no longer make one. The leader
+//! takes up the configuration it is sent, and its replicas' repeated
+//! proposals get the refused P2as sent again.
+use tracing::info;
+
+use crate::collections::BTreeMap;
+use crate::messages::{ConfigSyncMessage, Message, SendableMessage};
+use crate::time::Instant;
+use crate::types::{Config, NodeId};
+
+/// How the epoch a message was sent in compares with the receiver's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EpochCheck {
+    /// The same epoch, or the message carries none, e.g. from a client.
+    Current,
+    /// The sender is in an older epoch than the receiver.
+    Stale(u64),
+    /// The sender is in a newer epoch: the receiver missed a reconfiguration.
+    Ahead(u64),
+}
+
+impl EpochCheck {
+    pub fn of<T>(msg: &SendableMessage<T>, epoch: u64) -> EpochCheck {
+        match msg.epoch {
+            Some(theirs) if theirs < epoch => EpochCheck::Stale(theirs),
+            Some(theirs) if theirs > epoch => EpochCheck::Ahead(theirs),
+            _ => EpochCheck::Current,
+        }
+    }
+}
+
+/// The stale peers a node has sent its configuration to, and when, so that
+/// each is sent it at most once per `heartbeat_interval`.
+#[derive(Clone, Debug, Default)]
+pub struct EpochSync {
+    synced: BTreeMap<NodeId, Instant>,
+}
+
+impl EpochSync {
+    pub fn new() -> EpochSync {
+        EpochSync::default()
+    }
+
+    /// The `ConfigSync` to send back to the sender of `msg`, if it is a
+    /// message only leaders and acceptors send, from an older epoch than
+    /// `config`, and its sender was not sent one within the last
+    /// `heartbeat_interval`.
+    pub fn sync_for<T>(
+        &mut self,
+        node: NodeId,
+        config: &Config,
+        msg: &SendableMessage<T>,
+        now: Instant,
+    ) -> Option<SendableMessage<T>> {
+        let EpochCheck::Stale(theirs) = EpochCheck::of(msg, config.epoch) else {
+            return None;
+        };
+        if !synod(&msg.message) {
+            return None;
+        }
+        let peer = msg.message.sender()?;
+        let interval = config.timeout_config.heartbeat_interval;
+        if let Some(last) = self.synced.get(&peer) {
+            if now.duration_since(*last) < interval {
+                return None;
+            }
+        }
+        self.synced.insert(peer, now);
+        info!(
+            monotonic_counter.paxos.config.syncs_sent = 1u64,
+            "{}: {} is in epoch {}, sending it epoch {}", node, peer, theirs, config.epoch
+        );
+        Some(SendableMessage {
+            src: msg.dst.clone(),
+            dst: msg.src.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::ConfigSync(ConfigSyncMessage {
+                src: node,
+                config: config.clone(),
+            }),
+        })
+    }
+}
+
+// Whether `message` is one only a leader or an acceptor sends
+fn synod<T>(message: &Message<T>) -> bool {
+    matches!(
+        message,
+        Message::P1a(_)
+            | Message::P1b(_)
+            | Message::P2a(_)
+            | Message::P2b(_)
+            | Message::Preempted(_)
+            | Message::Decision(_)
+            | Message::PreP1a(_)
+            | Message::PreP1b(_)
+            | Message::TakeOver(_)
+            | Message::P1bMore(_)
+            | Message::AcceptedReply(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::messages::{HeartbeatMessage, P2bMessage, ProposeMessage};
+    use crate::nodes::clock::{ClockProvider, MockClock};
+    use crate::time::Duration;
+    use crate::types::{
+        AcceptorId, Address, BallotNumber, Command, CommandType, LeaderId, ReplicaId, Slot,
+    };
+
+    #[test]
+    fn stale_leaders_and_acceptors_are_sent_the_config_once_an_interval() {
+        let mut config = Config::new(
+            HashSet::from([ReplicaId::new(3)]),
+            HashSet::from([AcceptorId::new(1)]),
+            HashSet::from([LeaderId::new(2)]),
+            BTreeMap::new(),
+            None,
+        );
+        config.epoch = 4;
+        let sent_in = |epoch: Option<u64>, message: Message| SendableMessage {
+            src: Address::new("peer".to_string(), 1),
+            dst: Address::new("us".to_string(), 1),
+            seq: None,
+            lamport: None,
+            epoch,
+            message,
+        };
+        let p2b = || {
+            Message::P2b(P2bMessage {
+                src: AcceptorId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(2)),
+                slot_number: Slot(1),
+            })
+        };
+        assert_eq!(
+            EpochCheck::of(&sent_in(Some(4), p2b()), 4),
+            EpochCheck::Current
+        );
+        assert_eq!(
+            EpochCheck::of(&sent_in(None, p2b()), 4),
+            EpochCheck::Current
+        );
+        assert_eq!(
+            EpochCheck::of(&sent_in(Some(6), p2b()), 4),
+            EpochCheck::Ahead(6)
+        );
+
+        let mut sync = EpochSync::new();
+        let now = MockClock::new().now();
+        let us = NodeId::from(LeaderId::new(2));
+        let reply = sync
+            .sync_for(us, &config, &sent_in(Some(3), p2b()), now)
+            .unwrap();
+        assert_eq!(reply.dst, Address::new("peer".to_string(), 1));
+        assert!(matches!(
+            &reply.message,
+            Message::ConfigSync(m) if m.src == us && m.config.epoch == 4
+        ));
+        // Not again within the interval
+        assert!(sync
+            .sync_for(us, &config, &sent_in(Some(3), p2b()), now)
+            .is_none());
+        let later = now + config.timeout_config.heartbeat_interval + Duration::from_millis(1);
+        assert!(sync
+            .sync_for(us, &config, &sent_in(Some(3), p2b()), later)
+            .is_some());
+
+        // Never to a replica, nor for a heartbeat, whose sender could be one
+        let propose = Message::Propose(ProposeMessage {
+            src: ReplicaId::new(3),
+            slot_number: Slot(1),
+            command: Command {
+                client_id: NodeId::new(9),
+                request_id: 1,
+                op: CommandType::Op(Vec::new()),
+            },
+        });
+        let heartbeat = Message::Heartbeat(HeartbeatMessage {
+            src: NodeId::from(AcceptorId::new(1)),
+            ballot: None,
+            quorum_lost: false,
+            promised: None,
+            lease: None,
+            commit_index: None,
+        });
+        let mut sync = EpochSync::new();
+        assert!(sync
+            .sync_for(us, &config, &sent_in(Some(0), propose), now)
+            .is_none());
+        assert!(sync
+            .sync_for(us, &config, &sent_in(Some(0), heartbeat), now)
+            .is_none());
+    }
+}
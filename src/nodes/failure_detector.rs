@@ -0,0 +1,166 @@
+//! Timeout-based failure detector.
+//!
+//! Nodes note when they last heard from each peer (any protocol message or a
+//! `Heartbeat` counts) and suspect peers that have been silent for longer than
+//! `TimeoutConfig::suspect_timeout`. Suspicion is only a hint: it feeds health
+//! reporting and retry decisions, never safety.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::HashMap;
+use crate::nodes::freeze::{age, rewind};
+use crate::time::{Duration, Instant};
+use crate::types::{Address, Config, NodeId};
+
+#[derive(Clone, Debug)]
+pub struct FailureDetector {
+    suspect_after: Duration,
+    last_heard: HashMap<NodeId, Instant>,
+}
+
+impl FailureDetector {
+    pub fn new(suspect_after: Duration) -> FailureDetector {
+        FailureDetector {
+            suspect_after,
+            last_heard: HashMap::new(),
+        }
+    }
+
+    pub fn set_suspect_after(&mut self, suspect_after: Duration) {
+        self.suspect_after = suspect_after;
+    }
+
+    /// Record that `node` was heard from at `now`.
+    pub fn heard_from(&mut self, node: NodeId, now: Instant) {
+        let last = self.last_heard.entry(node).or_insert(now);
+        if now > *last {
+            *last = now;
+        }
+    }
+
+    /// Suspect `node` until it is next heard from, whatever it was heard
+    /// from last.
+    pub fn suspect(&mut self, node: NodeId) {
+        self.last_heard.remove(&node);
+    }
+
+    /// Suspect every node `config` places at `address`, e.g. when the
+    /// transport reports its link there down.
+    pub fn suspect_address(&mut self, config: &Config, address: &Address) {
+        for (node, _) in config.id_address_map.iter().filter(|(_, a)| *a == address) {
+            self.suspect(*node);
+        }
+    }
+
+    pub fn last_heard(&self, node: &NodeId) -> Option<Instant> {
+        self.last_heard.get(node).copied()
+    }
+
+    /// A node is suspected if it has never been heard from or has been silent too long.
+    pub fn is_suspected(&self, node: &NodeId, now: Instant) -> bool {
+        match self.last_heard.get(node) {
+            None => true,
+            Some(last) => now.duration_since(*last) > self.suspect_after,
+        }
+    }
+
+    /// How many of `nodes` are not currently suspected.
+    pub fn reachable_count<I>(&self, nodes: I, now: Instant) -> usize
+    where
+        I: IntoIterator<Item = NodeId>,
+    {
+        nodes
+            .into_iter()
+            .filter(|node| !self.is_suspected(node, now))
+            .count()
+    }
+
+    /// Capture the detector, with how long before `now` each peer was last heard from.
+    pub fn freeze(&self, now: Instant) -> FrozenFailureDetector {
+        FrozenFailureDetector {
+            suspect_after: self.suspect_after,
+            silent_for: self
+                .last_heard
+                .iter()
+                .map(|(node, last)| (*node, age(*last, now)))
+                .collect(),
+        }
+    }
+}
+
+/// A `FailureDetector` captured by `freeze`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrozenFailureDetector {
+    suspect_after: Duration,
+    silent_for: Vec<(NodeId, Duration)>,
+}
+
+impl FrozenFailureDetector {
+    /// The detector again, as of `now`.
+    pub fn thaw(self, now: Instant) -> FailureDetector {
+        FailureDetector {
+            suspect_after: self.suspect_after,
+            last_heard: self
+                .silent_for
+                .into_iter()
+                .map(|(node, silent)| (node, rewind(silent, now)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::nodes::clock::{ClockProvider, MockClock};
+
+    #[test]
+    fn failure_detector_suspects_silent_nodes() {
+        let mut clock = MockClock::new();
+        let mut fd = FailureDetector::new(Duration::from_millis(100));
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+
+        // Never heard from
+        assert!(fd.is_suspected(&a, clock.now()));
+
+        fd.heard_from(a, clock.now());
+        fd.heard_from(b, clock.now());
+        assert_eq!(fd.reachable_count([a, b], clock.now()), 2);
+
+        clock.advance(Duration::from_millis(60));
+        fd.heard_from(b, clock.now());
+        clock.advance(Duration::from_millis(60));
+        assert!(fd.is_suspected(&a, clock.now()));
+        assert!(!fd.is_suspected(&b, clock.now()));
+        assert_eq!(fd.reachable_count([a, b], clock.now()), 1);
+    }
+
+    #[test]
+    fn failure_detector_suspects_nodes_behind_a_down_link() {
+        let clock = MockClock::new();
+        let mut fd = FailureDetector::new(Duration::from_millis(100));
+        let (a, b) = (NodeId::new(1), NodeId::new(2));
+        let address = |port| Address::new("127.0.0.1".to_string(), port);
+        let config = Config::new(
+            HashSet::new(),
+            HashSet::new(),
+            HashSet::new(),
+            BTreeMap::from([(a, address(8001)), (b, address(8002))]),
+            None,
+        );
+        fd.heard_from(a, clock.now());
+        fd.heard_from(b, clock.now());
+
+        fd.suspect_address(&config, &address(8001));
+        assert!(fd.is_suspected(&a, clock.now()));
+        assert!(!fd.is_suspected(&b, clock.now()));
+        // Until it is heard from again
+        fd.heard_from(a, clock.now());
+        assert!(!fd.is_suspected(&a, clock.now()));
+    }
+}
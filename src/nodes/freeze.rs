@@ -0,0 +1,193 @@
+//! Node state captured for moving a running node to another host or runtime.
+//!
+//! `Leader::freeze`, `Acceptor::freeze` and `Replica::freeze` capture a
+//! node's whole protocol state, including its mailbox and its pending timers,
+//! in a serializable `Frozen*` value, and `thaw` builds the node again from
+//! one, on a new clock. The thawed node carries on where the frozen one
+//! stopped: it neither scouts again nor fetches what it already had.
+//!
+//! Points in time are frozen as how long before the freeze they were, and
+//! timers as how long after it they were due, so a node can be thawed on a
+//! clock with a different origin. The time a frozen node spends in transit
+//! is not counted.
+//!
+//! What the embedder plugged into a node is not frozen: its router, event
+//! sink, stores, proposal policy, slot allocator, state machine and command
+//! handlers. A thawed node starts with the same defaults as a new one, and
+//! the embedder sets them again, as after `new`. A replica's state machine
+//! is migrated by the application, like a snapshot for `Replica::recover`.
+use alloc::vec::Vec;
+
+use crate::nodes::clock::{ClockAction, ClockProvider};
+use crate::time::{Duration, Instant};
+
+/// Pending timers, each with how long until it is due.
+pub type Timers = Vec<(ClockAction, Duration)>;
+
+/// How long before `now` `then` was.
+pub(crate) fn age(then: Instant, now: Instant) -> Duration {
+    now.duration_since(then)
+}
+
+/// The instant `age` before `now`, or `now` if the clock does not reach back
+/// that far.
+pub(crate) fn rewind(age: Duration, now: Instant) -> Instant {
+    now.checked_sub(age).unwrap_or(now)
+}
+
+/// Schedule frozen `timers` on `clock`.
+pub(crate) fn schedule(clock: &mut (dyn ClockProvider + Send), timers: Timers) {
+    for (action, due_in) in timers {
+        clock.schedule(action, due_in);
+    }
+}
+
+/// Maps serialized as sequences of pairs, for maps whose keys serde_json
+/// cannot write as object keys.
+pub(crate) mod pairs {
+    use alloc::vec::Vec;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::string::ToString;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::messages::{Consistency, Message, RequestMessage, SendableMessage};
+    use crate::nodes::acceptor::Acceptor;
+    use crate::nodes::clock::MockClock;
+    use crate::nodes::leader::Leader;
+    use crate::nodes::mailbox::Mailbox;
+    use crate::nodes::node::Node;
+    use crate::nodes::replica::Replica;
+    use crate::types::{
+        AcceptorId, Address, Command, CommandType, Config, LeaderId, NodeId, ReplicaId, Slot,
+    };
+
+    /// Let `nodes` work and hand their messages to each other until none
+    /// are left, returning those addressed to anyone else.
+    fn route(nodes: &mut [(Address, &mut dyn Node)]) -> Vec<SendableMessage> {
+        let mut external = Vec::new();
+        loop {
+            let mut in_flight = Vec::new();
+            for (_, node) in nodes.iter_mut() {
+                while node.work_on_message() {}
+                while let Some(msg) = node.deliver_sent() {
+                    in_flight.push(msg);
+                }
+            }
+            if in_flight.is_empty() {
+                return external;
+            }
+            for msg in in_flight {
+                match nodes.iter_mut().find(|(address, _)| *address == msg.dst) {
+                    Some((_, node)) => node.accept_message(msg),
+                    None => external.push(msg),
+                }
+            }
+        }
+    }
+
+    fn clock() -> Box<MockClock> {
+        Box::new(MockClock::new())
+    }
+
+    #[test]
+    fn frozen_nodes_carry_on_where_they_stopped_once_thawed() {
+        let (acc, ldr, rep) = (AcceptorId::new(1), LeaderId::new(101), ReplicaId::new(201));
+        let address = |port| Address::new("127.0.0.1".to_string(), port);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::from([acc]),
+            HashSet::from([ldr]),
+            BTreeMap::from([
+                (acc.into(), address(8001)),
+                (ldr.into(), address(8101)),
+                (rep.into(), address(8201)),
+            ]),
+            None,
+        );
+        let mut acceptor: Acceptor =
+            Acceptor::new(acc, config.clone(), Mailbox::new(), clock()).unwrap();
+        let mut leader: Leader = Leader::new(ldr, config.clone(), Mailbox::new(), clock()).unwrap();
+        let mut replica: Replica =
+            Replica::new(rep, config.clone(), Mailbox::new(), clock()).unwrap();
+        replica.start_periodic_checks().unwrap();
+        route(&mut [
+            (address(8001), &mut acceptor),
+            (address(8101), &mut leader),
+            (address(8201), &mut replica),
+        ]);
+        assert!(leader.progress().leading);
+
+        // Frozen with the client's request still in the replica's inbox
+        let client = address(9000);
+        replica.accept_message(SendableMessage {
+            src: client.clone(),
+            dst: address(8201),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Request(RequestMessage {
+                src: client.clone(),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }),
+        });
+        let next_timeout = replica.next_timeout();
+        let frozen =
+            serde_json::to_string(&(acceptor.freeze(), leader.freeze(), replica.freeze())).unwrap();
+        drop((acceptor, leader, replica));
+
+        let (acceptor, leader, replica) = serde_json::from_str(&frozen).unwrap();
+        let mut acceptor: Acceptor = Acceptor::thaw(acceptor, clock()).unwrap();
+        let mut leader: Leader = Leader::thaw(leader, clock()).unwrap();
+        let mut replica: Replica = Replica::thaw(replica, clock()).unwrap();
+        assert_eq!(replica.next_timeout(), next_timeout);
+        assert_eq!(replica.pending(), 1);
+
+        let ballot = leader.progress().ballot;
+        let sent = route(&mut [
+            (address(8001), &mut acceptor),
+            (address(8101), &mut leader),
+            (address(8201), &mut replica),
+        ]);
+        // Decided under the same ballot, without scouting again
+        assert_eq!(leader.progress().ballot, ballot);
+        assert!(leader.progress().leading);
+        assert_eq!(replica.progress().frontier, Some(Slot(2)));
+        assert!(matches!(&sent[..], [msg] if msg.dst == client
+            && matches!(&msg.message, Message::Response(r) if r.slot == Slot(1))));
+    }
+}
@@ -0,0 +1,78 @@
+//! Readiness reporting for nodes.
+//!
+//! A node is `Ready` when it can make progress, `Degraded` when it can but
+//! something needs attention (e.g. a backed-up inbox), and `NotReady` when it
+//! cannot (e.g. it cannot reach a quorum). Embedders combine a node's own
+//! report with checks it cannot see, such as persistence, using `Health::and`.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Health {
+    Ready,
+    Degraded { reasons: Vec<String> },
+    NotReady { reasons: Vec<String> },
+}
+
+impl Health {
+    /// Build a report from failed checks: any `not_ready` reason wins over `degraded` ones.
+    pub fn from_checks(not_ready: Vec<String>, degraded: Vec<String>) -> Health {
+        if !not_ready.is_empty() {
+            Health::NotReady { reasons: not_ready }
+        } else if !degraded.is_empty() {
+            Health::Degraded { reasons: degraded }
+        } else {
+            Health::Ready
+        }
+    }
+
+    /// Whether the node should receive traffic: `Degraded` nodes still serve.
+    pub fn is_ready(&self) -> bool {
+        !matches!(self, Health::NotReady { .. })
+    }
+
+    pub fn reasons(&self) -> &[String] {
+        match self {
+            Health::Ready => &[],
+            Health::Degraded { reasons } | Health::NotReady { reasons } => reasons,
+        }
+    }
+
+    /// Combine two reports, keeping the worse status and the reasons behind it.
+    pub fn and(self, other: Health) -> Health {
+        match (self, other) {
+            (Health::NotReady { mut reasons }, Health::NotReady { reasons: more }) => {
+                reasons.extend(more);
+                Health::NotReady { reasons }
+            }
+            (h @ Health::NotReady { .. }, _) | (_, h @ Health::NotReady { .. }) => h,
+            (Health::Degraded { mut reasons }, Health::Degraded { reasons: more }) => {
+                reasons.extend(more);
+                Health::Degraded { reasons }
+            }
+            (h @ Health::Degraded { .. }, _) | (_, h @ Health::Degraded { .. }) => h,
+            (Health::Ready, Health::Ready) => Health::Ready,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn health_combines_to_the_worse_status() {
+        let degraded = Health::from_checks(vec![], vec!["inbox backed up".to_string()]);
+        let not_ready = Health::from_checks(vec!["no quorum".to_string()], vec![]);
+
+        assert_eq!(Health::Ready.and(Health::Ready), Health::Ready);
+        assert_eq!(Health::Ready.and(degraded.clone()), degraded);
+        assert_eq!(degraded.clone().and(not_ready.clone()), not_ready);
+        assert!(degraded.is_ready());
+        assert!(!not_ready.is_ready());
+    }
+}
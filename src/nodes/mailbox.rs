@@ -1,41 +1,188 @@
-use std::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::constants::DEDUP_WINDOW;
 use crate::messages;
+use crate::types::{Address, NodeId, Role};
 
 /// Sans-IO mailbox for nodes to send and receive messages.
-#[derive(Clone, Debug)]
-pub struct Mailbox {
-    pub inbox: VecDeque<messages::SendableMessage>,
-    pub outbox: VecDeque<messages::SendableMessage>,
+///
+/// Outgoing messages are stamped with a sequence number per destination and
+/// incoming ones are checked against a window of the sequence numbers recently
+/// seen from their sender, so a message a transport delivers twice is only
+/// handled once. Messages without a sequence number are always accepted.
+///
+/// A node receiving through `receive_as` only queues the messages its role
+/// handles, and answers any other with a `Misrouted` reply.
+///
+/// The mailbox also keeps the node's Lamport clock. Sending a message ticks
+/// it and stamps the message with it, and receiving one moves it past the
+/// time the message was stamped with, so stamps order the messages and
+/// audit events of the whole cluster consistently with causality.
+///
+/// Every message sent is stamped with the epoch of the node's configuration
+/// too, which the node keeps up to date with `set_epoch`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mailbox<T = Vec<u8>> {
+    pub inbox: VecDeque<messages::SendableMessage<T>>,
+    pub outbox: VecDeque<messages::SendableMessage<T>>,
+    // Last sequence number stamped per destination
+    sent_seq: BTreeMap<String, u64>,
+    // Recently received sequence numbers per sender
+    received_seq: BTreeMap<String, DedupWindow>,
+    #[serde(default)]
+    lamport: u64,
+    #[serde(default)]
+    epoch: u64,
+}
+
+/// The sequence numbers seen from one peer within `DEDUP_WINDOW` of the highest.
+///
+/// A peer that restarts before its numbering has moved a full window on may
+/// have its first few messages taken for duplicates; the protocol's retries
+/// recover them, as they would any lost message.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct DedupWindow {
+    highest: u64,
+    seen: BTreeSet<u64>,
+}
+
+impl DedupWindow {
+    /// Record `seq`, returning false if it was already seen.
+    fn observe(&mut self, seq: u64) -> bool {
+        if seq + DEDUP_WINDOW <= self.highest {
+            // Far behind anything recent: the peer restarted its numbering
+            *self = DedupWindow::default();
+        }
+        if !self.seen.insert(seq) {
+            return false;
+        }
+        if seq > self.highest {
+            self.highest = seq;
+            let floor = self.highest.saturating_sub(DEDUP_WINDOW);
+            self.seen = self.seen.split_off(&(floor + 1));
+        }
+        true
+    }
 }
 
-impl Default for Mailbox {
+impl<T> Default for Mailbox<T> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Mailbox {
+impl<T> Mailbox<T> {
     pub fn new() -> Self {
         Mailbox {
             inbox: VecDeque::new(),
             outbox: VecDeque::new(),
+            sent_seq: BTreeMap::new(),
+            received_seq: BTreeMap::new(),
+            lamport: 0,
+            epoch: 0,
         }
     }
 
-    pub fn receive(&mut self, msg: messages::SendableMessage) {
+    /// Queue `msg` for handling, returning false if it is a duplicate delivery.
+    pub fn receive(&mut self, msg: messages::SendableMessage<T>) -> bool {
+        if let Some(seq) = msg.seq {
+            let window = self.received_seq.entry(msg.src.to_string()).or_default();
+            if !window.observe(seq) {
+                return false;
+            }
+        }
+        if let Some(lamport) = msg.lamport {
+            self.lamport = self.lamport.max(lamport);
+        }
+        self.lamport += 1;
         self.inbox.push_back(msg);
+        true
     }
 
-    pub fn process_latest_in(&mut self) -> Option<messages::SendableMessage> {
+    /// The node's Lamport time: past every message it has sent or received.
+    pub fn lamport(&self) -> u64 {
+        self.lamport
+    }
+
+    /// Tick the Lamport clock for an event of the node's own, returning its time.
+    pub fn tick(&mut self) -> u64 {
+        self.lamport += 1;
+        self.lamport
+    }
+
+    /// The configuration epoch stamped on messages sent.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = epoch;
+    }
+
+    /// Queue `msg` for `node`, which plays `role`, returning false if it was
+    /// not queued. A message the role does not handle is answered with a
+    /// `Misrouted` reply and counted as `paxos.messages.misrouted`, and a
+    /// `Misrouted` reply from a peer is logged rather than queued.
+    pub fn receive_as(
+        &mut self,
+        role: Role,
+        node: NodeId,
+        msg: messages::SendableMessage<T>,
+    ) -> bool {
+        if let messages::Message::Misrouted(misrouted) = &msg.message {
+            error!(
+                "{}: {} did not handle the {} sent to it from here as a {}",
+                node, misrouted.src, misrouted.kind, misrouted.role
+            );
+            return false;
+        }
+        if msg.message.handled_by(role) {
+            return self.receive(msg);
+        }
+        warn!(
+            monotonic_counter.paxos.messages.misrouted = 1u64,
+            paxos.node.role = %role,
+            "{}: a {} does not handle {}, replying Misrouted",
+            node,
+            role,
+            msg
+        );
+        self.send(messages::SendableMessage {
+            src: msg.dst,
+            dst: msg.src,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::Misrouted(messages::MisroutedMessage {
+                src: node,
+                role,
+                kind: msg.message.kind().to_string(),
+            }),
+        });
+        false
+    }
+
+    pub fn process_latest_in(&mut self) -> Option<messages::SendableMessage<T>> {
         self.inbox.pop_front()
     }
 
-    pub fn send(&mut self, msg: messages::SendableMessage) {
+    pub fn send(&mut self, mut msg: messages::SendableMessage<T>) {
+        if msg.seq.is_none() {
+            let seq = self.sent_seq.entry(msg.dst.to_string()).or_insert(0);
+            *seq += 1;
+            msg.seq = Some(*seq);
+        }
+        msg.lamport = Some(self.tick());
+        msg.epoch = Some(self.epoch);
         self.outbox.push_back(msg);
     }
 
-    pub fn deliver_sent(&mut self) -> Option<messages::SendableMessage> {
+    pub fn deliver_sent(&mut self) -> Option<messages::SendableMessage<T>> {
         self.outbox.pop_front()
     }
 
@@ -46,4 +193,182 @@ impl Mailbox {
     pub fn clear_outbox(&mut self) {
         self.outbox.clear();
     }
+
+    /// The messages of type `M` waiting in the outbox, in the order sent.
+    pub fn outbox_of<'a, M: messages::Variant<T> + 'a>(&'a self) -> impl Iterator<Item = &'a M> {
+        self.outbox.iter().filter_map(|msg| M::of(&msg.message))
+    }
+
+    /// How many messages of type `M` are waiting in the outbox, e.g.
+    /// `count_outbox_of::<P2aMessage>()`.
+    pub fn count_outbox_of<M: messages::Variant<T>>(&self) -> usize {
+        self.outbox_of::<M>().count()
+    }
+
+    /// Remove the outbox messages `matching` picks and return them, in the
+    /// order sent, leaving the rest queued.
+    pub fn take_matching(
+        &mut self,
+        mut matching: impl FnMut(&messages::SendableMessage<T>) -> bool,
+    ) -> Vec<messages::SendableMessage<T>> {
+        let (taken, kept) = self.outbox.drain(..).partition(|msg| matching(msg));
+        self.outbox = kept;
+        taken.into()
+    }
+
+    /// The first message in the outbox sent to `dst`. Panics, listing the
+    /// outbox, if there is none.
+    #[track_caller]
+    pub fn assert_sent_to(&self, dst: &Address) -> &messages::SendableMessage<T> {
+        match self.outbox.iter().find(|msg| msg.dst == *dst) {
+            Some(msg) => msg,
+            None => {
+                let sent: Vec<String> = self.outbox.iter().map(ToString::to_string).collect();
+                panic!("nothing sent to {}; the outbox holds {:?}", dst, sent)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+
+    fn p1a(src_port: u64) -> SendableMessage {
+        SendableMessage {
+            src: Address::new("h".to_string(), src_port),
+            dst: Address::new("h".to_string(), 9),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                decided_below: Slot(0),
+            }),
+        }
+    }
+
+    #[test]
+    fn mailbox_drops_duplicate_deliveries() {
+        let mut sender: Mailbox = Mailbox::new();
+        sender.send(p1a(1));
+        sender.send(p1a(1));
+        let first = sender.deliver_sent().unwrap();
+        let second = sender.deliver_sent().unwrap();
+        assert_eq!((first.seq, second.seq), (Some(1), Some(2)));
+
+        let mut receiver: Mailbox = Mailbox::new();
+        assert!(receiver.receive(first.clone()));
+        assert!(receiver.receive(second));
+        assert!(!receiver.receive(first.clone()));
+        // Sequence numbers are per sender
+        let mut other = first;
+        other.src = Address::new("h".to_string(), 2);
+        assert!(receiver.receive(other));
+        // Unnumbered messages are never deduplicated
+        assert!(receiver.receive(p1a(1)));
+        assert!(receiver.receive(p1a(1)));
+        assert_eq!(receiver.inbox.len(), 5);
+
+        // A restarted sender numbers from 1 again
+        let mut restarted = p1a(1);
+        restarted.seq = Some(DEDUP_WINDOW + 10);
+        assert!(receiver.receive(restarted.clone()));
+        restarted.seq = Some(1);
+        assert!(receiver.receive(restarted));
+    }
+
+    #[test]
+    fn mailboxes_keep_a_lamport_clock_across_messages() {
+        let (mut a, mut b): (Mailbox, Mailbox) = (Mailbox::new(), Mailbox::new());
+        a.send(p1a(1));
+        a.send(p1a(1));
+        let first = a.deliver_sent().unwrap();
+        assert_eq!(first.lamport, Some(1));
+        assert_eq!(a.deliver_sent().unwrap().lamport, Some(2));
+
+        // b has done more than a, and moves past what it receives either way
+        for _ in 0..5 {
+            b.tick();
+        }
+        assert!(b.receive(first));
+        assert_eq!(b.lamport(), 6);
+        b.send(p1a(2));
+        let reply = b.deliver_sent().unwrap();
+        assert_eq!(reply.lamport, Some(7));
+        assert!(reply.to_string().ends_with(" at L7 in E0"));
+        assert!(a.receive(reply));
+        assert_eq!(a.lamport(), 8);
+    }
+
+    #[test]
+    fn mailbox_answers_messages_its_role_does_not_handle() {
+        let replica = NodeId::new(201);
+        let mut mailbox: Mailbox = Mailbox::new();
+        assert!(!mailbox.receive_as(Role::Replica, replica, p1a(1)));
+        assert!(mailbox.inbox.is_empty());
+        let reply = mailbox.deliver_sent().unwrap();
+        assert_eq!(reply.dst, Address::new("h".to_string(), 1));
+        let Message::Misrouted(misrouted) = reply.message else {
+            panic!("expected a Misrouted reply, got {}", reply);
+        };
+        assert_eq!((misrouted.src, misrouted.role), (replica, Role::Replica));
+        assert_eq!(misrouted.kind, "P1a");
+
+        // The sender is told, but does not answer in turn
+        let mut leader: Mailbox = Mailbox::new();
+        let reply = SendableMessage {
+            message: Message::Misrouted(misrouted),
+            ..p1a(1)
+        };
+        assert!(!leader.receive_as(Role::Leader, NodeId::new(1), reply));
+        assert!(leader.inbox.is_empty() && leader.outbox.is_empty());
+
+        assert!(mailbox.receive_as(Role::Acceptor, NodeId::new(1), p1a(1)));
+        assert_eq!(mailbox.inbox.len(), 1);
+    }
+
+    #[test]
+    fn outbox_queries_pick_messages_by_type_and_destination() {
+        let mut mailbox: Mailbox = Mailbox::new();
+        let p2b = |port| SendableMessage {
+            dst: Address::new("h".to_string(), port),
+            message: Message::P2b(P2bMessage {
+                src: AcceptorId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                slot_number: Slot(port),
+            }),
+            ..p1a(1)
+        };
+        mailbox.send(p1a(1));
+        mailbox.send(p2b(2));
+        mailbox.send(p2b(3));
+        assert_eq!(mailbox.count_outbox_of::<P1aMessage>(), 1);
+        assert_eq!(mailbox.count_outbox_of::<P2bMessage>(), 2);
+        assert_eq!(mailbox.count_outbox_of::<P2aMessage>(), 0);
+        let slots: Vec<Slot> = mailbox
+            .outbox_of::<P2bMessage>()
+            .map(|p2b| p2b.slot_number)
+            .collect();
+        assert_eq!(slots, [Slot(2), Slot(3)]);
+        assert_eq!(
+            mailbox
+                .assert_sent_to(&Address::new("h".to_string(), 3))
+                .message
+                .kind(),
+            "P2b"
+        );
+
+        let taken = mailbox.take_matching(|msg| matches!(msg.message, Message::P2b(_)));
+        assert_eq!(taken.len(), 2);
+        assert_eq!(taken[0].dst, Address::new("h".to_string(), 2));
+        assert_eq!(mailbox.outbox.len(), 1);
+        let missing = std::panic::catch_unwind(|| {
+            mailbox.assert_sent_to(&Address::new("h".to_string(), 2));
+        });
+        assert!(missing.is_err());
+    }
 }
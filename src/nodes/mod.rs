@@ -1,5 +1,24 @@
 pub mod acceptor;
+pub mod catch_up;
 pub mod clock;
+pub mod combined;
+pub mod config_timeline;
+pub mod discovery;
+pub mod epoch;
+pub mod failure_detector;
+pub mod freeze;
+pub mod health;
 pub mod leader;
 pub mod mailbox;
+pub mod node;
+pub mod phase1;
+pub mod phase2;
+pub mod quorum;
 pub mod replica;
+pub mod request_queue;
+pub mod result_cache;
+pub mod router;
+pub mod slot_allocator;
+pub mod slot_map;
+pub mod snapshot;
+pub mod validate;
@@ -0,0 +1,116 @@
+//! The surface shared by all protocol roles, so that runtimes and test
+//! harnesses can drive acceptors, leaders and replicas alike.
+//!
+//! Embedders without a runtime of their own can drive a node with `tick`
+//! alone, which does everything due and says when to come back.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::messages::SendableMessage;
+use crate::nodes::clock::ClockAction;
+use crate::nodes::health::Health;
+use crate::nodes::router::WireFormat;
+use crate::time::Duration;
+use crate::types::{Address, BallotNumber, Slot, TimeoutConfig};
+
+pub trait Node<T = Vec<u8>> {
+    /// Queue an inbound message in the node's inbox.
+    fn accept_message(&mut self, msg: SendableMessage<T>);
+
+    /// Handle the oldest queued message; returns false if there was none or it failed.
+    fn work_on_message(&mut self) -> bool;
+
+    /// Fire any expired timers.
+    fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>>;
+
+    /// Time until the next timer is due, if any are scheduled.
+    ///
+    /// A node only has work when a message arrives or a timer falls due, so
+    /// a driver should sleep until whichever comes first rather than poll
+    /// `work_on_message`: `None` means until the next message. `NodeRunner`
+    /// and `Simulation::run_idle` both keep to this.
+    fn next_timeout(&self) -> Option<Duration>;
+
+    /// Messages queued in the inbox, waiting to be handled.
+    fn pending(&self) -> usize;
+
+    /// Take the oldest message waiting in the outbox.
+    fn deliver_sent(&mut self) -> Option<SendableMessage<T>>;
+
+    fn health(&self) -> Health;
+
+    /// Replace the node's timeout parameters; they apply from the next time each timer is scheduled.
+    fn set_timeouts(&mut self, timeouts: TimeoutConfig);
+
+    /// The transport lost its link to `address`: suspect the nodes there
+    /// until they are next heard from, without waiting for them to go silent.
+    fn link_down(&mut self, _address: &Address) {}
+
+    /// The transport's link to `address` negotiated `format`: remember it in
+    /// the node's router, for encoding what is sent there.
+    fn link_negotiated(&mut self, _address: &Address, _format: WireFormat) {}
+
+    /// The format to encode messages to `address` in, if its link negotiated one.
+    fn wire_format(&self, _address: &Address) -> Option<WireFormat> {
+        None
+    }
+
+    /// Where the node has got to in the protocol, for operators.
+    fn progress(&self) -> Progress {
+        Progress::default()
+    }
+
+    /// Do one round of the node's work in a single call: fire the timers
+    /// that are due, handle up to `max_messages` queued messages and take
+    /// everything the node sent. An embedder's whole drive loop is to send
+    /// what it returns, queue arriving messages with `accept_message`, and
+    /// call `tick` again when one arrives or `next_timeout` has passed.
+    fn tick(&mut self, max_messages: usize) -> anyhow::Result<Tick<T>> {
+        self.check_timers()?;
+        for _ in 0..max_messages {
+            if self.pending() == 0 {
+                break;
+            }
+            // A message that fails to be handled is logged by the node and dropped
+            self.work_on_message();
+        }
+        let mut sent = Vec::new();
+        while let Some(msg) = self.deliver_sent() {
+            sent.push(msg);
+        }
+        let next_timeout = if self.pending() > 0 {
+            Some(Duration::ZERO)
+        } else {
+            self.next_timeout()
+        };
+        Ok(Tick { sent, next_timeout })
+    }
+}
+
+/// What a node did in one `Node::tick`.
+#[derive(Clone, Debug)]
+pub struct Tick<T = Vec<u8>> {
+    /// Everything the node sent, in order, for the transport to deliver.
+    pub sent: Vec<SendableMessage<T>>,
+    /// How long until the node should tick again if no message arrives
+    /// first: zero while messages are left over from this tick, and `None`
+    /// when it has no timer scheduled.
+    pub next_timeout: Option<Duration>,
+}
+
+/// A node's position in the protocol, as reported to operators.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    /// The ballot a leader scouts or leads with, or the one an acceptor has promised.
+    pub ballot: Option<BallotNumber>,
+    /// Whether a leader's ballot has been adopted.
+    pub leading: bool,
+    /// The first slot a leader has not seen decided, a replica has not
+    /// performed, or an acceptor has not accepted anything in since.
+    pub frontier: Option<Slot>,
+    /// The highest slot a leader or replica knows to be decided along with
+    /// every slot before it.
+    #[serde(default)]
+    pub committed: Option<Slot>,
+}
@@ -0,0 +1,190 @@
+//! The P1bs a leader gathers for one ballot's Phase 1.
+//!
+//! Acceptors in a quorum often report the same pvalue: each accepted the
+//! command the last leader sent them for a slot. Only the pvalue with the
+//! highest ballot in each slot matters once the ballot is adopted, so the
+//! reports are merged as they arrive, and hold one pvalue per slot however
+//! many acceptors reported it. What each acceptor reported is kept only as
+//! ballots, which is all a witness's report needs to be weighed against.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::messages::P1bMessage;
+use crate::nodes::quorum::Quorum;
+use crate::types::{AcceptorId, BallotNumber, PValue, Slot};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Phase1Reports<T = Vec<u8>> {
+    from: Vec<AcceptorId>,
+    gc_below: Slot,
+    // The pvalue with the highest ballot in each slot
+    accepted: BTreeMap<Slot, PValue<T>>,
+    // What witnesses reported accepting, without the commands
+    witnessed: Vec<(Slot, BallotNumber)>,
+    // The highest ballot each acceptor reported accepting in each slot
+    highest: BTreeMap<Slot, Vec<(AcceptorId, BallotNumber)>>,
+    // Pvalues reported for a slot that already had one as high
+    collapsed: u64,
+}
+
+impl<T> Default for Phase1Reports<T> {
+    fn default() -> Self {
+        Phase1Reports {
+            from: Vec::new(),
+            gc_below: Slot::default(),
+            accepted: BTreeMap::new(),
+            witnessed: Vec::new(),
+            highest: BTreeMap::new(),
+            collapsed: 0,
+        }
+    }
+}
+
+impl<T> Phase1Reports<T> {
+    /// Merge in `p1b`, returning false if its acceptor had already reported.
+    pub fn record(&mut self, p1b: P1bMessage<T>) -> bool {
+        if self.from.contains(&p1b.src) {
+            return false;
+        }
+        self.from.push(p1b.src);
+        self.gc_below = self.gc_below.max(p1b.gc_below);
+        for pvalue in p1b.accepted {
+            self.raise(p1b.src, pvalue.slot, &pvalue.ballot_number);
+            match self.accepted.get(&pvalue.slot) {
+                Some(held) if held.ballot_number >= pvalue.ballot_number => self.collapsed += 1,
+                Some(_) => {
+                    self.collapsed += 1;
+                    self.accepted.insert(pvalue.slot, pvalue);
+                }
+                None => {
+                    self.accepted.insert(pvalue.slot, pvalue);
+                }
+            }
+        }
+        for (slot, ballot) in p1b.witnessed {
+            self.raise(p1b.src, slot, &ballot);
+            if !self.witnessed.contains(&(slot, ballot.clone())) {
+                self.witnessed.push((slot, ballot));
+            }
+        }
+        true
+    }
+
+    fn raise(&mut self, src: AcceptorId, slot: Slot, ballot: &BallotNumber) {
+        let highest = self.highest.entry(slot).or_default();
+        match highest.iter_mut().find(|(acceptor, _)| *acceptor == src) {
+            Some((_, held)) if *held >= *ballot => {}
+            Some((_, held)) => *held = ballot.clone(),
+            None => highest.push((src, ballot.clone())),
+        }
+    }
+
+    /// The acceptors that have reported.
+    pub fn from(&self) -> impl Iterator<Item = &AcceptorId> + '_ {
+        self.from.iter()
+    }
+
+    /// The highest watermark reported: every slot below it was decided.
+    pub fn gc_below(&self) -> Slot {
+        self.gc_below
+    }
+
+    /// Pvalues left out for repeating a slot, since the reports began.
+    pub fn collapsed(&self) -> u64 {
+        self.collapsed
+    }
+
+    /// The pvalue with the highest ballot in each slot, in slot order.
+    pub fn into_accepted(self) -> impl Iterator<Item = PValue<T>> {
+        self.accepted.into_values()
+    }
+
+    /// A slot at or above `floor` in which a witness reported accepting a
+    /// command nobody has reported yet, and that may have been chosen: too
+    /// few of `quorum`'s acceptors rule it out. Phase 1 waits for it.
+    pub fn awaiting_witnessed_command(&self, floor: Slot, quorum: &Quorum) -> Option<Slot> {
+        for (slot, witnessed_ballot) in &self.witnessed {
+            if *slot < floor {
+                continue;
+            }
+            let held = self
+                .accepted
+                .get(slot)
+                .is_some_and(|pvalue| pvalue.ballot_number >= *witnessed_ballot);
+            if held {
+                continue;
+            }
+            // Acceptors that reported nothing as high in the slot rule it out
+            let not_ruled_out = self.highest.get(slot).map_or(0, |highest| {
+                highest
+                    .iter()
+                    .filter(|(_, ballot)| ballot >= witnessed_ballot)
+                    .count()
+            });
+            let ruled_out = self.from.len() - not_ruled_out;
+            let acceptors = quorum.acceptors().count();
+            if acceptors.saturating_sub(ruled_out) >= quorum.size() {
+                return Some(*slot);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    use crate::types::{Command, CommandType, LeaderId, NodeId, Round};
+
+    fn ballot(round: u64) -> BallotNumber {
+        BallotNumber {
+            round: Round(round),
+            leader: LeaderId::new(1),
+            incarnation: 0,
+        }
+    }
+
+    fn pvalue(round: u64, slot: u64) -> PValue {
+        PValue {
+            ballot_number: ballot(round),
+            slot: Slot(slot),
+            command: Command {
+                client_id: NodeId::new(9),
+                request_id: round,
+                op: CommandType::Op(vec![round as u8]),
+            },
+        }
+    }
+
+    fn p1b(acceptor: u64, accepted: Vec<PValue>) -> P1bMessage {
+        P1bMessage {
+            src: AcceptorId::new(acceptor),
+            ballot_number: ballot(9),
+            accepted,
+            gc_below: Slot(acceptor),
+            witnessed: vec![],
+            continues_from: None,
+            more_from: None,
+        }
+    }
+
+    #[test]
+    fn phase1_reports_keep_one_pvalue_per_slot() {
+        let mut reports = Phase1Reports::default();
+        assert!(reports.record(p1b(1, vec![pvalue(2, 4), pvalue(3, 5)])));
+        assert!(reports.record(p1b(2, vec![pvalue(2, 4), pvalue(4, 5)])));
+        assert!(reports.record(p1b(3, vec![pvalue(2, 4), pvalue(1, 6)])));
+        // An acceptor counts once
+        assert!(!reports.record(p1b(3, vec![pvalue(7, 7)])));
+
+        assert_eq!(reports.from().count(), 3);
+        assert_eq!(reports.gc_below(), Slot(3));
+        assert_eq!(reports.collapsed(), 3);
+        let accepted: Vec<PValue> = reports.into_accepted().collect();
+        assert_eq!(accepted, vec![pvalue(2, 4), pvalue(4, 5), pvalue(1, 6)]);
+    }
+}
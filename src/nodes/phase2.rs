@@ -0,0 +1,191 @@
+//! The acceptors that have accepted each slot a leader has in Phase 2.
+//!
+//! The tallies are split by slot into shards, and a run of P2bs is counted
+//! into each shard in turn. Counting the shards on threads of their own did
+//! not pay for the threads in `benches/phase2_shards.rs`, so until it does
+//! they are counted one after another on the leader's thread. Which P2bs
+//! count, those for the leader's active ballot, is decided by the leader
+//! before they are handed over, and the Decisions are sent by it after.
+use alloc::vec::Vec;
+
+use crate::collections::{BTreeMap, HashMap};
+use crate::nodes::quorum::{Quorum, Tally};
+use crate::types::{AcceptorId, Slot};
+
+type Shard = HashMap<Slot, Tally>;
+
+#[derive(Debug)]
+pub struct Phase2Tallies {
+    shards: Vec<Shard>,
+    // P2bs counted from the round's acceptors, across every shard
+    counted: u64,
+}
+
+impl Default for Phase2Tallies {
+    fn default() -> Self {
+        Phase2Tallies::new(1)
+    }
+}
+
+impl Phase2Tallies {
+    pub fn new(shards: usize) -> Phase2Tallies {
+        Phase2Tallies {
+            shards: (0..shards.max(1)).map(|_| HashMap::new()).collect(),
+            counted: 0,
+        }
+    }
+
+    /// Tallies kept in `shards` shards, holding those in `tallies`.
+    pub fn from_map(tallies: HashMap<Slot, Tally>, shards: usize) -> Phase2Tallies {
+        let mut sharded = Phase2Tallies::new(shards);
+        for (slot, tally) in tallies {
+            sharded.insert(slot, tally);
+        }
+        sharded
+    }
+
+    /// Every tally, by slot, e.g. for freezing.
+    pub fn to_map(&self) -> HashMap<Slot, Tally> {
+        self.iter()
+            .map(|(slot, tally)| (*slot, tally.clone()))
+            .collect()
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Spread the tallies over `shards` shards instead.
+    pub fn reshard(&mut self, shards: usize) {
+        let counted = self.counted();
+        *self = Phase2Tallies::from_map(self.to_map(), shards);
+        self.counted = counted;
+    }
+
+    fn shard_of(&self, slot: Slot) -> usize {
+        (slot.0 % self.shards.len() as u64) as usize
+    }
+
+    pub fn get(&self, slot: &Slot) -> Option<&Tally> {
+        self.shards[self.shard_of(*slot)].get(slot)
+    }
+
+    /// The tally for `slot`, started against `quorum` if it has none yet.
+    pub fn tally(&mut self, slot: Slot, quorum: impl FnOnce() -> Quorum) -> &mut Tally {
+        let shard = self.shard_of(slot);
+        self.shards[shard]
+            .entry(slot)
+            .or_insert_with(|| Tally::new(quorum()))
+    }
+
+    pub fn insert(&mut self, slot: Slot, tally: Tally) {
+        let shard = self.shard_of(slot);
+        self.shards[shard].insert(slot, tally);
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Slot, &Tally) -> bool) {
+        for shard in &mut self.shards {
+            shard.retain(|slot, tally| keep(slot, tally));
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Tally> + '_ {
+        self.shards.iter().flat_map(HashMap::values)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Slot, &Tally)> + '_ {
+        self.shards.iter().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(HashMap::is_empty)
+    }
+
+    /// P2bs counted so far from acceptors their slot's round started with.
+    pub fn counted(&self) -> u64 {
+        self.counted
+    }
+
+    /// Count each acceptor in `accepted` as having accepted its slot, starting
+    /// a slot's tally against `quorum()` if it has none. Returns every slot
+    /// counted in that has a quorum, in slot order, and whether this run gave
+    /// it one.
+    pub fn count(
+        &mut self,
+        accepted: impl IntoIterator<Item = (Slot, AcceptorId)>,
+        quorum: impl Fn() -> Quorum,
+    ) -> Vec<(Slot, bool)> {
+        let mut batches: Vec<Vec<(Slot, AcceptorId)>> =
+            (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (slot, acceptor) in accepted {
+            batches[self.shard_of(slot)].push((slot, acceptor));
+        }
+        let mut reached: BTreeMap<Slot, bool> = BTreeMap::new();
+        for (shard, batch) in self.shards.iter_mut().zip(batches) {
+            for (slot, acceptor) in batch {
+                let tally = shard.entry(slot).or_insert_with(|| Tally::new(quorum()));
+                let had_quorum = tally.reached();
+                if tally.add(acceptor) {
+                    self.counted += 1;
+                }
+                if tally.reached() {
+                    *reached.entry(slot).or_default() |= !had_quorum;
+                }
+            }
+        }
+        reached.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::HashSet;
+
+    const P2BS: usize = 1024;
+
+    fn quorum(acceptors: u64) -> Quorum {
+        Quorum::majority((1..=acceptors).map(AcceptorId::new).collect::<HashSet<_>>())
+    }
+
+    #[test]
+    fn sharded_tallies_count_like_one() {
+        let quorum = quorum(3);
+        // Every slot accepted by acceptors 1 and 2
+        let accepted: Vec<(Slot, AcceptorId)> = (1..=2)
+            .flat_map(|acceptor| (1..=P2BS as u64).map(move |slot| (slot, acceptor)))
+            .map(|(slot, acceptor)| (Slot(slot), AcceptorId::new(acceptor)))
+            .collect();
+
+        for shards in [1, 2, 8] {
+            let mut tallies = Phase2Tallies::new(shards);
+            let (first, second) = accepted.split_at(P2BS + 1);
+            let mut reached = tallies.count(first.iter().copied(), || quorum.clone());
+            // The slots acceptor 2 reached a quorum for in this batch
+            assert_eq!(reached.len(), 1);
+            assert_eq!(reached.pop(), Some((Slot(1), true)));
+
+            let reached = tallies.count(second.iter().copied(), || quorum.clone());
+            assert_eq!(reached.len(), P2BS - 1);
+            assert!(reached.iter().all(|(_, newly)| *newly));
+            assert!(reached.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+            // Acceptor 4 is not one of the round's
+            let late = [(Slot(3), AcceptorId::new(3)), (Slot(3), AcceptorId::new(4))];
+            assert_eq!(
+                tallies.count(late, || quorum.clone()),
+                vec![(Slot(3), false)]
+            );
+            assert_eq!(tallies.counted(), 2 * P2BS as u64 + 1);
+            assert_eq!(tallies.len(), P2BS);
+
+            tallies.reshard(3);
+            assert_eq!(tallies.get(&Slot(3)).map(Tally::count), Some(3));
+            assert_eq!(tallies.counted(), 2 * P2BS as u64 + 1);
+        }
+    }
+}
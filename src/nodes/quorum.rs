@@ -0,0 +1,149 @@
+//! The acceptors a round of the protocol counts answers from.
+//!
+//! A leader captures a `Quorum` from its configuration when it starts a
+//! round: a scout for Phase 1, or a commander for one slot's Phase 2. The
+//! round is then counted against those acceptors until it ends, even if the
+//! leader moves to a configuration with other acceptors in the meantime, so
+//! a quorum is never made up of answers from two different acceptor sets.
+use serde::{Deserialize, Serialize};
+
+use crate::collections::HashSet;
+use crate::types::{AcceptorId, Config};
+
+/// A set of acceptors and how many of them make a quorum.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quorum {
+    acceptors: HashSet<AcceptorId>,
+    size: usize,
+}
+
+impl Quorum {
+    /// A majority of `acceptors`.
+    pub fn majority(acceptors: HashSet<AcceptorId>) -> Quorum {
+        let size = (acceptors.len() / 2) + 1;
+        Quorum { acceptors, size }
+    }
+
+    /// A majority of `config`'s acceptors.
+    pub fn of(config: &Config) -> Quorum {
+        Quorum::majority(config.acceptors.clone())
+    }
+
+    /// How many of the acceptors make a quorum.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn acceptors(&self) -> impl Iterator<Item = &AcceptorId> + '_ {
+        self.acceptors.iter()
+    }
+
+    pub fn contains(&self, acceptor: &AcceptorId) -> bool {
+        self.acceptors.contains(acceptor)
+    }
+
+    /// Whether every quorum of these acceptors shares an acceptor with every
+    /// quorum of `other`'s, so that no value can be chosen by one while
+    /// another is chosen by the other.
+    pub fn intersects(&self, other: &Quorum) -> bool {
+        let shared = self.acceptors.intersection(&other.acceptors).count();
+        // Acceptors each side's quorums must take from the shared ones
+        let ours = self.size.saturating_sub(self.acceptors.len() - shared);
+        let theirs = other.size.saturating_sub(other.acceptors.len() - shared);
+        ours + theirs > shared
+    }
+
+    /// Whether `answered` holds enough of the acceptors for a quorum.
+    /// Answers from acceptors outside the set do not count.
+    pub fn reached_by<'a>(&self, answered: impl IntoIterator<Item = &'a AcceptorId>) -> bool {
+        answered
+            .into_iter()
+            .filter(|acceptor| self.contains(acceptor))
+            .count()
+            >= self.size
+    }
+}
+
+/// The acceptors that have answered a round, counted against the quorum the
+/// round started with.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tally {
+    quorum: Quorum,
+    answered: HashSet<AcceptorId>,
+}
+
+impl Tally {
+    pub fn new(quorum: Quorum) -> Tally {
+        Tally {
+            quorum,
+            answered: HashSet::new(),
+        }
+    }
+
+    pub fn quorum(&self) -> &Quorum {
+        &self.quorum
+    }
+
+    /// Count `acceptor`'s answer, returning false if it is not one of the
+    /// round's acceptors. Answering twice counts once.
+    pub fn add(&mut self, acceptor: AcceptorId) -> bool {
+        if !self.quorum.contains(&acceptor) {
+            return false;
+        }
+        self.answered.insert(acceptor);
+        true
+    }
+
+    /// The round's acceptors that have answered.
+    pub fn answered(&self) -> impl Iterator<Item = &AcceptorId> + '_ {
+        self.answered.iter()
+    }
+
+    /// How many of the round's acceptors have answered.
+    pub fn count(&self) -> usize {
+        self.answered.len()
+    }
+
+    pub fn reached(&self) -> bool {
+        self.count() >= self.quorum.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acceptors(ids: &[u64]) -> HashSet<AcceptorId> {
+        ids.iter().map(|id| AcceptorId::new(*id)).collect()
+    }
+
+    #[test]
+    fn tally_counts_only_the_acceptors_its_round_started_with() {
+        let quorum = Quorum::majority(acceptors(&[1, 2, 3]));
+        assert_eq!(quorum.size(), 2);
+        assert!(!quorum.reached_by(&acceptors(&[1, 4, 5])));
+        assert!(quorum.reached_by(&acceptors(&[1, 3, 4])));
+
+        let mut tally = Tally::new(quorum);
+        assert!(tally.add(AcceptorId::new(1)));
+        assert!(tally.add(AcceptorId::new(1)));
+        assert!(!tally.add(AcceptorId::new(4)));
+        assert_eq!(tally.count(), 1);
+        assert!(!tally.reached());
+        assert!(tally.add(AcceptorId::new(2)));
+        assert!(tally.reached());
+    }
+
+    #[test]
+    fn quorums_intersect_only_across_small_enough_membership_changes() {
+        let three = Quorum::majority(acceptors(&[1, 2, 3]));
+        assert!(three.intersects(&three));
+        assert!(three.intersects(&Quorum::majority(acceptors(&[1, 2, 3, 4]))));
+        assert!(three.intersects(&Quorum::majority(acceptors(&[1, 2]))));
+        // {1, 2} and {3, 4, 5} are quorums of each
+        assert!(!three.intersects(&Quorum::majority(acceptors(&[1, 2, 3, 4, 5]))));
+        // Replacing an acceptor in one step: {1, 3} and {2, 4}
+        assert!(!three.intersects(&Quorum::majority(acceptors(&[1, 2, 4]))));
+        assert!(!three.intersects(&Quorum::majority(acceptors(&[4, 5, 6]))));
+    }
+}
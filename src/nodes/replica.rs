@@ -1,58 +1,325 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
 
-use tracing::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, error, info, warn};
 
-use crate::constants::WINDOW;
+use crate::audit::{AuditEvent, Stamped};
+use crate::collections::{BTreeMap, HashMap, HashSet};
+use crate::constants::{
+    CATCH_UP_BACKLOG, CATCH_UP_CHUNK, INBOX_BACKPRESSURE, LAG_ALERT_SLOTS, MAX_BUFFERED_DECISIONS,
+    MAX_PENDING_BARRIERS, MAX_PROPOSAL_RETRIES, MAX_REPROPOSE_BACKOFF, MAX_SLOT,
+    RESULT_CACHE_CAPACITY, WINDOW,
+};
+use crate::events::{Event, EventSink, NoEvents};
+use crate::membership::{MembershipError, MembershipManager};
 use crate::messages;
+use crate::nodes::catch_up::{CatchUp, FrozenCatchUp};
 use crate::nodes::clock::{ClockAction, ClockProvider};
+use crate::nodes::config_timeline::ConfigTimeline;
+use crate::nodes::discovery::Discovery;
+use crate::nodes::epoch::EpochSync;
+use crate::nodes::failure_detector::{FailureDetector, FrozenFailureDetector};
+use crate::nodes::freeze::{self, age, rewind, Timers};
+use crate::nodes::health::Health;
 use crate::nodes::mailbox::Mailbox;
+use crate::nodes::node::{Node, Progress};
+use crate::nodes::request_queue::RequestQueue;
+use crate::nodes::result_cache::ResultCache;
+use crate::nodes::router::{ConfigRouter, Router, WireFormat};
+use crate::nodes::slot_allocator::{Sequential, SlotAllocator};
+use crate::nodes::slot_map::{MemoryMode, SlotMap, SlotMapError};
+use crate::nodes::snapshot::{Session, Snapshot};
+use crate::nodes::validate::{SlotBound, Validator};
+use crate::persistence::{DecisionLog, Decisions, RequestStore};
+use crate::state_machine::{
+    encode_batch_results, ApplyContext, CommandHandler, CommandRegistry, NullStateMachine,
+    StateMachine,
+};
+use crate::time::{Duration, Instant};
 use crate::types;
 
-pub enum ReplicaMessageIn {
-    Request(messages::RequestMessage),
-    Decision(messages::DecisionMessage),
+pub enum ReplicaMessageIn<T = Vec<u8>> {
+    Request(messages::RequestMessage<T>),
+    Decision(messages::DecisionMessage<T>),
+    ProposeRejected(messages::ProposeRejectedMessage),
+    ProposeAccepted(messages::ProposeAcceptedMessage),
+    DecisionFetch(messages::DecisionFetchMessage),
+    DecisionFetchReply(messages::DecisionFetchReplyMessage<T>),
+    Barrier(messages::BarrierMessage),
 }
 
-pub struct Replica {
+/// How often a proposal not yet decided has been sent again, and how long
+/// until it is next, counted in repropose checks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ProposalRetry {
+    attempts: u32,
+    wait: u32,
+    /// The leader that acknowledged taking the proposal up, if any.
+    #[serde(default)]
+    accepted_by: Option<types::LeaderId>,
+}
+
+/// The commands of one client performed in slots since forgotten: the
+/// highest request id among them, and which of the 63 ids just below it.
+/// Older request ids are taken as performed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ForgottenRequests {
+    highest: u64,
+    // Bit i is set if request highest - i was performed
+    performed: u64,
+}
+
+impl ForgottenRequests {
+    fn record(&mut self, request_id: u64) {
+        if self.performed == 0 || request_id > self.highest {
+            let shift = request_id - self.highest;
+            let kept = if shift < u64::from(u64::BITS) {
+                self.performed << shift
+            } else {
+                0
+            };
+            self.performed = kept | 1;
+            self.highest = request_id;
+        } else if self.highest - request_id < u64::from(u64::BITS) {
+            self.performed |= 1 << (self.highest - request_id);
+        }
+    }
+
+    fn contains(&self, request_id: u64) -> bool {
+        match self.highest.checked_sub(request_id) {
+            None => false,
+            Some(behind) if behind < u64::from(u64::BITS) => self.performed & (1 << behind) != 0,
+            Some(_) => true,
+        }
+    }
+}
+
+/// A `Replica` captured by `Replica::freeze`. See `nodes::freeze`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrozenReplica<T = Vec<u8>> {
+    node_id: types::ReplicaId,
+    address: types::Address,
+    slot_in: types::Slot,
+    slot_out: types::Slot,
+    proposals: SlotMap<types::Command<T>>,
+    decisions: SlotMap<types::Command<T>>,
+    highest_refused: Option<types::Slot>,
+    max_buffered_decisions: u64,
+    memory_mode: MemoryMode,
+    #[serde(with = "freeze::pairs")]
+    performed: HashMap<types::CommandId, types::Slot>,
+    #[serde(default)]
+    forgotten: HashMap<types::NodeId, ForgottenRequests>,
+    results: ResultCache,
+    requests: RequestQueue<T>,
+    max_outstanding_per_client: Option<usize>,
+    config: types::Config,
+    config_timeline: ConfigTimeline,
+    mailbox: Mailbox<T>,
+    proposal_retries: HashMap<types::Slot, ProposalRetry>,
+    max_proposal_retries: u32,
+    active_ballot: Option<types::BallotNumber>,
+    passed_over: HashSet<types::LeaderId>,
+    // The slot_out seen at the last progress check and how long ago it last changed
+    slot_out_progress: (types::Slot, Duration),
+    catch_up: FrozenCatchUp,
+    audit_events: Vec<Stamped>,
+    failure_detector: FrozenFailureDetector,
+    validator: Validator,
+    slot_bound: SlotBound,
+    leaders_without_quorum: HashSet<types::NodeId>,
+    pending_changed: bool,
+    poisoned: Option<(types::Slot, String)>,
+    lag_threshold: u64,
+    lagging: bool,
+    answered_until: types::Slot,
+    max_command_size: Option<usize>,
+    #[serde(default)]
+    installing: Option<types::Slot>,
+    timers: Timers,
+    #[serde(default)]
+    discovery: Option<Discovery>,
+    #[serde(default)]
+    proxy_requests: bool,
+    #[serde(default)]
+    certificates: SlotMap<messages::DecisionCertificate>,
+    #[serde(default)]
+    leader_commit_index: types::CommitIndex,
+    #[serde(default)]
+    barriers: BTreeMap<types::Slot, Vec<(types::Address, u64)>>,
+}
+
+pub struct Replica<T = Vec<u8>> {
     node_id: types::ReplicaId,
     address: types::Address,
-    slot_in: u64,
-    slot_out: u64,
-    proposals: HashMap<u64, types::Command>,
-    decisions: HashMap<u64, types::Command>,
-    requests: Vec<types::Command>,
+    slot_in: types::Slot,
+    slot_out: types::Slot,
+    proposals: SlotMap<types::Command<T>>,
+    decisions: SlotMap<types::Command<T>>,
+    // The certificates the decisions held came with, passed on to peers
+    // catching up when the config certifies decisions
+    certificates: SlotMap<messages::DecisionCertificate>,
+    // Highest decision refused for lying beyond the window, fetched again once it moves on
+    highest_refused: Option<types::Slot>,
+    // Slots past slot_out that decisions are buffered for while a gap is filled
+    max_buffered_decisions: u64,
+    // Whether performed slots beyond those retained are forgotten
+    memory_mode: MemoryMode,
+    // Commands already performed, and the slot they were performed in, so
+    // duplicates decided in later slots are skipped
+    performed: HashMap<types::CommandId, types::Slot>,
+    // Commands performed in slots since forgotten, per client, so their
+    // duplicates are skipped too
+    forgotten: HashMap<types::NodeId, ForgottenRequests>,
+    // Recent results, for answering retries of commands already performed
+    results: ResultCache,
+    // Client requests not yet proposed, queued per client
+    requests: RequestQueue<T>,
+    // Proposals one client may have outstanding at once, if limited
+    max_outstanding_per_client: Option<usize>,
     config: types::Config,
-    mailbox: Mailbox,
+    // Configuration changes decided so far, by the slot they take effect in
+    config_timeline: ConfigTimeline,
+    mailbox: Mailbox<T>,
     // Clock provider for scheduling timeouts and retries
     clock: Box<dyn ClockProvider + Send>,
-    // Track when proposals were sent for timeout management
-    proposal_times: HashMap<u64, Duration>, // slot -> timeout duration
+    // Reproposals of each undecided proposal, for backing off between them
+    proposal_retries: HashMap<types::Slot, ProposalRetry>,
+    // Reproposals of one slot after which the active leader is suspected
+    max_proposal_retries: u32,
+    // The highest ballot a leader has announced itself active under
+    active_ballot: Option<types::BallotNumber>,
+    // Leaders suspected of not deciding our proposals, proposed to only when
+    // no other leader is left
+    passed_over: HashSet<types::LeaderId>,
+    // The slot_out seen at the last progress check and when it last changed
+    slot_out_progress: (types::Slot, Instant),
+    // Chunks of missing decisions being fetched, and how many may be at once
+    catch_up: CatchUp,
+    // Events for the audit journal, drained by the embedder
+    audit_events: Vec<Stamped>,
+    // Where high-level events are published for other subsystems
+    events: Box<dyn EventSink + Send>,
+    // When each peer was last heard from
+    failure_detector: FailureDetector,
+    // Checks received messages and counts the malformed ones dropped
+    validator: Validator,
+    // Refuses decisions for slots absurdly far past slot_out
+    slot_bound: SlotBound,
+    // Leaders whose last heartbeat said they cannot reach a quorum of acceptors
+    leaders_without_quorum: HashSet<types::NodeId>,
+    // Resolves peers to addresses at send time
+    router: Box<dyn Router + Send>,
+    // Which open slots this replica proposes into
+    slot_allocator: Box<dyn SlotAllocator + Send>,
+    // Durable copy of pending_requests(), if the embedder provided one
+    request_store: Option<Box<dyn RequestStore<T> + Send>>,
+    // Where performed decisions are logged, if anywhere
+    decision_log: Option<Box<dyn DecisionLog<T> + Send>>,
+    // Whether the set of pending requests changed since it was last stored
+    pending_changed: bool,
+    // Application state that decided operations are applied to
+    state_machine: Box<dyn StateMachine<T> + Send>,
+    // Handlers for `CommandType::App` commands, by kind
+    command_registry: CommandRegistry,
+    // The slot whose operation panicked in the state machine, and the panic
+    // message; nothing more is performed until recover() is called
+    poisoned: Option<(types::Slot, String)>,
+    // Lag beyond which the replica alerts and fetches the gap at once, and
+    // whether it is currently beyond it
+    lag_threshold: u64,
+    lagging: bool,
+    // Slots below this were answered before recover() replayed them
+    answered_until: types::Slot,
+    // Largest encoded command taken up from clients, if limited
+    max_command_size: Option<usize>,
+    // The last slot of the snapshot being installed; nothing is performed meanwhile
+    installing: Option<types::Slot>,
+    // Peers in older configuration epochs sent this one's
+    epochs: EpochSync,
+    // Peers' addresses learned by gossip, in discovery mode
+    discovery: Option<Discovery>,
+    // Whether requests that cannot be decided here are passed to a peer
+    // replica, and the peer the next one goes to
+    proxy_requests: bool,
+    next_proxy: usize,
+    // When the active leader's lease runs out, as its last heartbeat told
+    lease_until: Option<Instant>,
+    // The highest commit index an active leader's heartbeat has reported
+    leader_commit_index: types::CommitIndex,
+    // Clients waiting for the commit index to cover a slot, by that slot
+    barriers: BTreeMap<types::Slot, Vec<(types::Address, u64)>>,
 }
 
-impl Replica {
+impl<T: types::Payload> Replica<T> {
     pub fn new(
         replica_id: types::ReplicaId,
         config: types::Config,
-        mailbox: Mailbox,
+        mut mailbox: Mailbox<T>,
         clock: Box<dyn ClockProvider + Send>,
-    ) -> anyhow::Result<Replica> {
+    ) -> anyhow::Result<Replica<T>> {
         let addr = config
             .get_address(replica_id.as_ref())
             .ok_or(anyhow::anyhow!("Failed to get address"))?;
+        mailbox.set_epoch(config.epoch);
 
+        let now = clock.now();
         Ok(Replica {
             node_id: replica_id,
             address: addr.clone(),
-            slot_in: 1,
-            slot_out: 1,
-            proposals: HashMap::new(),
-            decisions: HashMap::new(),
-            requests: Vec::new(),
+            failure_detector: FailureDetector::new(config.timeout_config.suspect_timeout),
+            validator: Validator::default(),
+            slot_bound: SlotBound::default(),
+            leaders_without_quorum: HashSet::new(),
+            router: Box::new(ConfigRouter::new(&config)),
+            slot_allocator: Box::new(Sequential),
+            slot_in: types::Slot::FIRST,
+            slot_out: types::Slot::FIRST,
+            proposals: SlotMap::default(),
+            decisions: SlotMap::default(),
+            certificates: SlotMap::default(),
+            highest_refused: None,
+            max_buffered_decisions: MAX_BUFFERED_DECISIONS,
+            memory_mode: MemoryMode::Unbounded,
+            performed: HashMap::new(),
+            forgotten: HashMap::new(),
+            results: ResultCache::new(RESULT_CACHE_CAPACITY),
+            requests: RequestQueue::default(),
+            max_outstanding_per_client: None,
+            config_timeline: ConfigTimeline::new(config.clone()),
             config,
             mailbox,
             clock,
-            proposal_times: HashMap::new(),
+            proposal_retries: HashMap::new(),
+            max_proposal_retries: MAX_PROPOSAL_RETRIES,
+            active_ballot: None,
+            passed_over: HashSet::new(),
+            slot_out_progress: (types::Slot::FIRST, now),
+            catch_up: CatchUp::default(),
+            audit_events: Vec::new(),
+            events: Box::new(NoEvents),
+            state_machine: Box::new(NullStateMachine),
+            command_registry: CommandRegistry::new(),
+            request_store: None,
+            decision_log: None,
+            pending_changed: false,
+            poisoned: None,
+            lag_threshold: LAG_ALERT_SLOTS,
+            lagging: false,
+            answered_until: types::Slot(0),
+            max_command_size: None,
+            installing: None,
+            epochs: EpochSync::new(),
+            discovery: None,
+            proxy_requests: false,
+            next_proxy: 0,
+            lease_until: None,
+            leader_commit_index: types::CommitIndex::default(),
+            barriers: BTreeMap::new(),
         })
     }
 
@@ -63,8 +330,617 @@ impl Replica {
         Ok(())
     }
 
-    pub fn accept_message(&mut self, msg: messages::SendableMessage) {
-        self.mailbox.receive(msg);
+    /// Capture this replica's state, to `thaw` it elsewhere. The state
+    /// machine is not part of it.
+    pub fn freeze(&self) -> FrozenReplica<T> {
+        let now = self.clock.now();
+        let (progress_slot, progress_since) = self.slot_out_progress;
+        FrozenReplica {
+            node_id: self.node_id,
+            address: self.address.clone(),
+            slot_in: self.slot_in,
+            slot_out: self.slot_out,
+            proposals: self.proposals.clone(),
+            decisions: self.decisions.clone(),
+            certificates: self.certificates.clone(),
+            leader_commit_index: self.leader_commit_index,
+            barriers: self.barriers.clone(),
+            highest_refused: self.highest_refused,
+            max_buffered_decisions: self.max_buffered_decisions,
+            memory_mode: self.memory_mode,
+            performed: self.performed.clone(),
+            forgotten: self.forgotten.clone(),
+            results: self.results.clone(),
+            requests: self.requests.clone(),
+            max_outstanding_per_client: self.max_outstanding_per_client,
+            config: self.config.clone(),
+            config_timeline: self.config_timeline.clone(),
+            mailbox: self.mailbox.clone(),
+            proposal_retries: self.proposal_retries.clone(),
+            max_proposal_retries: self.max_proposal_retries,
+            active_ballot: self.active_ballot.clone(),
+            passed_over: self.passed_over.clone(),
+            slot_out_progress: (progress_slot, age(progress_since, now)),
+            catch_up: self.catch_up.freeze(now),
+            audit_events: self.audit_events.clone(),
+            failure_detector: self.failure_detector.freeze(now),
+            validator: self.validator.clone(),
+            slot_bound: self.slot_bound.clone(),
+            leaders_without_quorum: self.leaders_without_quorum.clone(),
+            pending_changed: self.pending_changed,
+            poisoned: self.poisoned.clone(),
+            lag_threshold: self.lag_threshold,
+            lagging: self.lagging,
+            answered_until: self.answered_until,
+            max_command_size: self.max_command_size,
+            installing: self.installing,
+            timers: self.clock.pending(),
+            discovery: self.discovery.clone(),
+            proxy_requests: self.proxy_requests,
+        }
+    }
+
+    /// Build a frozen replica again, its timers scheduled on `clock`. Its
+    /// state machine, restored by the application to the frozen replica's
+    /// `slot_out`, is installed with `set_state_machine` before it handles
+    /// messages.
+    pub fn thaw(
+        frozen: FrozenReplica<T>,
+        mut clock: Box<dyn ClockProvider + Send>,
+    ) -> anyhow::Result<Replica<T>> {
+        let now = clock.now();
+        freeze::schedule(clock.as_mut(), frozen.timers);
+        let (progress_slot, progress_age) = frozen.slot_out_progress;
+        let mut mailbox = frozen.mailbox;
+        mailbox.set_epoch(frozen.config.epoch);
+        Ok(Replica {
+            node_id: frozen.node_id,
+            address: frozen.address,
+            failure_detector: frozen.failure_detector.thaw(now),
+            validator: frozen.validator,
+            slot_bound: frozen.slot_bound,
+            leaders_without_quorum: frozen.leaders_without_quorum,
+            router: Box::new(ConfigRouter::new(&frozen.config)),
+            slot_allocator: Box::new(Sequential),
+            slot_in: frozen.slot_in,
+            slot_out: frozen.slot_out,
+            proposals: frozen.proposals,
+            decisions: frozen.decisions,
+            certificates: frozen.certificates,
+            highest_refused: frozen.highest_refused,
+            max_buffered_decisions: frozen.max_buffered_decisions,
+            memory_mode: frozen.memory_mode,
+            performed: frozen.performed,
+            forgotten: frozen.forgotten,
+            results: frozen.results,
+            requests: frozen.requests,
+            max_outstanding_per_client: frozen.max_outstanding_per_client,
+            config_timeline: frozen.config_timeline,
+            config: frozen.config,
+            mailbox,
+            clock,
+            proposal_retries: frozen.proposal_retries,
+            max_proposal_retries: frozen.max_proposal_retries,
+            active_ballot: frozen.active_ballot,
+            passed_over: frozen.passed_over,
+            slot_out_progress: (progress_slot, rewind(progress_age, now)),
+            catch_up: frozen.catch_up.thaw(now),
+            audit_events: frozen.audit_events,
+            events: Box::new(NoEvents),
+            state_machine: Box::new(NullStateMachine),
+            command_registry: CommandRegistry::new(),
+            request_store: None,
+            decision_log: None,
+            pending_changed: frozen.pending_changed,
+            poisoned: frozen.poisoned,
+            lag_threshold: frozen.lag_threshold,
+            lagging: frozen.lagging,
+            answered_until: frozen.answered_until,
+            max_command_size: frozen.max_command_size,
+            installing: frozen.installing,
+            epochs: EpochSync::new(),
+            discovery: frozen.discovery,
+            proxy_requests: frozen.proxy_requests,
+            next_proxy: 0,
+            lease_until: None,
+            leader_commit_index: frozen.leader_commit_index,
+            barriers: frozen.barriers,
+        })
+    }
+
+    /// Replace the application state that decided operations are applied to.
+    pub fn set_state_machine(&mut self, state_machine: Box<dyn StateMachine<T> + Send>) {
+        self.state_machine = state_machine;
+    }
+
+    /// Perform `CommandType::App` commands of `kind` with `handler`. Commands
+    /// of a kind with no handler are not proposed.
+    pub fn register_command_kind(
+        &mut self,
+        kind: u16,
+        handler: Box<dyn CommandHandler + Send>,
+    ) -> anyhow::Result<()> {
+        self.command_registry.register(kind, handler)
+    }
+
+    /// Turn away client commands that encode to more than `limit` bytes, or
+    /// take up commands of any size if `None`. Commands are measured as
+    /// `JsonCodec` encodes them, so without `std` none are turned away.
+    pub fn set_max_command_size(&mut self, limit: Option<usize>) {
+        self.max_command_size = limit;
+    }
+
+    /// Propose at most `limit` commands from any one client at a time, or
+    /// any number if `None`. Its other requests wait until one is decided,
+    /// leaving the open slots to other clients.
+    pub fn set_max_outstanding_per_client(&mut self, limit: Option<usize>) {
+        self.max_outstanding_per_client = limit;
+    }
+
+    /// Pass client requests on to a peer replica, rather than queueing or
+    /// turning them away, while no leader known to be active could decide
+    /// them. The peer answers the client itself. Off by default.
+    pub fn set_proxy_requests(&mut self, proxy: bool) {
+        self.proxy_requests = proxy;
+    }
+
+    /// The slot whose operation panicked in the state machine, and why, if
+    /// one did.
+    pub fn poisoned(&self) -> Option<(types::Slot, &str)> {
+        self.poisoned
+            .as_ref()
+            .map(|(slot, reason)| (*slot, reason.as_str()))
+    }
+
+    /// Replace the state machine with `state_machine`, restored from a
+    /// snapshot of every slot below `slot`, and perform the decisions from
+    /// `slot` on again. This is how a poisoned replica recovers: `slot` is
+    /// typically the last one passed to `StateMachine::on_snapshot`, or 1 for
+    /// a fresh state machine. Clients are only answered for the slots not
+    /// performed before.
+    pub fn recover(
+        &mut self,
+        state_machine: Box<dyn StateMachine<T> + Send>,
+        slot: types::Slot,
+    ) -> anyhow::Result<()> {
+        if slot < self.decisions.floor() || slot > self.slot_out {
+            return Err(anyhow::anyhow!(
+                "cannot replay from slot {}: decisions are held from {} and performed up to {}",
+                slot,
+                self.decisions.floor(),
+                self.slot_out
+            ));
+        }
+        if let Some((panicked, reason)) = self.poisoned.take() {
+            info!(
+                "{}: recovering from panic in slot {} ({}), replaying from {}",
+                self.node_id, panicked, reason, slot
+            );
+        }
+        self.state_machine = state_machine;
+        self.performed.retain(|_, performed| *performed < slot);
+        self.answered_until = self.answered_until.max(self.slot_out);
+        self.slot_out = slot;
+        self.perform_decided();
+        self.collect_garbage();
+        Ok(())
+    }
+
+    /// A snapshot of this replica's log for another replica to install,
+    /// along with a copy of its state machine as it is now.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            through: types::Slot(self.slot_out.0 - 1),
+            sessions: self
+                .performed
+                .iter()
+                .map(|(command_id, slot)| Session {
+                    command_id: *command_id,
+                    slot: *slot,
+                    result: self
+                        .results
+                        .peek(command_id)
+                        .map(|cached| cached.result.clone()),
+                })
+                .collect(),
+            config_timeline: self.config_timeline.clone(),
+        }
+    }
+
+    /// Stop performing decisions while the state for a snapshot through
+    /// slot `through` is being restored. Decisions up to `through` that
+    /// arrive meanwhile are dropped, and later ones are held until
+    /// `install_snapshot` or `abort_snapshot_install` is called.
+    pub fn begin_snapshot_install(&mut self, through: types::Slot) -> anyhow::Result<()> {
+        if through < self.slot_out {
+            return Err(anyhow::anyhow!(
+                "cannot install a snapshot through slot {}: performed up to {} already",
+                through,
+                self.slot_out
+            ));
+        }
+        if let Some(installing) = self.installing.filter(|installing| *installing != through) {
+            return Err(anyhow::anyhow!(
+                "already installing a snapshot through slot {}",
+                installing
+            ));
+        }
+        info!(
+            "{}: installing a snapshot through slot {}, performing nothing meanwhile",
+            self.node_id, through
+        );
+        self.installing = Some(through);
+        Ok(())
+    }
+
+    /// Replace the state machine with `state_machine`, restored from the
+    /// state `snapshot` goes with, and carry on from the slot after it.
+    ///
+    /// Decisions up to the snapshot are forgotten, the commands it performed
+    /// are taken as performed here, and own proposals in the slots it covers
+    /// that it did not perform are proposed again. Decisions held for later
+    /// slots are then performed on the new state machine. Starts the install
+    /// if `begin_snapshot_install` was not called.
+    pub fn install_snapshot(
+        &mut self,
+        state_machine: Box<dyn StateMachine<T> + Send>,
+        snapshot: Snapshot,
+    ) -> anyhow::Result<()> {
+        self.begin_snapshot_install(snapshot.through)?;
+        let resume = snapshot.through.next();
+        self.installing = None;
+        self.poisoned = None;
+        self.state_machine = state_machine;
+
+        // Own proposals the snapshot did not perform lost their slots
+        let lost: Vec<types::Command<T>> = self
+            .proposals
+            .range(self.proposals.floor()..resume)
+            .map(|(_, command)| command)
+            .filter(|command| !snapshot.performed(&command.id()))
+            .cloned()
+            .collect();
+        for command in lost {
+            self.requests.push_front(command);
+        }
+        self.proposals.collect_garbage(resume);
+        self.decisions.collect_garbage(resume);
+        self.certificates.collect_garbage(resume);
+        self.proposal_retries.retain(|slot, _| *slot >= resume);
+        self.catch_up.forget_below(resume);
+
+        self.forget_performed_below(resume);
+        for session in snapshot.sessions {
+            self.performed.insert(session.command_id, session.slot);
+            if let Some(result) = session.result {
+                let evicted = self
+                    .results
+                    .insert(session.command_id, session.slot, result);
+                self.record_evictions(evicted);
+            }
+        }
+        let (performed, forgotten) = (&self.performed, &self.forgotten);
+        self.requests.retain(|command| {
+            let id = command.id();
+            !performed.contains_key(&id)
+                && forgotten
+                    .get(&id.client_id)
+                    .is_none_or(|requests| !requests.contains(id.request_id))
+        });
+        self.pending_changed = true;
+
+        // The configurations as of the snapshot, then those decided after it
+        self.config_timeline = snapshot.config_timeline;
+        for (slot, command) in self.decisions.iter() {
+            self.config_timeline.record(*slot, &command.op);
+        }
+        self.config = self.config_timeline.current().clone();
+        self.mailbox.set_epoch(self.config.epoch);
+        self.router.reconfigure(&self.config);
+        self.slot_allocator.reconfigure(&self.config);
+        self.slot_out = resume;
+        self.slot_in = self.slot_in.max(resume);
+        self.apply_config_changes();
+
+        info!(
+            "{}: installed a snapshot through slot {}",
+            self.node_id, snapshot.through
+        );
+        self.perform_decided();
+        self.collect_garbage();
+        self.propose()?;
+        self.store_pending_requests()
+    }
+
+    /// Give up installing a snapshot and perform decisions again, fetching
+    /// those dropped meanwhile.
+    pub fn abort_snapshot_install(&mut self) {
+        if self.installing.take().is_some() {
+            warn!("{}: gave up installing a snapshot", self.node_id);
+            self.perform_decided();
+            self.collect_garbage();
+        }
+    }
+
+    // The next slot to perform once any snapshot being installed is
+    fn next_to_perform(&self) -> types::Slot {
+        self.installing
+            .map_or(self.slot_out, |through| through.next().max(self.slot_out))
+    }
+
+    /// Hold proposals and decisions to a window, forgetting performed slots
+    /// beyond those retained.
+    pub fn set_memory_mode(&mut self, mode: MemoryMode) {
+        self.proposals.set_mode(mode);
+        self.decisions.set_mode(mode);
+        self.certificates.set_mode(mode);
+        self.memory_mode = mode;
+    }
+
+    /// Keep the results of at most `capacity` performed commands for
+    /// answering retried requests.
+    pub fn set_result_cache_capacity(&mut self, capacity: usize) {
+        let evicted = self.results.set_capacity(capacity);
+        self.record_evictions(evicted);
+    }
+
+    /// Keep undecided client requests in `store`, first re-queueing any it already holds.
+    ///
+    /// Call before the replica handles messages, so that requests accepted
+    /// before a crash are proposed again after the restart.
+    pub fn set_request_store(
+        &mut self,
+        mut store: Box<dyn RequestStore<T> + Send>,
+    ) -> anyhow::Result<()> {
+        let recovered = store.load()?;
+        let known: HashSet<types::CommandId> = self
+            .pending_requests()
+            .iter()
+            .map(types::Command::id)
+            .chain(self.performed.keys().cloned())
+            .collect();
+        let mut requeued = 0;
+        for command in recovered {
+            if !known.contains(&command.id()) {
+                self.requests.push(command);
+                requeued += 1;
+            }
+        }
+        if requeued > 0 {
+            info!("{}: recovered {} pending requests", self.node_id, requeued);
+        }
+        self.request_store = Some(store);
+        self.pending_changed = true;
+        self.propose()?;
+        self.store_pending_requests()
+    }
+
+    /// Append every decision to `log` as it is performed, for comparing
+    /// replicas with `persistence::verify::verify_decision_logs`. A log with
+    /// a `sync_interval`, such as a group-committing `FileDecisionLog`, is
+    /// synced that often.
+    pub fn set_decision_log(&mut self, log: Box<dyn DecisionLog<T> + Send>) {
+        self.clock.cancel(&ClockAction::SyncDecisionLog);
+        if let Some(interval) = log.sync_interval() {
+            self.clock.schedule(ClockAction::SyncDecisionLog, interval);
+        }
+        self.decision_log = Some(log);
+    }
+
+    fn sync_decision_log(&mut self) {
+        let Some(log) = self.decision_log.as_mut() else {
+            return;
+        };
+        if let Err(e) = log.sync() {
+            warn!("{}: failed to sync the decision log: {}", self.node_id, e);
+        }
+        if let Some(interval) = log.sync_interval() {
+            self.clock.schedule(ClockAction::SyncDecisionLog, interval);
+        }
+    }
+
+    /// The decisions this replica has performed in `slots`, in slot order.
+    ///
+    /// With a decision log they are streamed from the log, so slots long
+    /// since forgotten can be read without holding them all in memory, e.g.
+    /// to serve a lagging peer, audit the log or feed a change data capture
+    /// consumer. Without one, only the performed decisions still in memory
+    /// are returned.
+    pub fn decisions_iter(
+        &mut self,
+        slots: Range<types::Slot>,
+    ) -> anyhow::Result<Decisions<'_, T>> {
+        if let Some(log) = self.decision_log.as_mut() {
+            return log.range(slots);
+        }
+        let performed = slots.start..slots.end.min(self.slot_out);
+        Ok(Box::new(
+            self.decisions
+                .range(performed)
+                .map(|(slot, command)| Ok((*slot, command.clone()))),
+        ))
+    }
+
+    /// Client commands this replica holds that have not been decided yet:
+    /// outstanding proposals in slot order, then queued requests.
+    pub fn pending_requests(&self) -> Vec<types::Command<T>> {
+        self.proposals
+            .values()
+            .cloned()
+            .chain(self.requests.iter().cloned())
+            .collect()
+    }
+
+    fn store_pending_requests(&mut self) -> anyhow::Result<()> {
+        if !self.pending_changed || self.request_store.is_none() {
+            return Ok(());
+        }
+        let pending = self.pending_requests();
+        if let Some(store) = self.request_store.as_mut() {
+            store.store(&pending)?;
+        }
+        self.pending_changed = false;
+        Ok(())
+    }
+
+    /// Publish configuration changes to `sink`.
+    pub fn set_event_sink(&mut self, sink: Box<dyn EventSink + Send>) {
+        self.events = sink;
+    }
+
+    /// Alert, and fetch missing decisions without waiting for `slot_out` to
+    /// stall, once the replica lags more than `threshold` slots behind the
+    /// highest decision it has seen. Defaults to `LAG_ALERT_SLOTS`.
+    pub fn set_lag_threshold(&mut self, threshold: u64) {
+        self.lag_threshold = threshold;
+    }
+
+    /// Buffer decisions at most `slots` past `slot_out` while waiting on a
+    /// gap. Decisions further ahead are dropped, the gap is fetched, and
+    /// the dropped ones are fetched again once `slot_out` comes within
+    /// reach. Defaults to `MAX_BUFFERED_DECISIONS`.
+    pub fn set_max_buffered_decisions(&mut self, slots: u64) {
+        self.max_buffered_decisions = slots.max(1);
+    }
+
+    /// Refuse decisions, and free slots reported by leaders, more than
+    /// `max_ahead` past `slot_out`, without fetching up to them. Defaults to
+    /// `MAX_SLOTS_AHEAD`.
+    pub fn set_max_slots_ahead(&mut self, max_ahead: u64) {
+        self.slot_bound.set_max_ahead(max_ahead);
+    }
+
+    /// Suspect the active leader, and propose to the other leaders first,
+    /// once a proposal has been sent again `retries` times without being
+    /// decided. Reproposals back off exponentially in between. Defaults to
+    /// `MAX_PROPOSAL_RETRIES`.
+    pub fn set_max_proposal_retries(&mut self, retries: u32) {
+        self.max_proposal_retries = retries.max(1);
+    }
+
+    /// Decisions held for slots not performed yet, waiting on a gap.
+    pub fn buffered_decisions(&self) -> usize {
+        self.decisions.range(self.slot_out..MAX_SLOT.next()).count()
+    }
+
+    /// Slots between the last one performed and the highest seen decided.
+    pub fn lag(&self) -> u64 {
+        self.decisions
+            .last_slot()
+            .max(self.highest_refused)
+            .map_or(0, |decided| decided.next().since(self.slot_out))
+    }
+
+    /// Publish whether the lag crossed the threshold, either way, since the
+    /// last check, and start catching up as soon as it goes over.
+    fn check_lag(&mut self) {
+        let lag = self.lag();
+        if lag > self.lag_threshold && !self.lagging {
+            self.lagging = true;
+            warn!(
+                "{}: {} slots behind the decisions seen, over the threshold of {}",
+                self.node_id, lag, self.lag_threshold
+            );
+            self.events.publish(Event::ReplicaLagging {
+                replica: self.node_id,
+                lag,
+                threshold: self.lag_threshold,
+            });
+            if let Err(e) = self.fetch_missing_decisions() {
+                warn!("{}: failed to fetch missing decisions: {}", self.node_id, e);
+            }
+        } else if lag <= self.lag_threshold && self.lagging {
+            self.lagging = false;
+            info!("{}: caught up to {} slots behind", self.node_id, lag);
+            self.events.publish(Event::ReplicaCaughtUp {
+                replica: self.node_id,
+                lag,
+            });
+        }
+    }
+
+    /// Replace how peers and clients are resolved to addresses.
+    pub fn set_router(&mut self, mut router: Box<dyn Router + Send>) {
+        router.reconfigure(&self.config);
+        if let Some(discovery) = &self.discovery {
+            discovery.route(router.as_mut());
+        }
+        self.router = router;
+    }
+
+    /// Learn peers' addresses by gossip, starting from `seeds`, and gossip
+    /// every `heartbeat_interval`. See `nodes::discovery`.
+    pub fn enable_discovery(&mut self, seeds: Vec<types::Address>) {
+        let discovery = Discovery::new(
+            self.node_id.into(),
+            self.address.clone(),
+            seeds,
+            &self.config,
+        );
+        discovery.route(self.router.as_mut());
+        self.discovery = Some(discovery);
+        self.gossip();
+    }
+
+    /// The addresses discovered so far, in discovery mode.
+    pub fn discovery(&self) -> Option<&Discovery> {
+        self.discovery.as_ref()
+    }
+
+    fn gossip(&mut self) {
+        let Some(discovery) = self.discovery.as_mut() else {
+            return;
+        };
+        for sendable in discovery.gossip(&self.config) {
+            self.mailbox.send(sendable);
+        }
+        let interval = self.config.timeout_config.heartbeat_interval;
+        self.clock.schedule(ClockAction::Gossip, interval);
+    }
+
+    fn gossiped(&mut self, from: &types::Address, gossip: &messages::GossipMessage) {
+        let Some(discovery) = self.discovery.as_mut() else {
+            return;
+        };
+        discovery.receive(from, gossip, self.router.as_mut());
+    }
+
+    /// Replace how this replica picks the slots it proposes into.
+    pub fn set_slot_allocator(&mut self, mut slot_allocator: Box<dyn SlotAllocator + Send>) {
+        slot_allocator.reconfigure(&self.config);
+        self.slot_allocator = slot_allocator;
+    }
+
+    /// Malformed messages received and dropped so far. See `nodes::validate`.
+    pub fn malformed(&self) -> u64 {
+        self.validator.rejected()
+    }
+
+    /// Slots refused so far for being out of range. See `nodes::validate::SlotBound`.
+    pub fn out_of_range(&self) -> u64 {
+        self.slot_bound.refused()
+    }
+
+    pub fn accept_message(&mut self, msg: messages::SendableMessage<T>) {
+        if !self.validator.admit(self.node_id, &msg) {
+            return;
+        }
+        let now = self.clock.now();
+        if let Some(sender) = msg.message.sender() {
+            self.failure_detector.heard_from(sender, now);
+        }
+        if let messages::Message::Gossip(gossip) = &msg.message {
+            self.gossiped(&msg.src, gossip);
+            return;
+        }
+        if let Some(sync) = self
+            .epochs
+            .sync_for(self.node_id.into(), &self.config, &msg, now)
+        {
+            self.mailbox.send(sync);
+        }
+        self.mailbox
+            .receive_as(types::Role::Replica, self.node_id.into(), msg);
     }
 
     pub fn work_on_message(&mut self) -> bool {
@@ -75,6 +951,31 @@ impl Replica {
         let inbox_received = match received_msg.message {
             messages::Message::Request(_msg) => ReplicaMessageIn::Request(_msg),
             messages::Message::Decision(_msg) => ReplicaMessageIn::Decision(_msg),
+            messages::Message::ProposeRejected(_msg) => ReplicaMessageIn::ProposeRejected(_msg),
+            messages::Message::ProposeAccepted(_msg) => ReplicaMessageIn::ProposeAccepted(_msg),
+            messages::Message::DecisionFetch(_msg) => ReplicaMessageIn::DecisionFetch(_msg),
+            messages::Message::DecisionFetchReply(_msg) => {
+                ReplicaMessageIn::DecisionFetchReply(_msg)
+            }
+            messages::Message::Barrier(_msg) => ReplicaMessageIn::Barrier(_msg),
+            // Liveness was already recorded by the failure detector on arrival
+            messages::Message::Heartbeat(heartbeat) => {
+                if let Some(lease) = &heartbeat.lease {
+                    self.leader_leased(&heartbeat, lease);
+                }
+                if let Some(ballot) = heartbeat.ballot {
+                    self.leader_active(ballot);
+                }
+                if let Some(commit_index) = heartbeat.commit_index {
+                    self.leader_commit_index = self.leader_commit_index.max(commit_index);
+                }
+                if heartbeat.quorum_lost {
+                    self.leaders_without_quorum.insert(heartbeat.src);
+                } else {
+                    self.leaders_without_quorum.remove(&heartbeat.src);
+                }
+                return true;
+            }
             _ => {
                 error!(
                     "{}: Replica received unexpected message in mailbox: {:?}",
@@ -106,127 +1007,892 @@ impl Replica {
     // replica removes that command from the set proposals and
     // returns it to set requests so it can be proposed again at a
     // later time. Next, the replica invokes perform().
-    pub fn handle_msg(&mut self, msg: ReplicaMessageIn) -> anyhow::Result<()> {
+    pub fn handle_msg(&mut self, msg: ReplicaMessageIn<T>) -> anyhow::Result<()> {
+        let _span = debug_span!(
+            "replica.handle_msg",
+            paxos.node.role = "replica",
+            paxos.node.id = %self.node_id,
+            paxos.lamport = self.mailbox.lamport(),
+        )
+        .entered();
         match msg {
             ReplicaMessageIn::Request(req) => {
                 debug!("{}: received RequestMessage: {:?}", req.src, req.command);
-                self.requests.push(req.command.clone());
+                // Responses go back to wherever the client's request came from
+                self.router.learn(req.command.client_id, req.src.clone());
+                if self.poisoned.is_some() {
+                    // Local state cannot be trusted until recover() is called
+                    self.send_response(
+                        req.command.id(),
+                        self.slot_out - 1,
+                        messages::ResponseStatus::Unavailable,
+                        Vec::new(),
+                    )?;
+                } else if let Some((size, limit)) = self.oversized(&req.command) {
+                    warn!(
+                        "{}: turning away {}: {} bytes exceeds the limit of {}",
+                        self.node_id,
+                        req.command.id(),
+                        size,
+                        limit
+                    );
+                    self.send_response(
+                        req.command.id(),
+                        self.slot_out - 1,
+                        messages::ResponseStatus::TooLarge {
+                            size: size as u64,
+                            limit: limit as u64,
+                        },
+                        Vec::new(),
+                    )?;
+                } else if self.answer_from_cache(&req)? || self.read_locally(&req)? {
+                    // Answered without consensus
+                } else if let Err(e) = self.validate_membership(&req.command) {
+                    warn!(
+                        "{}: not proposing membership change {}: {}",
+                        self.node_id,
+                        req.command.id(),
+                        e
+                    );
+                } else if let Some(kind) = self.unhandled_kind(&req.command) {
+                    warn!(
+                        "{}: not proposing {}: no handler for command kind {}",
+                        self.node_id,
+                        req.command.id(),
+                        kind
+                    );
+                } else if self.proxy_requests
+                    && req.proxied_by.is_none()
+                    && self.stranded()
+                    && self.proxy(&req)?
+                {
+                    // Passed on to a peer replica, which answers the client
+                } else if self.cluster_unavailable() {
+                    // Queueing would leave the client waiting on a quorum that may not return
+                    debug!(
+                        "{}: no leader has a quorum, turning away {}",
+                        self.node_id,
+                        req.command.id()
+                    );
+                    self.send_response(
+                        req.command.id(),
+                        self.slot_out - 1,
+                        messages::ResponseStatus::Unavailable,
+                        Vec::new(),
+                    )?;
+                } else {
+                    self.requests.push(req.command.clone());
+                    self.pending_changed = true;
+                }
             }
             ReplicaMessageIn::Decision(dec) => {
                 debug!("{}: received DecisionMessage: {:?}", dec.src, dec.command);
-                self.decisions.insert(dec.slot_number, dec.command.clone());
-
-                // Clean up timeout tracking for this slot since we got a decision
-                self.proposal_times.remove(&dec.slot_number);
-
-                while self.decisions.contains_key(&self.slot_out) {
-                    if let Some(_proposal) = self.proposals.get(&self.slot_out) {
-                        // In any case, we will delete the proposal from self.proposals
-                        if let Some(proposal) = self.proposals.remove(&self.slot_out) {
-                            self.requests.push(proposal);
-                        } else {
-                            let _ = self.proposals.remove(&self.slot_out);
-                        }
+                if self.receive_certified(
+                    dec.src.into(),
+                    dec.slot_number,
+                    dec.command,
+                    dec.certificate,
+                ) {
+                    self.passed_over.remove(&dec.src);
+                }
+            }
+            ReplicaMessageIn::ProposeRejected(rejected) => {
+                debug!(
+                    "{}: proposal {} for slot {} rejected: {:?}",
+                    rejected.src, rejected.command_id, rejected.slot_number, rejected.reason
+                );
+                match rejected.reason {
+                    messages::RejectReason::SlotOccupied => {
+                        let free_slot = rejected.free_slot.filter(|free| {
+                            self.slot_bound.admit(
+                                self.node_id,
+                                "ProposeRejected",
+                                *free,
+                                self.slot_out,
+                            )
+                        });
+                        self.slot_allocator
+                            .slot_taken(rejected.slot_number, free_slot);
+                        self.reslot_proposal(rejected.slot_number, rejected.command_id);
+                    }
+                    messages::RejectReason::Duplicate { slot } => {
+                        self.follow_duplicate(rejected.slot_number, slot, rejected.command_id)?;
                     }
-                    // Also clean up timeout tracking as we advance slot_out
-                    self.proposal_times.remove(&self.slot_out);
-                    self.perform(self.slot_out);
+                    // Retried by the repropose timer
+                    messages::RejectReason::NotActive | messages::RejectReason::Throttled => {}
+                }
+            }
+            ReplicaMessageIn::ProposeAccepted(accepted) => {
+                debug!(
+                    "{}: proposal {} for slot {} taken up",
+                    accepted.src, accepted.command_id, accepted.slot_number
+                );
+                let slot = accepted.slot_number;
+                let ours = self
+                    .proposals
+                    .get(&slot)
+                    .is_some_and(|command| command.id() == accepted.command_id);
+                if ours && !self.decisions.contains_key(&slot) {
+                    self.proposal_retries.entry(slot).or_default().accepted_by = Some(accepted.src);
+                }
+            }
+            ReplicaMessageIn::DecisionFetch(fetch) => {
+                debug!("{}: received DecisionFetch: {:?}", fetch.src, fetch.slots);
+                self.reply_to_decision_fetch(fetch)?;
+            }
+            ReplicaMessageIn::DecisionFetchReply(reply) => {
+                debug!(
+                    "{}: received {} fetched decisions",
+                    reply.src,
+                    reply.decisions.len()
+                );
+                let slots: Vec<types::Slot> =
+                    reply.decisions.iter().map(|(slot, _)| *slot).collect();
+                let mut certificates: HashMap<_, _> = reply.certificates.into_iter().collect();
+                for (slot, command) in reply.decisions {
+                    let certificate = certificates.remove(&slot);
+                    self.receive_certified(reply.src.into(), slot, command, certificate);
+                }
+                // A reply handled while the inbox keeps up opens the window
+                // for the next chunks; a backed-up inbox narrows it
+                let backlogged = self.mailbox.inbox.len() >= CATCH_UP_BACKLOG;
+                if self.catch_up.answered(&slots, backlogged) {
+                    self.fetch_missing_decisions()?;
                 }
             }
+            ReplicaMessageIn::Barrier(barrier) => {
+                debug!(
+                    "{}: received Barrier {} for slot {}",
+                    barrier.src, barrier.id, barrier.slot
+                );
+                self.wait_for_commit(barrier);
+            }
         };
+        self.release_barriers();
         self.propose()?;
-        Ok(())
+        self.store_pending_requests()
     }
 
-    // perform() is invoked with the same sequence of commands at
-    // all replicas. First, it checks to see if it has already
-    // performed the command. Different replicas may end up proposing
-    // the same command for different slots, and thus the same
-    // command may be decided multiple times. The corresponding
-    // operation is evaluated only if the command is new and it is
-    // not a reconfiguration request. If so, perform() applies the
-    // requested operation to the application state. In either case,
-    // the function increments slot_out.
-    pub fn perform(&mut self, slot: u64) {
-        if let Some(command) = self.decisions.get(&slot) {
-            for s in 1..self.slot_out {
-                if self.decisions.get(&s) == Some(command) {
-                    self.slot_out += 1;
-                    return;
-                }
-            }
-            if let types::CommandType::Reconfig(_) = &command.op {
-                self.slot_out += 1;
-                return;
-            }
+    /// The highest slot known to be decided along with every slot before it:
+    /// the last one this replica performed, or later if an active leader
+    /// has reported deciding further.
+    pub fn commit_index(&self) -> types::CommitIndex {
+        types::CommitIndex(self.slot_out - 1).max(self.leader_commit_index)
+    }
+
+    /// The slots this replica has performed, all of them decided. Barriers
+    /// are answered with it rather than `commit_index`, so that a session
+    /// moved past it can read from this replica straight away.
+    fn performed_index(&self) -> types::CommitIndex {
+        types::CommitIndex(self.slot_out - 1)
+    }
+
+    /// Answer `barrier` once this replica has performed its slot. With too
+    /// many barriers already waiting, answer straight away with the slots
+    /// performed so far, which the client can tell fall short.
+    fn wait_for_commit(&mut self, barrier: messages::BarrierMessage) {
+        let waiting: usize = self.barriers.values().map(Vec::len).sum();
+        if !self.performed_index().covers(barrier.slot) && waiting < MAX_PENDING_BARRIERS {
+            self.barriers
+                .entry(barrier.slot)
+                .or_default()
+                .push((barrier.src, barrier.id));
+            return;
         }
-        self.slot_out += 1;
+        if !self.performed_index().covers(barrier.slot) {
+            warn!(
+                monotonic_counter.paxos.replica.barriers_turned_away = 1u64,
+                "{}: {} barriers waiting, answering barrier {} for slot {} early",
+                self.node_id,
+                waiting,
+                barrier.id,
+                barrier.slot
+            );
+        }
+        self.send_committed(barrier.src, barrier.id);
     }
 
-    // propose() tries to transfer requests from the set requests
-    // to proposals. It uses slot_in to look for unused slots within
-    // the window of slots with known configurations. For each such
-    // slot, it first checks if the configuration for that slot is
-    // different from the prior slot by checking if the decision in
-    // (slot_in - WINDOW) is a reconfiguration command. If so, the
-    // function updates the configuration for slot s. Then the
-    // function pops a request from requests and adds it as a
-    // proposal for slot_in to the set proposals. Finally, it sends a
-    // Propose message to all leaders in the configuration of
-    // slot_in.
-    pub fn propose(&mut self) -> anyhow::Result<()> {
-        let mut new_proposals = Vec::new(); // Track newly created proposals
+    /// Answer the barriers whose slots have now been performed.
+    fn release_barriers(&mut self) {
+        let waiting = self.barriers.split_off(&self.slot_out);
+        let covered = core::mem::replace(&mut self.barriers, waiting);
+        for (src, id) in covered.into_values().flatten() {
+            self.send_committed(src, id);
+        }
+    }
 
-        while !self.requests.is_empty() && self.slot_in < self.slot_out + WINDOW {
-            if !self.decisions.contains_key(&self.slot_in) {
-                let command = self.requests.remove(0);
-                self.proposals.insert(self.slot_in, command.clone());
-                let leaders: Vec<_> = self.config.leaders.iter().cloned().collect();
-                for ldr in leaders {
-                    self.send_message(ldr, self.slot_in, command.clone())?;
-                }
-                // Track this as a new proposal that needs timeout monitoring
-                new_proposals.push(self.slot_in);
+    fn send_committed(&mut self, client: types::Address, id: u64) {
+        self.mailbox.send(messages::SendableMessage {
+            src: self.address.clone(),
+            dst: client,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::Committed(messages::CommittedMessage {
+                src: self.node_id,
+                id,
+                commit_index: self.performed_index(),
+            }),
+        });
+    }
+
+    /// Answer a retried request with its cached result, returning false if
+    /// the command's result is not cached.
+    fn answer_from_cache(&mut self, req: &messages::RequestMessage<T>) -> anyhow::Result<bool> {
+        let command_id = req.command.id();
+        let Some(cached) = self.results.get(&command_id).cloned() else {
+            return Ok(false);
+        };
+        debug!(
+            monotonic_counter.paxos.replica.result_cache.hits = 1u64,
+            "{}: answering retried {} from the result cache", self.node_id, command_id
+        );
+        self.send_response(
+            command_id,
+            cached.slot,
+            messages::ResponseStatus::Performed,
+            cached.result,
+        )?;
+        Ok(true)
+    }
+
+    /// Check a membership change or delta against the current configuration, so that
+    /// one that cannot apply is not proposed. It is checked again when it
+    /// takes effect.
+    fn validate_membership(&self, command: &types::Command<T>) -> Result<(), MembershipError> {
+        match &command.op {
+            types::CommandType::Membership(change) => {
+                MembershipManager::validate(&self.config, change)
             }
-            self.slot_in += 1;
-            if self.slot_in > WINDOW && self.decisions.contains_key(&(self.slot_in - WINDOW)) {
-                if let types::CommandType::Reconfig(config) =
-                    &self.decisions[&(self.slot_in - WINDOW)].op
-                {
-                    self.config = config.clone();
-                    info!(
-                        "{}: updated config: {:?}",
-                        self.slot_in - WINDOW,
-                        self.decisions[&(self.slot_in - WINDOW)].op
-                    );
-                }
+            types::CommandType::ConfigDelta(delta) => {
+                MembershipManager::merge(&self.config, delta).map(|_| ())
             }
+            _ => Ok(()),
         }
+    }
 
-        // Schedule timeouts for new proposals
-        if !new_proposals.is_empty() {
-            self.schedule_proposal_timeouts(new_proposals)?;
+    /// The kind of an application command no handler is registered for.
+    fn unhandled_kind(&self, command: &types::Command<T>) -> Option<u16> {
+        match &command.op {
+            types::CommandType::App { kind, .. } if !self.command_registry.contains(*kind) => {
+                Some(*kind)
+            }
+            _ => None,
         }
+    }
 
-        Ok(())
+    /// The encoded size of `command` and the limit, if it exceeds the limit.
+    fn oversized(&self, command: &types::Command<T>) -> Option<(usize, usize)> {
+        let limit = self.max_command_size?;
+        let size = encoded_len(command)?;
+        (size > limit).then_some((size, limit))
     }
 
-    /// Schedule timeouts for newly created proposals
-    fn schedule_proposal_timeouts(&mut self, slots: Vec<u64>) -> anyhow::Result<()> {
-        let slots_len = slots.len();
-        for slot in slots {
-            let timeout = self.config.timeout_config.min_timeout;
-            self.proposal_times.insert(slot, timeout);
+    fn record_evictions(&self, evicted: Vec<types::CommandId>) {
+        if !evicted.is_empty() {
+            debug!(
+                monotonic_counter.paxos.replica.result_cache.evictions = evicted.len() as u64,
+                "{}: evicted {} cached results",
+                self.node_id,
+                evicted.len()
+            );
         }
+    }
 
-        // Schedule a general repropose check if not already scheduled
-        // (This is a periodic check, not per-proposal)
-        if self.proposal_times.len() == slots_len {
-            // This was the first batch of proposals, start the periodic check
-            self.schedule_repropose_check()?;
+    /// Answer a read from local state if the request's consistency level
+    /// allows it, returning false if it has to go through consensus.
+    fn read_locally(&mut self, req: &messages::RequestMessage<T>) -> anyhow::Result<bool> {
+        if self.config.timeout_config.lease.is_some() && !self.lease_running() {
+            // Without a leased leader, this replica may be cut off from decisions
+            return Ok(false);
+        }
+        let current = match req.consistency {
+            messages::Consistency::Linearizable => false,
+            // Everything the client has already seen must be reflected
+            messages::Consistency::Sequential { after_slot } => self.slot_out > after_slot,
+            messages::Consistency::Eventual => true,
+        };
+        if !current {
+            return Ok(false);
         }
+        let answer = match &req.command.op {
+            types::CommandType::Op(op) => self.state_machine.query(op),
+            types::CommandType::App { kind, payload } => self
+                .command_registry
+                .get(*kind)
+                .and_then(|handler| handler.query(payload)),
+            _ => None,
+        };
+        let Some(result) = answer else {
+            return Ok(false);
+        };
+        debug!(
+            "{}: answered {} locally at slot {}",
+            self.node_id,
+            req.command.id(),
+            self.slot_out - 1
+        );
+        self.send_response(
+            req.command.id(),
+            self.slot_out - 1,
+            messages::ResponseStatus::Performed,
+            result,
+        )?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Whether every leader still heard from reports that it cannot reach a
+    /// quorum of acceptors, so new commands could not be decided.
+    fn cluster_unavailable(&self) -> bool {
+        let now = self.clock.now();
+        let mut live = self
+            .config
+            .leaders
+            .iter()
+            .map(|l| types::NodeId::from(*l))
+            .filter(|l| !self.failure_detector.is_suspected(l, now))
+            .peekable();
+        live.peek().is_some() && live.all(|l| self.leaders_without_quorum.contains(&l))
+    }
+
+    /// Whether no leader known to be active is heard from, or none reaches
+    /// a quorum, so a request taken up here may wait indefinitely.
+    fn stranded(&self) -> bool {
+        let now = self.clock.now();
+        let active_heard = self
+            .active_ballot
+            .as_ref()
+            .is_some_and(|b| !self.failure_detector.is_suspected(b.leader.as_ref(), now));
+        !active_heard || self.cluster_unavailable()
+    }
+
+    /// Pass `req` on to the next peer replica in turn, preferring those still
+    /// heard from. Returns false if there is no peer to pass it to.
+    fn proxy(&mut self, req: &messages::RequestMessage<T>) -> anyhow::Result<bool> {
+        let now = self.clock.now();
+        let mut peers: Vec<types::NodeId> = self
+            .config
+            .replicas
+            .iter()
+            .filter(|rep| **rep != self.node_id)
+            .map(|rep| *rep.as_ref())
+            .collect();
+        // Sets iterate in no particular order
+        peers.sort();
+        if peers
+            .iter()
+            .any(|peer| !self.failure_detector.is_suspected(peer, now))
+        {
+            peers.retain(|peer| !self.failure_detector.is_suspected(peer, now));
+        }
+        if peers.is_empty() {
+            return Ok(false);
+        }
+        let peer = peers[self.next_proxy % peers.len()];
+        self.next_proxy = self.next_proxy.wrapping_add(1);
+        let Some(dst) = self.router.resolve(&peer) else {
+            return Ok(false);
+        };
+        info!(
+            monotonic_counter.paxos.replica.requests_proxied = 1u64,
+            "{}: no active leader to decide {}, passing it to {}",
+            self.node_id,
+            req.command.id(),
+            peer
+        );
+        let sendable = messages::SendableMessage {
+            src: self.address.clone(),
+            dst,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::Request(messages::RequestMessage {
+                // The client's address, so the peer answers it directly
+                src: req.src.clone(),
+                command: req.command.clone(),
+                consistency: req.consistency,
+                proxied_by: Some(self.node_id),
+            }),
+        };
+        self.mailbox.send(sendable);
+        Ok(true)
+    }
+
+    /// Another command holds `slot` at a leader: return our command to the front
+    /// of requests so propose() moves it to the next free slot.
+    fn reslot_proposal(&mut self, slot: types::Slot, command_id: types::CommandId) {
+        if self.decisions.contains_key(&slot) {
+            return;
+        }
+        if self.proposals.get(&slot).map(|c| c.id()) == Some(command_id) {
+            if let Some(command) = self.proposals.remove(&slot) {
+                self.proposal_retries.remove(&slot);
+                self.requests.push_front(command);
+            }
+        }
+    }
+
+    /// A leader holds our command in `held` already, proposed there by another
+    /// replica: wait for it to be decided in `held` rather than in `slot`.
+    fn follow_duplicate(
+        &mut self,
+        slot: types::Slot,
+        held: types::Slot,
+        command_id: types::CommandId,
+    ) -> anyhow::Result<()> {
+        if self.proposals.get(&slot).map(|c| c.id()) != Some(command_id) {
+            return Ok(());
+        }
+        let decided_there = self.decisions.get(&held).map(types::Command::id) == Some(command_id);
+        if decided_there || self.has_performed(&command_id) {
+            self.proposals.remove(&slot);
+            self.proposal_retries.remove(&slot);
+            self.pending_changed = true;
+            return Ok(());
+        }
+        // We hold something else for that slot, or it is out of reach: keep
+        // proposing where we are and leave it to the repropose timer
+        if held < self.slot_out
+            || self.decisions.contains_key(&held)
+            || self.proposals.contains_key(&held)
+            || self.proposals.check(held).is_err()
+        {
+            return Ok(());
+        }
+        if let Some(command) = self.proposals.remove(&slot) {
+            debug!(
+                "{}: {} is proposed in slot {} already, moving it from slot {}",
+                self.node_id, command_id, held, slot
+            );
+            self.proposal_retries.remove(&slot);
+            self.proposals.insert(held, command)?;
+            self.schedule_proposal_timeouts(vec![held])?;
+        }
+        Ok(())
+    }
+
+    /// Take up the decision of `command` for `slot`, from a leader's
+    /// Decision or a peer's DecisionFetchReply, unless its certificate fails
+    /// to check out. Returns whether it was taken up.
+    fn receive_certified(
+        &mut self,
+        src: types::NodeId,
+        slot: types::Slot,
+        command: types::Command<T>,
+        certificate: Option<messages::DecisionCertificate>,
+    ) -> bool {
+        if let Err(e) = self.check_certificate(slot, certificate.as_ref()) {
+            warn!(
+                monotonic_counter.paxos.replica.decisions_uncertified = 1u64,
+                "{}: ignoring the decision for slot {} from {}: {}", self.node_id, slot, src, e
+            );
+            return false;
+        }
+        self.receive_decision(slot, command);
+        if let Some(certificate) = certificate.filter(|_| self.decisions.contains_key(&slot)) {
+            // Only slots held as decisions are within the window
+            let _ = self.certificates.insert(slot, certificate);
+        }
+        true
+    }
+
+    /// Check `certificate` against the configuration in effect at `slot`.
+    /// One is required if the configuration certifies decisions.
+    fn check_certificate(
+        &self,
+        slot: types::Slot,
+        certificate: Option<&messages::DecisionCertificate>,
+    ) -> anyhow::Result<()> {
+        match certificate {
+            Some(certificate) => certificate.verify(&self.config_timeline.config_at(slot)),
+            None if self.config.certify_decisions => anyhow::bail!("it carries no certificate"),
+            None => Ok(()),
+        }
+    }
+
+    fn receive_decision(&mut self, slot: types::Slot, command: types::Command<T>) {
+        if self.installing.is_some_and(|through| slot <= through) {
+            // The snapshot being installed holds it
+            return;
+        }
+        let next = self.next_to_perform();
+        if !self.slot_bound.admit(self.node_id, "Decision", slot, next) {
+            return;
+        }
+        if slot.since(next) >= self.max_buffered_decisions {
+            // Too far ahead to buffer: fill the gap first, and fetch this
+            // again once it is within reach
+            debug!(
+                monotonic_counter.paxos.replica.decisions_shed = 1u64,
+                "{}: dropped the decision for slot {}, more than {} slots past {}",
+                self.node_id,
+                slot,
+                self.max_buffered_decisions,
+                next
+            );
+            self.highest_refused = self.highest_refused.max(Some(slot));
+            if let Err(e) = self.fetch_missing_decisions() {
+                warn!("{}: failed to fetch missing decisions: {}", self.node_id, e);
+            }
+            self.check_lag();
+            return;
+        }
+        match self.decisions.insert(slot, command) {
+            Ok(_) => {
+                if let Some(decided) = self.decisions.get(&slot) {
+                    self.config_timeline.record(slot, &decided.op);
+                }
+            }
+            // Performed long ago
+            Err(SlotMapError::Collected { .. }) => return,
+            Err(e) => {
+                // Fetched again once the window has moved on
+                debug!("{}: refused decision: {}", self.node_id, e);
+                self.highest_refused = self.highest_refused.max(Some(slot));
+                self.check_lag();
+                return;
+            }
+        }
+
+        // Clean up timeout tracking for this slot since we got a decision
+        self.proposal_retries.remove(&slot);
+        self.perform_decided();
+        self.collect_garbage();
+        // Dropped decisions come back within reach as slot_out moves on
+        if self
+            .highest_refused
+            .is_some_and(|refused| refused >= self.slot_out)
+        {
+            if let Err(e) = self.fetch_missing_decisions() {
+                warn!("{}: failed to fetch missing decisions: {}", self.node_id, e);
+            }
+        }
+        self.check_lag();
+    }
+
+    /// Perform the decisions from slot_out on, for as long as there are no gaps.
+    fn perform_decided(&mut self) {
+        if self.installing.is_some() {
+            return;
+        }
+        while let Some(decided) = self.decisions.get(&self.slot_out) {
+            if self.poisoned.is_some() {
+                break;
+            }
+            let decided_id = decided.id();
+            // In any case, we will delete the proposal from self.proposals,
+            // but it only goes back to requests if a different command won the
+            // slot and it has not been decided in another one
+            if let Some(proposal) = self.proposals.remove(&self.slot_out) {
+                let proposal_id = proposal.id();
+                if proposal_id != decided_id
+                    && !self.decisions.values().any(|c| c.id() == proposal_id)
+                {
+                    self.requests.push(proposal);
+                } else {
+                    self.pending_changed = true;
+                }
+            }
+            // Also clean up timeout tracking as we advance slot_out
+            self.proposal_retries.remove(&self.slot_out);
+            self.log_decision(self.slot_out);
+            self.perform(self.slot_out);
+        }
+    }
+
+    fn log_decision(&mut self, slot: types::Slot) {
+        let (Some(log), Some(command)) = (self.decision_log.as_mut(), self.decisions.get(&slot))
+        else {
+            return;
+        };
+        // The log is for inspection only; performing goes on without it
+        if let Err(e) = log.append(slot, command) {
+            warn!(
+                "{}: failed to log the decision in slot {}: {}",
+                self.node_id, slot, e
+            );
+        }
+    }
+
+    /// Forget the slots performed longer ago than the memory mode retains.
+    fn collect_garbage(&mut self) {
+        let Some(watermark) = self.memory_mode.watermark(self.slot_out) else {
+            return;
+        };
+        if watermark <= self.decisions.floor() {
+            return;
+        }
+        // Configurations are read from the decision WINDOW slots behind slot_in
+        while self.slot_in < watermark + WINDOW {
+            self.advance_slot_in();
+        }
+        self.state_machine.on_snapshot(watermark);
+        self.decisions.collect_garbage(watermark);
+        self.certificates.collect_garbage(watermark);
+        self.proposals.collect_garbage(watermark);
+        self.proposal_retries.retain(|slot, _| *slot >= watermark);
+        self.forget_performed_below(watermark);
+    }
+
+    /// Forget the slots below `watermark` that commands were performed in,
+    /// keeping a window of each client's request ids instead. Clients number
+    /// their requests in order, so a command decided again later is still
+    /// known to be performed unless 64 newer ones of its client were too.
+    fn forget_performed_below(&mut self, watermark: types::Slot) {
+        let forgotten = &mut self.forgotten;
+        self.performed.retain(|command_id, slot| {
+            if *slot >= watermark {
+                return true;
+            }
+            forgotten
+                .entry(command_id.client_id)
+                .or_default()
+                .record(command_id.request_id);
+            false
+        });
+    }
+
+    /// Whether `command_id` was performed here, in a slot still remembered or not.
+    fn has_performed(&self, command_id: &types::CommandId) -> bool {
+        self.performed.contains_key(command_id)
+            || self
+                .forgotten
+                .get(&command_id.client_id)
+                .is_some_and(|requests| requests.contains(command_id.request_id))
+    }
+
+    // perform() is invoked with the same sequence of commands at
+    // all replicas. First, it checks to see if it has already
+    // performed the command. Different replicas may end up proposing
+    // the same command for different slots, and thus the same
+    // command may be decided multiple times. The corresponding
+    // operation is evaluated only if the command is new and it is
+    // not a reconfiguration request. If so, perform() applies the
+    // requested operation to the application state. In either case,
+    // the function increments slot_out.
+    pub fn perform(&mut self, slot: types::Slot) {
+        if let Some(command) = self.decisions.get(&slot) {
+            if self.has_performed(&command.id()) {
+                // Decided again after a retry: answer as the first time
+                let command_id = command.id();
+                let cached = self.results.get(&command_id).cloned();
+                if let Some(cached) = cached.filter(|_| slot >= self.answered_until) {
+                    if let Err(e) = self.send_response(
+                        command_id,
+                        cached.slot,
+                        messages::ResponseStatus::Performed,
+                        cached.result,
+                    ) {
+                        error!(
+                            "{}: failed to respond to {}: {}",
+                            self.node_id, command_id, e
+                        );
+                    }
+                }
+                self.slot_out += 1;
+                return;
+            }
+            self.performed.insert(command.id(), slot);
+            let applied = match &command.op {
+                types::CommandType::Reconfig(_)
+                | types::CommandType::Membership(_)
+                | types::CommandType::ConfigDelta(_) => {
+                    self.slot_out += 1;
+                    return;
+                }
+                types::CommandType::Refused(reason) => {
+                    let status = messages::ResponseStatus::Refused(*reason);
+                    let command_id = command.id();
+                    if slot >= self.answered_until {
+                        if let Err(e) = self.send_response(command_id, slot, status, Vec::new()) {
+                            error!(
+                                "{}: failed to respond to {}: {}",
+                                self.node_id, command_id, e
+                            );
+                        }
+                    }
+                    self.slot_out += 1;
+                    return;
+                }
+                types::CommandType::Op(op) => {
+                    let ctx = ApplyContext { slot, command };
+                    let state_machine = self.state_machine.as_mut();
+                    apply_guarded(|| apply_hooked(state_machine, &ctx, op))
+                }
+                types::CommandType::Batch(ops) => {
+                    let ctx = ApplyContext { slot, command };
+                    let state_machine = self.state_machine.as_mut();
+                    apply_guarded(|| {
+                        let results: Vec<Vec<u8>> = ops
+                            .iter()
+                            .map(|op| apply_hooked(state_machine, &ctx, op))
+                            .collect();
+                        encode_batch_results(&results)
+                    })
+                }
+                types::CommandType::App { kind, payload } => {
+                    match self.command_registry.get_mut(*kind) {
+                        Some(handler) => apply_guarded(|| handler.apply(payload)),
+                        None => {
+                            // Not proposed here, so another replica lacks the same handler
+                            warn!(
+                                "{}: no handler for command kind {} in slot {}",
+                                self.node_id, kind, slot
+                            );
+                            Ok(Vec::new())
+                        }
+                    }
+                }
+            };
+            let result = match applied {
+                Ok(result) => result,
+                Err(reason) => {
+                    error!(
+                        "{}: state machine panicked performing {} in slot {}: {}",
+                        self.node_id,
+                        command.id(),
+                        slot,
+                        reason
+                    );
+                    self.performed.remove(&command.id());
+                    self.poisoned = Some((slot, reason));
+                    return;
+                }
+            };
+            let command_id = command.id();
+            let evicted = self.results.insert(command_id, slot, result.clone());
+            self.record_evictions(evicted);
+            debug!(
+                monotonic_counter.paxos.replica.performed = 1u64,
+                paxos.slot = slot.0,
+                "{}: performed {} in slot {}",
+                self.node_id,
+                command_id,
+                slot
+            );
+            if slot < self.answered_until {
+                // Replayed by recover(): the client already has its answer
+            } else if let Err(e) = self.send_response(
+                command_id,
+                slot,
+                messages::ResponseStatus::Performed,
+                result,
+            ) {
+                error!(
+                    "{}: failed to respond to {}: {}",
+                    self.node_id, command_id, e
+                );
+            }
+        }
+        self.slot_out += 1;
+    }
+
+    // propose() tries to transfer requests from the set requests
+    // to proposals. It uses slot_in to look for unused slots within
+    // the window of slots with known configurations. For each such
+    // slot, it first checks if the configuration for that slot is
+    // different from the prior slot by checking if the decision in
+    // (slot_in - WINDOW) is a reconfiguration command. If so, the
+    // function updates the configuration for slot s. Then the
+    // function pops a request from requests and adds it as a
+    // proposal for slot_in to the set proposals. Finally, it sends a
+    // Propose message to all leaders in the configuration of
+    // slot_in.
+    pub fn propose(&mut self) -> anyhow::Result<()> {
+        let mut new_proposals = Vec::new(); // Track newly created proposals
+
+        while !self.requests.is_empty() && self.slot_in < self.slot_out + WINDOW {
+            if !self.decisions.contains_key(&self.slot_in)
+                && !self.proposals.contains_key(&self.slot_in)
+                && self.slot_allocator.claims(self.slot_in)
+            {
+                let (proposals, limit) = (&self.proposals, self.max_outstanding_per_client);
+                let under_limit = |client: &types::NodeId| {
+                    limit.is_none_or(|limit| {
+                        proposals
+                            .values()
+                            .filter(|c| c.client_id == *client)
+                            .count()
+                            < limit
+                    })
+                };
+                // Every client with requests waiting is at its limit
+                let Some(command) = self.requests.pop_next(under_limit) else {
+                    break;
+                };
+                self.proposals.insert(self.slot_in, command.clone())?;
+                for ldr in self.proposal_targets() {
+                    self.send_message(ldr, self.slot_in, command.clone())?;
+                }
+                // Track this as a new proposal that needs timeout monitoring
+                new_proposals.push(self.slot_in);
+            }
+            self.advance_slot_in();
+        }
+
+        // Schedule timeouts for new proposals
+        if !new_proposals.is_empty() {
+            self.schedule_proposal_timeouts(new_proposals)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move slot_in on, switching to the configuration decided WINDOW slots
+    /// before it if there is one.
+    fn advance_slot_in(&mut self) {
+        self.slot_in += 1;
+        self.apply_config_changes();
+    }
+
+    /// Apply the configuration changes that take effect up to slot_in.
+    fn apply_config_changes(&mut self) {
+        for applied in self.config_timeline.advance(self.slot_in) {
+            let slot = applied.decided;
+            let config = match applied.config {
+                Ok(config) => config,
+                Err(e) => {
+                    // Every replica skips it, having applied the same changes before it
+                    warn!(
+                        "{}: skipping membership change decided in slot {}: {}",
+                        self.node_id, slot, e
+                    );
+                    continue;
+                }
+            };
+            self.router.reconfigure(&config);
+            self.slot_allocator.reconfigure(&config);
+            self.audit(AuditEvent::ReconfigApplied {
+                replica: self.node_id,
+                slot,
+                config: Box::new(config.clone()),
+            });
+            self.events.publish(Event::ReconfigApplied {
+                replica: self.node_id,
+                slot,
+                config: Box::new(config.clone()),
+            });
+            info!("{}: updated config: {:?}", slot, config);
+            self.mailbox.set_epoch(config.epoch);
+            self.config = config;
+        }
+    }
+
+    /// Schedule timeouts for newly created proposals
+    fn schedule_proposal_timeouts(&mut self, slots: Vec<types::Slot>) -> anyhow::Result<()> {
+        let slots_len = slots.len();
+        for slot in slots {
+            self.proposal_retries.insert(slot, ProposalRetry::default());
+        }
+
+        // Schedule a general repropose check if not already scheduled
+        // (This is a periodic check, not per-proposal)
+        if self.proposal_retries.len() == slots_len {
+            // This was the first batch of proposals, start the periodic check
+            self.schedule_repropose_check()?;
+        }
+
+        Ok(())
     }
 
     /// Handle timer events from the clock system
@@ -240,6 +1906,8 @@ impl Replica {
                 // Check if slot_out progress is stuck and try to advance
                 self.check_slot_progress()?;
             }
+            ClockAction::SyncDecisionLog => self.sync_decision_log(),
+            ClockAction::Gossip => self.gossip(),
             _ => {
                 // Ignore action types not relevant to replicas
             }
@@ -247,28 +1915,52 @@ impl Replica {
         Ok(())
     }
 
-    /// Repropose requests for slots that haven't received decisions within timeout
+    /// Repropose requests for slots that haven't received decisions within
+    /// timeout, each backing off exponentially. A slot that has been
+    /// reproposed `max_proposal_retries` times gets the active leader
+    /// suspected and is proposed to the other leaders from then on. A
+    /// proposal a leader acknowledged is reproposed to that leader alone,
+    /// while it is neither suspected nor passed over.
     fn repropose_pending_requests(&mut self) -> anyhow::Result<()> {
         let mut slots_to_repropose = Vec::new();
-
-        // Find slots with proposals but no decisions that have timed out
+        let mut stalled = None;
         for &slot in self.proposals.keys() {
-            if !self.decisions.contains_key(&slot) {
-                // This proposal hasn't received a decision yet
-                slots_to_repropose.push(slot);
+            if self.decisions.contains_key(&slot) {
+                continue;
+            }
+            let retry = self.proposal_retries.entry(slot).or_default();
+            if retry.wait > 0 {
+                retry.wait -= 1;
+                continue;
+            }
+            retry.attempts += 1;
+            retry.wait = (1u32 << retry.attempts.min(31)).min(MAX_REPROPOSE_BACKOFF) - 1;
+            if retry.attempts >= self.max_proposal_retries {
+                stalled = stalled.max(Some((retry.attempts, slot)));
+                *retry = ProposalRetry::default();
             }
+            slots_to_repropose.push((slot, retry.accepted_by));
+        }
+        if let Some((attempts, slot)) = stalled {
+            self.suspect_active_leader(slot, attempts);
         }
 
         // Repropose to leaders (they might have changed or previous messages lost)
-        for slot in slots_to_repropose {
-            if let Some(command) = self.proposals.get(&slot).cloned() {
-                let leaders: Vec<_> = self.config.leaders.iter().cloned().collect();
-                for ldr in leaders {
-                    self.send_message(ldr, slot, command.clone())?;
-                }
-                // Update timeout for this proposal
-                let timeout = self.config.timeout_config.min_timeout;
-                self.proposal_times.insert(slot, timeout);
+        let leaders = self.proposal_targets();
+        let now = self.clock.now();
+        for (slot, accepted_by) in slots_to_repropose {
+            let Some(command) = self.proposals.get(&slot).cloned() else {
+                continue;
+            };
+            let accepted_by = accepted_by.filter(|l| {
+                leaders.contains(l) && !self.failure_detector.is_suspected(l.as_ref(), now)
+            });
+            let targets = match accepted_by {
+                Some(leader) => vec![leader],
+                None => leaders.clone(),
+            };
+            for ldr in targets {
+                self.send_message(ldr, slot, command.clone())?;
             }
         }
 
@@ -277,15 +1969,264 @@ impl Replica {
         Ok(())
     }
 
+    /// Note the lease an active leader says it holds for `lease.duration`
+    /// more, taken to end `max_clock_skew` early.
+    fn leader_leased(&mut self, heartbeat: &messages::HeartbeatMessage, lease: &messages::Lease) {
+        if heartbeat.ballot.as_ref() != Some(&lease.ballot) {
+            return;
+        }
+        let skew = self.config.timeout_config.max_clock_skew;
+        self.lease_until = Some(self.clock.now() + lease.duration.saturating_sub(skew));
+    }
+
+    /// Whether the lease an active leader last said it held is still running.
+    fn lease_running(&self) -> bool {
+        self.lease_until
+            .is_some_and(|until| self.clock.now() < until)
+    }
+
+    /// The leader announcing `ballot` is active. A leader passed over gets
+    /// another chance once it leads under a new ballot.
+    fn leader_active(&mut self, ballot: types::BallotNumber) {
+        if self.active_ballot.as_ref().is_some_and(|b| *b >= ballot) {
+            return;
+        }
+        self.passed_over.remove(&ballot.leader);
+        self.active_ballot = Some(ballot);
+    }
+
+    /// Give up on the active leader deciding our proposals: suspect it and
+    /// propose to the other leaders first until it decides something again.
+    fn suspect_active_leader(&mut self, slot: types::Slot, attempts: u32) {
+        let Some(leader) = self.active_ballot.as_ref().map(|b| b.leader) else {
+            warn!(
+                "{}: slot {} undecided after {} reproposals, with no active leader known",
+                self.node_id, slot, attempts
+            );
+            return;
+        };
+        if !self.passed_over.insert(leader) {
+            return;
+        }
+        warn!(
+            monotonic_counter.paxos.replica.leaders_suspected = 1u64,
+            "{}: slot {} undecided after {} reproposals, suspecting {}",
+            self.node_id,
+            slot,
+            attempts,
+            leader
+        );
+        self.failure_detector.suspect(leader.into());
+        self.events.publish(Event::LeaderSuspected {
+            replica: self.node_id,
+            leader,
+            slot,
+            attempts,
+        });
+    }
+
+    /// The leaders to send proposals to: those not passed over, or every
+    /// leader if all of them have been.
+    fn proposal_targets(&self) -> Vec<types::LeaderId> {
+        let leaders = self
+            .config
+            .leaders
+            .iter()
+            .filter(|l| !self.passed_over.contains(*l))
+            .copied()
+            .collect::<Vec<_>>();
+        if leaders.is_empty() {
+            self.config.leaders.iter().copied().collect()
+        } else {
+            leaders
+        }
+    }
+
     /// Check if slot_out is making progress, and handle stalls
     fn check_slot_progress(&mut self) -> anyhow::Result<()> {
-        // This is a more complex scenario - if slot_out is stuck waiting for a decision
-        // that may never come, we might need to trigger leader election or other recovery
-        // For now, just schedule the next check
+        // If slot_out is stuck waiting for a decision that was lost while later
+        // slots were decided, ask leaders and peer replicas for the gap.
+        let now = self.clock.now();
+        let stall_timeout = self.config.timeout_config.slot_stall_timeout;
+        self.catch_up.forget_below(self.slot_out);
+        let expired = self.catch_up.expire(now, stall_timeout);
+        if expired > 0 {
+            debug!(
+                "{}: {} decision fetches went unanswered, window now {}",
+                self.node_id,
+                expired,
+                self.catch_up.window()
+            );
+        }
+        let lag = self.lag();
+        debug!(
+            histogram.paxos.replica.lag = lag,
+            "{}: {} slots behind", self.node_id, lag
+        );
+        self.check_lag();
+        let (last_slot_out, since) = self.slot_out_progress;
+        if self.slot_out != last_slot_out {
+            self.slot_out_progress = (self.slot_out, now);
+        } else if now.duration_since(since) >= stall_timeout {
+            let missing = self.missing_slots().len();
+            if missing > 0 {
+                info!(
+                    "{}: slot_out {} stalled, fetching {} missing decisions",
+                    self.node_id, self.slot_out, missing
+                );
+                self.fetch_missing_decisions()?;
+            }
+            self.fill_stalled_slots()?;
+        }
         self.schedule_slot_check()?;
         Ok(())
     }
 
+    /// Propose into slot_out and the gaps after it if no replica seems to
+    /// have: the slot allocator may have left them to replicas that had
+    /// nothing to propose.
+    ///
+    /// Pending requests go in first, then copies of later decided commands,
+    /// which perform() skips as duplicates.
+    fn fill_stalled_slots(&mut self) -> anyhow::Result<()> {
+        let mut gaps = self.missing_slots();
+        if !gaps.contains(&self.slot_out) {
+            gaps.insert(0, self.slot_out);
+        }
+        let mut filled = Vec::new();
+        for slot in gaps {
+            if self.decisions.contains_key(&slot) || self.proposals.contains_key(&slot) {
+                continue;
+            }
+            let command = if let Some(command) = self.requests.pop_next(|_| true) {
+                command
+            } else {
+                match self
+                    .decisions
+                    .iter()
+                    .filter(|(decided, _)| **decided > slot)
+                    .min_by_key(|(decided, _)| **decided)
+                {
+                    Some((_, command)) => command.clone(),
+                    // Nothing is waiting on this slot
+                    None => break,
+                }
+            };
+            debug!(
+                "{}: filling stalled slot {} with {}",
+                self.node_id,
+                slot,
+                command.id()
+            );
+            self.proposals.insert(slot, command.clone())?;
+            let leaders: Vec<_> = self.config.leaders.iter().cloned().collect();
+            for ldr in leaders {
+                self.send_message(ldr, slot, command.clone())?;
+            }
+            filled.push(slot);
+        }
+        if !filled.is_empty() {
+            info!("{}: filled {} stalled slots", self.node_id, filled.len());
+            self.schedule_proposal_timeouts(filled)?;
+        }
+        Ok(())
+    }
+
+    /// Slots from slot_out up to the highest decided slot that have no
+    /// decision yet, as far as the window and the decision buffer reach.
+    fn missing_slots(&self) -> Vec<types::Slot> {
+        let max_decided = match self.decisions.last_slot().max(self.highest_refused) {
+            Some(slot) => slot,
+            None => return Vec::new(),
+        };
+        let next = self.next_to_perform();
+        next.up_to(max_decided.next())
+            .take_while(|slot| {
+                self.decisions.check(*slot).is_ok()
+                    && slot.since(next) < self.max_buffered_decisions
+            })
+            .filter(|slot| !self.decisions.contains_key(slot))
+            .collect()
+    }
+
+    /// Fetch as many chunks of the missing decisions as the catch-up window
+    /// allows, each from the next leader or peer replica in turn.
+    fn fetch_missing_decisions(&mut self) -> anyhow::Result<()> {
+        let leaders = self.config.leaders.iter().map(|ldr| *ldr.as_ref());
+        let peers = self
+            .config
+            .replicas
+            .iter()
+            .filter(|rep| **rep != self.node_id)
+            .map(|rep| *rep.as_ref());
+        let mut destinations: Vec<types::NodeId> = leaders.chain(peers).collect();
+        if destinations.is_empty() {
+            return Ok(());
+        }
+        // Sets iterate in no particular order
+        destinations.sort();
+        let missing = self.missing_slots();
+        for slots in self.catch_up.next_chunks(&missing, self.clock.now()) {
+            let node = destinations[self.catch_up.next_peer(destinations.len())];
+            let dst = self
+                .router
+                .resolve(&node)
+                .ok_or(anyhow::anyhow!("Address for {} not found", node))?;
+            let sendable = messages::SendableMessage {
+                src: self.address.clone(),
+                dst,
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: messages::Message::DecisionFetch(messages::DecisionFetchMessage {
+                    src: self.node_id,
+                    slots,
+                }),
+            };
+            self.mailbox.send(sendable);
+        }
+        Ok(())
+    }
+
+    /// Answer a peer's DecisionFetch with whichever of the requested decisions
+    /// we know, at most a chunk of them.
+    fn reply_to_decision_fetch(
+        &mut self,
+        fetch: messages::DecisionFetchMessage,
+    ) -> anyhow::Result<()> {
+        let decisions: Vec<(types::Slot, types::Command<T>)> = fetch
+            .slots
+            .iter()
+            .filter_map(|slot| self.decisions.get(slot).map(|cmd| (*slot, cmd.clone())))
+            .take(CATCH_UP_CHUNK)
+            .collect();
+        if decisions.is_empty() {
+            return Ok(());
+        }
+        let certificates = decisions
+            .iter()
+            .filter_map(|(slot, _)| Some((*slot, self.certificates.get(slot)?.clone())))
+            .collect();
+        let dst = self
+            .router
+            .resolve(fetch.src.as_ref())
+            .ok_or(anyhow::anyhow!("Replica address not found"))?;
+        let sendable = messages::SendableMessage {
+            src: self.address.clone(),
+            dst,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::DecisionFetchReply(messages::DecisionFetchReplyMessage {
+                src: self.node_id,
+                decisions,
+                certificates,
+            }),
+        };
+        self.mailbox.send(sendable);
+        Ok(())
+    }
+
     /// Schedule a repropose check
     fn schedule_repropose_check(&mut self) -> anyhow::Result<()> {
         let timeout = self.config.timeout_config.min_timeout * 2; // Slightly longer interval
@@ -296,7 +2237,7 @@ impl Replica {
 
     /// Schedule a slot progress check
     fn schedule_slot_check(&mut self) -> anyhow::Result<()> {
-        let timeout = self.config.timeout_config.max_timeout; // Longer interval for progress checks
+        let timeout = self.config.timeout_config.slot_stall_timeout;
         self.clock.schedule(ClockAction::CheckSlotWindow, timeout);
         Ok(())
     }
@@ -313,8 +2254,8 @@ impl Replica {
     fn send_message(
         &mut self,
         ldr: types::LeaderId,
-        slot: u64,
-        command: types::Command,
+        slot: types::Slot,
+        command: types::Command<T>,
     ) -> anyhow::Result<()> {
         let msg = messages::ProposeMessage {
             src: self.node_id,
@@ -322,36 +2263,227 @@ impl Replica {
             command: command.clone(),
         };
         let ldr_address = self
-            .config
-            .get_address(ldr.as_ref())
+            .router
+            .resolve(ldr.as_ref())
             .ok_or(anyhow::anyhow!("Leader address not found"))?;
         let sendable = messages::SendableMessage {
             src: self.address.clone(),
-            dst: ldr_address.clone(),
+            dst: ldr_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
             message: messages::Message::Propose(msg),
         };
         self.mailbox.send(sendable);
         Ok(())
     }
 
+    fn send_response(
+        &mut self,
+        command_id: types::CommandId,
+        slot: types::Slot,
+        status: messages::ResponseStatus,
+        result: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let client_address = self
+            .router
+            .resolve(&command_id.client_id)
+            .ok_or(anyhow::anyhow!("Client address not found"))?;
+        let sendable = messages::SendableMessage {
+            src: self.address.clone(),
+            dst: client_address,
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: messages::Message::Response(messages::ResponseMessage {
+                src: self.node_id,
+                command_id,
+                result,
+                slot,
+                status,
+            }),
+        };
+        self.mailbox.send(sendable);
+        Ok(())
+    }
+
+    /// Take the events recorded for the audit journal since the last call.
+    pub fn drain_audit_events(&mut self) -> Vec<AuditEvent> {
+        self.drain_stamped_audit_events()
+            .into_iter()
+            .map(|stamped| stamped.event)
+            .collect()
+    }
+
+    /// Take the events recorded for the audit journal since the last call,
+    /// each with the Lamport time it was recorded at.
+    pub fn drain_stamped_audit_events(&mut self) -> Vec<Stamped> {
+        core::mem::take(&mut self.audit_events)
+    }
+
+    fn audit(&mut self, event: AuditEvent) {
+        let lamport = self.mailbox.tick();
+        self.audit_events.push(Stamped { lamport, event });
+    }
+
     /// Helper to drain the outbox
     pub fn drain_outbox(&mut self) {
         self.mailbox.clear_outbox();
     }
 }
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::messages::*;
-    use crate::nodes::mailbox::Mailbox;
-    use crate::types::*;
-    use std::collections::{BTreeMap, HashSet};
+/// The size of `command` as `JsonCodec` puts it on the wire.
+#[cfg(feature = "std")]
+fn encoded_len<T: types::Payload>(command: &types::Command<T>) -> Option<usize> {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
 
-    fn setup() -> Replica {
-        let mailbox = Mailbox::new();
-        let rep = ReplicaId::new(1);
-        let accept = AcceptorId::new(1);
-        let lead = LeaderId::new(1);
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, command).ok()?;
+    Some(counter.0)
+}
+
+#[cfg(not(feature = "std"))]
+fn encoded_len<T>(_command: &types::Command<T>) -> Option<usize> {
+    None
+}
+
+/// Run `apply`, turning a panic into an error carrying its message.
+#[cfg(feature = "std")]
+fn apply_guarded(apply: impl FnOnce() -> Vec<u8>) -> Result<Vec<u8>, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(apply)).map_err(|panic| {
+        panic
+            .downcast_ref::<&str>()
+            .map(|reason| String::from(*reason))
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into())
+    })
+}
+
+/// Without `std` a panic cannot be caught, so it takes the replica down.
+#[cfg(not(feature = "std"))]
+fn apply_guarded(apply: impl FnOnce() -> Vec<u8>) -> Result<Vec<u8>, String> {
+    Ok(apply())
+}
+
+/// Apply `op` with the state machine's hooks around it.
+fn apply_hooked<T>(
+    state_machine: &mut (dyn StateMachine<T> + Send),
+    ctx: &ApplyContext<'_, T>,
+    op: &T,
+) -> Vec<u8> {
+    state_machine.on_before_apply(ctx);
+    let result = state_machine.apply(op);
+    state_machine.on_after_apply(ctx, &result);
+    result
+}
+
+impl<T: types::Payload> Node<T> for Replica<T> {
+    fn accept_message(&mut self, msg: messages::SendableMessage<T>) {
+        Replica::accept_message(self, msg)
+    }
+
+    fn work_on_message(&mut self) -> bool {
+        Replica::work_on_message(self)
+    }
+
+    fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>> {
+        Replica::check_timers(self)
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.clock.next_timeout()
+    }
+
+    fn deliver_sent(&mut self) -> Option<messages::SendableMessage<T>> {
+        self.mailbox.deliver_sent()
+    }
+
+    fn pending(&self) -> usize {
+        self.mailbox.inbox.len()
+    }
+
+    fn health(&self) -> Health {
+        let now = self.clock.now();
+        let mut not_ready: Vec<String> = Vec::new();
+        let mut degraded: Vec<String> = Vec::new();
+        if let Some((slot, reason)) = &self.poisoned {
+            not_ready.push(alloc::format!(
+                "state machine panicked in slot {}: {}",
+                slot,
+                reason
+            ));
+        }
+        let leaders = self.config.leaders.iter().map(|l| (*l).into());
+        if self.failure_detector.reachable_count(leaders, now) == 0 {
+            not_ready.push("no leader reachable".into());
+        } else if self.cluster_unavailable() {
+            degraded.push("no leader reaches a quorum of acceptors".into());
+        }
+        if self.mailbox.inbox.len() > INBOX_BACKPRESSURE {
+            degraded.push(alloc::format!(
+                "inbox backed up: {} messages",
+                self.mailbox.inbox.len()
+            ));
+        }
+        if self.lag() > self.lag_threshold {
+            degraded.push(alloc::format!(
+                "{} slots behind the decisions seen",
+                self.lag()
+            ));
+        }
+        Health::from_checks(not_ready, degraded)
+    }
+
+    fn set_timeouts(&mut self, timeouts: types::TimeoutConfig) {
+        self.failure_detector
+            .set_suspect_after(timeouts.suspect_timeout);
+        self.config.timeout_config = timeouts;
+    }
+
+    fn link_down(&mut self, address: &types::Address) {
+        self.failure_detector.suspect_address(&self.config, address);
+    }
+
+    fn link_negotiated(&mut self, address: &types::Address, format: WireFormat) {
+        self.router.set_wire_format(address, format);
+    }
+
+    fn wire_format(&self, address: &types::Address) -> Option<WireFormat> {
+        self.router.wire_format(address)
+    }
+
+    fn progress(&self) -> Progress {
+        Progress {
+            frontier: Some(self.slot_out),
+            committed: Some(self.commit_index().0),
+            ..Progress::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::messages::*;
+    use crate::nodes::mailbox::Mailbox;
+    use crate::types::*;
+    use alloc::string::{String, ToString};
+    use serde::{Deserialize, Serialize};
+
+    fn setup() -> Replica {
+        let mailbox = Mailbox::new();
+        let rep = ReplicaId::new(1);
+        let accept = AcceptorId::new(1);
+        let lead = LeaderId::new(1);
 
         let config = Config::new(
             HashSet::from([rep]),
@@ -375,13 +2507,15 @@ mod tests {
 
         // Inject request
         let command = Command {
-            client_id: replica.node_id.as_ref().clone(),
+            client_id: *replica.node_id.as_ref(),
             request_id: 1,
             op: CommandType::Op(vec![1, 2, 3]),
         };
         let req_msg = RequestMessage {
             src: replica.address.clone(),
             command: command.clone(),
+            consistency: Consistency::Linearizable,
+            proxied_by: None,
         };
         replica
             .handle_msg(ReplicaMessageIn::Request(req_msg))
@@ -405,13 +2539,15 @@ mod tests {
 
         // Inject a request to trigger proposal
         let command = Command {
-            client_id: replica.node_id.as_ref().clone(),
+            client_id: *replica.node_id.as_ref(),
             request_id: 1,
             op: CommandType::Op(vec![1, 2, 3]),
         };
         let req_msg = RequestMessage {
             src: replica.address.clone(),
             command: command.clone(),
+            consistency: Consistency::Linearizable,
+            proxied_by: None,
         };
         replica
             .handle_msg(ReplicaMessageIn::Request(req_msg))
@@ -419,7 +2555,7 @@ mod tests {
 
         // Should have created timeout tracking for the proposal
         assert!(
-            !replica.proposal_times.is_empty(),
+            !replica.proposal_retries.is_empty(),
             "Should have timeout tracking for proposals"
         );
 
@@ -427,8 +2563,8 @@ mod tests {
         assert_eq!(replica.proposals.len(), 1);
 
         // The proposal should be for slot 1 (slot_in starts at 1)
-        assert!(replica.proposals.contains_key(&1));
-        assert!(replica.proposal_times.contains_key(&1));
+        assert!(replica.proposals.contains_key(&Slot(1)));
+        assert!(replica.proposal_retries.contains_key(&Slot(1)));
     }
 
     #[test]
@@ -437,26 +2573,29 @@ mod tests {
 
         // Create a proposal first
         let command = Command {
-            client_id: replica.node_id.as_ref().clone(),
+            client_id: *replica.node_id.as_ref(),
             request_id: 1,
             op: CommandType::Op(vec![1, 2, 3]),
         };
         let req_msg = RequestMessage {
             src: replica.address.clone(),
             command: command.clone(),
+            consistency: Consistency::Linearizable,
+            proxied_by: None,
         };
         replica
             .handle_msg(ReplicaMessageIn::Request(req_msg))
             .unwrap();
 
         // Verify timeout tracking exists
-        assert!(replica.proposal_times.contains_key(&1));
+        assert!(replica.proposal_retries.contains_key(&Slot(1)));
 
         // Now send a decision for that slot
         let decision_msg = DecisionMessage {
             src: LeaderId::new(1), // Decision comes from a leader
-            slot_number: 1,
+            slot_number: Slot(1),
             command: command.clone(),
+            certificate: None,
         };
         replica
             .handle_msg(ReplicaMessageIn::Decision(decision_msg))
@@ -464,7 +2603,7 @@ mod tests {
 
         // Timeout tracking should be cleaned up
         assert!(
-            !replica.proposal_times.contains_key(&1),
+            !replica.proposal_retries.contains_key(&Slot(1)),
             "Timeout tracking should be cleaned up after decision"
         );
     }
@@ -474,15 +2613,20 @@ mod tests {
         let mut replica = setup();
 
         // Create a proposal that hasn't received a decision
-        replica.proposals.insert(
-            1,
-            Command {
-                client_id: replica.node_id.as_ref().clone(),
-                request_id: 1,
-                op: CommandType::Op(vec![1, 2, 3]),
-            },
-        );
-        replica.proposal_times.insert(1, Duration::from_millis(100));
+        replica
+            .proposals
+            .insert(
+                Slot(1),
+                Command {
+                    client_id: *replica.node_id.as_ref(),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1, 2, 3]),
+                },
+            )
+            .unwrap();
+        replica
+            .proposal_retries
+            .insert(Slot(1), ProposalRetry::default());
 
         // Clear outbox to test reproposing
         replica.mailbox.clear_outbox();
@@ -493,14 +2637,2230 @@ mod tests {
             .unwrap();
 
         // Should have sent new Propose messages to all leaders
-        let propose_messages: Vec<_> = replica
+        // Should send to all leaders in config (we have 1 leader in setup)
+        assert_eq!(
+            replica.mailbox.count_outbox_of::<ProposeMessage>(),
+            replica.config.leaders.len()
+        );
+    }
+
+    #[test]
+    fn replica_proposes_typed_payload() {
+        #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+        enum KvOp {
+            Put(String, u64),
+        }
+
+        let rep = ReplicaId::new(1);
+        let lead = LeaderId::new(1);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8080)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica<KvOp> = Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(KvOp::Put("x".to_string(), 1)),
+        };
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: replica.address.clone(),
+                command: command.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+
+        assert!(replica.mailbox.outbox.iter().any(|msg| matches!(
+            &msg.message,
+            Message::Propose(p) if p.command == command
+        )));
+    }
+
+    #[test]
+    fn replica_performs_duplicate_decisions_once() {
+        let mut replica = setup();
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1, 2, 3]),
+        };
+
+        // The same command decided for two slots, e.g. proposed by two replicas
+        for slot in [1, 2] {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command.clone(),
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+
+        assert_eq!(replica.slot_out, Slot(3));
+        assert_eq!(replica.performed.len(), 1);
+    }
+
+    #[test]
+    fn replica_answers_retries_from_the_result_cache() {
+        let mut replica = setup();
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        let request = || {
+            ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command: command.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            })
+        };
+        let responses = |replica: &mut Replica| -> Vec<Slot> {
+            let slots = replica
+                .mailbox
+                .outbox
+                .iter()
+                .filter_map(|msg| match &msg.message {
+                    Message::Response(r) => Some(r.slot),
+                    _ => None,
+                })
+                .collect();
+            replica.mailbox.clear_outbox();
+            slots
+        };
+        let proposed = |replica: &Replica| {
+            replica
+                .mailbox
+                .outbox
+                .iter()
+                .any(|msg| matches!(msg.message, Message::Propose(_)))
+        };
+
+        replica.handle_msg(request()).unwrap();
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: command.clone(),
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(responses(&mut replica), vec![Slot(1)]);
+
+        // The retry is answered straight away, from the slot it was performed in
+        replica.handle_msg(request()).unwrap();
+        assert!(!proposed(&replica));
+        assert_eq!(responses(&mut replica), vec![Slot(1)]);
+
+        // Another replica proposed the retry too, and it was decided again
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(2),
+                command: command.clone(),
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(responses(&mut replica), vec![Slot(1)]);
+
+        // Once evicted, a retry has to go through consensus again
+        replica.set_result_cache_capacity(0);
+        replica.handle_msg(request()).unwrap();
+        assert!(proposed(&replica));
+        assert!(responses(&mut replica).is_empty());
+        assert_eq!(replica.performed.len(), 1);
+    }
+
+    #[test]
+    fn replica_applies_membership_changes_at_the_window_boundary() {
+        let mut replica = setup();
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let new_leader = Address::new("127.0.0.1".to_string(), 8083);
+        let membership = |request_id: u64, change: MembershipChange| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Membership(change),
+        };
+        let request = |command: Command| {
+            ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command,
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            })
+        };
+
+        // Removing the only leader is refused before it is proposed
+        replica
+            .handle_msg(request(membership(
+                1,
+                MembershipChange::RemoveLeader {
+                    id: LeaderId::new(1),
+                },
+            )))
+            .unwrap();
+        assert!(replica.proposals.is_empty());
+
+        let add = membership(
+            2,
+            MembershipChange::AddLeader {
+                id: LeaderId::new(2),
+                address: new_leader.clone(),
+            },
+        );
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: add,
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(replica.config.leaders.len(), 1);
+
+        replica.mailbox.clear_outbox();
+        for request_id in 3..3 + WINDOW {
+            replica
+                .handle_msg(request(Command {
+                    client_id: NodeId::new(9),
+                    request_id,
+                    op: CommandType::Op(vec![]),
+                }))
+                .unwrap();
+        }
+        // Slots up to WINDOW go to the old leaders, and the slot the change
+        // takes effect in goes to the new one too
+        assert!(replica.config.leaders.contains(&LeaderId::new(2)));
+        let proposed_to_new: Vec<Slot> = replica
             .mailbox
             .outbox
             .iter()
-            .filter(|msg| matches!(msg.message, Message::Propose(_)))
+            .filter_map(|msg| match &msg.message {
+                Message::Propose(p) if msg.dst == new_leader => Some(p.slot_number),
+                _ => None,
+            })
             .collect();
+        assert_eq!(proposed_to_new, vec![Slot(1) + WINDOW]);
+    }
 
-        // Should send to all leaders in config (we have 1 leader in setup)
-        assert_eq!(propose_messages.len(), replica.config.leaders.len());
+    #[test]
+    fn replica_applies_a_reconfig_decided_out_of_order_at_its_slot() {
+        let mut replica = setup();
+        let new_leader = Address::new("127.0.0.1".to_string(), 8083);
+        let decide = |replica: &mut Replica, slot_number: u64, op: CommandType| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot_number),
+                    command: Command {
+                        client_id: NodeId::new(8),
+                        request_id: slot_number,
+                        op,
+                    },
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+
+        // Slot 3 is decided before the slots ahead of it
+        decide(
+            &mut replica,
+            3,
+            CommandType::Membership(MembershipChange::AddLeader {
+                id: LeaderId::new(2),
+                address: new_leader.clone(),
+            }),
+        );
+        assert_eq!(
+            replica.config_timeline.scheduled().collect::<Vec<_>>(),
+            vec![Slot(3) + WINDOW]
+        );
+        decide(&mut replica, 2, CommandType::Op(vec![]));
+        decide(&mut replica, 1, CommandType::Op(vec![]));
+        assert_eq!(replica.slot_out, Slot(4));
+        assert_eq!(replica.config.leaders.len(), 1);
+
+        replica.mailbox.clear_outbox();
+        for request_id in 1..=WINDOW {
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: Address::new("127.0.0.1".to_string(), 9000),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id,
+                        op: CommandType::Op(vec![]),
+                    },
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }))
+                .unwrap();
+        }
+        let proposed_to_new: Vec<Slot> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Propose(p) if msg.dst == new_leader => Some(p.slot_number),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(proposed_to_new, vec![Slot(3) + WINDOW]);
+        assert_eq!(replica.config.leaders.len(), 2);
+    }
+
+    #[test]
+    fn replica_dispatches_app_commands_to_the_handler_for_their_kind() {
+        use crate::state_machine::CommandHandler;
+
+        struct Counter(u8);
+        impl CommandHandler for Counter {
+            fn apply(&mut self, payload: &[u8]) -> Vec<u8> {
+                self.0 += payload.len() as u8;
+                vec![self.0]
+            }
+            fn query(&self, _payload: &[u8]) -> Option<Vec<u8>> {
+                Some(vec![self.0])
+            }
+        }
+
+        let mut replica = setup();
+        let reverse = |payload: &[u8]| payload.iter().rev().copied().collect();
+        replica.register_command_kind(1, Box::new(reverse)).unwrap();
+        replica
+            .register_command_kind(2, Box::new(Counter(0)))
+            .unwrap();
+        assert!(replica
+            .register_command_kind(2, Box::new(Counter(0)))
+            .is_err());
+
+        let app = |request_id: u64, kind: u16, payload: &[u8]| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::App {
+                kind,
+                payload: bytes::Bytes::copy_from_slice(payload),
+            },
+        };
+        let results = |replica: &mut Replica| -> Vec<Vec<u8>> {
+            let results = replica
+                .mailbox
+                .outbox
+                .iter()
+                .filter_map(|msg| match &msg.message {
+                    Message::Response(r) => Some(r.result.clone()),
+                    _ => None,
+                })
+                .collect();
+            replica.mailbox.clear_outbox();
+            results
+        };
+        // Kind 3 was registered by another replica, but not this one
+        for (slot, command) in [
+            (1, app(1, 1, &[1, 2, 3])),
+            (2, app(2, 2, &[7, 7])),
+            (3, app(3, 3, &[1])),
+        ] {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command,
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+        let performed: Vec<Vec<u8>> = (1..=3)
+            .map(|request_id| {
+                let id = CommandId {
+                    client_id: NodeId::new(9),
+                    request_id,
+                };
+                replica.results.get(&id).unwrap().result.clone()
+            })
+            .collect();
+        assert_eq!(performed, vec![vec![3, 2, 1], vec![2], vec![]]);
+        assert_eq!(replica.slot_out, Slot(4));
+
+        let request = |command: Command, consistency: Consistency| {
+            ReplicaMessageIn::Request(RequestMessage {
+                src: Address::new("127.0.0.1".to_string(), 9000),
+                command,
+                consistency,
+                proxied_by: None,
+            })
+        };
+        // Reads of a kind are answered by its handler
+        replica
+            .handle_msg(request(app(4, 2, &[]), Consistency::Eventual))
+            .unwrap();
+        assert_eq!(results(&mut replica), vec![vec![2]]);
+
+        // A kind with no handler here is not proposed
+        replica
+            .handle_msg(request(app(5, 3, &[1]), Consistency::Linearizable))
+            .unwrap();
+        assert!(replica.proposals.is_empty());
+        replica
+            .handle_msg(request(app(6, 1, &[1]), Consistency::Linearizable))
+            .unwrap();
+        assert_eq!(replica.proposals.len(), 1);
+    }
+
+    #[test]
+    fn replica_applies_decisions_and_responds_to_the_client() {
+        use crate::state_machine::{KvCommand, KvStore};
+
+        let mailbox = Mailbox::new();
+        let rep = ReplicaId::new(1);
+        let lead = LeaderId::new(1);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8080)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica<KvCommand> = Replica::new(rep, config, mailbox, clock).unwrap();
+        replica.set_state_machine(Box::new(KvStore::new()));
+
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let ops = [
+            KvCommand::Put {
+                key: "k".to_string(),
+                value: vec![7],
+            },
+            KvCommand::Get {
+                key: "k".to_string(),
+            },
+        ];
+        for (i, op) in ops.into_iter().enumerate() {
+            let command = Command {
+                client_id: NodeId::new(9),
+                request_id: i as u64,
+                op: CommandType::Op(op),
+            };
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: client.clone(),
+                    command: command.clone(),
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }))
+                .unwrap();
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: lead,
+                    slot_number: Slot(i as u64 + 1),
+                    command,
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+
+        let responses: Vec<_> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(r) if msg.dst == client => Some(r.result.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(responses, vec![vec![], vec![7]]);
+
+        // A batch is performed in one slot and answered with each op's result
+        let batch = Command {
+            client_id: NodeId::new(9),
+            request_id: 2,
+            op: CommandType::Batch(vec![
+                KvCommand::Delete {
+                    key: "k".to_string(),
+                },
+                KvCommand::Get {
+                    key: "k".to_string(),
+                },
+            ]),
+        };
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: lead,
+                slot_number: Slot(3),
+                command: batch,
+                certificate: None,
+            }))
+            .unwrap();
+        let Some(Message::Response(answer)) = replica.mailbox.outbox.back().map(|m| &m.message)
+        else {
+            panic!("expected a response to the batch");
+        };
+        assert_eq!(answer.slot, Slot(3));
+        assert_eq!(
+            crate::state_machine::decode_batch_results(&answer.result),
+            Some(vec![vec![7], vec![]])
+        );
+    }
+
+    #[test]
+    fn replica_calls_state_machine_hooks_around_each_slot() {
+        // Records what it is told, in order
+        struct Hooks(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+        impl StateMachine for Hooks {
+            fn apply(&mut self, op: &Vec<u8>) -> Vec<u8> {
+                self.0.lock().unwrap().push(format!("apply {:?}", op));
+                op.clone()
+            }
+            fn on_before_apply(&mut self, ctx: &ApplyContext<'_, Vec<u8>>) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("before {} {}", ctx.slot, ctx.command.request_id));
+            }
+            fn on_after_apply(&mut self, ctx: &ApplyContext<'_, Vec<u8>>, result: &[u8]) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("after {} {:?}", ctx.slot, result));
+            }
+            fn on_snapshot(&mut self, slot: types::Slot) {
+                self.0.lock().unwrap().push(format!("snapshot {}", slot));
+            }
+        }
+
+        let mut replica = setup();
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        replica.set_state_machine(Box::new(Hooks(events.clone())));
+        replica.set_memory_mode(MemoryMode::SlotBounded { retained: WINDOW });
+        let decide = |replica: &mut Replica, slot: u64, op: CommandType<Vec<u8>>| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id: slot,
+                        op,
+                    },
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+
+        decide(&mut replica, 1, CommandType::Op(vec![1]));
+        // Reconfigurations are not applied, so they get no hooks either
+        let config = replica.config.clone();
+        decide(&mut replica, 2, CommandType::Reconfig(config));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["before 1 1", "apply [1]", "after 1 [1]"]
+        );
+
+        // Decisions performed longer ago than retained are snapshotted, then forgotten
+        for slot in 3..=WINDOW + 2 {
+            decide(&mut replica, slot, CommandType::Op(vec![]));
+        }
+        assert_eq!(
+            events.lock().unwrap().last().map(String::as_str),
+            Some("snapshot 3")
+        );
+        assert_eq!(replica.decisions.floor(), Slot(3));
+    }
+
+    #[test]
+    fn replica_skips_a_command_decided_again_after_its_slot_is_forgotten() {
+        // Records the operations it applies
+        struct Log(std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>);
+        impl StateMachine for Log {
+            fn apply(&mut self, op: &Vec<u8>) -> Vec<u8> {
+                self.0.lock().unwrap().push(op.clone());
+                op.clone()
+            }
+        }
+
+        let mut replica = setup();
+        let applied = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        replica.set_state_machine(Box::new(Log(applied.clone())));
+        replica.set_memory_mode(MemoryMode::SlotBounded { retained: WINDOW });
+        let decide = |replica: &mut Replica, slot: u64, client: u64, request_id: u64| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: Command {
+                        client_id: NodeId::new(client),
+                        request_id,
+                        op: CommandType::Op(vec![client as u8, request_id as u8]),
+                    },
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+
+        decide(&mut replica, 1, 9, 1);
+        for slot in 2..=WINDOW + 2 {
+            decide(&mut replica, slot, 8, slot);
+        }
+        assert_eq!(replica.decisions.floor(), Slot(3));
+        // Slot 1 is forgotten along with the command performed in it
+        assert!(!replica.performed.contains_key(&CommandId {
+            client_id: NodeId::new(9),
+            request_id: 1,
+        }));
+
+        // Proposed again by another replica and decided past the watermark
+        decide(&mut replica, WINDOW + 3, 9, 1);
+        decide(&mut replica, WINDOW + 4, 9, 2);
+        // An earlier request of the same client decided only now is new
+        decide(&mut replica, WINDOW + 5, 9, 0);
+        assert_eq!(replica.slot_out, Slot(WINDOW + 6));
+        let applied: Vec<Vec<u8>> = applied
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|op| op[0] == 9)
+            .cloned()
+            .collect();
+        assert_eq!(applied, vec![vec![9, 1], vec![9, 2], vec![9, 0]]);
+    }
+
+    #[test]
+    fn replica_installs_a_snapshot_while_decisions_arrive() {
+        // Records the operations it applies
+        struct Log(std::sync::Arc<std::sync::Mutex<Vec<Vec<u8>>>>);
+        impl StateMachine for Log {
+            fn apply(&mut self, op: &Vec<u8>) -> Vec<u8> {
+                self.0.lock().unwrap().push(op.clone());
+                op.clone()
+            }
+        }
+
+        let mut replica = setup();
+        let before = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let after = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        replica.set_state_machine(Box::new(Log(before.clone())));
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        replica.router.learn(NodeId::new(9), client.clone());
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+        let decide = |replica: &mut Replica, slot: u64, request_id: u64| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(request_id),
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+
+        decide(&mut replica, 1, 1);
+        // Proposed here: 20 ends up performed by the snapshot, 30 does not,
+        // and 40 is still waiting to be proposed
+        replica.proposals.insert(Slot(2), command(20)).unwrap();
+        replica.proposals.insert(Slot(3), command(30)).unwrap();
+        replica.requests.push(command(40));
+
+        assert!(replica.begin_snapshot_install(Slot(0)).is_err());
+        replica.begin_snapshot_install(Slot(4)).unwrap();
+        // Covered by the snapshot, or held until it is installed
+        decide(&mut replica, 3, 3);
+        decide(&mut replica, 6, 6);
+        decide(&mut replica, 5, 5);
+        assert_eq!(replica.slot_out, Slot(2));
+        assert!(!replica.decisions.contains_key(&Slot(3)));
+        assert!(replica.begin_snapshot_install(Slot(5)).is_err());
+
+        let snapshot = Snapshot {
+            through: Slot(4),
+            sessions: vec![
+                Session {
+                    command_id: command(20).id(),
+                    slot: Slot(2),
+                    result: Some(vec![20]),
+                },
+                Session {
+                    command_id: command(40).id(),
+                    slot: Slot(4),
+                    result: None,
+                },
+            ],
+            config_timeline: ConfigTimeline::new(replica.config.clone()),
+        };
+        replica
+            .install_snapshot(Box::new(Log(after.clone())), snapshot)
+            .unwrap();
+        assert_eq!(*before.lock().unwrap(), vec![vec![1]]);
+        assert_eq!(*after.lock().unwrap(), vec![vec![5], vec![6]]);
+        assert_eq!(replica.slot_out, Slot(7));
+        assert_eq!(replica.decisions.floor(), Slot(5));
+        // Only the command the snapshot did not perform is proposed again
+        let proposed: Vec<u64> = replica
+            .proposals
+            .values()
+            .map(|command| command.request_id)
+            .collect();
+        assert_eq!(proposed, vec![30]);
+        assert!(replica.requests.is_empty());
+
+        // A late decision for a slot the snapshot covers changes nothing
+        decide(&mut replica, 4, 4);
+        assert_eq!(replica.slot_out, Slot(7));
+        assert_eq!(after.lock().unwrap().len(), 2);
+
+        // A retry of a command the snapshot performed is answered from it
+        replica.drain_outbox();
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command: command(20),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        let answered: Vec<(Slot, Vec<u8>)> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(response) if msg.dst == client => {
+                    Some((response.slot, response.result.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(answered, vec![(Slot(2), vec![20])]);
+    }
+
+    #[test]
+    fn replica_is_poisoned_by_a_panicking_state_machine_until_recovered() {
+        // Panics on an empty operation, as a buggy application might
+        struct Fragile;
+        impl StateMachine for Fragile {
+            fn apply(&mut self, op: &Vec<u8>) -> Vec<u8> {
+                assert!(!op.is_empty(), "empty operation");
+                op.clone()
+            }
+        }
+
+        let mut replica = setup();
+        replica.set_state_machine(Box::new(Fragile));
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        replica.router.learn(NodeId::new(9), client.clone());
+        let command = |request_id: u64, op: Vec<u8>| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(op),
+        };
+        for (slot, op) in [(1, vec![1]), (2, vec![]), (3, vec![3])] {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(slot, op),
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+        assert_eq!(replica.slot_out, Slot(2));
+        assert_eq!(replica.poisoned(), Some((Slot(2), "empty operation")));
+        assert!(replica
+            .health()
+            .reasons()
+            .contains(&"state machine panicked in slot 2: empty operation".to_string()));
+
+        // Clients are turned away rather than answered from suspect state
+        replica.drain_outbox();
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command: command(4, vec![4]),
+                consistency: Consistency::Eventual,
+                proxied_by: None,
+            }))
+            .unwrap();
+        let statuses = |replica: &mut Replica| -> Vec<(u64, ResponseStatus)> {
+            replica
+                .mailbox
+                .outbox
+                .drain(..)
+                .filter_map(|msg| match msg.message {
+                    Message::Response(response) => {
+                        Some((response.command_id.request_id, response.status))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(
+            statuses(&mut replica),
+            vec![(4, ResponseStatus::Unavailable)]
+        );
+        assert!(replica.requests.is_empty());
+
+        // Replaying from the start answers only the slots not answered before
+        replica
+            .recover(Box::new(NullStateMachine), Slot(1))
+            .unwrap();
+        assert_eq!(replica.poisoned(), None);
+        assert_eq!(replica.slot_out, Slot(4));
+        assert_eq!(
+            statuses(&mut replica),
+            vec![
+                (2, ResponseStatus::Performed),
+                (3, ResponseStatus::Performed)
+            ]
+        );
+        assert!(replica
+            .recover(Box::new(NullStateMachine), Slot(5))
+            .is_err());
+    }
+
+    #[test]
+    fn replica_turns_away_commands_over_the_size_limit() {
+        let mut replica = setup();
+        replica.set_max_command_size(Some(256));
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        for (request_id, len) in [(1, 1024), (2, 8)] {
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: client.clone(),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id,
+                        op: CommandType::Op(vec![1; len]),
+                    },
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }))
+                .unwrap();
+        }
+
+        let statuses: Vec<_> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(response) => {
+                    Some((response.command_id.request_id, response.status))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(statuses.len(), 1);
+        assert!(matches!(
+            statuses[0],
+            (1, ResponseStatus::TooLarge { size, limit: 256 }) if size > 1024
+        ));
+        // Only the small command was proposed
+        assert_eq!(replica.proposals.len(), 1);
+        assert_eq!(replica.proposals.values().next().unwrap().request_id, 2);
+    }
+
+    #[test]
+    fn replica_shares_open_slots_between_clients() {
+        let mut replica = setup();
+        replica.set_max_outstanding_per_client(Some(2));
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let request = |client_id: u64, request_id: u64| Command {
+            client_id: NodeId::new(client_id),
+            request_id,
+            op: CommandType::Op(vec![]),
+        };
+        // A chatty client sends a burst, then a second client sends two requests
+        let burst = (1..=4).map(|request_id| request(7, request_id));
+        let others = (1..=2).map(|request_id| request(8, request_id));
+        for command in burst.chain(others) {
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: client.clone(),
+                    command,
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }))
+                .unwrap();
+        }
+        let proposed = |replica: &Replica| {
+            replica
+                .proposals
+                .iter()
+                .map(|(slot, c)| (*slot, c.client_id, c.request_id))
+                .collect::<Vec<_>>()
+        };
+        // The burst gets two slots and the other client the rest it asked for
+        assert_eq!(
+            proposed(&replica),
+            vec![
+                (Slot(1), NodeId::new(7), 1),
+                (Slot(2), NodeId::new(7), 2),
+                (Slot(3), NodeId::new(8), 1),
+                (Slot(4), NodeId::new(8), 2),
+            ]
+        );
+        assert_eq!(replica.requests.len(), 2);
+
+        // Each decision lets the chatty client propose one more
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: request(7, 1),
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(
+            proposed(&replica).last(),
+            Some(&(Slot(5), NodeId::new(7), 3))
+        );
+        assert_eq!(replica.requests.len(), 1);
+        assert_eq!(replica.pending_requests().len(), 5);
+    }
+
+    #[test]
+    fn replica_answers_reads_locally_when_consistency_allows() {
+        use crate::state_machine::{KvCommand, KvStore};
+
+        let rep = ReplicaId::new(1);
+        let lead = LeaderId::new(1);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8080)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica<KvCommand> =
+            Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+        replica.set_state_machine(Box::new(KvStore::new()));
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let command = |request_id: u64, op: KvCommand| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(op),
+        };
+        let get = || KvCommand::Get {
+            key: "k".to_string(),
+        };
+        let put = command(
+            0,
+            KvCommand::Put {
+                key: "k".to_string(),
+                value: vec![7],
+            },
+        );
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command: put.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: lead,
+                slot_number: Slot(1),
+                command: put,
+                certificate: None,
+            }))
+            .unwrap();
+        replica.mailbox.clear_outbox();
+
+        let reads = [
+            (1, Consistency::Eventual),
+            (
+                2,
+                Consistency::Sequential {
+                    after_slot: Slot(1),
+                },
+            ),
+            // The client has seen slot 3, which this replica has not performed
+            (
+                3,
+                Consistency::Sequential {
+                    after_slot: Slot(3),
+                },
+            ),
+            (4, Consistency::Linearizable),
+        ];
+        for (request_id, consistency) in reads {
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: client.clone(),
+                    command: command(request_id, get()),
+                    consistency,
+                    proxied_by: None,
+                }))
+                .unwrap();
+        }
+
+        let answered: Vec<_> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(r) => Some((r.command_id.request_id, r.result.clone(), r.slot)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(answered, vec![(1, vec![7], Slot(1)), (2, vec![7], Slot(1))]);
+        let proposed: HashSet<u64> = replica.proposals.values().map(|c| c.request_id).collect();
+        assert_eq!(proposed, HashSet::from([3, 4]));
+    }
+
+    #[test]
+    fn replica_reads_locally_only_under_a_running_lease() {
+        use crate::state_machine::{KvCommand, KvStore};
+
+        let rep = ReplicaId::new(1);
+        let lead = LeaderId::new(1);
+        let mut config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8080)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        config.timeout_config.lease = Some(Duration::from_secs(5));
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica<KvCommand> =
+            Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+        replica.set_state_machine(Box::new(KvStore::new()));
+        let read = |replica: &mut Replica<KvCommand>, request_id: u64| {
+            replica.mailbox.clear_outbox();
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: Address::new("127.0.0.1".to_string(), 9000),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id,
+                        op: CommandType::Op(KvCommand::Get {
+                            key: "k".to_string(),
+                        }),
+                    },
+                    consistency: Consistency::Eventual,
+                    proxied_by: None,
+                }))
+                .unwrap();
+            replica.mailbox.count_outbox_of::<ResponseMessage>() == 1
+        };
+
+        // No leader has said it holds a lease
+        assert!(!read(&mut replica, 1));
+
+        let ballot = BallotNumber::new(lead);
+        replica.accept_message(SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8080),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: lead.into(),
+                ballot: Some(ballot.clone()),
+                quorum_lost: false,
+                promised: None,
+                lease: Some(Lease {
+                    ballot,
+                    id: 0,
+                    duration: Duration::from_secs(5),
+                }),
+                commit_index: None,
+            }),
+        });
+        assert!(replica.work_on_message());
+        assert!(read(&mut replica, 2));
+
+        // Taken to run out the clock skew early
+        let mut later = crate::nodes::clock::MockClock::new();
+        later.set_time(replica.clock.now() + Duration::from_millis(4900));
+        replica.clock = Box::new(later);
+        assert!(!read(&mut replica, 3));
+    }
+
+    #[test]
+    fn replica_requeues_proposal_only_when_another_command_wins() {
+        let mut replica = setup();
+        let ours = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        let theirs = Command {
+            client_id: NodeId::new(10),
+            request_id: 1,
+            op: CommandType::Op(vec![2]),
+        };
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: replica.address.clone(),
+                command: ours.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        assert_eq!(replica.proposals.get(&Slot(1)), Some(&ours));
+
+        // Another replica's command wins slot 1: ours is re-proposed in slot 2
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: theirs,
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(replica.proposals.get(&Slot(2)), Some(&ours));
+
+        // Ours wins slot 2: nothing is re-proposed
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(2),
+                command: ours,
+                certificate: None,
+            }))
+            .unwrap();
+        assert!(replica.proposals.is_empty());
+        assert!(replica.requests.is_empty());
+    }
+
+    #[test]
+    fn replica_fetches_missing_decisions_when_stalled() {
+        let mut replica = setup();
+        replica.config.timeout_config.slot_stall_timeout = Duration::ZERO;
+        let decide = |replica: &mut Replica, slot: u64| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id: slot,
+                        op: CommandType::Op(vec![]),
+                    },
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+        decide(&mut replica, 1);
+        decide(&mut replica, 4);
+        assert_eq!(replica.slot_out, Slot(2));
+
+        // First check notices slot_out moved; the next sees it stalled
+        replica.handle_timer(ClockAction::CheckSlotWindow).unwrap();
+        assert!(replica.mailbox.outbox.is_empty());
+        replica.handle_timer(ClockAction::CheckSlotWindow).unwrap();
+
+        let fetches: Vec<_> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::DecisionFetch(fetch) => Some(fetch.slots.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fetches, vec![vec![Slot(2), Slot(3)]]);
+
+        // A peer's reply fills the gap and execution catches up
+        replica
+            .handle_msg(ReplicaMessageIn::DecisionFetchReply(
+                DecisionFetchReplyMessage {
+                    src: ReplicaId::new(2),
+                    decisions: (2..4)
+                        .map(|slot| {
+                            (
+                                Slot(slot),
+                                Command {
+                                    client_id: NodeId::new(9),
+                                    request_id: slot,
+                                    op: CommandType::Op(vec![]),
+                                },
+                            )
+                        })
+                        .collect(),
+                    certificates: vec![],
+                },
+            ))
+            .unwrap();
+        assert_eq!(replica.slot_out, Slot(5));
+    }
+
+    #[test]
+    fn replica_reproposes_only_to_the_leader_that_took_its_proposal_up() {
+        let (rep, ldr1, ldr2) = (ReplicaId::new(1), LeaderId::new(1), LeaderId::new(2));
+        let address = |port| Address::new("127.0.0.1".to_string(), port);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([ldr1, ldr2]),
+            BTreeMap::from([
+                (rep.into(), address(8080)),
+                (ldr1.into(), address(8081)),
+                (ldr2.into(), address(8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica = Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: address(9000),
+                command: command.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        replica.accept_message(SendableMessage {
+            src: address(8081),
+            dst: address(8080),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::ProposeAccepted(ProposeAcceptedMessage {
+                src: ldr1,
+                slot_number: Slot(1),
+                command_id: command.id(),
+            }),
+        });
+        assert!(replica.work_on_message());
+        replica.mailbox.clear_outbox();
+
+        let repropose = |replica: &mut Replica| {
+            replica
+                .handle_timer(ClockAction::ReproposePendingRequests)
+                .unwrap();
+            let mut ports: Vec<String> = replica
+                .mailbox
+                .outbox
+                .drain(..)
+                .map(|msg| msg.dst.to_string())
+                .collect();
+            ports.sort();
+            ports
+        };
+        assert_eq!(repropose(&mut replica), [address(8081).to_string()]);
+        // Once the leader is suspected, every leader is asked again
+        replica.link_down(&address(8081));
+        assert!(repropose(&mut replica).is_empty());
+        assert_eq!(
+            repropose(&mut replica),
+            [address(8081).to_string(), address(8082).to_string()]
+        );
+    }
+
+    #[test]
+    fn replica_backs_off_reproposals_and_passes_over_a_leader_that_never_decides() {
+        use crate::events::EventBus;
+
+        let (rep, ldr1, ldr2) = (ReplicaId::new(1), LeaderId::new(1), LeaderId::new(2));
+        let address = |port| Address::new("127.0.0.1".to_string(), port);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([ldr1, ldr2]),
+            BTreeMap::from([
+                (rep.into(), address(8080)),
+                (ldr1.into(), address(8081)),
+                (ldr2.into(), address(8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica = Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+        let bus = EventBus::new();
+        let events = bus.subscribe_channel();
+        replica.set_event_sink(Box::new(bus));
+        replica.set_max_proposal_retries(3);
+        let heartbeat = |round| SendableMessage {
+            src: address(8081),
+            dst: address(8080),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: ldr1.into(),
+                ballot: Some(BallotNumber {
+                    round,
+                    leader: ldr1,
+                    incarnation: 0,
+                }),
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+        };
+        replica.accept_message(heartbeat(Round(1)));
+        assert!(replica.work_on_message());
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: address(9000),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        replica.mailbox.clear_outbox();
+
+        let mut proposed_to = Vec::new();
+        for _ in 0..7 {
+            replica
+                .handle_timer(ClockAction::ReproposePendingRequests)
+                .unwrap();
+            let mut leaders: Vec<_> = replica
+                .mailbox
+                .outbox
+                .drain(..)
+                .filter_map(|msg| {
+                    [8081, 8082]
+                        .into_iter()
+                        .find(|port| msg.dst == address(*port))
+                })
+                .collect();
+            leaders.sort();
+            proposed_to.push(leaders);
+        }
+        // Waits of one, then three checks; the third reproposal skips leader 1
+        assert_eq!(
+            proposed_to,
+            [
+                vec![8081, 8082],
+                vec![],
+                vec![8081, 8082],
+                vec![],
+                vec![],
+                vec![],
+                vec![8082]
+            ]
+        );
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![Event::LeaderSuspected {
+                replica: rep,
+                leader: ldr1,
+                slot: Slot(1),
+                attempts: 3,
+            }]
+        );
+        let now = replica.clock.now();
+        assert!(replica.failure_detector.is_suspected(&ldr1.into(), now));
+
+        // Leading under a new ballot, it is proposed to again
+        replica.accept_message(heartbeat(Round(2)));
+        assert!(replica.work_on_message());
+        assert_eq!(replica.proposal_targets().len(), 2);
+    }
+
+    #[test]
+    fn replica_alerts_and_catches_up_when_lag_crosses_the_threshold() {
+        use crate::events::EventBus;
+
+        let mut replica = setup();
+        let bus = EventBus::new();
+        let events = bus.subscribe_channel();
+        replica.set_event_sink(Box::new(bus));
+        replica.set_lag_threshold(2);
+        let now = replica.clock.now();
+        replica
+            .failure_detector
+            .heard_from(LeaderId::new(1).into(), now);
+        let command = |slot: u64| Command {
+            client_id: NodeId::new(9),
+            request_id: slot,
+            op: CommandType::Op(vec![]),
+        };
+        for slot in [1, 3] {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(slot),
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+        assert_eq!(replica.lag(), 2);
+        assert!(events.try_recv().is_err());
+        assert!(replica.mailbox.outbox.is_empty());
+
+        // Over the threshold: fetched straight away, without waiting for a stall
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(5),
+                command: command(5),
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::ReplicaLagging {
+                replica: replica.node_id,
+                lag: 4,
+                threshold: 2,
+            }
+        );
+        let fetched: Vec<_> = replica
+            .mailbox
+            .outbox
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::DecisionFetch(fetch) => Some(fetch.slots.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fetched, vec![vec![Slot(2), Slot(4)]]);
+        assert_eq!(
+            replica.health().reasons(),
+            ["4 slots behind the decisions seen".to_string()]
+        );
+
+        replica
+            .handle_msg(ReplicaMessageIn::DecisionFetchReply(
+                DecisionFetchReplyMessage {
+                    src: ReplicaId::new(2),
+                    decisions: [2, 4].map(|slot| (Slot(slot), command(slot))).to_vec(),
+                    certificates: vec![],
+                },
+            ))
+            .unwrap();
+        assert_eq!(replica.lag(), 0);
+        // Back within the threshold as soon as slot 2 is performed
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            vec![Event::ReplicaCaughtUp {
+                replica: replica.node_id,
+                lag: 2,
+            }]
+        );
+        assert_eq!(replica.health(), Health::Ready);
+    }
+
+    #[test]
+    fn replica_buffers_decisions_only_so_far_past_a_gap() {
+        let mut replica = setup();
+        replica.set_max_buffered_decisions(8);
+        let command = |slot: u64| Command {
+            client_id: NodeId::new(9),
+            request_id: slot,
+            op: CommandType::Op(vec![]),
+        };
+        let fetches = |replica: &mut Replica| -> Vec<Slot> {
+            replica
+                .mailbox
+                .outbox
+                .drain(..)
+                .filter_map(|msg| match msg.message {
+                    Message::DecisionFetch(fetch) => Some(fetch.slots),
+                    _ => None,
+                })
+                .flatten()
+                .collect()
+        };
+
+        // Slot 1 is missing while the rest of the log streams in
+        for slot in 2..=40 {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(slot),
+                    certificate: None,
+                }))
+                .unwrap();
+            assert!(replica.buffered_decisions() <= 8);
+        }
+        assert_eq!(replica.buffered_decisions(), 7);
+        assert_eq!(replica.decisions.last_slot(), Some(Slot(8)));
+        // Reaching the cap fetched the gap, and nothing beyond the buffer
+        assert_eq!(fetches(&mut replica), vec![Slot(1)]);
+
+        // Filling the gap performs the buffer, then the dropped decisions
+        // are fetched again a buffer at a time until the log is complete
+        let mut missing = vec![Slot(1)];
+        while !missing.is_empty() {
+            replica
+                .handle_msg(ReplicaMessageIn::DecisionFetchReply(
+                    DecisionFetchReplyMessage {
+                        src: ReplicaId::new(2),
+                        decisions: missing.iter().map(|s| (*s, command(s.0))).collect(),
+                        certificates: vec![],
+                    },
+                ))
+                .unwrap();
+            assert!(replica.buffered_decisions() <= 8);
+            missing = fetches(&mut replica);
+            assert!(missing.iter().all(|slot| slot.since(replica.slot_out) < 8));
+        }
+        assert_eq!(replica.slot_out, Slot(41));
+    }
+
+    #[test]
+    fn replica_catches_up_in_chunks_spread_across_peers() {
+        let mut replica = setup();
+        replica.config.timeout_config.slot_stall_timeout = Duration::ZERO;
+        let peer = ReplicaId::new(2);
+        replica.config.replicas.insert(peer);
+        replica
+            .config
+            .id_address_map
+            .insert(peer.into(), Address::new("127.0.0.1".to_string(), 8090));
+        replica.router.reconfigure(&replica.config);
+        let command = |slot: u64| Command {
+            client_id: NodeId::new(9),
+            request_id: slot,
+            op: CommandType::Op(vec![]),
+        };
+        for slot in [1, 200] {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(slot),
+                    certificate: None,
+                }))
+                .unwrap();
+        }
+        let fetches = |replica: &mut Replica| -> Vec<(Address, Vec<Slot>)> {
+            replica
+                .mailbox
+                .outbox
+                .drain(..)
+                .filter_map(|msg| match msg.message {
+                    Message::DecisionFetch(fetch) => Some((msg.dst, fetch.slots)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // A stalled replica starts with a single chunk
+        replica.handle_timer(ClockAction::CheckSlotWindow).unwrap();
+        replica.handle_timer(ClockAction::CheckSlotWindow).unwrap();
+        let first = fetches(&mut replica);
+        assert_eq!(first.len(), 1);
+        assert_eq!(
+            first[0].1,
+            Slot(2)
+                .up_to(Slot(2 + CATCH_UP_CHUNK as u64))
+                .collect::<Vec<_>>()
+        );
+
+        // Keeping up with the reply lets two chunks go out, one to each peer
+        replica
+            .handle_msg(ReplicaMessageIn::DecisionFetchReply(
+                DecisionFetchReplyMessage {
+                    src: ReplicaId::new(2),
+                    decisions: first[0]
+                        .1
+                        .iter()
+                        .map(|slot| (*slot, command(slot.0)))
+                        .collect(),
+                    certificates: vec![],
+                },
+            ))
+            .unwrap();
+        assert_eq!(replica.slot_out, Slot(2 + CATCH_UP_CHUNK as u64));
+        let next = fetches(&mut replica);
+        assert_eq!(next.len(), 2);
+        assert_ne!(next[0].0, next[1].0);
+        assert_eq!(next[0].1[0], replica.slot_out);
+        assert_eq!(next[1].1[0], replica.slot_out + CATCH_UP_CHUNK as u64);
+    }
+
+    #[test]
+    fn replica_answers_decision_fetch_with_known_decisions() {
+        let mut replica = setup();
+        let peer = ReplicaId::new(2);
+        replica
+            .config
+            .id_address_map
+            .insert(peer.into(), Address::new("127.0.0.1".to_string(), 8090));
+        replica.router.reconfigure(&replica.config);
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![]),
+                },
+                certificate: None,
+            }))
+            .unwrap();
+
+        replica
+            .handle_msg(ReplicaMessageIn::DecisionFetch(DecisionFetchMessage {
+                src: peer,
+                slots: vec![Slot(1), Slot(2)],
+            }))
+            .unwrap();
+
+        let reply = replica
+            .mailbox
+            .outbox
+            .iter()
+            .find_map(|msg| match &msg.message {
+                Message::DecisionFetchReply(reply) => Some(reply.clone()),
+                _ => None,
+            })
+            .expect("should reply to the fetch");
+        assert_eq!(reply.decisions.len(), 1);
+        assert_eq!(reply.decisions[0].0, Slot(1));
+    }
+
+    #[test]
+    fn replica_reslots_proposal_when_slot_occupied() {
+        let mut replica = setup();
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: replica.address.clone(),
+                command: command.clone(),
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }))
+            .unwrap();
+        assert!(replica.proposals.contains_key(&Slot(1)));
+
+        replica
+            .handle_msg(ReplicaMessageIn::ProposeRejected(ProposeRejectedMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command_id: command.id(),
+                reason: RejectReason::SlotOccupied,
+                free_slot: None,
+            }))
+            .unwrap();
+
+        assert!(!replica.proposals.contains_key(&Slot(1)));
+        assert_eq!(replica.proposals.get(&Slot(2)), Some(&command));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn replica_decision_logs_show_where_replicas_diverge() {
+        use crate::persistence::file::FileDecisionLog;
+        use crate::persistence::verify::verify_decision_logs;
+
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+        let mut logs = Vec::new();
+        for (replica_id, slot_two) in [(201, 2), (202, 2), (203, 7)] {
+            let path = std::env::temp_dir().join(format!(
+                "multifaustus-decisions-{}-{}-{}.jsonl",
+                std::process::id(),
+                line!(),
+                replica_id
+            ));
+            let _ = std::fs::remove_file(&path);
+            let mut replica = setup();
+            replica.set_decision_log(Box::new(FileDecisionLog::open(&path).unwrap()));
+            // Out of order: logged as performed, in slot order
+            for (slot, request_id) in [(2, slot_two), (1, 1)] {
+                replica
+                    .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                        src: LeaderId::new(1),
+                        slot_number: Slot(slot),
+                        command: command(request_id),
+                        certificate: None,
+                    }))
+                    .unwrap();
+            }
+            let log: Vec<(Slot, Command)> = FileDecisionLog::read(&path).unwrap();
+            assert_eq!(
+                log,
+                vec![(Slot(1), command(1)), (Slot(2), command(slot_two))]
+            );
+            let streamed: Vec<(Slot, Command)> = replica
+                .decisions_iter(Slot(2)..Slot(10))
+                .unwrap()
+                .collect::<anyhow::Result<_>>()
+                .unwrap();
+            assert_eq!(streamed, log[1..]);
+            logs.push((NodeId::new(replica_id), log));
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        let divergence = verify_decision_logs(&logs).unwrap_err();
+        assert_eq!(divergence.slot, Slot(2));
+        assert_eq!(divergence.offenders(), vec![NodeId::new(203)]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn replicas_sync_a_group_committing_decision_log_on_their_clock() {
+        use crate::persistence::file::{FileDecisionLog, GroupCommit};
+
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-decisions-{}-{}.jsonl",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+        let decide = |replica: &mut Replica, slot: u64| {
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(slot),
+                    command: command(slot),
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+        let logged = || FileDecisionLog::read::<Vec<u8>>(&path).unwrap().len();
+
+        let mut replica = setup();
+        let log = FileDecisionLog::open(&path)
+            .unwrap()
+            .with_group_commit(GroupCommit {
+                max_batch: 100,
+                interval: Duration::from_millis(10),
+            });
+        replica.set_decision_log(Box::new(log));
+        assert!(replica
+            .clock
+            .pending()
+            .iter()
+            .any(|(action, _)| matches!(action, ClockAction::SyncDecisionLog)));
+        decide(&mut replica, 1);
+        decide(&mut replica, 2);
+        assert_eq!(logged(), 0);
+
+        // The clock calls for a sync
+        replica.handle_timer(ClockAction::SyncDecisionLog).unwrap();
+        assert_eq!(logged(), 2);
+        decide(&mut replica, 3);
+        // A crash before the next sync loses only the third decision
+        std::mem::forget(replica);
+        assert_eq!(logged(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn replica_recovers_pending_requests_after_restart() {
+        use crate::persistence::file::FileRequestStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-requests-{}-{}.json",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+
+        let mut replica = setup();
+        replica
+            .set_request_store(Box::new(FileRequestStore::new(&path)))
+            .unwrap();
+        for request_id in 1..=2 {
+            replica
+                .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                    src: Address::new("127.0.0.1".to_string(), 9000),
+                    command: command(request_id),
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }))
+                .unwrap();
+        }
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: command(1),
+                certificate: None,
+            }))
+            .unwrap();
+        assert_eq!(replica.pending_requests(), vec![command(2)]);
+        // Crash: the undecided request only survives in the store
+        drop(replica);
+
+        let mut replica = setup();
+        assert!(replica.pending_requests().is_empty());
+        replica
+            .set_request_store(Box::new(FileRequestStore::new(&path)))
+            .unwrap();
+        assert_eq!(replica.pending_requests(), vec![command(2)]);
+        // and is proposed again straight away
+        assert!(replica.mailbox.outbox.iter().any(|msg| matches!(
+            &msg.message,
+            Message::Propose(p) if p.command == command(2)
+        )));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replica_is_not_ready_without_a_leader() {
+        let mut replica = setup();
+        assert!(!replica.health().is_ready());
+
+        replica.accept_message(SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: replica.address.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: LeaderId::new(1).into(),
+                ballot: None,
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+        });
+        assert!(replica.work_on_message());
+        assert_eq!(replica.health(), Health::Ready);
+    }
+
+    #[test]
+    fn replica_turns_requests_away_while_no_leader_has_a_quorum() {
+        let mut replica = setup();
+        let heartbeat = |quorum_lost: bool| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8080),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: LeaderId::new(1).into(),
+                ballot: None,
+                quorum_lost,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+        };
+        let request = |request_id: u64| RequestMessage {
+            src: Address::new("127.0.0.1".to_string(), 9000),
+            command: Command {
+                client_id: NodeId::new(9),
+                request_id,
+                op: CommandType::Op(vec![request_id as u8]),
+            },
+            consistency: Consistency::Linearizable,
+            proxied_by: None,
+        };
+
+        replica.accept_message(heartbeat(true));
+        assert!(replica.work_on_message());
+        assert!(matches!(replica.health(), Health::Degraded { .. }));
+        replica.drain_outbox();
+        replica
+            .handle_msg(ReplicaMessageIn::Request(request(1)))
+            .unwrap();
+        assert!(replica.pending_requests().is_empty());
+        assert_eq!(replica.mailbox.outbox.len(), 1);
+        assert!(matches!(
+            &replica.mailbox.outbox[0].message,
+            Message::Response(r) if r.status == ResponseStatus::Unavailable
+        ));
+
+        // Once the leader reaches a quorum again, requests are queued as usual
+        replica.accept_message(heartbeat(false));
+        assert!(replica.work_on_message());
+        assert_eq!(replica.health(), Health::Ready);
+        replica.drain_outbox();
+        replica
+            .handle_msg(ReplicaMessageIn::Request(request(2)))
+            .unwrap();
+        assert!(replica
+            .mailbox
+            .outbox
+            .iter()
+            .all(|msg| matches!(msg.message, Message::Propose(_))));
+    }
+
+    #[test]
+    fn replica_refuses_decisions_far_past_its_watermark() {
+        let mut replica = setup();
+        replica.set_max_slots_ahead(1000);
+        let decision = |slot: u64| {
+            ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(slot),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: slot,
+                    op: CommandType::Op(vec![1]),
+                },
+                certificate: None,
+            })
+        };
+        replica.handle_msg(decision(1002)).unwrap();
+        // Neither buffered nor fetched up to
+        assert_eq!(replica.out_of_range(), 1);
+        assert_eq!(replica.highest_refused, None);
+        assert!(replica.mailbox.outbox.is_empty());
+
+        replica.handle_msg(decision(1)).unwrap();
+        assert_eq!(replica.out_of_range(), 1);
+        assert_eq!(replica.progress().frontier, Some(Slot(2)));
+    }
+
+    #[test]
+    fn replica_without_an_active_leader_passes_requests_to_a_peer() {
+        let mut replica = setup();
+        let peer = ReplicaId::new(2);
+        let peer_address = Address::new("127.0.0.1".to_string(), 8090);
+        replica.config.replicas.insert(peer);
+        replica
+            .config
+            .id_address_map
+            .insert(peer.into(), peer_address.clone());
+        replica.router.reconfigure(&replica.config);
+        replica.set_proxy_requests(true);
+        let client = Address::new("127.0.0.1".to_string(), 9000);
+        let request = |request_id: u64, proxied_by: Option<ReplicaId>| {
+            ReplicaMessageIn::Request(RequestMessage {
+                src: client.clone(),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id,
+                    op: CommandType::Op(vec![1]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by,
+            })
+        };
+
+        // No leader has announced itself active, so the request goes to the
+        // peer, which is to answer the client
+        replica.handle_msg(request(1, None)).unwrap();
+        let proxied = replica.mailbox.assert_sent_to(&peer_address);
+        let Message::Request(proxied) = &proxied.message else {
+            panic!("expected a request, got {}", proxied);
+        };
+        assert_eq!(proxied.src, client);
+        assert_eq!(proxied.proxied_by, Some(replica.node_id));
+        assert_eq!(replica.mailbox.count_outbox_of::<ProposeMessage>(), 0);
+        assert_eq!(replica.mailbox.count_outbox_of::<ResponseMessage>(), 0);
+        assert!(replica.proposals.is_empty());
+
+        // A request already passed on is taken up, not passed on again
+        replica.mailbox.clear_outbox();
+        replica.handle_msg(request(2, Some(peer))).unwrap();
+        assert_eq!(replica.mailbox.count_outbox_of::<RequestMessage>(), 0);
+        assert_eq!(replica.proposals.len(), 1);
+
+        // With proxying off, requests are taken up as before
+        replica.set_proxy_requests(false);
+        replica.handle_msg(request(3, None)).unwrap();
+        assert_eq!(replica.mailbox.count_outbox_of::<RequestMessage>(), 0);
+        assert_eq!(replica.proposals.len(), 2);
+    }
+
+    #[test]
+    fn replica_performs_only_certified_decisions_when_the_config_asks() {
+        let mut replica = setup();
+        replica.config = replica.config.clone().with_certified_decisions();
+        replica.config_timeline = ConfigTimeline::new(replica.config.clone());
+        let decision = |acceptors: Option<Vec<u64>>| {
+            ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                certificate: acceptors.map(|acceptors| DecisionCertificate {
+                    ballot_number: BallotNumber::new(LeaderId::new(1)),
+                    acceptors: acceptors.into_iter().map(AcceptorId::new).collect(),
+                }),
+            })
+        };
+
+        // Uncertified, short of a quorum, or vouched for by a stranger
+        for uncertified in [None, Some(vec![]), Some(vec![7])] {
+            replica.handle_msg(decision(uncertified)).unwrap();
+            assert_eq!(replica.slot_out, Slot(1));
+        }
+        replica.handle_msg(decision(Some(vec![1]))).unwrap();
+        assert_eq!(replica.slot_out, Slot(2));
+    }
+
+    #[test]
+    fn replica_answers_barriers_once_it_has_performed_their_slot() {
+        use crate::client::Client;
+        use crate::state_machine::{KvCommand, KvStore};
+
+        let (rep, lead) = (ReplicaId::new(1), LeaderId::new(1));
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (rep.into(), Address::new("127.0.0.1".to_string(), 8080)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        let clock = Box::new(crate::nodes::clock::MockClock::new());
+        let mut replica: Replica<KvCommand> =
+            Replica::new(rep, config, Mailbox::new(), clock).unwrap();
+        replica.set_state_machine(Box::new(KvStore::new()));
+        let mut client = Client::new(NodeId::new(9), client_address());
+        let committed = |replica: &mut Replica<KvCommand>| -> Vec<CommittedMessage> {
+            let answered = replica
+                .mailbox
+                .outbox_of::<CommittedMessage>()
+                .cloned()
+                .collect();
+            replica.mailbox.clear_outbox();
+            answered
+        };
+        let decide = |replica: &mut Replica<KvCommand>, slot: u64| {
+            let key = format!("k{}", slot);
+            replica
+                .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                    src: lead,
+                    slot_number: Slot(slot),
+                    command: Command {
+                        client_id: NodeId::new(8),
+                        request_id: slot,
+                        op: CommandType::Op(KvCommand::Put {
+                            key,
+                            value: vec![1],
+                        }),
+                    },
+                    certificate: None,
+                }))
+                .unwrap();
+        };
+        let barrier = client.barrier(&replica.address, Slot(2));
+        replica.accept_message(barrier);
+        assert!(replica.work_on_message());
+
+        // The active leader reports slot 4 decided, which this replica has
+        // not performed yet: the commit index moves, the barrier waits
+        replica.accept_message(SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: replica.address.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: lead.into(),
+                ballot: Some(BallotNumber::new(lead)),
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: Some(CommitIndex(Slot(4))),
+            }),
+        });
+        assert!(replica.work_on_message());
+        assert_eq!(replica.commit_index(), CommitIndex(Slot(4)));
+        assert_eq!(replica.progress().committed, Some(Slot(4)));
+        assert!(committed(&mut replica).is_empty());
+
+        decide(&mut replica, 1);
+        assert!(committed(&mut replica).is_empty());
+        decide(&mut replica, 2);
+        let answered = committed(&mut replica);
+        assert_eq!(answered.len(), 1);
+        assert_eq!(client.committed(&answered[0]), Slot(2));
+
+        // The session has moved up to the barrier, and the replica that
+        // answered it serves the session's next read locally
+        let read = client.command(KvCommand::Get {
+            key: "k2".to_string(),
+        });
+        replica
+            .handle_msg(ReplicaMessageIn::Request(RequestMessage {
+                src: client_address(),
+                command: read.clone(),
+                consistency: client.sequential(),
+                proxied_by: None,
+            }))
+            .unwrap();
+        let response = replica
+            .mailbox
+            .outbox_of::<ResponseMessage>()
+            .find(|response| response.command_id == read.id())
+            .cloned()
+            .unwrap();
+        assert_eq!(client.receive(&response), Some(Ok(vec![1])));
+        assert_eq!(
+            replica
+                .mailbox
+                .count_outbox_of::<ProposeMessage<KvCommand>>(),
+            0
+        );
+
+        // A barrier already performed is answered straight away
+        replica.mailbox.clear_outbox();
+        let barrier = client.barrier(&replica.address, Slot(1));
+        replica.accept_message(barrier);
+        assert!(replica.work_on_message());
+        assert_eq!(committed(&mut replica).len(), 1);
+    }
+
+    fn client_address() -> Address {
+        Address::new("127.0.0.1".to_string(), 9000)
+    }
+
+    #[test]
+    fn replica_keeps_waiting_barriers_across_freeze_and_thaw() {
+        let mut replica = setup();
+        replica
+            .handle_msg(ReplicaMessageIn::Barrier(BarrierMessage {
+                src: client_address(),
+                id: 4,
+                slot: Slot(1),
+            }))
+            .unwrap();
+        replica.leader_commit_index = CommitIndex(Slot(3));
+        let frozen = serde_json::to_string(&replica.freeze()).unwrap();
+        let mut replica: Replica = Replica::thaw(
+            serde_json::from_str(&frozen).unwrap(),
+            Box::new(crate::nodes::clock::MockClock::new()),
+        )
+        .unwrap();
+        assert_eq!(replica.commit_index(), CommitIndex(Slot(3)));
+
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                certificate: None,
+            }))
+            .unwrap();
+        let committed = replica
+            .mailbox
+            .outbox
+            .iter()
+            .find_map(|msg| match &msg.message {
+                Message::Committed(committed) => Some((msg.dst.clone(), committed.id)),
+                _ => None,
+            });
+        assert_eq!(committed, Some((client_address(), 4)));
+    }
+
+    #[test]
+    fn replica_checks_certificates_of_decisions_fetched_while_catching_up() {
+        let mut replica = setup();
+        replica.config = replica.config.clone().with_certified_decisions();
+        replica.config_timeline = ConfigTimeline::new(replica.config.clone());
+        let peer = ReplicaId::new(2);
+        let command = Command {
+            client_id: NodeId::new(9),
+            request_id: 1,
+            op: CommandType::Op(vec![1]),
+        };
+        let certificate = |acceptors: Vec<u64>| DecisionCertificate {
+            ballot_number: BallotNumber::new(LeaderId::new(1)),
+            acceptors: acceptors.into_iter().map(AcceptorId::new).collect(),
+        };
+        let fetched = |certificates: Vec<(Slot, DecisionCertificate)>| {
+            ReplicaMessageIn::DecisionFetchReply(DecisionFetchReplyMessage {
+                src: peer,
+                decisions: vec![(Slot(1), command.clone())],
+                certificates,
+            })
+        };
+
+        // A decision ignored for its certificate is not taken up when a peer
+        // offers it again, with no certificate or the same bad one
+        replica
+            .handle_msg(ReplicaMessageIn::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: command.clone(),
+                certificate: Some(certificate(vec![7])),
+            }))
+            .unwrap();
+        replica.handle_msg(fetched(vec![])).unwrap();
+        replica
+            .handle_msg(fetched(vec![(Slot(1), certificate(vec![7]))]))
+            .unwrap();
+        assert_eq!(replica.slot_out, Slot(1));
+
+        replica
+            .handle_msg(fetched(vec![(Slot(1), certificate(vec![1]))]))
+            .unwrap();
+        assert_eq!(replica.slot_out, Slot(2));
+
+        // And it passes the certificate on to peers catching up in turn
+        replica.config.replicas.insert(peer);
+        replica
+            .config
+            .id_address_map
+            .insert(peer.into(), Address::new("127.0.0.1".to_string(), 8090));
+        replica.router.reconfigure(&replica.config);
+        replica.mailbox.clear_outbox();
+        replica
+            .handle_msg(ReplicaMessageIn::DecisionFetch(DecisionFetchMessage {
+                src: peer,
+                slots: vec![Slot(1)],
+            }))
+            .unwrap();
+        let reply = replica
+            .mailbox
+            .outbox_of::<DecisionFetchReplyMessage>()
+            .next()
+            .cloned()
+            .unwrap();
+        assert_eq!(reply.certificates, [(Slot(1), certificate(vec![1]))]);
     }
 }
@@ -0,0 +1,141 @@
+//! Client requests waiting for a replica to propose them.
+//!
+//! Requests queue per client, and the replica takes them from the clients
+//! in round-robin, one at a time. A client that sends a burst of requests
+//! then only gets every other slot while another client is waiting, instead
+//! of every slot until its burst has been proposed. Each client's requests
+//! are still proposed in the order they arrived.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{BTreeMap, VecDeque};
+use crate::types::{Command, NodeId};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RequestQueue<T = Vec<u8>> {
+    queues: BTreeMap<NodeId, VecDeque<Command<T>>>,
+    // The client last taken from; the next turn goes to the one after it
+    last: Option<NodeId>,
+}
+
+impl<T> Default for RequestQueue<T> {
+    fn default() -> Self {
+        RequestQueue {
+            queues: BTreeMap::new(),
+            last: None,
+        }
+    }
+}
+
+impl<T> RequestQueue<T> {
+    pub fn push(&mut self, command: Command<T>) {
+        self.queues
+            .entry(command.client_id)
+            .or_default()
+            .push_back(command);
+    }
+
+    /// Queue `command` ahead of its client's other requests, as when it lost
+    /// the slot it was proposed in.
+    pub fn push_front(&mut self, command: Command<T>) {
+        self.queues
+            .entry(command.client_id)
+            .or_default()
+            .push_front(command);
+    }
+
+    /// Take the oldest request of the next client in turn for which
+    /// `eligible` holds, skipping the others.
+    pub fn pop_next(&mut self, eligible: impl Fn(&NodeId) -> bool) -> Option<Command<T>> {
+        let client = {
+            let after = self.last.and_then(|last| {
+                self.queues
+                    .range(last..)
+                    .map(|(client, _)| client)
+                    .find(|client| **client != last && eligible(client))
+            });
+            let from_start = || self.queues.keys().find(|client| eligible(client));
+            *after.or_else(from_start)?
+        };
+        let queue = self.queues.get_mut(&client)?;
+        let command = queue.pop_front();
+        if queue.is_empty() {
+            self.queues.remove(&client);
+        }
+        self.last = Some(client);
+        command
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    /// Drop the queued requests for which `keep` does not hold.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Command<T>) -> bool) {
+        for queue in self.queues.values_mut() {
+            queue.retain(&mut keep);
+        }
+        self.queues.retain(|_, queue| !queue.is_empty());
+    }
+
+    /// Every queued request, client by client.
+    pub fn iter(&self) -> impl Iterator<Item = &Command<T>> {
+        self.queues.values().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommandType;
+
+    fn command(client: u64, request_id: u64) -> Command {
+        Command {
+            client_id: NodeId::new(client),
+            request_id,
+            op: CommandType::Op(alloc::vec![]),
+        }
+    }
+
+    #[test]
+    fn request_queue_takes_turns_between_clients() {
+        let mut queue = RequestQueue::default();
+        for request_id in 1..=4 {
+            queue.push(command(1, request_id));
+        }
+        queue.push(command(2, 1));
+        queue.push(command(3, 1));
+        assert_eq!(queue.len(), 6);
+
+        let mut taken = |eligible: &dyn Fn(&NodeId) -> bool| {
+            queue
+                .pop_next(eligible)
+                .map(|c| (c.client_id, c.request_id))
+        };
+        assert_eq!(taken(&|_| true), Some((NodeId::new(1), 1)));
+        assert_eq!(taken(&|_| true), Some((NodeId::new(2), 1)));
+        assert_eq!(taken(&|_| true), Some((NodeId::new(3), 1)));
+        // Only the chatty client is left
+        assert_eq!(taken(&|_| true), Some((NodeId::new(1), 2)));
+        assert_eq!(taken(&|_| true), Some((NodeId::new(1), 3)));
+        // Ineligible clients are skipped
+        assert_eq!(taken(&|client| *client != NodeId::new(1)), None);
+
+        // A request put back goes ahead of its client's others
+        queue.push_front(command(1, 3));
+        queue.push(command(2, 2));
+        assert_eq!(
+            queue.iter().map(|c| c.request_id).collect::<Vec<_>>(),
+            [3, 4, 2]
+        );
+        assert_eq!(queue.pop_next(|_| true).unwrap().client_id, NodeId::new(2));
+        assert_eq!(queue.pop_next(|_| true).unwrap().request_id, 3);
+        assert_eq!(queue.pop_next(|_| true).unwrap().request_id, 4);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+//! Results of performed commands, kept for answering retried requests.
+//!
+//! A client that times out sends its request again, and the command may
+//! already have been performed. The replica answers such retries from this
+//! cache instead of proposing the command a second time. The cache holds at
+//! most `capacity` results and evicts the least recently used first, so a
+//! client retrying a command long after it was performed may get no answer.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{BTreeMap, HashMap};
+use crate::types::{CommandId, Slot};
+
+/// A cached result, and the slot its command was performed in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResult {
+    pub slot: Slot,
+    pub result: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultCache {
+    capacity: usize,
+    // Each result with the tick it was last used at
+    #[serde(with = "crate::nodes::freeze::pairs")]
+    entries: HashMap<CommandId, (u64, CachedResult)>,
+    // Commands by the tick they were last used at, least recent first
+    recency: BTreeMap<u64, CommandId>,
+    tick: u64,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> ResultCache {
+        ResultCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Hold at most `capacity` results, returning the commands evicted to fit.
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<CommandId> {
+        self.capacity = capacity;
+        self.evict()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The result for `command_id`, marking it as recently used.
+    pub fn get(&mut self, command_id: &CommandId) -> Option<&CachedResult> {
+        self.tick += 1;
+        let (used, _) = self.entries.get_mut(command_id)?;
+        self.recency.remove(used);
+        *used = self.tick;
+        self.recency.insert(self.tick, *command_id);
+        self.entries.get(command_id).map(|(_, cached)| cached)
+    }
+
+    /// The result for `command_id`, leaving its recency as it is.
+    pub fn peek(&self, command_id: &CommandId) -> Option<&CachedResult> {
+        self.entries.get(command_id).map(|(_, cached)| cached)
+    }
+
+    /// Cache `result`, returning the commands evicted to make room.
+    pub fn insert(&mut self, command_id: CommandId, slot: Slot, result: Vec<u8>) -> Vec<CommandId> {
+        if self.capacity == 0 {
+            return Vec::new();
+        }
+        self.tick += 1;
+        let cached = CachedResult { slot, result };
+        if let Some((used, _)) = self.entries.insert(command_id, (self.tick, cached)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.tick, command_id);
+        self.evict()
+    }
+
+    fn evict(&mut self) -> Vec<CommandId> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > self.capacity {
+            let Some((_, command_id)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&command_id);
+            evicted.push(command_id);
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    use crate::types::NodeId;
+
+    fn id(request_id: u64) -> CommandId {
+        CommandId {
+            client_id: NodeId::new(9),
+            request_id,
+        }
+    }
+
+    #[test]
+    fn result_cache_evicts_the_least_recently_used_result() {
+        let mut cache = ResultCache::new(2);
+        assert!(cache.insert(id(1), Slot(1), vec![1]).is_empty());
+        assert!(cache.insert(id(2), Slot(2), vec![2]).is_empty());
+        // Reading the first result makes the second the least recently used
+        assert_eq!(cache.get(&id(1)).map(|c| c.slot), Some(Slot(1)));
+        assert_eq!(cache.insert(id(3), Slot(3), vec![3]), vec![id(2)]);
+        assert!(cache.get(&id(2)).is_none());
+        assert_eq!(
+            cache.get(&id(3)),
+            Some(&CachedResult {
+                slot: Slot(3),
+                result: vec![3]
+            })
+        );
+
+        assert_eq!(cache.set_capacity(1), vec![id(1)]);
+        assert_eq!(cache.len(), 1);
+        // A cache without capacity holds nothing
+        cache.set_capacity(0);
+        assert!(cache.insert(id(4), Slot(4), vec![4]).is_empty());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+//! Resolving node IDs to transport addresses.
+//!
+//! Nodes address each other by `NodeId` and ask their `Router` for the
+//! current `Address` only when a message is sent, so an address that changes
+//! (a node that moves, a reconfiguration) takes effect on the next send.
+//!
+//! A broadcast goes to every destination the router can resolve. One it
+//! cannot, e.g. because of a typo in the configuration, is skipped and
+//! reported, and does not keep the message from the others.
+//!
+//! The router also remembers the `WireFormat` each peer's link negotiated,
+//! so the outbox pump can encode what is sent there in it.
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::collections::BTreeMap;
+use crate::messages::Message;
+use crate::types::{Address, Config, NodeId};
+
+pub trait Router {
+    /// Where messages for `node` should be sent, if it can be reached.
+    fn resolve(&self, node: &NodeId) -> Option<Address>;
+
+    /// Remember where a node outside the configuration (e.g. a client) can be reached.
+    fn learn(&mut self, node: NodeId, address: Address);
+
+    /// Send to `node` at `address` from now on, as gossip found it, whatever
+    /// the configuration says. See `nodes::discovery`.
+    fn discovered(&mut self, node: NodeId, address: Address) {
+        self.learn(node, address);
+    }
+
+    /// Drop any cached routes that `config` supersedes.
+    fn reconfigure(&mut self, config: &Config);
+
+    /// Remember the format the link to `address` negotiated.
+    fn set_wire_format(&mut self, _address: &Address, _format: WireFormat) {}
+
+    /// The format negotiated for the link to `address`, if any.
+    fn wire_format(&self, _address: &Address) -> Option<WireFormat> {
+        None
+    }
+}
+
+/// The codec and compression a link carries messages in, by the names they
+/// are registered under in the transport's `Codecs`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WireFormat {
+    pub codec: String,
+    pub compression: String,
+}
+
+impl WireFormat {
+    pub fn new(codec: &str, compression: &str) -> WireFormat {
+        WireFormat {
+            codec: codec.to_string(),
+            compression: compression.to_string(),
+        }
+    }
+}
+
+/// Plain JSON, which every peer reads.
+impl Default for WireFormat {
+    fn default() -> Self {
+        WireFormat::new("json", "none")
+    }
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.codec, self.compression)
+    }
+}
+
+/// Routes from the configuration's address map, falling back to learned
+/// addresses. Discovered addresses take precedence over both.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigRouter {
+    routes: BTreeMap<NodeId, Address>,
+    learned: BTreeMap<NodeId, Address>,
+    // Addresses gossip found, which outlive reconfigurations
+    discovered: BTreeMap<NodeId, Address>,
+    // Negotiated formats by address, which outlive reconfigurations
+    formats: BTreeMap<String, WireFormat>,
+}
+
+impl ConfigRouter {
+    pub fn new(config: &Config) -> ConfigRouter {
+        ConfigRouter {
+            routes: config.id_address_map.clone(),
+            learned: BTreeMap::new(),
+            discovered: BTreeMap::new(),
+            formats: BTreeMap::new(),
+        }
+    }
+}
+
+impl Router for ConfigRouter {
+    fn resolve(&self, node: &NodeId) -> Option<Address> {
+        self.discovered
+            .get(node)
+            .or_else(|| self.routes.get(node))
+            .or_else(|| self.learned.get(node))
+            .cloned()
+    }
+
+    fn learn(&mut self, node: NodeId, address: Address) {
+        self.learned.insert(node, address);
+    }
+
+    fn discovered(&mut self, node: NodeId, address: Address) {
+        self.discovered.insert(node, address);
+    }
+
+    fn reconfigure(&mut self, config: &Config) {
+        self.routes = config.id_address_map.clone();
+        // The configuration is authoritative for its members
+        self.learned
+            .retain(|node, _| !self.routes.contains_key(node));
+    }
+
+    fn set_wire_format(&mut self, address: &Address, format: WireFormat) {
+        self.formats.insert(address.to_string(), format);
+    }
+
+    fn wire_format(&self, address: &Address) -> Option<WireFormat> {
+        self.formats.get(&address.to_string()).cloned()
+    }
+}
+
+/// The destinations of a broadcast that the router had no address for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unroutable {
+    /// The kind of message broadcast, as in `Message::kind`.
+    pub kind: &'static str,
+    pub nodes: Vec<NodeId>,
+}
+
+impl Unroutable {
+    /// Log the skipped destinations, counting them as `paxos.messages.unroutable`.
+    pub fn report(&self, node: impl fmt::Display) {
+        warn!(
+            monotonic_counter.paxos.messages.unroutable = self.nodes.len() as u64,
+            paxos.message.kind = self.kind,
+            "{}: {}",
+            node,
+            self
+        );
+    }
+}
+
+impl fmt::Display for Unroutable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no address to send {} to for", self.kind)?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, node)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for Unroutable {}
+
+/// Hand a copy of `message` to `send` for each of `nodes` that `router`
+/// resolves, then report those it did not resolve.
+pub fn broadcast<T: Clone>(
+    router: &dyn Router,
+    nodes: impl IntoIterator<Item = NodeId>,
+    message: &Message<T>,
+    mut send: impl FnMut(Address, Message<T>),
+) -> Result<(), Unroutable> {
+    let mut unroutable = Vec::new();
+    for node in nodes {
+        match router.resolve(&node) {
+            Some(address) => send(address, message.clone()),
+            None => unroutable.push(node),
+        }
+    }
+    if !unroutable.is_empty() {
+        return Err(Unroutable {
+            kind: message.kind(),
+            nodes: unroutable,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::HashSet;
+    use crate::types::*;
+
+    fn config(port: u64) -> Config {
+        Config::new(
+            HashSet::new(),
+            HashSet::new(),
+            HashSet::from([LeaderId::new(1)]),
+            BTreeMap::from([(NodeId::new(1), Address::new("h".to_string(), port))]),
+            None,
+        )
+    }
+
+    #[test]
+    fn config_router_follows_reconfiguration() {
+        let mut router = ConfigRouter::new(&config(1));
+        let client = NodeId::new(9);
+        router.learn(client, Address::new("c".to_string(), 1));
+        assert_eq!(
+            router.resolve(&NodeId::new(1)),
+            Some(Address::new("h".to_string(), 1))
+        );
+
+        router.reconfigure(&config(2));
+        assert_eq!(
+            router.resolve(&NodeId::new(1)),
+            Some(Address::new("h".to_string(), 2))
+        );
+        // Learned routes outside the configuration survive
+        assert_eq!(
+            router.resolve(&client),
+            Some(Address::new("c".to_string(), 1))
+        );
+        assert_eq!(router.resolve(&NodeId::new(3)), None);
+    }
+
+    #[test]
+    fn discovered_addresses_outrank_the_configuration() {
+        let mut router = ConfigRouter::new(&config(1));
+        let moved = Address::new("h".to_string(), 5);
+        router.discovered(NodeId::new(1), moved.clone());
+        assert_eq!(router.resolve(&NodeId::new(1)), Some(moved.clone()));
+        router.reconfigure(&config(2));
+        assert_eq!(router.resolve(&NodeId::new(1)), Some(moved));
+    }
+
+    #[test]
+    fn config_router_remembers_negotiated_formats_across_reconfiguration() {
+        let mut router = ConfigRouter::new(&config(1));
+        let address = Address::new("h".to_string(), 1);
+        assert_eq!(router.wire_format(&address), None);
+        router.set_wire_format(&address, WireFormat::new("bincode", "zstd"));
+        router.reconfigure(&config(2));
+        assert_eq!(
+            router.wire_format(&address),
+            Some(WireFormat::new("bincode", "zstd"))
+        );
+        assert_eq!(router.wire_format(&Address::new("h".to_string(), 2)), None);
+    }
+}
@@ -0,0 +1,128 @@
+//! Strategies for choosing which slots a replica proposes into.
+//!
+//! Replicas that propose into the same slot concurrently collide: a leader
+//! takes up one command and the others have to be re-slotted. An allocator
+//! can keep replicas out of each other's way. Slots it passes over are left
+//! to other replicas; if one is still undecided once the log stalls on it,
+//! the replica fills it anyway, so no strategy can block progress.
+use alloc::vec::Vec;
+
+use crate::types::{Config, ReplicaId, Slot};
+
+pub trait SlotAllocator {
+    /// Whether this replica should propose into `slot`.
+    fn claims(&self, slot: Slot) -> bool;
+
+    /// A leader already holds another command for `slot`, and suggested
+    /// `free_slot` as the next slot it has nothing for.
+    fn slot_taken(&mut self, _slot: Slot, _free_slot: Option<Slot>) {}
+
+    /// The replica switched to `config`.
+    fn reconfigure(&mut self, _config: &Config) {}
+}
+
+/// Propose into every open slot in turn: replicas proposing at the same
+/// time race for the same slots.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sequential;
+
+impl SlotAllocator for Sequential {
+    fn claims(&self, _slot: Slot) -> bool {
+        true
+    }
+}
+
+/// Slots are dealt out to the replicas in the configuration in turn, ordered
+/// by id, so replicas only collide when filling a slot another one left open.
+#[derive(Clone, Debug)]
+pub struct RoundRobin {
+    replica: ReplicaId,
+    // This replica's position among the configured replicas, and how many there are
+    index: u64,
+    count: u64,
+}
+
+impl RoundRobin {
+    pub fn new(replica: ReplicaId, config: &Config) -> RoundRobin {
+        let mut allocator = RoundRobin {
+            replica,
+            index: 0,
+            count: 1,
+        };
+        allocator.reconfigure(config);
+        allocator
+    }
+}
+
+impl SlotAllocator for RoundRobin {
+    fn claims(&self, slot: Slot) -> bool {
+        slot.saturating_sub(1).0 % self.count == self.index
+    }
+
+    fn reconfigure(&mut self, config: &Config) {
+        let mut replicas: Vec<&ReplicaId> = config.replicas.iter().collect();
+        replicas.sort_by_key(|id| *id.as_ref());
+        // A replica outside the configuration proposes wherever it can
+        let (index, count) = match replicas.iter().position(|id| **id == self.replica) {
+            Some(index) => (index, replicas.len()),
+            None => (0, 1),
+        };
+        self.index = index as u64;
+        self.count = count as u64;
+    }
+}
+
+/// Follow the leaders' suggestions: after a collision, skip past every slot
+/// the leader already holds a command for rather than trying each in turn.
+///
+/// This helps a replica that has fallen behind the others; replicas that
+/// propose at the same moment still collide over the slot they are sent to.
+#[derive(Clone, Copy, Debug)]
+pub struct LeaderAssigned {
+    // Lowest slot no leader has reported taken
+    floor: Slot,
+}
+
+impl Default for LeaderAssigned {
+    fn default() -> Self {
+        LeaderAssigned { floor: Slot::FIRST }
+    }
+}
+
+impl SlotAllocator for LeaderAssigned {
+    fn claims(&self, slot: Slot) -> bool {
+        slot >= self.floor
+    }
+
+    fn slot_taken(&mut self, slot: Slot, free_slot: Option<Slot>) {
+        self.floor = self.floor.max(free_slot.unwrap_or(slot.next()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::{BTreeMap, HashSet};
+
+    #[test]
+    fn round_robin_deals_slots_in_replica_order() {
+        let replicas = [ReplicaId::new(7), ReplicaId::new(3), ReplicaId::new(5)];
+        let config = Config::new(
+            HashSet::from(replicas),
+            HashSet::new(),
+            HashSet::new(),
+            BTreeMap::new(),
+            None,
+        );
+        let claimed = |id: u64| -> Vec<Slot> {
+            let allocator = RoundRobin::new(ReplicaId::new(id), &config);
+            Slot(1)
+                .up_to(Slot(7))
+                .filter(|slot| allocator.claims(*slot))
+                .collect()
+        };
+        assert_eq!(claimed(3), vec![Slot(1), Slot(4)]);
+        assert_eq!(claimed(5), vec![Slot(2), Slot(5)]);
+        assert_eq!(claimed(7), vec![Slot(3), Slot(6)]);
+    }
+}
@@ -0,0 +1,244 @@
+//! Slot-indexed state that can be held to a fixed window.
+//!
+//! By default nodes keep every slot they have seen, so their memory grows
+//! with the log. In `MemoryMode::SlotBounded` each slot map holds a window
+//! of slots starting at a GC watermark: slots below it are forgotten as the
+//! watermark advances, and a slot past the end of the window is refused with
+//! a `SlotMapError` rather than stored.
+//!
+//! Watermarks only advance over slots that are decided: a replica's trails
+//! its `slot_out`, a leader's trails the slots it has seen decided, and an
+//! acceptor follows the watermark leaders send with their P2as. A replica
+//! that falls further behind than `retained` slots can no longer fetch what
+//! it missed, so every node in a cluster should use the same mode.
+use core::fmt;
+use core::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::constants::WINDOW;
+use crate::types::Slot;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryMode {
+    /// Keep every slot.
+    #[default]
+    Unbounded,
+    /// Keep `retained` decided slots behind the GC watermark, for peers that
+    /// are catching up, and `WINDOW` slots ahead of them. `retained` is at
+    /// least `WINDOW`, so a command decided twice is still recognised.
+    SlotBounded { retained: u64 },
+}
+
+impl MemoryMode {
+    /// How many decided slots to keep, if bounded.
+    pub fn retained(&self) -> Option<u64> {
+        match self {
+            MemoryMode::Unbounded => None,
+            MemoryMode::SlotBounded { retained } => Some((*retained).max(WINDOW)),
+        }
+    }
+
+    /// How many slots a map may hold, if bounded.
+    pub fn capacity(&self) -> Option<u64> {
+        self.retained().map(|retained| retained + WINDOW)
+    }
+
+    /// The GC watermark for a node whose lowest undecided slot is `undecided`.
+    pub fn watermark(&self, undecided: Slot) -> Option<Slot> {
+        self.retained()
+            .map(|retained| undecided.saturating_sub(retained).max(Slot::FIRST))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotMapError {
+    /// The slot is below the GC watermark: it was decided and forgotten.
+    Collected { slot: Slot, floor: Slot },
+    /// The slot is past the end of the window.
+    WindowExceeded {
+        slot: Slot,
+        floor: Slot,
+        capacity: u64,
+    },
+}
+
+impl fmt::Display for SlotMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotMapError::Collected { slot, floor } => {
+                write!(f, "slot {} is below the GC watermark {}", slot, floor)
+            }
+            SlotMapError::WindowExceeded {
+                slot,
+                floor,
+                capacity,
+            } => write!(
+                f,
+                "slot {} is beyond the window of {} slots from {}",
+                slot, capacity, floor
+            ),
+        }
+    }
+}
+
+impl core::error::Error for SlotMapError {}
+
+/// A map from slot to `V` holding only the slots from `floor`, and at most
+/// `capacity` of those if it is bounded.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SlotMap<V> {
+    entries: BTreeMap<Slot, V>,
+    floor: Slot,
+    capacity: Option<u64>,
+}
+
+impl<V> Default for SlotMap<V> {
+    fn default() -> Self {
+        SlotMap::new(MemoryMode::Unbounded)
+    }
+}
+
+impl<V> SlotMap<V> {
+    pub fn new(mode: MemoryMode) -> SlotMap<V> {
+        SlotMap {
+            entries: BTreeMap::new(),
+            floor: Slot::FIRST,
+            capacity: mode.capacity(),
+        }
+    }
+
+    /// Switch to `mode`, keeping the slots already held.
+    pub fn set_mode(&mut self, mode: MemoryMode) {
+        self.capacity = mode.capacity();
+    }
+
+    /// The GC watermark: slots below it are no longer held.
+    pub fn floor(&self) -> Slot {
+        self.floor
+    }
+
+    /// Whether `slot` could be stored.
+    pub fn check(&self, slot: Slot) -> Result<(), SlotMapError> {
+        if slot < self.floor {
+            return Err(SlotMapError::Collected {
+                slot,
+                floor: self.floor,
+            });
+        }
+        match self.capacity {
+            Some(capacity) if slot >= self.floor + capacity => Err(SlotMapError::WindowExceeded {
+                slot,
+                floor: self.floor,
+                capacity,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn insert(&mut self, slot: Slot, value: V) -> Result<Option<V>, SlotMapError> {
+        self.check(slot)?;
+        Ok(self.entries.insert(slot, value))
+    }
+
+    /// Forget every slot below `watermark`. The floor never moves back.
+    pub fn collect_garbage(&mut self, watermark: Slot) {
+        if watermark > self.floor {
+            self.floor = watermark;
+            self.entries = self.entries.split_off(&watermark);
+        }
+    }
+
+    pub fn get(&self, slot: &Slot) -> Option<&V> {
+        self.entries.get(slot)
+    }
+
+    pub fn contains_key(&self, slot: &Slot) -> bool {
+        self.entries.contains_key(slot)
+    }
+
+    pub fn remove(&mut self, slot: &Slot) -> Option<V> {
+        self.entries.remove(slot)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The highest slot held.
+    pub fn last_slot(&self) -> Option<Slot> {
+        self.entries.keys().next_back().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Slot, &V)> {
+        self.entries.iter()
+    }
+
+    /// The slots held in `slots`, in order.
+    pub fn range(&self, slots: Range<Slot>) -> impl Iterator<Item = (&Slot, &V)> {
+        self.entries.range(slots)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Slot> {
+        self.entries.keys()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_slot_map_refuses_slots_outside_its_window() {
+        let mode = MemoryMode::SlotBounded { retained: 10 };
+        assert_eq!(mode.capacity(), Some(10 + WINDOW));
+        let mut map: SlotMap<Slot> = SlotMap::new(mode);
+        for slot in Slot(1).up_to(Slot(11 + WINDOW)) {
+            map.insert(slot, slot).unwrap();
+        }
+        assert_eq!(
+            map.insert(Slot(11 + WINDOW), Slot(0)),
+            Err(SlotMapError::WindowExceeded {
+                slot: Slot(11 + WINDOW),
+                floor: Slot(1),
+                capacity: 10 + WINDOW
+            })
+        );
+
+        map.collect_garbage(mode.watermark(Slot(14)).unwrap());
+        assert_eq!(map.floor(), Slot(4));
+        assert_eq!(map.keys().next(), Some(&Slot(4)));
+        assert_eq!(
+            map.insert(Slot(3), Slot(0)),
+            Err(SlotMapError::Collected {
+                slot: Slot(3),
+                floor: Slot(4)
+            })
+        );
+        map.insert(Slot(13 + WINDOW), Slot(0)).unwrap();
+        assert!(map.len() as u64 <= mode.capacity().unwrap());
+
+        // The watermark never moves back
+        map.collect_garbage(Slot(2));
+        assert_eq!(map.floor(), Slot(4));
+    }
+
+    #[test]
+    fn unbounded_slot_map_only_refuses_collected_slots() {
+        let mut map: SlotMap<()> = SlotMap::default();
+        assert_eq!(MemoryMode::Unbounded.watermark(Slot(100)), None);
+        map.insert(Slot(1_000_000), ()).unwrap();
+        map.collect_garbage(Slot(10));
+        assert!(map.insert(Slot(9), ()).is_err());
+        assert_eq!(map.last_slot(), Some(Slot(1_000_000)));
+    }
+}
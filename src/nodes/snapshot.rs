@@ -0,0 +1,46 @@
+//! What a replica needs besides application state to install a snapshot.
+//!
+//! A replica far behind, or one starting afresh, can take up the log after a
+//! slot from a state machine restored elsewhere instead of performing every
+//! decision up to it. Moving the application state is up to the embedder;
+//! the `Snapshot` that goes with it tells the replica where the state
+//! leaves off, which commands it performed, so that they are neither
+//! performed nor proposed again and retries of them are answered, and the
+//! configurations decided up to it.
+//!
+//! Only commands the replica it was taken from still remembers performing
+//! are in `sessions`, as many as its memory mode retains.
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::nodes::config_timeline::ConfigTimeline;
+use crate::types::{CommandId, Slot};
+
+/// A client command performed in a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub command_id: CommandId,
+    /// The slot it was performed in.
+    pub slot: Slot,
+    /// Its result, if still cached, for answering retries.
+    pub result: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The last slot applied to the state the snapshot goes with.
+    pub through: Slot,
+    pub sessions: Vec<Session>,
+    /// The configuration changes decided up to `through`.
+    pub config_timeline: ConfigTimeline,
+}
+
+impl Snapshot {
+    /// Whether `command_id` was performed in a slot the snapshot covers.
+    pub fn performed(&self, command_id: &CommandId) -> bool {
+        self.sessions
+            .iter()
+            .any(|session| session.command_id == *command_id)
+    }
+}
@@ -0,0 +1,400 @@
+//! Structural checks on received messages.
+//!
+//! Nodes check every message they receive before it reaches their inbox,
+//! and drop the ones no correct peer would send: a ballot made by another
+//! leader than the one sending it, a slot outside `1..=MAX_SLOT`, accepted
+//! values under a higher ballot than the promise reported with them, an empty
+//! list of slots to look up, a reconfiguration or a synced or gossiped
+//! configuration leaving a role with no members, or a batch of no operations. The checks
+//! only look at the message, never at the node's state, so a message is
+//! judged the same by every node.
+//!
+//! A slot within `1..=MAX_SLOT` can still be absurdly far ahead of the log,
+//! e.g. from a buggy peer or one replaying a message with a mangled slot.
+//! Acceptors and replicas also hold the slots they are sent to a `SlotBound`
+//! past their own watermark, and refuse and count those beyond it.
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::constants::{MAX_SLOT, MAX_SLOTS_AHEAD};
+use crate::messages::{Message, SendableMessage};
+use crate::types::{BallotNumber, Command, CommandType, Config, LeaderId, NodeId, PValue, Slot};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Malformed {
+    /// A ballot that was not made by the leader sending it.
+    BallotNotSenders { ballot: BallotNumber, src: NodeId },
+    /// A slot outside `1..=MAX_SLOT`.
+    SlotOutOfRange(Slot),
+    /// An accepted value under a higher ballot than the promise sent with it.
+    AcceptedAbovePromise {
+        slot: Slot,
+        accepted: BallotNumber,
+        promised: BallotNumber,
+    },
+    /// A lookup of no slots at all.
+    NoSlots,
+    /// A reconfiguration to a configuration with no members in some role.
+    EmptyRole,
+    /// A batch of no operations.
+    EmptyBatch,
+}
+
+impl Malformed {
+    /// A short name for the check that failed, for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Malformed::BallotNotSenders { .. } => "ballot_not_senders",
+            Malformed::SlotOutOfRange(_) => "slot_out_of_range",
+            Malformed::AcceptedAbovePromise { .. } => "accepted_above_promise",
+            Malformed::NoSlots => "no_slots",
+            Malformed::EmptyRole => "empty_role",
+            Malformed::EmptyBatch => "empty_batch",
+        }
+    }
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Malformed::BallotNotSenders { ballot, src } => {
+                write!(f, "ballot {:?} was not made by its sender {}", ballot, src)
+            }
+            Malformed::SlotOutOfRange(slot) => write!(f, "slot {} is out of range", slot),
+            Malformed::AcceptedAbovePromise {
+                slot,
+                accepted,
+                promised,
+            } => write!(
+                f,
+                "slot {} accepted under {:?}, above the promise {:?}",
+                slot, accepted, promised
+            ),
+            Malformed::NoSlots => write!(f, "no slots to look up"),
+            Malformed::EmptyRole => write!(f, "reconfiguration leaves a role with no members"),
+            Malformed::EmptyBatch => write!(f, "batch of no operations"),
+        }
+    }
+}
+
+impl core::error::Error for Malformed {}
+
+/// Check `message` for structural errors.
+pub fn check<T>(message: &Message<T>) -> Result<(), Malformed> {
+    match message {
+        Message::P1a(m) => made_by(&m.ballot_number, m.src),
+        Message::PreP1a(m) => made_by(&m.ballot_number, m.src),
+        Message::P1bMore(m) => made_by(&m.ballot_number, m.src),
+        Message::P2a(m) => {
+            made_by(&m.ballot_number, m.src)?;
+            slot(m.slot_number)?;
+            command(&m.command)
+        }
+        Message::P1b(m) => accepted(&m.accepted, Some(&m.ballot_number)),
+        Message::AcceptedReply(m) => accepted(&m.accepted, None),
+        Message::P2b(m) => slot(m.slot_number),
+        Message::Decision(m) => {
+            slot(m.slot_number)?;
+            command(&m.command)
+        }
+        Message::Propose(m) => {
+            slot(m.slot_number)?;
+            command(&m.command)
+        }
+        Message::ProposeRejected(m) => slot(m.slot_number),
+        Message::ProposeAccepted(m) => slot(m.slot_number),
+        Message::Request(m) => command(&m.command),
+        Message::DecisionFetch(m) => slots(&m.slots),
+        Message::QueryAccepted(m) => slots(&m.slots),
+        Message::Barrier(m) => slot(m.slot),
+        Message::DecisionFetchReply(m) => m.decisions.iter().try_for_each(|(s, c)| {
+            slot(*s)?;
+            command(c)
+        }),
+        Message::Heartbeat(m) => match &m.ballot {
+            Some(ballot) if NodeId::from(ballot.leader) != m.src => {
+                Err(Malformed::BallotNotSenders {
+                    ballot: ballot.clone(),
+                    src: m.src,
+                })
+            }
+            _ => Ok(()),
+        },
+        Message::ConfigSync(m) => config(&m.config),
+        Message::Gossip(m) => config(&m.config),
+        Message::Preempted(_)
+        | Message::PreP1b(_)
+        | Message::TakeOver(_)
+        | Message::Response(_)
+        | Message::Committed(_)
+        | Message::Misrouted(_) => Ok(()),
+    }
+}
+
+fn made_by(ballot: &BallotNumber, src: LeaderId) -> Result<(), Malformed> {
+    if ballot.leader != src {
+        return Err(Malformed::BallotNotSenders {
+            ballot: ballot.clone(),
+            src: src.into(),
+        });
+    }
+    Ok(())
+}
+
+fn slot(slot: Slot) -> Result<(), Malformed> {
+    if !(Slot::FIRST..=MAX_SLOT).contains(&slot) {
+        return Err(Malformed::SlotOutOfRange(slot));
+    }
+    Ok(())
+}
+
+fn slots(slots: &[Slot]) -> Result<(), Malformed> {
+    if slots.is_empty() {
+        return Err(Malformed::NoSlots);
+    }
+    slots.iter().try_for_each(|s| slot(*s))
+}
+
+fn accepted<T>(pvalues: &[PValue<T>], promised: Option<&BallotNumber>) -> Result<(), Malformed> {
+    for pvalue in pvalues {
+        slot(pvalue.slot)?;
+        if let Some(promised) = promised.filter(|promised| pvalue.ballot_number > **promised) {
+            return Err(Malformed::AcceptedAbovePromise {
+                slot: pvalue.slot,
+                accepted: pvalue.ballot_number.clone(),
+                promised: promised.clone(),
+            });
+        }
+        command(&pvalue.command)?;
+    }
+    Ok(())
+}
+
+fn command<T>(command: &Command<T>) -> Result<(), Malformed> {
+    match &command.op {
+        CommandType::Reconfig(reconfig) => config(reconfig),
+        CommandType::Batch(ops) if ops.is_empty() => Err(Malformed::EmptyBatch),
+        _ => Ok(()),
+    }
+}
+
+fn config(config: &Config) -> Result<(), Malformed> {
+    if config.replicas.is_empty() || config.acceptors.is_empty() || config.leaders.is_empty() {
+        return Err(Malformed::EmptyRole);
+    }
+    Ok(())
+}
+
+/// Checks the messages a node receives and counts those it drops.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validator {
+    rejected: u64,
+}
+
+impl Validator {
+    /// Whether `msg`, received by `node`, is well-formed. A malformed
+    /// message is logged and counted as `paxos.messages.malformed`.
+    pub fn admit<T>(&mut self, node: impl fmt::Display, msg: &SendableMessage<T>) -> bool {
+        let Err(e) = check(&msg.message) else {
+            return true;
+        };
+        self.rejected += 1;
+        warn!(
+            monotonic_counter.paxos.messages.malformed = 1u64,
+            paxos.malformed.reason = e.reason(),
+            "{}: dropped malformed {}: {}",
+            node,
+            msg,
+            e
+        );
+        false
+    }
+
+    /// Malformed messages dropped so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+/// Refuses slots further past a node's watermark than any correct peer
+/// would send, and counts them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlotBound {
+    max_ahead: u64,
+    refused: u64,
+}
+
+impl Default for SlotBound {
+    fn default() -> Self {
+        SlotBound::new(MAX_SLOTS_AHEAD)
+    }
+}
+
+impl SlotBound {
+    pub fn new(max_ahead: u64) -> SlotBound {
+        SlotBound {
+            max_ahead: max_ahead.max(1),
+            refused: 0,
+        }
+    }
+
+    pub fn set_max_ahead(&mut self, max_ahead: u64) {
+        self.max_ahead = max_ahead.max(1);
+    }
+
+    /// Whether `slot`, named in a `kind` message `node` received, is within
+    /// reach of `watermark`. A slot beyond it is logged and counted as
+    /// `paxos.messages.out_of_range`.
+    pub fn admit(
+        &mut self,
+        node: impl fmt::Display,
+        kind: &'static str,
+        slot: Slot,
+        watermark: Slot,
+    ) -> bool {
+        if slot.since(watermark) <= self.max_ahead {
+            return true;
+        }
+        self.refused += 1;
+        warn!(
+            monotonic_counter.paxos.messages.out_of_range = 1u64,
+            paxos.message.kind = kind,
+            "{}: refused slot {} in a {}, more than {} slots past {}",
+            node,
+            slot,
+            kind,
+            self.max_ahead,
+            watermark
+        );
+        false
+    }
+
+    /// Out-of-range slots refused so far.
+    pub fn refused(&self) -> u64 {
+        self.refused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::messages::{DecisionFetchMessage, P1bMessage, P2aMessage, RequestMessage};
+    use crate::types::{AcceptorId, Address, Config, ReplicaId, Round};
+
+    fn ballot(round: u64, leader: u64) -> BallotNumber {
+        BallotNumber {
+            round: Round(round),
+            leader: LeaderId::new(leader),
+            incarnation: 0,
+        }
+    }
+
+    fn op(n: u8) -> Command {
+        Command {
+            client_id: NodeId::new(9),
+            request_id: u64::from(n),
+            op: CommandType::Op(vec![n]),
+        }
+    }
+
+    fn p2a(src: u64, ballot: BallotNumber, slot: u64) -> Message {
+        Message::P2a(P2aMessage {
+            src: LeaderId::new(src),
+            ballot_number: ballot,
+            slot_number: Slot(slot),
+            command: op(1),
+            gc_below: Slot(0),
+        })
+    }
+
+    #[test]
+    fn validator_drops_structurally_invalid_messages() {
+        assert_eq!(check(&p2a(1, ballot(2, 1), 1)), Ok(()));
+        assert_eq!(
+            check(&p2a(2, ballot(2, 1), 1)),
+            Err(Malformed::BallotNotSenders {
+                ballot: ballot(2, 1),
+                src: LeaderId::new(2).into(),
+            })
+        );
+        assert_eq!(
+            check(&p2a(1, ballot(2, 1), 0)),
+            Err(Malformed::SlotOutOfRange(Slot(0)))
+        );
+        assert_eq!(
+            check(&p2a(1, ballot(2, 1), MAX_SLOT.0 + 1)),
+            Err(Malformed::SlotOutOfRange(MAX_SLOT.next()))
+        );
+
+        let p1b = Message::<Vec<u8>>::P1b(P1bMessage {
+            src: AcceptorId::new(1),
+            ballot_number: ballot(2, 1),
+            accepted: vec![PValue {
+                ballot_number: ballot(3, 2),
+                slot: Slot(1),
+                command: op(1),
+            }],
+            gc_below: Slot(0),
+            witnessed: vec![],
+            continues_from: None,
+            more_from: None,
+        });
+        assert_eq!(check(&p1b).unwrap_err().reason(), "accepted_above_promise");
+
+        let fetch = Message::<Vec<u8>>::DecisionFetch(DecisionFetchMessage {
+            src: ReplicaId::new(1),
+            slots: vec![],
+        });
+        assert_eq!(check(&fetch), Err(Malformed::NoSlots));
+
+        let address = Address::new("127.0.0.1".to_string(), 9000);
+        let reconfig = Message::<Vec<u8>>::Request(RequestMessage {
+            src: address.clone(),
+            command: Command {
+                client_id: NodeId::new(9),
+                request_id: 1,
+                op: CommandType::Reconfig(Config::new(
+                    HashSet::from([ReplicaId::new(1)]),
+                    HashSet::new(),
+                    HashSet::from([LeaderId::new(1)]),
+                    BTreeMap::new(),
+                    None,
+                )),
+            },
+            consistency: Default::default(),
+            proxied_by: None,
+        });
+        assert_eq!(check(&reconfig), Err(Malformed::EmptyRole));
+
+        let batch = Message::<Vec<u8>>::Request(RequestMessage {
+            src: address.clone(),
+            command: Command {
+                client_id: NodeId::new(9),
+                request_id: 2,
+                op: CommandType::Batch(vec![]),
+            },
+            consistency: Default::default(),
+            proxied_by: None,
+        });
+        assert_eq!(check(&batch), Err(Malformed::EmptyBatch));
+
+        let mut validator = Validator::default();
+        let sendable = |message| SendableMessage {
+            src: address.clone(),
+            dst: address.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message,
+        };
+        assert!(validator.admit("a1", &sendable(p2a(1, ballot(2, 1), 1))));
+        assert!(!validator.admit("a1", &sendable(p2a(1, ballot(2, 1), 0))));
+        assert_eq!(validator.rejected(), 1);
+    }
+}
@@ -0,0 +1,247 @@
+//! Disk faults on demand, for testing recovery.
+//!
+//! A `FaultyStore` wraps any store and makes chosen writes misbehave the way
+//! disks do. The faults to inject are kept in a `Faults` that the test holds
+//! on to after handing the store to a node, so it can arm them at the point
+//! it wants to test:
+//!
+//! - `Fault::WriteError`: the write fails and nothing reaches the store.
+//! - `Fault::TornWrite`: part of the write reaches the store before it
+//!   fails. A whole-value store is left corrupt, and loads fail until a
+//!   later write succeeds; an appended record is lost.
+//! - `Fault::FsyncError`: the write reaches the store, but the caller gets
+//!   an error, as when the sync that would make it durable fails.
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::messages;
+use crate::persistence::{BallotStore, DecisionLog, Decisions, OutboxStore, RequestStore};
+use crate::types;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    WriteError,
+    TornWrite,
+    FsyncError,
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    // Writes attempted so far
+    writes: u64,
+    // Faults to inject, by the number of the write they hit
+    armed: BTreeMap<u64, Fault>,
+    // Fault for every write from now on
+    always: Option<Fault>,
+}
+
+/// The faults a `FaultyStore` injects. Clones share the same plan.
+#[derive(Clone, Debug, Default)]
+pub struct Faults(Arc<Mutex<Plan>>);
+
+impl Faults {
+    pub fn new() -> Faults {
+        Faults::default()
+    }
+
+    /// Make the next write fail with `fault`.
+    pub fn fail_next(&self, fault: Fault) {
+        self.fail_after(0, fault);
+    }
+
+    /// Let `writes` more writes through, then fail the one after with `fault`.
+    pub fn fail_after(&self, writes: u64, fault: Fault) {
+        let mut plan = self.lock();
+        let at = plan.writes + writes + 1;
+        plan.armed.insert(at, fault);
+    }
+
+    /// Fail every write with `fault` until `heal`.
+    pub fn fail_always(&self, fault: Fault) {
+        self.lock().always = Some(fault);
+    }
+
+    /// Stop injecting faults.
+    pub fn heal(&self) {
+        let mut plan = self.lock();
+        plan.armed.clear();
+        plan.always = None;
+    }
+
+    /// Writes attempted so far, failed or not.
+    pub fn writes(&self) -> u64 {
+        self.lock().writes
+    }
+
+    fn next_write(&self) -> Option<Fault> {
+        let mut plan = self.lock();
+        plan.writes += 1;
+        let writes = plan.writes;
+        plan.armed.remove(&writes).or(plan.always)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Plan> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A store whose writes fail as `Faults` says.
+#[derive(Debug)]
+pub struct FaultyStore<S> {
+    inner: S,
+    faults: Faults,
+    // A torn write left the store unreadable
+    corrupt: bool,
+}
+
+impl<S> FaultyStore<S> {
+    pub fn new(inner: S, faults: Faults) -> FaultyStore<S> {
+        FaultyStore {
+            inner,
+            faults,
+            corrupt: false,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn check_readable(&self) -> anyhow::Result<()> {
+        if self.corrupt {
+            anyhow::bail!("injected fault: store corrupted by a torn write");
+        }
+        Ok(())
+    }
+
+    /// Write through `write` unless a fault says otherwise. `tear` writes
+    /// the part of a torn write that reaches the store, if any does.
+    fn write(
+        &mut self,
+        write: impl FnOnce(&mut S) -> anyhow::Result<()>,
+        tear: impl FnOnce(&mut S) -> anyhow::Result<()>,
+        whole_value: bool,
+    ) -> anyhow::Result<()> {
+        match self.faults.next_write() {
+            None => {
+                write(&mut self.inner)?;
+                self.corrupt = false;
+                Ok(())
+            }
+            Some(Fault::WriteError) => anyhow::bail!("injected fault: write failed"),
+            Some(Fault::TornWrite) => {
+                tear(&mut self.inner)?;
+                self.corrupt |= whole_value;
+                anyhow::bail!("injected fault: torn write")
+            }
+            Some(Fault::FsyncError) => {
+                write(&mut self.inner)?;
+                self.corrupt = false;
+                anyhow::bail!("injected fault: fsync failed")
+            }
+        }
+    }
+}
+
+impl<S: BallotStore> BallotStore for FaultyStore<S> {
+    fn load(&mut self) -> anyhow::Result<Option<types::BallotNumber>> {
+        self.check_readable()?;
+        self.inner.load()
+    }
+
+    fn store(&mut self, ballot: &types::BallotNumber) -> anyhow::Result<()> {
+        self.write(|inner| inner.store(ballot), |_| Ok(()), true)
+    }
+}
+
+impl<T, S: RequestStore<T>> RequestStore<T> for FaultyStore<S> {
+    fn load(&mut self) -> anyhow::Result<Vec<types::Command<T>>> {
+        self.check_readable()?;
+        self.inner.load()
+    }
+
+    fn store(&mut self, pending: &[types::Command<T>]) -> anyhow::Result<()> {
+        let torn = &pending[..pending.len() / 2];
+        self.write(
+            |inner| inner.store(pending),
+            |inner| inner.store(torn),
+            true,
+        )
+    }
+}
+
+impl<T, S: OutboxStore<T>> OutboxStore<T> for FaultyStore<S> {
+    fn load(&mut self) -> anyhow::Result<Vec<messages::SendableMessage<T>>> {
+        self.check_readable()?;
+        self.inner.load()
+    }
+
+    fn store(&mut self, unsent: &[messages::SendableMessage<T>]) -> anyhow::Result<()> {
+        let torn = &unsent[..unsent.len() / 2];
+        self.write(|inner| inner.store(unsent), |inner| inner.store(torn), true)
+    }
+}
+
+impl<T, S: DecisionLog<T>> DecisionLog<T> for FaultyStore<S> {
+    fn load(&mut self) -> anyhow::Result<Vec<(types::Slot, types::Command<T>)>> {
+        self.inner.load()
+    }
+
+    fn append(&mut self, slot: types::Slot, command: &types::Command<T>) -> anyhow::Result<()> {
+        // A cut-short record is skipped when the log is read back
+        self.write(|inner| inner.append(slot, command), |_| Ok(()), false)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.inner.sync()
+    }
+
+    fn sync_interval(&self) -> Option<crate::time::Duration> {
+        self.inner.sync_interval()
+    }
+
+    fn range<'a>(&'a mut self, slots: Range<types::Slot>) -> anyhow::Result<Decisions<'a, T>>
+    where
+        T: 'a,
+    {
+        self.inner.range(slots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::VolatileBallotStore;
+    use crate::types::{BallotNumber, LeaderId, Round};
+
+    #[test]
+    fn faulty_store_injects_faults_at_the_armed_writes() {
+        let faults = Faults::new();
+        let mut store = FaultyStore::new(VolatileBallotStore::default(), faults.clone());
+        let ballot = |round| BallotNumber {
+            round,
+            leader: LeaderId::new(1),
+            incarnation: 0,
+        };
+
+        faults.fail_after(1, Fault::WriteError);
+        store.store(&ballot(Round(1))).unwrap();
+        assert!(store.store(&ballot(Round(2))).is_err());
+        assert_eq!(store.load().unwrap(), Some(ballot(Round(1))));
+
+        // The write is kept even though the caller is told it failed
+        faults.fail_next(Fault::FsyncError);
+        assert!(store.store(&ballot(Round(3))).is_err());
+        assert_eq!(store.load().unwrap(), Some(ballot(Round(3))));
+
+        // A torn write leaves nothing readable until a write succeeds
+        faults.fail_always(Fault::TornWrite);
+        assert!(store.store(&ballot(Round(4))).is_err());
+        assert!(store.load().is_err());
+        faults.heal();
+        store.store(&ballot(Round(5))).unwrap();
+        assert_eq!(store.load().unwrap(), Some(ballot(Round(5))));
+        assert_eq!(faults.writes(), 5);
+    }
+}
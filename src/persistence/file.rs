@@ -0,0 +1,479 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::constants::{GROUP_COMMIT_INTERVAL_MS, GROUP_COMMIT_MAX_BATCH};
+use crate::messages;
+use crate::persistence::monitor::StorageMonitor;
+use crate::persistence::{BallotStore, DecisionLog, Decisions, InRange, OutboxStore, RequestStore};
+use crate::types;
+
+/// Replace the file at `path` with `bytes` so that a crash leaves either the
+/// old or the new contents: write a sibling temporary file, sync it, rename it
+/// over the original and sync the directory. Both syncs count towards the
+/// fsync latency reported to `monitor`.
+fn write_atomically(path: &Path, bytes: &[u8], monitor: &StorageMonitor) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    let written = Instant::now();
+    file.sync_all()?;
+    let mut fsync = written.elapsed();
+    fs::rename(&tmp, path)?;
+    // Make the rename itself durable
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        let dir = File::open(dir)?;
+        let syncing = Instant::now();
+        dir.sync_all()?;
+        fsync += syncing.elapsed();
+    }
+    monitor.record(path, written.duration_since(started), fsync);
+    Ok(())
+}
+
+/// Stores the ballot as JSON in a single file, replaced atomically on each store.
+#[derive(Debug)]
+pub struct FileBallotStore {
+    path: PathBuf,
+    monitor: StorageMonitor,
+}
+
+impl FileBallotStore {
+    pub fn new(path: impl AsRef<Path>) -> FileBallotStore {
+        FileBallotStore {
+            path: path.as_ref().to_path_buf(),
+            monitor: StorageMonitor::default(),
+        }
+    }
+
+    /// Report write latencies to `monitor`, e.g. one shared with other stores on the same disk.
+    pub fn with_monitor(mut self, monitor: StorageMonitor) -> FileBallotStore {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn monitor(&self) -> &StorageMonitor {
+        &self.monitor
+    }
+}
+
+impl BallotStore for FileBallotStore {
+    fn load(&mut self) -> anyhow::Result<Option<types::BallotNumber>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, ballot: &types::BallotNumber) -> anyhow::Result<()> {
+        write_atomically(&self.path, &serde_json::to_vec(ballot)?, &self.monitor)
+    }
+}
+
+/// Stores a replica's pending commands as a JSON array in a single file,
+/// replaced atomically on each store.
+#[derive(Debug)]
+pub struct FileRequestStore {
+    path: PathBuf,
+    monitor: StorageMonitor,
+}
+
+impl FileRequestStore {
+    pub fn new(path: impl AsRef<Path>) -> FileRequestStore {
+        FileRequestStore {
+            path: path.as_ref().to_path_buf(),
+            monitor: StorageMonitor::default(),
+        }
+    }
+
+    /// Report write latencies to `monitor`, e.g. one shared with other stores on the same disk.
+    pub fn with_monitor(mut self, monitor: StorageMonitor) -> FileRequestStore {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn monitor(&self) -> &StorageMonitor {
+        &self.monitor
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> RequestStore<T> for FileRequestStore {
+    fn load(&mut self) -> anyhow::Result<Vec<types::Command<T>>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, pending: &[types::Command<T>]) -> anyhow::Result<()> {
+        write_atomically(&self.path, &serde_json::to_vec(pending)?, &self.monitor)
+    }
+}
+
+/// Stores a node's unsent messages as a JSON array in a single file,
+/// replaced atomically on each store.
+#[derive(Debug)]
+pub struct FileOutboxStore {
+    path: PathBuf,
+    monitor: StorageMonitor,
+}
+
+impl FileOutboxStore {
+    pub fn new(path: impl AsRef<Path>) -> FileOutboxStore {
+        FileOutboxStore {
+            path: path.as_ref().to_path_buf(),
+            monitor: StorageMonitor::default(),
+        }
+    }
+
+    /// Report write latencies to `monitor`, e.g. one shared with other stores on the same disk.
+    pub fn with_monitor(mut self, monitor: StorageMonitor) -> FileOutboxStore {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn monitor(&self) -> &StorageMonitor {
+        &self.monitor
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> OutboxStore<T> for FileOutboxStore {
+    fn load(&mut self) -> anyhow::Result<Vec<messages::SendableMessage<T>>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, unsent: &[messages::SendableMessage<T>]) -> anyhow::Result<()> {
+        write_atomically(&self.path, &serde_json::to_vec(unsent)?, &self.monitor)
+    }
+}
+
+/// When a `FileDecisionLog` syncs the decisions appended to it.
+///
+/// Rather than writing each decision through, the log holds them back and
+/// writes and syncs them together once `max_batch` are waiting, or when the
+/// replica's clock calls for a sync every `interval`. A crash loses the
+/// decisions held back, but never one the log acknowledged as synced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupCommit {
+    pub max_batch: usize,
+    pub interval: Duration,
+}
+
+impl Default for GroupCommit {
+    fn default() -> Self {
+        GroupCommit {
+            max_batch: GROUP_COMMIT_MAX_BATCH,
+            interval: Duration::from_millis(GROUP_COMMIT_INTERVAL_MS),
+        }
+    }
+}
+
+/// Appends a replica's decisions to a file, one JSON `[slot, command]` per line.
+///
+/// By default each decision is written through as it is appended, without
+/// a sync. With `with_group_commit` they are synced in batches instead.
+#[derive(Debug)]
+pub struct FileDecisionLog {
+    path: PathBuf,
+    file: File,
+    monitor: StorageMonitor,
+    group_commit: Option<GroupCommit>,
+    // Lines appended but not yet written, and the last slot among them
+    held: Vec<u8>,
+    held_count: usize,
+    held_through: Option<types::Slot>,
+    synced_through: Option<types::Slot>,
+}
+
+impl FileDecisionLog {
+    /// Open (or create) the log at `path` for appending.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<FileDecisionLog> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(FileDecisionLog {
+            path,
+            file,
+            monitor: StorageMonitor::default(),
+            group_commit: None,
+            held: Vec::new(),
+            held_count: 0,
+            held_through: None,
+            synced_through: None,
+        })
+    }
+
+    /// Sync appended decisions in batches, as `group_commit` says.
+    pub fn with_group_commit(mut self, group_commit: GroupCommit) -> FileDecisionLog {
+        self.group_commit = Some(group_commit);
+        self
+    }
+
+    /// Report write latencies to `monitor`, e.g. one shared with other stores on the same disk.
+    pub fn with_monitor(mut self, monitor: StorageMonitor) -> FileDecisionLog {
+        self.monitor = monitor;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn monitor(&self) -> &StorageMonitor {
+        &self.monitor
+    }
+
+    /// The slot of the last decision synced by a group commit: it and every
+    /// decision appended before it survive a crash.
+    pub fn synced_through(&self) -> Option<types::Slot> {
+        self.synced_through
+    }
+
+    /// Decisions appended and held back, not yet synced.
+    pub fn held(&self) -> usize {
+        self.held_count
+    }
+
+    /// Read the log at `path` without opening it for appending, e.g. a copy
+    /// taken from another replica. A torn final line is skipped.
+    pub fn read<T: DeserializeOwned>(
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<Vec<(types::Slot, types::Command<T>)>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut decisions = Vec::new();
+        for line in reader.lines() {
+            if let Ok(decision) = serde_json::from_str(&line?) {
+                decisions.push(decision);
+            }
+        }
+        Ok(decisions)
+    }
+
+    // Write the decisions held back and sync them, in one go
+    fn commit(&mut self) -> anyhow::Result<()> {
+        if self.held.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        self.file.write_all(&self.held)?;
+        let written = Instant::now();
+        self.file.sync_data()?;
+        self.monitor.record(
+            &self.path,
+            written.duration_since(started),
+            written.elapsed(),
+        );
+        self.held.clear();
+        self.held_count = 0;
+        self.synced_through = self.held_through.take();
+        Ok(())
+    }
+}
+
+impl Drop for FileDecisionLog {
+    fn drop(&mut self) {
+        // Closing the log is no crash: keep what was held back
+        let _ = self.commit();
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> DecisionLog<T> for FileDecisionLog {
+    fn load(&mut self) -> anyhow::Result<Vec<(types::Slot, types::Command<T>)>> {
+        self.commit()?;
+        FileDecisionLog::read(&self.path)
+    }
+
+    /// Read a line at a time, stopping once past `slots`.
+    fn range<'a>(&'a mut self, slots: Range<types::Slot>) -> anyhow::Result<Decisions<'a, T>>
+    where
+        T: 'a,
+    {
+        // Decisions held back are read back too
+        self.commit()?;
+        let reader = BufReader::new(File::open(&self.path)?);
+        let logged = reader.lines().filter_map(|line| match line {
+            // A torn final line is skipped, as by `read`
+            Ok(line) => serde_json::from_str(&line).ok().map(Ok),
+            Err(e) => Some(Err(e.into())),
+        });
+        Ok(Box::new(InRange::new(logged, slots)))
+    }
+
+    fn append(&mut self, slot: types::Slot, command: &types::Command<T>) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(&(slot, command))?;
+        line.push(b'\n');
+        let Some(group_commit) = self.group_commit else {
+            self.file.write_all(&line)?;
+            self.file.flush()?;
+            return Ok(());
+        };
+        self.held.extend_from_slice(&line);
+        self.held_count += 1;
+        self.held_through = Some(slot);
+        if self.held_count >= group_commit.max_batch {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.commit()
+    }
+
+    fn sync_interval(&self) -> Option<Duration> {
+        self.group_commit.map(|group_commit| group_commit.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::*;
+
+    #[test]
+    fn file_ballot_store_round_trips() {
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-ballot-{}-{}.json",
+            std::process::id(),
+            line!()
+        ));
+        let _ = fs::remove_file(&path);
+        let mut store = FileBallotStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+
+        let ballot = BallotNumber {
+            round: Round(7),
+            leader: LeaderId::new(2),
+            incarnation: 0,
+        };
+        store.store(&ballot).unwrap();
+        assert_eq!(FileBallotStore::new(&path).load().unwrap(), Some(ballot));
+        assert!(store.monitor().fsync_latency().is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_decision_log_streams_each_slot_once_in_range() {
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-decisions-{}-{}.jsonl",
+            std::process::id(),
+            line!()
+        ));
+        let _ = fs::remove_file(&path);
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+        let mut log = FileDecisionLog::open(&path).unwrap();
+        // A first life logs slots 1 to 3, a second one 1 to 6
+        for slot in (1..4).chain(1..7) {
+            DecisionLog::append(&mut log, Slot(slot), &command(slot)).unwrap();
+        }
+        // A write torn by a crash
+        log.file.write_all(b"[7,{\"client_id\"").unwrap();
+
+        let read = |log: &mut FileDecisionLog, slots| {
+            DecisionLog::<Vec<u8>>::range(log, slots)
+                .unwrap()
+                .map(|decision| decision.unwrap().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(read(&mut log, Slot(2)..Slot(5)), [2, 3, 4].map(Slot));
+        assert_eq!(
+            read(&mut log, Slot(0)..Slot(100)),
+            [1, 2, 3, 4, 5, 6].map(Slot)
+        );
+        assert!(read(&mut log, Slot(8)..Slot(9)).is_empty());
+        let (slot, logged) = DecisionLog::<Vec<u8>>::range(&mut log, Slot(5)..Slot(6))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!((slot, logged), (Slot(5), command(5)));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn group_commit_recovers_exactly_the_synced_decisions_after_a_crash() {
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-decisions-{}-{}.jsonl",
+            std::process::id(),
+            line!()
+        ));
+        let _ = fs::remove_file(&path);
+        let command = |request_id: u64| Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![request_id as u8]),
+        };
+        let group_commit = GroupCommit {
+            max_batch: 3,
+            interval: Duration::from_secs(3600),
+        };
+        let mut log = FileDecisionLog::open(&path)
+            .unwrap()
+            .with_group_commit(group_commit);
+        assert_eq!(
+            DecisionLog::<Vec<u8>>::sync_interval(&log),
+            Some(group_commit.interval)
+        );
+        // Two full batches are synced, the seventh decision is held back
+        for slot in 1..8 {
+            DecisionLog::append(&mut log, Slot(slot), &command(slot)).unwrap();
+        }
+        assert_eq!(log.synced_through(), Some(Slot(6)));
+        assert_eq!(log.held(), 1);
+        assert!(log.monitor().fsync_latency().is_some());
+
+        // A crash loses what was held back, and only that
+        std::mem::forget(log);
+        let recovered: Vec<(Slot, Command)> = FileDecisionLog::read(&path).unwrap();
+        assert_eq!(
+            recovered,
+            (1..7)
+                .map(|slot| (Slot(slot), command(slot)))
+                .collect::<Vec<_>>()
+        );
+
+        // A sync acknowledges a partial batch
+        let mut log = FileDecisionLog::open(&path)
+            .unwrap()
+            .with_group_commit(group_commit);
+        DecisionLog::append(&mut log, Slot(7), &command(7)).unwrap();
+        assert_eq!(log.synced_through(), None);
+        DecisionLog::<Vec<u8>>::sync(&mut log).unwrap();
+        assert_eq!(log.synced_through(), Some(Slot(7)));
+        std::mem::forget(log);
+        let recovered: Vec<(Slot, Command)> = FileDecisionLog::read(&path).unwrap();
+        assert_eq!(recovered.last(), Some(&(Slot(7), command(7))));
+        assert_eq!(recovered.len(), 7);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,5 +1,161 @@
-/// From "Paxos Made Simple":
-///     Agents operate at arbitrary speed, may fail by stopping, and may restart.
-///     Since all agents may fail after a value is chosen and then restart,
-///     a solution is impossible unless some information can be re-membered
-///     by an agent that has failed and restarted.
+//! From "Paxos Made Simple":
+//!     Agents operate at arbitrary speed, may fail by stopping, and may restart.
+//!     Since all agents may fail after a value is chosen and then restart,
+//!     a solution is impossible unless some information can be re-membered
+//!     by an agent that has failed and restarted.
+#[cfg(feature = "std")]
+pub mod faulty;
+#[cfg(feature = "std")]
+pub mod file;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(all(feature = "object-store", not(target_arch = "wasm32")))]
+pub mod object_store;
+#[cfg(feature = "async")]
+pub mod snapshots;
+pub mod verify;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::messages;
+use crate::time::Duration;
+use crate::types;
+
+/// Durable cell holding an acceptor's highest promised ballot.
+///
+/// Kept apart from accepted-pvalue storage, which may be flushed lazily:
+/// an acceptor must never promise a lower ballot after a restart, so every
+/// `store` has to be durable before the promise is sent.
+///
+/// Leaders use one too, for the last ballot they moved to, so that after a
+/// restart they can reclaim leadership without first being preempted, under a
+/// new incarnation that no earlier ballot of theirs shares.
+pub trait BallotStore {
+    /// The last stored ballot, or None if nothing was ever stored.
+    fn load(&mut self) -> anyhow::Result<Option<types::BallotNumber>>;
+
+    /// Durably replace the stored ballot.
+    fn store(&mut self, ballot: &types::BallotNumber) -> anyhow::Result<()>;
+}
+
+/// Keeps the ballot in memory only; promises do not survive a restart.
+#[derive(Clone, Debug, Default)]
+pub struct VolatileBallotStore {
+    ballot: Option<types::BallotNumber>,
+}
+
+impl BallotStore for VolatileBallotStore {
+    fn load(&mut self) -> anyhow::Result<Option<types::BallotNumber>> {
+        Ok(self.ballot.clone())
+    }
+
+    fn store(&mut self, ballot: &types::BallotNumber) -> anyhow::Result<()> {
+        self.ballot = Some(ballot.clone());
+        Ok(())
+    }
+}
+
+/// Durable copy of the client commands a replica holds that have not been decided yet.
+///
+/// Without one, requests queued at a replica that crashes are lost and only
+/// come back if the client retries. Every `store` replaces the whole set.
+pub trait RequestStore<T = Vec<u8>> {
+    /// The last stored commands, oldest first; empty if nothing was ever stored.
+    fn load(&mut self) -> anyhow::Result<Vec<types::Command<T>>>;
+
+    /// Durably replace the stored commands.
+    fn store(&mut self, pending: &[types::Command<T>]) -> anyhow::Result<()>;
+}
+
+/// Durable copy of the messages a node has sent that the transport has not taken yet.
+///
+/// Without one, messages waiting in the outbox of a node that crashes are
+/// lost: a Decision or P2b that never goes out costs a round of retries, or
+/// a replica's catch-up. Every `store` replaces the whole set.
+pub trait OutboxStore<T = Vec<u8>> {
+    /// The last stored messages, in the order they were sent; empty if nothing was ever stored.
+    fn load(&mut self) -> anyhow::Result<Vec<messages::SendableMessage<T>>>;
+
+    /// Durably replace the stored messages.
+    fn store(&mut self, unsent: &[messages::SendableMessage<T>]) -> anyhow::Result<()>;
+}
+
+/// Decisions read back from a `DecisionLog` one at a time.
+pub type Decisions<'a, T> =
+    Box<dyn Iterator<Item = anyhow::Result<(types::Slot, types::Command<T>)>> + 'a>;
+
+/// Append-only record of the decisions a replica has performed, in slot order.
+///
+/// Replicas keep decisions in memory only; a log of them lets operators
+/// compare replicas after the fact, see `verify::verify_decision_logs`, and
+/// lets anyone read back decisions the replica has since forgotten. A
+/// replica that restarts performs its decisions again and appends them again.
+pub trait DecisionLog<T = Vec<u8>> {
+    /// Every logged decision, in the order they were appended.
+    fn load(&mut self) -> anyhow::Result<Vec<(types::Slot, types::Command<T>)>>;
+
+    fn append(&mut self, slot: types::Slot, command: &types::Command<T>) -> anyhow::Result<()>;
+
+    /// Make every decision appended so far durable. Logs that write each
+    /// append through have nothing to do.
+    fn sync(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// How often a log that holds appends back wants `sync` called, e.g. by
+    /// the replica's clock; None for logs that write each append through.
+    fn sync_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// The logged decisions in `slots`, in slot order and each slot once:
+    /// those logged again after a restart are skipped. Logs that can should
+    /// read them as they are iterated; by default the whole log is loaded.
+    fn range<'a>(&'a mut self, slots: Range<types::Slot>) -> anyhow::Result<Decisions<'a, T>>
+    where
+        T: 'a,
+    {
+        let logged = self.load()?;
+        Ok(Box::new(InRange::new(logged.into_iter().map(Ok), slots)))
+    }
+}
+
+/// The decisions of a log in `slots`, as `DecisionLog::range` returns them,
+/// from every decision in the log in the order they were appended.
+pub struct InRange<I> {
+    logged: I,
+    slots: Range<types::Slot>,
+}
+
+impl<I> InRange<I> {
+    pub fn new(logged: I, slots: Range<types::Slot>) -> InRange<I> {
+        InRange { logged, slots }
+    }
+}
+
+impl<T, I> Iterator for InRange<I>
+where
+    I: Iterator<Item = anyhow::Result<(types::Slot, types::Command<T>)>>,
+{
+    type Item = anyhow::Result<(types::Slot, types::Command<T>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.slots.is_empty() {
+            let (slot, command) = match self.logged.next()? {
+                Ok(decision) => decision,
+                Err(e) => return Some(Err(e)),
+            };
+            // Each life of the replica logs its slots in order, and a later
+            // life only repeats what an earlier one logged before going on
+            if slot >= self.slots.end {
+                self.slots.start = self.slots.end;
+            } else if slot >= self.slots.start {
+                self.slots.start = slot + 1;
+                return Some(Ok((slot, command)));
+            }
+        }
+        None
+    }
+}
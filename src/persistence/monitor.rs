@@ -0,0 +1,127 @@
+//! Latency tracking for durable writes.
+//!
+//! Slow disks are the most common reason a cluster that looks healthy stops
+//! deciding anything: acceptors sync every promise before answering it. File
+//! stores report each write to a `StorageMonitor`, which records the
+//! `paxos.storage.write_ms` and `paxos.storage.fsync_ms` histograms, warns
+//! about each slow sync and turns the smoothed sync latency into a `Health`
+//! report for the embedder to combine with the node's own.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::constants::{SLOW_FSYNC_MS, STALLED_FSYNC_MS};
+use crate::nodes::health::Health;
+
+/// Shared by every store writing to the same disk; clones report to the same figures.
+#[derive(Clone, Debug)]
+pub struct StorageMonitor {
+    slow_fsync: Duration,
+    stalled_fsync: Duration,
+    // Smoothed fsync latency
+    fsync_latency: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Default for StorageMonitor {
+    fn default() -> Self {
+        StorageMonitor::new(
+            Duration::from_millis(SLOW_FSYNC_MS),
+            Duration::from_millis(STALLED_FSYNC_MS),
+        )
+    }
+}
+
+impl StorageMonitor {
+    /// Report `Degraded` once syncs take `slow_fsync` on average, and
+    /// `NotReady` once they take `stalled_fsync`.
+    pub fn new(slow_fsync: Duration, stalled_fsync: Duration) -> StorageMonitor {
+        StorageMonitor {
+            slow_fsync,
+            stalled_fsync,
+            fsync_latency: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Record one durable write of `path`: `write` spent handing the bytes to
+    /// the OS, `fsync` waiting for them to reach the disk.
+    pub fn record(&self, path: &Path, write: Duration, fsync: Duration) {
+        let write_ms = write.as_secs_f64() * 1000.0;
+        let fsync_ms = fsync.as_secs_f64() * 1000.0;
+        debug!(
+            histogram.paxos.storage.write_ms = write_ms,
+            histogram.paxos.storage.fsync_ms = fsync_ms,
+            "storage: wrote {}",
+            path.display()
+        );
+        if fsync >= self.slow_fsync {
+            warn!(
+                paxos.storage.path = %path.display(),
+                paxos.storage.fsync_ms = fsync_ms,
+                "storage: fsync of {} took {:?}",
+                path.display(),
+                fsync
+            );
+        }
+        let mut latency = self.fsync_latency.lock().unwrap_or_else(|e| e.into_inner());
+        // Same smoothing as the leaders' P2b latency: 7/8 history, 1/8 sample
+        *latency = Some(match *latency {
+            Some(average) => (average * 7 + fsync) / 8,
+            None => fsync,
+        });
+    }
+
+    /// Smoothed fsync latency, or None before the first write.
+    pub fn fsync_latency(&self) -> Option<Duration> {
+        *self.fsync_latency.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn health(&self) -> Health {
+        let Some(latency) = self.fsync_latency() else {
+            return Health::Ready;
+        };
+        let reason = |threshold| {
+            vec![format!(
+                "storage fsync latency {:?} exceeds {:?}",
+                latency, threshold
+            )]
+        };
+        if latency >= self.stalled_fsync {
+            Health::from_checks(reason(self.stalled_fsync), vec![])
+        } else if latency >= self.slow_fsync {
+            Health::from_checks(vec![], reason(self.slow_fsync))
+        } else {
+            Health::Ready
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_monitor_reports_slow_fsyncs() {
+        let monitor = StorageMonitor::new(Duration::from_millis(100), Duration::from_secs(1));
+        let path = Path::new("ballot.json");
+        assert_eq!(monitor.health(), Health::Ready);
+
+        monitor.record(path, Duration::from_millis(1), Duration::from_millis(5));
+        assert_eq!(monitor.health(), Health::Ready);
+
+        // One slow sync is smoothed over; a disk that stays slow is not
+        let shared = monitor.clone();
+        shared.record(path, Duration::from_millis(1), Duration::from_millis(400));
+        assert_eq!(monitor.health(), Health::Ready);
+        for _ in 0..8 {
+            shared.record(path, Duration::from_millis(1), Duration::from_millis(400));
+        }
+        assert!(matches!(monitor.health(), Health::Degraded { .. }));
+
+        for _ in 0..32 {
+            shared.record(path, Duration::from_millis(1), Duration::from_secs(3));
+        }
+        assert!(!monitor.health().is_ready());
+    }
+}
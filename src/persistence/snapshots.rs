@@ -0,0 +1,86 @@
+//! Snapshots kept in storage shared by the replicas.
+//!
+//! A replica's `Snapshot` only describes the application state it goes
+//! with, which the embedder moves. Kept together in a `SnapshotStorage`, a
+//! large snapshot can be written once by a healthy replica and fetched by a
+//! replica bootstrapping from it, instead of being streamed from a peer.
+use std::future::Future;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::nodes::snapshot::Snapshot;
+use crate::types::Slot;
+
+/// A snapshot as kept in storage: the replica's `Snapshot` and the
+/// application state it goes with, encoded by the application.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredSnapshot {
+    pub snapshot: Snapshot,
+    pub state: Vec<u8>,
+}
+
+impl StoredSnapshot {
+    /// The last slot the snapshot covers.
+    pub fn through(&self) -> Slot {
+        self.snapshot.through
+    }
+}
+
+/// Where replicas put snapshots and bootstrapping replicas fetch them.
+///
+/// Each snapshot is kept under the last slot it covers. A snapshot must be
+/// fully stored before `latest` returns it, so a replica never fetches one
+/// half written.
+pub trait SnapshotStorage {
+    fn put(&self, snapshot: &StoredSnapshot) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// The snapshot through `through`, if one is stored.
+    fn get(
+        &self,
+        through: Slot,
+    ) -> impl Future<Output = anyhow::Result<Option<StoredSnapshot>>> + Send;
+
+    /// The snapshot through the highest slot stored, if any.
+    fn latest(&self) -> impl Future<Output = anyhow::Result<Option<StoredSnapshot>>> + Send;
+}
+
+/// Keeps snapshots in memory, e.g. for tests or nodes sharing a process.
+#[derive(Debug, Default)]
+pub struct MemorySnapshotStorage {
+    snapshots: Mutex<BTreeMap<Slot, StoredSnapshot>>,
+}
+
+impl MemorySnapshotStorage {
+    pub fn new() -> MemorySnapshotStorage {
+        MemorySnapshotStorage::default()
+    }
+}
+
+impl SnapshotStorage for MemorySnapshotStorage {
+    async fn put(&self, snapshot: &StoredSnapshot) -> anyhow::Result<()> {
+        let mut snapshots = self
+            .snapshots
+            .lock()
+            .expect("snapshot storage lock poisoned");
+        snapshots.insert(snapshot.through(), snapshot.clone());
+        Ok(())
+    }
+
+    async fn get(&self, through: Slot) -> anyhow::Result<Option<StoredSnapshot>> {
+        let snapshots = self
+            .snapshots
+            .lock()
+            .expect("snapshot storage lock poisoned");
+        Ok(snapshots.get(&through).cloned())
+    }
+
+    async fn latest(&self) -> anyhow::Result<Option<StoredSnapshot>> {
+        let snapshots = self
+            .snapshots
+            .lock()
+            .expect("snapshot storage lock poisoned");
+        Ok(snapshots.values().next_back().cloned())
+    }
+}
@@ -0,0 +1,123 @@
+//! Cross-checking the decision logs of several replicas.
+//!
+//! Replicas that agree decide the same command in every slot. Given each
+//! replica's `DecisionLog`, `verify_decision_logs` finds the first slot where
+//! that does not hold, and which replicas hold which command there. Logs may
+//! cover different ranges of slots; only slots logged more than once are
+//! compared, including a slot a replica logged twice across a restart.
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::collections::BTreeMap;
+use crate::types::{Command, NodeId, Slot};
+
+/// The decisions in one replica's log, in the order they were logged.
+pub type LoggedDecisions<T> = Vec<(Slot, Command<T>)>;
+
+// Each command logged in a slot, and the nodes that logged it
+type Holders<T> = Vec<(Command<T>, Vec<NodeId>)>;
+
+/// The first slot in which the logs disagree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence<T = Vec<u8>> {
+    pub slot: Slot,
+    /// Each command logged in the slot and the nodes that logged it, the
+    /// command most nodes logged first.
+    pub holders: Holders<T>,
+}
+
+impl<T> Divergence<T> {
+    /// The nodes that logged anything but the most common command.
+    pub fn offenders(&self) -> Vec<NodeId> {
+        self.holders
+            .iter()
+            .skip(1)
+            .flat_map(|(_, nodes)| nodes.iter().copied())
+            .collect()
+    }
+}
+
+impl<T> fmt::Display for Divergence<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "logs diverge at slot {}: ", self.slot)?;
+        for (i, (command, nodes)) in self.holders.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{} logged by", command.id())?;
+            for (j, node) in nodes.iter().enumerate() {
+                write!(f, "{} {}", if j > 0 { "," } else { "" }, node)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that every slot logged more than once holds the same command in
+/// each log, returning how many distinct slots were logged.
+pub fn verify_decision_logs<T: PartialEq + Clone>(
+    logs: &[(NodeId, LoggedDecisions<T>)],
+) -> Result<usize, Divergence<T>> {
+    let mut slots: BTreeMap<Slot, Holders<T>> = BTreeMap::new();
+    for (node, log) in logs {
+        for (slot, command) in log {
+            let holders = slots.entry(*slot).or_default();
+            match holders.iter_mut().find(|(held, _)| held == command) {
+                Some((_, nodes)) if nodes.contains(node) => {}
+                Some((_, nodes)) => nodes.push(*node),
+                None => holders.push((command.clone(), alloc::vec![*node])),
+            }
+        }
+    }
+    if let Some((slot, holders)) = slots.iter().find(|(_, holders)| holders.len() > 1) {
+        let mut holders = holders.clone();
+        holders.sort_by_key(|(_, nodes)| core::cmp::Reverse(nodes.len()));
+        return Err(Divergence {
+            slot: *slot,
+            holders,
+        });
+    }
+    Ok(slots.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    use crate::types::CommandType;
+
+    fn command(request_id: u64) -> Command {
+        Command {
+            client_id: NodeId::new(9),
+            request_id,
+            op: CommandType::Op(vec![]),
+        }
+    }
+
+    #[test]
+    fn verify_finds_the_first_slot_the_logs_disagree_on() {
+        let (a, b, c) = (NodeId::new(201), NodeId::new(202), NodeId::new(203));
+        let agreed: Vec<(Slot, Command)> =
+            (1..=3).map(|slot| (Slot(slot), command(slot))).collect();
+        let mut logs = vec![
+            (a, agreed.clone()),
+            (b, agreed.clone()),
+            // Behind, but consistent as far as it goes
+            (c, agreed[..1].to_vec()),
+        ];
+        assert_eq!(verify_decision_logs(&logs), Ok(3));
+
+        // Node c performed something else in slot 2, and node b in slot 3
+        logs[2].1.push((Slot(2), command(7)));
+        logs[1].1[2].1 = command(8);
+        let divergence = verify_decision_logs(&logs).unwrap_err();
+        assert_eq!(divergence.slot, Slot(2));
+        assert_eq!(divergence.holders[0], (command(2), vec![a, b]));
+        assert_eq!(divergence.offenders(), vec![c]);
+
+        // So did a restarted replica that logged slot 1 twice
+        let restarted = vec![(a, vec![(Slot(1), command(1)), (Slot(1), command(5))])];
+        assert_eq!(verify_decision_logs(&restarted).unwrap_err().slot, Slot(1));
+    }
+}
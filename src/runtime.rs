@@ -0,0 +1,859 @@
+//! Drives sans-IO nodes with real I/O.
+//!
+//! A `NodeRunner` owns one node. It feeds the node messages from an inbound
+//! channel (e.g. the receiver returned by `TcpServer::bind`), fires its timers
+//! when they come due, and hands everything in its outbox to a `Transport`.
+//!
+//! Operational settings can be changed without restarting the node by sending
+//! a `RuntimeConfig` through the runner's `ReloadHandle`, either from the
+//! admin server's `PUT /config` or from the embedder's SIGHUP handler (e.g.
+//! `handle.reload(RuntimeConfig::load(path)?)`).
+//!
+//! A `Supervisor` runs `NodeRunner`s as tasks and restarts any that panic,
+//! rebuilding them (and so reloading their persistent state) with backoff.
+//!
+//! A transport that reports its links, such as `TcpSender`, hands its
+//! `LinkEvent`s to the runner through `set_link_events`. The runner tells
+//! the node whenever a link goes down, so it suspects the peers there at
+//! once, and keeps each link's health for the admin server's `GET /status`.
+//!
+//! Between steps the runner sleeps until the node's next timer is due, or a
+//! message, link event or reload arrives, and wakes for nothing else: an idle
+//! node costs no CPU between its timers. Only messages the transport could
+//! not take yet bring it back sooner, to retry them. Embedders driving nodes
+//! themselves should keep to the same contract, see `Node::next_timeout`.
+//!
+//! A process running several nodes from one config should bind their
+//! listeners with `bind_local_nodes`, which refuses configs that give two
+//! nodes the same address instead of leaving one of them deaf.
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::reload;
+
+use crate::admin::{HealthSource, LinkSource, StatusSource};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::collections::BTreeMap;
+use crate::messages::{Message, SendableMessage};
+use crate::nodes::health::Health;
+use crate::nodes::node::{Node, Progress};
+use crate::time::{Duration, Instant};
+use crate::transport::pump::{OutboxJournal, OutboxPump};
+use crate::transport::tcp::TcpServer;
+use crate::transport::{LinkEvent, LinkHealth, Transport};
+use crate::types::{Address, Config, NodeId, Payload, TimeoutConfig};
+
+/// How long the runner waits before retrying messages the transport could
+/// not take, when nothing wakes it sooner.
+const RETRY_WAIT: Duration = Duration::from_millis(100);
+
+/// Settings that can change while a node runs. Fields left unset keep their
+/// current value. Membership is deliberately absent: it only changes through
+/// a `Reconfig`, `Membership` or `ConfigDelta` command decided by consensus.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeConfig {
+    #[serde(default)]
+    pub timeouts: Option<TimeoutConfig>,
+    /// A `tracing` level such as `"info"` or `"debug"`.
+    #[serde(default)]
+    pub log_level: Option<String>,
+    /// Client requests admitted per second; `0` removes the limit.
+    #[serde(default)]
+    pub max_requests_per_sec: Option<u32>,
+}
+
+impl RuntimeConfig {
+    /// Read a runtime config from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<RuntimeConfig> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// Sends `RuntimeConfig` updates to a running `NodeRunner`.
+#[derive(Clone, Debug)]
+pub struct ReloadHandle(pub(crate) mpsc::UnboundedSender<RuntimeConfig>);
+
+impl ReloadHandle {
+    /// Queue `config` to be applied on the runner's next step.
+    pub fn reload(&self, config: RuntimeConfig) -> anyhow::Result<()> {
+        if let Some(level) = &config.log_level {
+            level.parse::<LevelFilter>()?;
+        }
+        self.0
+            .send(config)
+            .map_err(|_| anyhow::anyhow!("node runner has stopped"))
+    }
+}
+
+/// Applies a new log level, e.g. through a `tracing_subscriber::reload::Handle`.
+pub type LogLevelSetter = Box<dyn Fn(LevelFilter) -> anyhow::Result<()> + Send>;
+
+/// A level filter layer whose level a `NodeRunner` can change, for building
+/// the embedder's subscriber, and the setter to hand to `set_log_level_setter`.
+pub fn reloadable_log_level<S>(
+    initial: LevelFilter,
+) -> (reload::Layer<LevelFilter, S>, LogLevelSetter)
+where
+    S: tracing::Subscriber + 'static,
+{
+    let (layer, handle) = reload::Layer::new(initial);
+    let setter: LogLevelSetter = Box::new(move |level| Ok(handle.reload(level)?));
+    (layer, setter)
+}
+
+/// Token bucket admitting client requests at a steady rate, with bursts of up to one second's worth.
+struct RequestLimiter {
+    per_sec: u32,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RequestLimiter {
+    fn new(per_sec: u32, now: Instant) -> RequestLimiter {
+        RequestLimiter {
+            per_sec,
+            tokens: per_sec as f64,
+            refilled: now,
+        }
+    }
+
+    fn admit(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec as f64).min(self.per_sec as f64);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct NodeRunner<N, T = Vec<u8>> {
+    node: N,
+    inbound: mpsc::UnboundedReceiver<SendableMessage<T>>,
+    outbox: OutboxPump<T>,
+    // Last health and progress reports, published for the admin server
+    health: Arc<Mutex<Health>>,
+    progress: Arc<Mutex<Progress>>,
+    // Links the transport reports on, and their health by destination
+    link_events: Option<mpsc::UnboundedReceiver<LinkEvent>>,
+    links: Arc<Mutex<BTreeMap<String, LinkHealth>>>,
+    reload_tx: mpsc::UnboundedSender<RuntimeConfig>,
+    reload_rx: mpsc::UnboundedReceiver<RuntimeConfig>,
+    log_level: Option<LogLevelSetter>,
+    // Admission control for client requests, if a limit is set
+    limiter: Option<RequestLimiter>,
+}
+
+impl<N, T> NodeRunner<N, T>
+where
+    N: Node<T> + Send,
+    T: Payload + Send + 'static,
+{
+    pub fn new(
+        node: N,
+        inbound: mpsc::UnboundedReceiver<SendableMessage<T>>,
+        transport: Box<dyn Transport<T> + Send>,
+    ) -> NodeRunner<N, T> {
+        let health = Arc::new(Mutex::new(node.health()));
+        let progress = Arc::new(Mutex::new(node.progress()));
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        NodeRunner {
+            node,
+            inbound,
+            outbox: OutboxPump::new(transport),
+            health,
+            progress,
+            link_events: None,
+            links: Arc::new(Mutex::new(BTreeMap::new())),
+            reload_tx,
+            reload_rx,
+            log_level: None,
+            limiter: None,
+        }
+    }
+
+    /// Let `RuntimeConfig::log_level` change the level, see `reloadable_log_level`.
+    pub fn set_log_level_setter(&mut self, setter: LogLevelSetter) {
+        self.log_level = Some(setter);
+    }
+
+    /// Journal the messages `journal` selects until the transport takes
+    /// them, first queueing any left unsent by an earlier run, e.g.
+    /// `OutboxJournal::for_role(Role::Leader, Box::new(FileOutboxStore::new(path)))`.
+    pub fn set_outbox_journal(&mut self, journal: OutboxJournal<T>) -> anyhow::Result<()> {
+        self.outbox.set_journal(journal)
+    }
+
+    /// Follow the health of the transport's links, e.g. from
+    /// `TcpSender::take_link_events`.
+    pub fn set_link_events(&mut self, events: mpsc::UnboundedReceiver<LinkEvent>) {
+        self.link_events = Some(events);
+    }
+
+    /// A handle for changing this runner's settings while it runs.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle(self.reload_tx.clone())
+    }
+
+    /// Apply the settings in `config` that are set.
+    pub fn apply(&mut self, config: RuntimeConfig) {
+        if let Some(timeouts) = config.timeouts {
+            self.node.set_timeouts(timeouts);
+        }
+        if let Some(level) = config.log_level {
+            match (level.parse::<LevelFilter>(), &self.log_level) {
+                (Ok(level), Some(set_level)) => {
+                    if let Err(e) = set_level(level) {
+                        warn!("runtime: failed to change log level: {}", e);
+                    }
+                }
+                (Ok(_), None) => warn!("runtime: log level is not reloadable"),
+                (Err(e), _) => warn!("runtime: invalid log level {:?}: {}", level, e),
+            }
+        }
+        if let Some(per_sec) = config.max_requests_per_sec {
+            self.limiter = (per_sec > 0).then(|| RequestLimiter::new(per_sec, Instant::now()));
+        }
+        info!("runtime: applied config reload");
+    }
+
+    fn deliver(&mut self, msg: SendableMessage<T>) {
+        if let (Message::Request(_), Some(limiter)) = (&msg.message, self.limiter.as_mut()) {
+            if !limiter.admit(Instant::now()) {
+                // Clients retry unanswered requests
+                warn!("runtime: request limit reached, dropping [{}]", msg);
+                return;
+            }
+        }
+        self.node.accept_message(msg);
+    }
+
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+
+    /// Health as of the runner's last step, for serving from an `AdminServer`.
+    pub fn health_source(&self) -> HealthSource {
+        let health = self.health.clone();
+        Arc::new(move || health.lock().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// Progress as of the runner's last step, for serving from an `AdminServer`.
+    pub fn status_source(&self) -> StatusSource {
+        let progress = self.progress.clone();
+        Arc::new(move || progress.lock().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// Link health as of the runner's last step, for serving from an `AdminServer`.
+    pub fn link_source(&self) -> LinkSource {
+        let links = self.links.clone();
+        Arc::new(move || links.lock().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    fn follow_links(&mut self) {
+        let Some(events) = self.link_events.as_mut() else {
+            return;
+        };
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        for event in received {
+            self.follow_link(event);
+        }
+    }
+
+    fn follow_link(&mut self, event: LinkEvent) {
+        match &event {
+            LinkEvent::Up(_) | LinkEvent::Moved { .. } => {}
+            LinkEvent::Negotiated { dst, format } => self.node.link_negotiated(dst, format.clone()),
+            LinkEvent::Down { dst, .. } | LinkEvent::SendFailed { dst, .. } => {
+                self.node.link_down(dst)
+            }
+        }
+        self.links
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(event.dst().to_string())
+            .or_default()
+            .record(&event);
+    }
+
+    /// Handle every queued message and expired timer, then flush the outbox.
+    pub fn step(&mut self) {
+        while let Ok(config) = self.reload_rx.try_recv() {
+            self.apply(config);
+        }
+        self.follow_links();
+        while let Ok(msg) = self.inbound.try_recv() {
+            self.deliver(msg);
+        }
+        while self.node.work_on_message() {}
+        if let Err(e) = self.node.check_timers() {
+            error!("runtime: timer handling failed: {}", e);
+        }
+        self.outbox.pump(&mut self.node);
+        if let Ok(mut health) = self.health.lock() {
+            *health = self.node.health();
+        }
+        if let Ok(mut progress) = self.progress.lock() {
+            *progress = self.node.progress();
+        }
+    }
+
+    /// Run until the inbound channel closes, then return the node.
+    pub async fn run(mut self) -> N {
+        loop {
+            self.step();
+            let retry = (self.outbox.pending() > 0).then_some(RETRY_WAIT);
+            let wait = match (self.node.next_timeout(), retry) {
+                (Some(timeout), Some(retry)) => Some(timeout.min(retry)),
+                (timeout, retry) => timeout.or(retry),
+            };
+            let following = self.link_events.is_some();
+            let link_events = self.link_events.as_mut();
+            tokio::select! {
+                msg = self.inbound.recv() => match msg {
+                    Some(msg) => self.deliver(msg),
+                    None => return self.node,
+                },
+                Some(config) = self.reload_rx.recv() => self.apply(config),
+                event = async { link_events?.recv().await }, if following => {
+                    match event {
+                        Some(event) => self.follow_link(event),
+                        None => self.link_events = None,
+                    }
+                }
+                _ = tokio::time::sleep(wait.unwrap_or_default()), if wait.is_some() => {}
+            }
+        }
+    }
+}
+
+/// Runs nodes as tasks and restarts them when they crash.
+///
+/// Each node is built by a start function rather than handed over directly,
+/// so a restart goes through the same path as the first start: a node that
+/// loads its stores there (e.g. `Acceptor::set_ballot_store`) comes back with
+/// the state it had persisted. Restarts back off exponentially from
+/// `min_backoff` to `max_backoff`, and the backoff resets once a node has
+/// stayed up for `max_backoff`. A node whose runner returns, because its
+/// inbound channel closed, is not restarted.
+pub struct Supervisor {
+    min_backoff: Duration,
+    max_backoff: Duration,
+    audit_log: Option<Arc<Mutex<AuditLog>>>,
+    tasks: JoinSet<()>,
+}
+
+impl Supervisor {
+    pub fn new(min_backoff: Duration, max_backoff: Duration) -> Supervisor {
+        Supervisor {
+            min_backoff,
+            max_backoff,
+            audit_log: None,
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Append crashes and restarts to `log`.
+    pub fn with_audit_log(mut self, log: AuditLog) -> Supervisor {
+        self.audit_log = Some(Arc::new(Mutex::new(log)));
+        self
+    }
+
+    /// Run the node `start` builds, building and running it again whenever
+    /// it panics or `start` fails. Must be called from within a tokio runtime.
+    pub fn supervise<N, T, F, Fut>(&mut self, node: NodeId, mut start: F)
+    where
+        N: Node<T> + Send + 'static,
+        T: Payload + Send + 'static,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<NodeRunner<N, T>>> + Send,
+    {
+        let (min_backoff, max_backoff) = (self.min_backoff, self.max_backoff);
+        let audit_log = self.audit_log.clone();
+        let record = move |event: AuditEvent| {
+            if let Some(log) = &audit_log {
+                let mut log = log.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(e) = log.append(event) {
+                    warn!("supervisor: failed to append to the audit log: {}", e);
+                }
+            }
+        };
+        self.tasks.spawn(async move {
+            let mut backoff = min_backoff;
+            let mut restarts = 0;
+            loop {
+                let reason = match start().await {
+                    Ok(runner) => {
+                        let started = Instant::now();
+                        match tokio::spawn(runner.run()).await {
+                            Ok(_) => {
+                                info!("supervisor: {} stopped", node);
+                                return;
+                            }
+                            Err(e) => {
+                                if started.elapsed() >= max_backoff {
+                                    backoff = min_backoff;
+                                }
+                                format!("crashed: {}", e)
+                            }
+                        }
+                    }
+                    Err(e) => format!("failed to start: {}", e),
+                };
+                error!(
+                    "supervisor: {} {}, restarting in {:?}",
+                    node, reason, backoff
+                );
+                record(AuditEvent::NodeCrashed {
+                    node,
+                    reason,
+                    restart_in: backoff,
+                });
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(max_backoff);
+                restarts += 1;
+                record(AuditEvent::NodeRestarted { node, restarts });
+            }
+        });
+    }
+
+    /// Wait until every supervised node has stopped.
+    pub async fn wait(mut self) {
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+/// Messages arriving for one node, as handed out by `TcpServer::bind`.
+pub type Inbound<T = Vec<u8>> = mpsc::UnboundedReceiver<SendableMessage<T>>;
+
+/// Why `bind_local_nodes` could not give every local node a listener of its own.
+#[derive(Debug)]
+pub enum BindError {
+    /// The config gives these nodes the same address, so all but one would miss their messages.
+    SharedAddress {
+        address: Address,
+        nodes: Vec<NodeId>,
+    },
+    /// A local node has no address in the config.
+    NoAddress(NodeId),
+    /// A local node's address is not an IP address and port.
+    InvalidAddress { node: NodeId, address: Address },
+    /// Listening on a local node's address failed, e.g. because the port is taken.
+    Bind {
+        node: NodeId,
+        address: Address,
+        error: std::io::Error,
+    },
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::SharedAddress { address, nodes } => {
+                let nodes: Vec<String> = nodes.iter().map(|n| n.to_string()).collect();
+                write!(f, "{} is configured for {}", address, nodes.join(", "))
+            }
+            BindError::NoAddress(node) => write!(f, "{} has no configured address", node),
+            BindError::InvalidAddress { node, address } => {
+                write!(f, "{} has an invalid address {}", node, address)
+            }
+            BindError::Bind {
+                node,
+                address,
+                error,
+            } => write!(f, "{} cannot listen on {}: {}", node, address, error),
+        }
+    }
+}
+
+impl std::error::Error for BindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BindError::Bind { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Bind a `TcpServer` on the configured address of each node in `local`,
+/// after checking that `config` gives no two nodes the same address.
+///
+/// Nothing is left listening if any node fails to bind.
+pub async fn bind_local_nodes<T: Payload + Send + 'static>(
+    config: &Config,
+    local: &[NodeId],
+) -> Result<BTreeMap<NodeId, (TcpServer<T>, Inbound<T>)>, BindError> {
+    if let Some((address, nodes)) = config.shared_addresses().into_iter().next() {
+        return Err(BindError::SharedAddress { address, nodes });
+    }
+    let mut servers = BTreeMap::new();
+    for node in local {
+        let address = config
+            .get_address(node)
+            .ok_or(BindError::NoAddress(*node))?;
+        let socket_addr: SocketAddr =
+            address
+                .to_string()
+                .parse()
+                .map_err(|_| BindError::InvalidAddress {
+                    node: *node,
+                    address: address.clone(),
+                })?;
+        let bound = TcpServer::bind(socket_addr)
+            .await
+            .map_err(|e| BindError::Bind {
+                node: *node,
+                address: address.clone(),
+                error: match e.downcast::<std::io::Error>() {
+                    Ok(error) => error,
+                    Err(e) => std::io::Error::other(e.to_string()),
+                },
+            })?;
+        servers.insert(*node, bound);
+    }
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collections::{BTreeMap, HashSet};
+    use crate::messages::*;
+    use crate::nodes::acceptor::Acceptor;
+    use crate::nodes::clock::SystemClock;
+    use crate::nodes::mailbox::Mailbox;
+    use crate::transport::TransportError;
+    use crate::types::*;
+
+    struct Capture(Arc<Mutex<Vec<SendableMessage>>>);
+
+    impl Transport for Capture {
+        fn send(&self, message: &SendableMessage) -> Result<(), TransportError> {
+            self.0.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn node_runner_delivers_replies_to_transport() {
+        let accept = AcceptorId::new(1);
+        let lead = LeaderId::new(1);
+        let config = Config::new(
+            HashSet::new(),
+            HashSet::from([accept]),
+            HashSet::from([lead]),
+            BTreeMap::from([
+                (accept.into(), Address::new("127.0.0.1".to_string(), 8081)),
+                (lead.into(), Address::new("127.0.0.1".to_string(), 8082)),
+            ]),
+            None,
+        );
+        let acceptor =
+            Acceptor::new(accept, config, Mailbox::new(), Box::new(SystemClock::new())).unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = mpsc::unbounded_channel();
+        let runner = NodeRunner::new(acceptor, rx, Box::new(Capture(sent.clone())));
+
+        tx.send(SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8081),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: lead,
+                ballot_number: BallotNumber::new(lead),
+                decided_below: Slot(0),
+            }),
+        })
+        .unwrap();
+        drop(tx);
+        runner.run().await;
+
+        let sent = sent.lock().unwrap();
+        assert!(sent
+            .iter()
+            .any(|msg| matches!(msg.message, Message::P1b(_))));
+    }
+
+    /// Counts the messages it is handed and the times its timers are
+    /// checked, and remembers its timeouts.
+    #[derive(Default)]
+    struct Counter {
+        received: usize,
+        checks: usize,
+        timeouts: TimeoutConfig,
+    }
+
+    impl Node for Counter {
+        fn accept_message(&mut self, _msg: SendableMessage) {
+            self.received += 1;
+        }
+
+        fn work_on_message(&mut self) -> bool {
+            false
+        }
+
+        fn check_timers(&mut self) -> anyhow::Result<Vec<crate::nodes::clock::ClockAction>> {
+            self.checks += 1;
+            Ok(Vec::new())
+        }
+
+        fn next_timeout(&self) -> Option<Duration> {
+            None
+        }
+
+        fn deliver_sent(&mut self) -> Option<SendableMessage> {
+            None
+        }
+
+        fn pending(&self) -> usize {
+            0
+        }
+
+        fn health(&self) -> Health {
+            Health::Ready
+        }
+
+        fn set_timeouts(&mut self, timeouts: TimeoutConfig) {
+            self.timeouts = timeouts;
+        }
+    }
+
+    fn request(request_id: u64) -> SendableMessage {
+        SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 9000),
+            dst: Address::new("127.0.0.1".to_string(), 8081),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Request(RequestMessage {
+                src: Address::new("127.0.0.1".to_string(), 9000),
+                command: Command {
+                    client_id: NodeId::new(100),
+                    request_id,
+                    op: CommandType::Op(vec![]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }),
+        }
+    }
+
+    /// Panics on the first message it is handed if `crash` is set.
+    struct Crashing {
+        crash: bool,
+    }
+
+    impl Node for Crashing {
+        fn accept_message(&mut self, _msg: SendableMessage) {
+            assert!(!self.crash, "crashing as asked");
+        }
+
+        fn work_on_message(&mut self) -> bool {
+            false
+        }
+
+        fn check_timers(&mut self) -> anyhow::Result<Vec<crate::nodes::clock::ClockAction>> {
+            Ok(Vec::new())
+        }
+
+        fn next_timeout(&self) -> Option<Duration> {
+            None
+        }
+
+        fn deliver_sent(&mut self) -> Option<SendableMessage> {
+            None
+        }
+
+        fn pending(&self) -> usize {
+            0
+        }
+
+        fn health(&self) -> Health {
+            Health::Ready
+        }
+
+        fn set_timeouts(&mut self, _timeouts: TimeoutConfig) {}
+    }
+
+    #[tokio::test]
+    async fn supervisor_restarts_crashed_nodes_with_backoff() {
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-supervisor-{}-{}.jsonl",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut supervisor = Supervisor::new(Duration::from_millis(5), Duration::from_millis(50))
+            .with_audit_log(AuditLog::open(&path).unwrap());
+        let starts = Arc::new(Mutex::new(0));
+        let node = NodeId::new(1);
+
+        let counted = starts.clone();
+        supervisor.supervise(node, move || {
+            let counted = counted.clone();
+            async move {
+                let mut starts = counted.lock().unwrap();
+                *starts += 1;
+                // The first two runs crash on their first message, the third
+                // finds its inbound channel closed and stops
+                let (tx, rx) = mpsc::unbounded_channel();
+                if *starts < 3 {
+                    tx.send(request(*starts)).unwrap();
+                }
+                Ok(NodeRunner::new(
+                    Crashing { crash: *starts < 3 },
+                    rx,
+                    Box::new(Capture(Arc::new(Mutex::new(Vec::new())))),
+                ))
+            }
+        });
+        tokio::time::timeout(std::time::Duration::from_secs(5), supervisor.wait())
+            .await
+            .unwrap();
+
+        assert_eq!(*starts.lock().unwrap(), 3);
+        let events: Vec<AuditEvent> = AuditLog::query_path(&path, |_| true)
+            .unwrap()
+            .into_iter()
+            .map(|record| record.event)
+            .collect();
+        let backoffs: Vec<Duration> = events
+            .iter()
+            .filter_map(|event| match event {
+                AuditEvent::NodeCrashed { restart_in, .. } => Some(*restart_in),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            backoffs,
+            vec![Duration::from_millis(5), Duration::from_millis(10)]
+        );
+        assert!(events.contains(&AuditEvent::NodeRestarted { node, restarts: 2 }));
+        assert!(events.iter().all(|event| event.node() == node));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_local_nodes_fails_fast_on_address_conflicts() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = Address::new(
+            "127.0.0.1".to_string(),
+            taken.local_addr().unwrap().port() as u64,
+        );
+        let config = |addresses: BTreeMap<NodeId, Address>| {
+            Config::new(
+                HashSet::new(),
+                HashSet::new(),
+                HashSet::new(),
+                addresses,
+                None,
+            )
+        };
+        let (a, b) = (NodeId::new(1), NodeId::new(2));
+
+        let shared = config(BTreeMap::from([(a, taken.clone()), (b, taken.clone())]));
+        match bind_local_nodes::<Vec<u8>>(&shared, &[a]).await.err() {
+            Some(BindError::SharedAddress { address, nodes }) => {
+                assert_eq!(address, taken);
+                assert_eq!(nodes, vec![a, b]);
+            }
+            other => panic!("expected a shared address, got {:?}", other),
+        }
+
+        let in_use = config(BTreeMap::from([(a, taken.clone())]));
+        match bind_local_nodes::<Vec<u8>>(&in_use, &[a]).await.err() {
+            Some(BindError::Bind { node, error, .. }) => {
+                assert_eq!(node, a);
+                assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+            }
+            other => panic!("expected the port to be taken, got {:?}", other),
+        }
+
+        let free = config(BTreeMap::from([(
+            a,
+            Address::new("127.0.0.1".to_string(), 0),
+        )]));
+        let servers = bind_local_nodes::<Vec<u8>>(&free, &[a]).await.unwrap();
+        assert!(servers.contains_key(&a));
+    }
+
+    #[tokio::test]
+    async fn node_runner_applies_runtime_config() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut runner = NodeRunner::new(
+            Counter::default(),
+            rx,
+            Box::new(Capture(Arc::new(Mutex::new(Vec::new())))),
+        );
+        let timeouts = TimeoutConfig {
+            heartbeat_interval: Duration::from_millis(50),
+            ..TimeoutConfig::default()
+        };
+        runner
+            .reload_handle()
+            .reload(RuntimeConfig {
+                timeouts: Some(timeouts.clone()),
+                max_requests_per_sec: Some(2),
+                ..RuntimeConfig::default()
+            })
+            .unwrap();
+        for request_id in 0..5 {
+            tx.send(request(request_id)).unwrap();
+        }
+        runner.step();
+        assert_eq!(runner.node().timeouts, timeouts);
+        // Only a burst's worth of requests got through
+        assert_eq!(runner.node().received, 2);
+
+        // Lifting the limit admits everything again
+        runner
+            .reload_handle()
+            .reload(RuntimeConfig {
+                max_requests_per_sec: Some(0),
+                ..RuntimeConfig::default()
+            })
+            .unwrap();
+        for request_id in 5..10 {
+            tx.send(request(request_id)).unwrap();
+        }
+        runner.step();
+        assert_eq!(runner.node().received, 7);
+    }
+
+    #[tokio::test]
+    async fn node_runner_sleeps_until_a_message_arrives() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let runner = NodeRunner::new(
+            Counter::default(),
+            rx,
+            Box::new(Capture(Arc::new(Mutex::new(Vec::new())))),
+        );
+        let running = tokio::spawn(runner.run());
+
+        // No timers and nothing to send: the runner steps once, then waits
+        tokio::time::sleep(std::time::Duration::from_millis(350)).await;
+        tx.send(request(1)).unwrap();
+        drop(tx);
+        let node = running.await.unwrap();
+        assert_eq!(node.received, 1);
+        assert_eq!(node.checks, 2);
+    }
+}
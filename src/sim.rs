@@ -0,0 +1,1452 @@
+//! Deterministic in-process cluster simulation.
+//!
+//! A `Simulation` owns a set of nodes that all read a shared simulated clock.
+//! Each `step` delivers the messages sent in the previous step, lets every
+//! node handle its inbox and fire due timers, and collects what they sent.
+//! Nothing depends on wall-clock time or thread scheduling, so runs are
+//! reproducible and tests can cover minutes of protocol time in milliseconds.
+//!
+//! The order messages are delivered in is set by a `Delivery` policy: in
+//! order on each link, shuffled by a seed, or with chosen messages held back,
+//! to reach interleavings that orderly delivery never produces.
+//!
+//! `run_idle` runs the cluster the way `NodeRunner` does, waking nodes only
+//! when a message is in flight or a timer is due, and `work` counts what they
+//! did, so tests can check that an idle cluster does nothing between deadlines.
+//!
+//! `partition` cuts a set of nodes off from the rest until `heal`, so tests
+//! can pin down what each side of a split cluster does.
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tracing::error;
+
+use crate::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use crate::messages::{Message, SendableMessage};
+use crate::nodes::acceptor::Acceptor;
+use crate::nodes::clock::{ClockAction, ClockProvider, LogicalClock, Ticks};
+use crate::nodes::health::Health;
+use crate::nodes::leader::Leader;
+use crate::nodes::mailbox::Mailbox;
+use crate::nodes::node::Node;
+use crate::nodes::replica::Replica;
+use crate::time::{Duration, Instant};
+use crate::types::{self, NodeId, Payload};
+
+pub mod debugger;
+
+/// The length of a simulated clock tick.
+pub const SIM_TICK: Duration = Duration::from_micros(1);
+
+/// Simulated time shared by every clock in a simulation. It is counted in
+/// `SIM_TICK`s, so a run's timers fall due at the same ticks on any host, and
+/// only read as an `Instant` when a node asks for one.
+#[derive(Clone, Debug)]
+pub struct SimTime(Arc<Mutex<LogicalClock>>);
+
+impl SimTime {
+    fn new() -> SimTime {
+        SimTime(Arc::new(Mutex::new(LogicalClock::new(SIM_TICK))))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.lock().now()
+    }
+
+    /// Ticks since the start of the simulation.
+    pub fn ticks(&self) -> Ticks {
+        self.lock().ticks()
+    }
+
+    /// Simulated time since the start of the simulation.
+    pub fn elapsed(&self) -> Duration {
+        let clock = self.lock();
+        clock.to_duration(clock.ticks())
+    }
+
+    /// The whole ticks `duration` takes, rounded up.
+    pub fn to_ticks(&self, duration: Duration) -> Ticks {
+        self.lock().to_ticks(duration)
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut clock = self.lock();
+        let ticks = clock.to_ticks(by);
+        clock.advance(ticks);
+    }
+
+    pub fn advance_ticks(&self, ticks: Ticks) {
+        self.lock().advance(ticks);
+    }
+
+    fn origin(&self) -> Instant {
+        self.lock().instant_at(0)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LogicalClock> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A node's clock in a simulation: its own timers, the simulation's time.
+#[derive(Debug)]
+pub struct SimClock {
+    time: SimTime,
+    timers: LogicalClock,
+}
+
+impl SimClock {
+    pub fn new(time: SimTime) -> SimClock {
+        let mut timers = LogicalClock::with_origin(time.origin(), SIM_TICK);
+        timers.set_ticks(time.ticks());
+        SimClock { time, timers }
+    }
+
+    fn sync(&mut self) {
+        self.timers.set_ticks(self.time.ticks());
+    }
+
+    // How long until `due`, by the simulation's time
+    fn due_in(&self, due: Ticks) -> Duration {
+        self.timers
+            .to_duration(due.saturating_sub(self.time.ticks()))
+    }
+}
+
+impl ClockProvider for SimClock {
+    fn now(&self) -> Instant {
+        self.time.now()
+    }
+
+    fn schedule(&mut self, action: ClockAction, delay: Duration) {
+        self.sync();
+        self.timers.schedule(action, delay);
+    }
+
+    fn schedule_at(&mut self, action: ClockAction, when: Instant) {
+        self.timers.schedule_at(action, when);
+    }
+
+    fn cancel(&mut self, action_type: &ClockAction) {
+        self.timers.cancel(action_type);
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        let (due, _) = self.timers.timers().next()?;
+        Some(self.due_in(due))
+    }
+
+    fn check_timers(&mut self) -> Vec<ClockAction> {
+        self.sync();
+        self.timers.check_timers()
+    }
+
+    fn pending(&self) -> Vec<(ClockAction, Duration)> {
+        self.timers
+            .timers()
+            .map(|(due, action)| (action.clone(), self.due_in(due)))
+            .collect()
+    }
+}
+
+/// The order a simulation delivers in-flight messages in.
+#[derive(Debug, Default)]
+pub enum Delivery<T = Vec<u8>> {
+    /// In order on each link, links one after another. The default.
+    #[default]
+    Fifo,
+    /// In a random order, the same for the same seed.
+    Random { seed: u64 },
+    /// Messages `delayed` picks out are held back for `steps` steps and then
+    /// delivered after everything else, to see what the cluster does while
+    /// they are late. Links stay in order otherwise.
+    Adversarial {
+        delayed: fn(&Message<T>) -> bool,
+        steps: u32,
+    },
+}
+
+// splitmix64, so that a seed shuffles the same on every platform
+#[derive(Debug)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn shuffle<I>(&mut self, items: &mut [I]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+pub struct Simulation<T = Vec<u8>> {
+    time: SimTime,
+    delivery: Delivery<T>,
+    rng: Rng,
+    nodes: BTreeMap<NodeId, Box<dyn Node<T> + Send>>,
+    // Routes destination addresses to simulated nodes
+    addresses: HashMap<String, NodeId>,
+    crashed: HashSet<NodeId>,
+    // Pairs of nodes that cannot reach each other, lower id first
+    disconnected: HashSet<(NodeId, NodeId)>,
+    in_flight: VecDeque<SendableMessage<T>>,
+    // Messages held back by `Delivery::Adversarial`, with the steps left
+    held: VecDeque<(u32, SendableMessage<T>)>,
+    // Every message sent by a simulated node, in order
+    sent: Vec<SendableMessage<T>>,
+    // Messages for addresses outside the simulation, e.g. client responses
+    external: Vec<SendableMessage<T>>,
+    // Messages handled, timers fired and messages sent by every node
+    work: u64,
+}
+
+impl<T: Payload + Send + 'static> Default for Simulation<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Payload + Send + 'static> Simulation<T> {
+    pub fn new() -> Simulation<T> {
+        Simulation {
+            time: SimTime::new(),
+            delivery: Delivery::Fifo,
+            rng: Rng(0),
+            nodes: BTreeMap::new(),
+            addresses: HashMap::new(),
+            crashed: HashSet::new(),
+            disconnected: HashSet::new(),
+            in_flight: VecDeque::new(),
+            held: VecDeque::new(),
+            sent: Vec::new(),
+            external: Vec::new(),
+            work: 0,
+        }
+    }
+
+    /// A clock reading this simulation's time, for constructing nodes.
+    pub fn clock(&self) -> Box<SimClock> {
+        Box::new(SimClock::new(self.time.clone()))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.time.now()
+    }
+
+    /// Ticks of `SIM_TICK` since the simulation started.
+    pub fn ticks(&self) -> Ticks {
+        self.time.ticks()
+    }
+
+    /// Deliver messages by `delivery` from the next step on.
+    pub fn set_delivery(&mut self, delivery: Delivery<T>) {
+        if let Delivery::Random { seed } = delivery {
+            self.rng = Rng(seed);
+        }
+        self.delivery = delivery;
+    }
+
+    pub fn add_node(
+        &mut self,
+        id: NodeId,
+        address: &types::Address,
+        node: Box<dyn Node<T> + Send>,
+    ) {
+        self.addresses.insert(address.to_string(), id);
+        self.nodes.insert(id, node);
+    }
+
+    /// Create and add every acceptor, leader and replica named in `config`.
+    pub fn add_cluster(&mut self, config: &types::Config) -> anyhow::Result<()> {
+        let address = |id: &NodeId| {
+            config
+                .get_address(id)
+                .cloned()
+                .ok_or(anyhow::anyhow!("{} has no address", id))
+        };
+        for id in config.acceptors.iter() {
+            let mut acceptor = Acceptor::new(*id, config.clone(), Mailbox::new(), self.clock())?;
+            acceptor.start_periodic_checks()?;
+            self.add_node((*id).into(), &address(id.as_ref())?, Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), self.clock())?;
+            self.add_node((*id).into(), &address(id.as_ref())?, Box::new(leader));
+        }
+        for id in config.replicas.iter() {
+            let mut replica = Replica::new(*id, config.clone(), Mailbox::new(), self.clock())?;
+            replica.start_periodic_checks()?;
+            self.add_node((*id).into(), &address(id.as_ref())?, Box::new(replica));
+        }
+        Ok(())
+    }
+
+    pub fn node(&self, id: &NodeId) -> Option<&(dyn Node<T> + Send)> {
+        self.nodes.get(id).map(|node| node.as_ref())
+    }
+
+    /// Stop a node: it no longer runs, and messages to it are dropped.
+    pub fn crash(&mut self, id: NodeId) {
+        self.crashed.insert(id);
+    }
+
+    /// Drop every message between `a` and `b`, in both directions, until they are reconnected.
+    pub fn disconnect(&mut self, a: NodeId, b: NodeId) {
+        self.disconnected.insert((a.min(b), a.max(b)));
+    }
+
+    pub fn reconnect(&mut self, a: NodeId, b: NodeId) {
+        self.disconnected.remove(&(a.min(b), a.max(b)));
+    }
+
+    /// Cut `side` off from every other node, keeping the links within it.
+    pub fn partition(&mut self, side: &[NodeId]) {
+        let others: Vec<NodeId> = self
+            .nodes
+            .keys()
+            .filter(|id| !side.contains(id))
+            .copied()
+            .collect();
+        for a in side {
+            for b in &others {
+                self.disconnect(*a, *b);
+            }
+        }
+    }
+
+    /// Reconnect every pair of nodes.
+    pub fn heal(&mut self) {
+        self.disconnected.clear();
+    }
+
+    /// Deliver a message from outside the simulation (e.g. a client request) on the next step.
+    pub fn inject(&mut self, msg: SendableMessage<T>) {
+        self.in_flight.push_back(msg);
+    }
+
+    /// Deliver in-flight messages, let every live node work, and collect what it sent.
+    pub fn step(&mut self) {
+        self.order_in_flight();
+        let mut in_flight: Vec<_> = self.in_flight.drain(..).collect();
+        if let Delivery::Adversarial { delayed, steps } = self.delivery {
+            let (late, on_time): (Vec<_>, _) =
+                in_flight.into_iter().partition(|msg| delayed(&msg.message));
+            in_flight = on_time;
+            self.held.extend(late.into_iter().map(|msg| (steps, msg)));
+        }
+        let (due, held) = self
+            .held
+            .drain(..)
+            .map(|(left, msg)| (left.saturating_sub(1), msg))
+            .partition(|(left, _)| *left == 0);
+        self.held = held;
+        in_flight.extend(due.into_iter().map(|(_, msg)| msg));
+        for msg in in_flight {
+            self.deliver(msg);
+        }
+        for (id, node) in self.nodes.iter_mut() {
+            if self.crashed.contains(id) {
+                continue;
+            }
+            while node.work_on_message() {
+                self.work += 1;
+            }
+            match node.check_timers() {
+                Ok(fired) => self.work += fired.len() as u64,
+                Err(e) => error!("sim: {} failed handling timers: {}", id, e),
+            }
+            while let Some(msg) = node.deliver_sent() {
+                self.work += 1;
+                self.sent.push(msg.clone());
+                self.in_flight.push_back(msg);
+            }
+        }
+    }
+
+    /// Deliver only the next in-flight message and let its destination
+    /// handle it, without firing any timers. Returns the message, or `None`
+    /// if nothing is in flight.
+    pub fn step_message(&mut self) -> Option<SendableMessage<T>> {
+        self.order_in_flight();
+        let msg = self
+            .in_flight
+            .pop_front()
+            .or_else(|| self.held.pop_front().map(|(_, msg)| msg))?;
+        let dst = self.addresses.get(&msg.dst.to_string()).copied();
+        self.deliver(msg.clone());
+        if let Some(node) = dst
+            .filter(|id| !self.crashed.contains(id))
+            .and_then(|id| self.nodes.get_mut(&id))
+        {
+            if node.work_on_message() {
+                self.work += 1;
+            }
+            while let Some(sent) = node.deliver_sent() {
+                self.work += 1;
+                self.sent.push(sent.clone());
+                self.in_flight.push_back(sent);
+            }
+        }
+        Some(msg)
+    }
+
+    // Nodes send to their peers in whatever order their hash sets iterate,
+    // which differs from one run to the next. Delivering in a fixed order
+    // keeps every node's inbox, and so the whole run, the same each time.
+    fn order_in_flight(&mut self) {
+        self.in_flight
+            .make_contiguous()
+            .sort_by_key(|msg| (msg.src.to_string(), msg.dst.to_string(), msg.seq));
+        match &self.delivery {
+            Delivery::Fifo => {}
+            Delivery::Random { .. } => self.rng.shuffle(self.in_flight.make_contiguous()),
+            Delivery::Adversarial { delayed, .. } => self
+                .in_flight
+                .make_contiguous()
+                .sort_by_key(|msg| delayed(&msg.message)),
+        }
+    }
+
+    fn deliver(&mut self, msg: SendableMessage<T>) {
+        let src = self.addresses.get(&msg.src.to_string());
+        match self.addresses.get(&msg.dst.to_string()) {
+            Some(id) if self.crashed.contains(id) => {}
+            Some(id)
+                if src.is_some_and(|src| {
+                    self.disconnected.contains(&(*src.min(id), *src.max(id)))
+                }) => {}
+            Some(id) => {
+                if let Some(node) = self.nodes.get_mut(id) {
+                    node.accept_message(msg);
+                }
+            }
+            None => self.external.push(msg),
+        }
+    }
+
+    /// Step repeatedly, advancing simulated time by `tick` after each step.
+    pub fn run_for(&mut self, duration: Duration, tick: Duration) {
+        let mut elapsed = Duration::ZERO;
+        while elapsed < duration {
+            self.step();
+            self.time.advance(tick);
+            elapsed += tick;
+        }
+    }
+
+    /// Step whenever a message is in flight or a timer is due, for
+    /// `duration`, moving the clock straight on to the next timer between.
+    pub fn run_idle(&mut self, duration: Duration) {
+        let end = self.time.ticks() + self.time.to_ticks(duration);
+        while self.time.ticks() < end {
+            if self.in_flight.is_empty() && self.held.is_empty() {
+                let wait = self
+                    .next_timeout()
+                    .map_or(end - self.time.ticks(), |wait| self.time.to_ticks(wait));
+                let wait = wait.min(end - self.time.ticks());
+                self.time.advance_ticks(wait);
+                if self.time.ticks() >= end {
+                    break;
+                }
+            }
+            self.step();
+            self.time.advance_ticks(1);
+        }
+    }
+
+    /// Time until the earliest timer of any live node is due, if any are scheduled.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.nodes
+            .iter()
+            .filter(|(id, _)| !self.crashed.contains(id))
+            .filter_map(|(_, node)| node.next_timeout())
+            .min()
+    }
+
+    /// Messages handled, timers fired and messages sent by every node so far.
+    pub fn work(&self) -> u64 {
+        self.work
+    }
+
+    pub fn sent(&self) -> &[SendableMessage<T>] {
+        &self.sent
+    }
+
+    /// Messages sent but not yet delivered.
+    pub fn in_flight(&self) -> impl Iterator<Item = &SendableMessage<T>> {
+        self.in_flight
+            .iter()
+            .chain(self.held.iter().map(|(_, msg)| msg))
+    }
+
+    /// How every node is doing, in id order.
+    pub fn statuses(&self) -> Vec<NodeStatus> {
+        self.nodes
+            .iter()
+            .map(|(id, node)| NodeStatus {
+                id: *id,
+                crashed: self.crashed.contains(id),
+                pending: node.pending(),
+                health: node.health(),
+            })
+            .collect()
+    }
+
+    /// Take the messages addressed outside the simulation so far.
+    pub fn take_external(&mut self) -> Vec<SendableMessage<T>> {
+        std::mem::take(&mut self.external)
+    }
+}
+
+/// A simulated node's state, as shown by `Simulation::statuses`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeStatus {
+    pub id: NodeId,
+    pub crashed: bool,
+    /// Messages waiting in the node's inbox.
+    pub pending: usize,
+    pub health: Health,
+}
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.crashed {
+            return write!(f, "{}: crashed", self.id);
+        }
+        write!(f, "{}: {} pending, ", self.id, self.pending)?;
+        match &self.health {
+            Health::Ready => write!(f, "ready"),
+            Health::Degraded { reasons } => write!(f, "degraded ({})", reasons.join("; ")),
+            Health::NotReady { reasons } => write!(f, "not ready ({})", reasons.join("; ")),
+        }
+    }
+}
+
+/// A config for a simulated cluster: acceptors are numbered from 1,
+/// leaders from 101 and replicas from 201.
+pub fn cluster_config(acceptors: u64, leaders: u64, replicas: u64) -> types::Config {
+    let acceptors: Vec<_> = (1..=acceptors).map(types::AcceptorId::new).collect();
+    let leaders: Vec<_> = (101..101 + leaders).map(types::LeaderId::new).collect();
+    let replicas: Vec<_> = (201..201 + replicas).map(types::ReplicaId::new).collect();
+    let ids = acceptors
+        .iter()
+        .map(|a| NodeId::from(*a))
+        .chain(leaders.iter().map(|l| NodeId::from(*l)))
+        .chain(replicas.iter().map(|r| NodeId::from(*r)));
+    let id_address_map = ids
+        .enumerate()
+        .map(|(i, id)| (id, types::Address::new("sim".to_string(), i as u64 + 1)))
+        .collect();
+    types::Config::new(
+        replicas.into_iter().collect(),
+        acceptors.into_iter().collect(),
+        leaders.into_iter().collect(),
+        id_address_map,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::nodes::slot_allocator::{LeaderAssigned, RoundRobin, Sequential, SlotAllocator};
+    use crate::nodes::slot_map::MemoryMode;
+    use crate::state_machine::{ApplyContext, StateMachine};
+
+    type AllocatorFor = fn(types::ReplicaId, &types::Config) -> Box<dyn SlotAllocator + Send>;
+
+    fn scouts_by(msgs: &[SendableMessage], leader: types::LeaderId) -> usize {
+        msgs.iter()
+            .filter(|msg| matches!(&msg.message, Message::P1a(p1a) if p1a.src == leader))
+            .count()
+    }
+
+    /// Ballots announced by active leaders, in order of first announcement.
+    fn active_ballots(msgs: &[SendableMessage]) -> Vec<types::BallotNumber> {
+        let mut ballots: Vec<types::BallotNumber> = Vec::new();
+        for msg in msgs {
+            if let Message::Heartbeat(HeartbeatMessage {
+                ballot: Some(ballot),
+                ..
+            }) = &msg.message
+            {
+                if !ballots.contains(ballot) {
+                    ballots.push(ballot.clone());
+                }
+            }
+        }
+        ballots
+    }
+
+    #[test]
+    fn passive_leader_does_not_scout_against_a_healthy_leader() {
+        let config = cluster_config(3, 2, 1);
+        let mut sim: Simulation = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        // Both leaders scout at startup; let leadership settle
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let settled = sim.sent().len();
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+
+        sim.run_for(Duration::from_secs(60), Duration::from_millis(10));
+
+        // The winner kept its ballot for the whole minute
+        let later = &sim.sent()[settled..];
+        assert_eq!(active_ballots(later), vec![winner.clone()]);
+        // Scouting on backoff would send several P1a rounds a minute even at
+        // the maximum timeout; suppressed, the passive leader sends none
+        let loser = *config
+            .leaders
+            .iter()
+            .find(|l| **l != winner.leader)
+            .unwrap();
+        assert_eq!(scouts_by(later, loser), 0);
+        assert_eq!(scouts_by(later, winner.leader), 0);
+    }
+
+    #[test]
+    fn idle_cluster_does_no_work_between_timer_deadlines() {
+        let config = cluster_config(3, 2, 2);
+        let mut sim: Simulation = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        // Both leaders scout at startup; let leadership settle
+        sim.run_idle(Duration::from_secs(5));
+        let settled = sim.sent().len();
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+
+        for _ in 0..10 {
+            // Let whatever the last deadline set off die down
+            while sim.in_flight().next().is_some() {
+                sim.step();
+                sim.time.advance_ticks(1);
+            }
+            let deadline = sim.ticks() + sim.time.to_ticks(sim.next_timeout().unwrap());
+            let idle = sim.work();
+            while sim.ticks() < deadline {
+                sim.step();
+                assert_eq!(
+                    sim.work(),
+                    idle,
+                    "woke up {} ticks early",
+                    deadline - sim.ticks()
+                );
+                sim.time.advance_ticks((deadline - sim.ticks()).min(997));
+            }
+            sim.step();
+            assert!(sim.work() > idle);
+        }
+        // Still the same leader, on the heartbeats alone
+        assert_eq!(active_ballots(&sim.sent()[settled..]), vec![winner]);
+    }
+
+    #[test]
+    fn passive_leader_takes_over_when_the_active_leader_fails() {
+        let config = cluster_config(3, 2, 1);
+        let mut sim: Simulation = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        let settled = sim.sent().len();
+
+        sim.crash(winner.leader.into());
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+
+        let successors = active_ballots(&sim.sent()[settled..]);
+        assert_eq!(successors.len(), 1);
+        assert_ne!(successors[0].leader, winner.leader);
+        assert!(successors[0] > winner);
+    }
+
+    #[test]
+    fn leased_leader_holds_off_successors_until_its_lease_runs_out() {
+        let mut config = cluster_config(3, 2, 1);
+        config.timeout_config.lease = Some(Duration::from_secs(8));
+        let mut sim: Simulation = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        let settled = sim.sent().len();
+
+        sim.crash(winner.leader.into());
+        // Suspected within two seconds, but the acceptors' grants still run
+        sim.run_for(Duration::from_secs(6), Duration::from_millis(10));
+        assert!(active_ballots(&sim.sent()[settled..]).is_empty());
+
+        sim.run_for(Duration::from_secs(15), Duration::from_millis(10));
+        let successors = active_ballots(&sim.sent()[settled..]);
+        assert_eq!(successors.len(), 1);
+        assert_ne!(successors[0].leader, winner.leader);
+    }
+
+    /// Send each of three replicas a request every tick for `rounds` ticks,
+    /// returning how many proposals leaders turned away because the slot was
+    /// taken, and how many distinct commands were answered.
+    fn reproposals(allocator: AllocatorFor, rounds: u64) -> (usize, usize) {
+        let config = cluster_config(3, 1, 3);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(leader));
+        }
+        for id in config.replicas.iter() {
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.set_slot_allocator(allocator(*id, &config));
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(2), Duration::from_millis(10));
+
+        let client = types::Address::new("client".to_string(), 1);
+        let mut request_id = 0;
+        for _ in 0..rounds {
+            for replica in config.replicas.iter() {
+                request_id += 1;
+                sim.inject(SendableMessage {
+                    src: client.clone(),
+                    dst: address((*replica).into()),
+                    seq: None,
+                    lamport: None,
+                    epoch: None,
+                    message: Message::Request(RequestMessage {
+                        src: client.clone(),
+                        command: types::Command {
+                            client_id: NodeId::new(999),
+                            request_id,
+                            op: types::CommandType::Op(vec![request_id as u8]),
+                        },
+                        consistency: Consistency::Linearizable,
+                        proxied_by: None,
+                    }),
+                });
+            }
+            sim.run_for(Duration::from_millis(10), Duration::from_millis(10));
+        }
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+
+        let collisions = sim
+            .sent()
+            .iter()
+            .filter(|msg| {
+                matches!(&msg.message, Message::ProposeRejected(rejected)
+                    if rejected.reason == RejectReason::SlotOccupied)
+            })
+            .count();
+        let answered: HashSet<types::CommandId> = sim
+            .take_external()
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(response) => Some(response.command_id),
+                _ => None,
+            })
+            .collect();
+        (collisions, answered.len())
+    }
+
+    #[test]
+    fn slot_allocators_reduce_reproposals_under_concurrent_load() {
+        let rounds = 20;
+        let (sequential, answered) = reproposals(|_, _| Box::new(Sequential), rounds);
+        assert_eq!(answered, 3 * rounds as usize);
+        let (round_robin, answered) =
+            reproposals(|id, config| Box::new(RoundRobin::new(id, config)), rounds);
+        assert_eq!(answered, 3 * rounds as usize);
+        let (leader_assigned, answered) =
+            reproposals(|_, _| Box::new(LeaderAssigned::default()), rounds);
+        assert_eq!(answered, 3 * rounds as usize);
+
+        // Replicas proposing in lockstep all chase the same leader hint, so
+        // only dealing out slots in advance avoids these collisions
+        assert!(sequential > 0);
+        assert_eq!(round_robin, 0);
+        assert!(leader_assigned <= sequential);
+    }
+
+    #[test]
+    fn command_sent_to_every_replica_is_decided_in_one_slot() {
+        let config = cluster_config(3, 1, 3);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(leader));
+        }
+        // Each replica proposes into slots of its own, so the same command
+        // reaches the leader for three different slots
+        for id in config.replicas.iter() {
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.set_slot_allocator(Box::new(RoundRobin::new(*id, &config)));
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(2), Duration::from_millis(10));
+
+        let client = types::Address::new("client".to_string(), 1);
+        let command = types::Command {
+            client_id: NodeId::new(999),
+            request_id: 1,
+            op: types::CommandType::Op(vec![1]),
+        };
+        for replica in config.replicas.iter() {
+            sim.inject(SendableMessage {
+                src: client.clone(),
+                dst: address((*replica).into()),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Request(RequestMessage {
+                    src: client.clone(),
+                    command: command.clone(),
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }),
+            });
+        }
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+
+        let decided: HashSet<types::Slot> = sim
+            .sent()
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Decision(decision) if decision.command.id() == command.id() => {
+                    Some(decision.slot_number)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(decided.len(), 1);
+        assert!(sim.sent().iter().any(|msg| matches!(
+            &msg.message,
+            Message::ProposeRejected(rejected)
+                if matches!(rejected.reason, RejectReason::Duplicate { .. })
+        )));
+        assert!(sim
+            .take_external()
+            .iter()
+            .any(|msg| matches!(&msg.message, Message::Response(_))));
+    }
+
+    /// Send `count` commands from `first_id` to the replicas in turn, one a
+    /// tick, and return how many distinct ones were answered.
+    fn run_workload(
+        sim: &mut Simulation,
+        config: &types::Config,
+        first_id: u64,
+        count: u64,
+    ) -> usize {
+        let client = types::Address::new("client".to_string(), 1);
+        let replicas: Vec<types::ReplicaId> = config.replicas.iter().copied().collect();
+        for request_id in first_id..first_id + count {
+            let replica = replicas[request_id as usize % replicas.len()];
+            sim.inject(SendableMessage {
+                src: client.clone(),
+                dst: config.get_address(replica.as_ref()).cloned().unwrap(),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Request(RequestMessage {
+                    src: client.clone(),
+                    command: types::Command {
+                        client_id: NodeId::new(999),
+                        request_id,
+                        op: types::CommandType::Op(vec![request_id as u8]),
+                    },
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }),
+            });
+            sim.run_for(Duration::from_millis(10), Duration::from_millis(10));
+        }
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+        let answered: HashSet<types::CommandId> = sim
+            .take_external()
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(response) => Some(response.command_id),
+                _ => None,
+            })
+            .collect();
+        answered.len()
+    }
+
+    #[test]
+    fn slot_bounded_cluster_forgets_decided_slots_and_survives_failover() {
+        let mode = MemoryMode::SlotBounded { retained: 10 };
+        let capacity = mode.capacity().unwrap() as usize;
+        let config = cluster_config(3, 2, 3);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.set_memory_mode(mode);
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let mut leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            leader.set_memory_mode(mode);
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(leader));
+        }
+        for id in config.replicas.iter() {
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.set_memory_mode(mode);
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+
+        // Many times the window's worth of commands go through
+        assert_eq!(run_workload(&mut sim, &config, 1, 100), 100);
+
+        sim.crash(winner.leader.into());
+        let failed_over = sim.sent().len();
+        assert_eq!(run_workload(&mut sim, &config, 101, 20), 20);
+
+        // The successor learned only the acceptors' windows, and started above
+        // the slots they had forgotten
+        let promises: Vec<&P1bMessage> = sim.sent()[failed_over..]
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::P1b(p1b) => Some(p1b),
+                _ => None,
+            })
+            .collect();
+        assert!(!promises.is_empty());
+        for p1b in promises {
+            assert!(p1b.gc_below > types::Slot(1));
+            assert!(p1b.accepted.len() <= capacity);
+        }
+    }
+
+    #[test]
+    fn witness_acceptor_completes_quorums_without_storing_commands() {
+        let witness = types::AcceptorId::new(3);
+        let config = cluster_config(3, 2, 3).with_witnesses([witness]);
+        let mut sim: Simulation = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        assert_eq!(run_workload(&mut sim, &config, 1, 20), 20);
+
+        // With the active leader and a full acceptor gone, the successor's
+        // quorums pair the witness with the remaining full acceptor
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        sim.crash(winner.leader.into());
+        sim.crash(types::AcceptorId::new(1).into());
+        let failed_over = sim.sent().len();
+        assert_eq!(run_workload(&mut sim, &config, 21, 20), 20);
+
+        let witness_promises: Vec<&P1bMessage> = sim.sent()[failed_over..]
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::P1b(p1b) if p1b.src == witness => Some(p1b),
+                _ => None,
+            })
+            .collect();
+        assert!(!witness_promises.is_empty());
+        for p1b in witness_promises {
+            assert!(p1b.accepted.is_empty());
+            assert!(p1b.witnessed.len() >= 20);
+        }
+    }
+
+    /// A node the test keeps a handle on while the simulation runs it.
+    struct Shared<N>(Arc<Mutex<N>>);
+
+    impl<N: Node> Node for Shared<N> {
+        fn accept_message(&mut self, msg: SendableMessage) {
+            self.0.lock().unwrap().accept_message(msg)
+        }
+        fn work_on_message(&mut self) -> bool {
+            self.0.lock().unwrap().work_on_message()
+        }
+        fn check_timers(&mut self) -> anyhow::Result<Vec<ClockAction>> {
+            self.0.lock().unwrap().check_timers()
+        }
+        fn next_timeout(&self) -> Option<Duration> {
+            self.0.lock().unwrap().next_timeout()
+        }
+        fn deliver_sent(&mut self) -> Option<SendableMessage> {
+            self.0.lock().unwrap().deliver_sent()
+        }
+        fn pending(&self) -> usize {
+            self.0.lock().unwrap().pending()
+        }
+        fn health(&self) -> Health {
+            self.0.lock().unwrap().health()
+        }
+        fn set_timeouts(&mut self, timeouts: types::TimeoutConfig) {
+            self.0.lock().unwrap().set_timeouts(timeouts)
+        }
+    }
+
+    #[test]
+    fn active_leader_hands_over_without_dropping_requests() {
+        let config = cluster_config(3, 2, 3);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        let mut leaders = HashMap::new();
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            let leader = Arc::new(Mutex::new(leader));
+            leaders.insert(*id, leader.clone());
+            sim.add_node(
+                (*id).into(),
+                &address((*id).into()),
+                Box::new(Shared(leader)),
+            );
+        }
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.replicas.iter() {
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        let target = *config
+            .leaders
+            .iter()
+            .find(|l| **l != winner.leader)
+            .unwrap();
+        // Only the active leader can hand over
+        assert!(leaders[&target]
+            .lock()
+            .unwrap()
+            .transfer_leadership(winner.leader)
+            .is_err());
+
+        let handoff = sim.sent().len();
+        leaders[&winner.leader]
+            .lock()
+            .unwrap()
+            .transfer_leadership(target)
+            .unwrap();
+        assert_eq!(run_workload(&mut sim, &config, 1, 50), 50);
+
+        // The target took over with a single round of scouts, and the old leader
+        // stepped down rather than competing for its ballot back
+        let later = &sim.sent()[handoff..];
+        let successors: Vec<_> = active_ballots(later)
+            .into_iter()
+            .filter(|ballot| *ballot != winner)
+            .collect();
+        assert_eq!(successors.len(), 1);
+        assert_eq!(successors[0].leader, target);
+        assert!(successors[0] > winner);
+        assert_eq!(scouts_by(later, target), config.acceptors.len());
+        assert_eq!(scouts_by(later, winner.leader), 0);
+    }
+
+    /// Cut the active leader off from the other leader, but from nothing
+    /// else, and return how many scouts the other leader sent and whether it
+    /// took over.
+    fn cut_off_leader_disruption(pre_vote: bool) -> (usize, bool) {
+        let config = cluster_config(3, 2, 1);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let mut leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            leader.set_pre_vote(pre_vote).unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(leader));
+        }
+        for id in config.replicas.iter() {
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        let other = *config
+            .leaders
+            .iter()
+            .find(|l| **l != winner.leader)
+            .unwrap();
+
+        sim.disconnect(winner.leader.into(), other.into());
+        let cut = sim.sent().len();
+        sim.run_for(Duration::from_secs(30), Duration::from_millis(10));
+        let later = &sim.sent()[cut..];
+        let took_over = active_ballots(later).iter().any(|b| b.leader == other);
+        let scouts = scouts_by(later, other);
+
+        if pre_vote {
+            // The pre-vote must not get in the way of a real failover
+            sim.crash(winner.leader.into());
+            let crashed = sim.sent().len();
+            sim.run_for(Duration::from_secs(15), Duration::from_millis(10));
+            let successors = active_ballots(&sim.sent()[crashed..]);
+            assert!(successors.iter().any(|b| b.leader == other));
+        }
+        (scouts, took_over)
+    }
+
+    #[test]
+    fn pre_vote_keeps_a_cut_off_leader_from_disrupting_the_active_one() {
+        let (scouts, took_over) = cut_off_leader_disruption(false);
+        assert!(scouts > 0);
+        assert!(took_over);
+
+        let (scouts, took_over) = cut_off_leader_disruption(true);
+        assert_eq!(scouts, 0);
+        assert!(!took_over);
+    }
+
+    #[test]
+    fn nodes_driven_by_tick_alone_serve_a_client() {
+        let config = cluster_config(3, 1, 1);
+        let time = SimTime::new();
+        let clock = || Box::new(SimClock::new(time.clone()));
+        let mut nodes: BTreeMap<String, Box<dyn Node>> = BTreeMap::new();
+        let address = |id: NodeId| config.get_address(&id).unwrap().to_string();
+        for id in config.acceptors.iter() {
+            let mut acceptor = Acceptor::new(*id, config.clone(), Mailbox::new(), clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            nodes.insert(address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), clock()).unwrap();
+            nodes.insert(address((*id).into()), Box::new(leader));
+        }
+        let replica = *config.replicas.iter().next().unwrap();
+        let mut node = Replica::new(replica, config.clone(), Mailbox::new(), clock()).unwrap();
+        node.start_periodic_checks().unwrap();
+        nodes.insert(address(replica.into()), Box::new(node));
+        let mut inbound = vec![request_to(config.get_address(replica.as_ref()).unwrap(), 1)];
+
+        // The whole drive loop: queue what arrived, tick every node at most
+        // a few messages at a time, and sleep until the earliest timeout
+        let mut answered = None;
+        for _ in 0..10_000 {
+            for msg in inbound.drain(..) {
+                match nodes.get_mut(&msg.dst.to_string()) {
+                    Some(node) => node.accept_message(msg),
+                    None => answered = Some(msg),
+                }
+            }
+            let mut wait: Option<Duration> = None;
+            for node in nodes.values_mut() {
+                let tick = node.tick(4).unwrap();
+                inbound.extend(tick.sent);
+                wait = match (wait, tick.next_timeout) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+            if answered.is_some() {
+                break;
+            }
+            if inbound.is_empty() {
+                time.advance(wait.expect("a leader scouts until it is elected"));
+            }
+        }
+        let answered = answered.expect("the request was answered");
+        assert!(matches!(
+            answered.message,
+            Message::Response(ResponseMessage {
+                status: ResponseStatus::Performed,
+                ..
+            })
+        ));
+
+        // Messages beyond the limit are left for the next tick, which is due at once
+        let leader_id = NodeId::from(*config.leaders.iter().next().unwrap());
+        let leader = nodes.get_mut(&address(leader_id)).unwrap();
+        for acceptor in config.acceptors.iter() {
+            leader.accept_message(SendableMessage {
+                src: config.get_address(acceptor.as_ref()).unwrap().clone(),
+                dst: config.get_address(&leader_id).unwrap().clone(),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Heartbeat(HeartbeatMessage {
+                    src: (*acceptor).into(),
+                    ballot: None,
+                    quorum_lost: false,
+                    promised: None,
+                    lease: None,
+                    commit_index: None,
+                }),
+            });
+        }
+        let tick = leader.tick(2).unwrap();
+        assert_eq!(tick.next_timeout, Some(Duration::ZERO));
+        assert_eq!(leader.pending(), 1);
+        let tick = leader.tick(2).unwrap();
+        assert!(tick.next_timeout.is_some_and(|wait| wait > Duration::ZERO));
+        assert_eq!(leader.pending(), 0);
+    }
+
+    /// Records the command each decision a replica performs carries.
+    struct Performed(Arc<Mutex<Vec<types::CommandId>>>);
+
+    impl StateMachine for Performed {
+        fn apply(&mut self, _op: &Vec<u8>) -> Vec<u8> {
+            Vec::new()
+        }
+        fn on_before_apply(&mut self, ctx: &ApplyContext<'_, Vec<u8>>) {
+            self.0.lock().unwrap().push(ctx.command.id());
+        }
+    }
+
+    fn request_to(replica: &types::Address, request_id: u64) -> SendableMessage {
+        let client = types::Address::new("client".to_string(), 1);
+        SendableMessage {
+            src: client.clone(),
+            dst: replica.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Request(RequestMessage {
+                src: client,
+                command: types::Command {
+                    client_id: NodeId::new(999),
+                    request_id,
+                    op: types::CommandType::Op(vec![request_id as u8]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }),
+        }
+    }
+
+    /// The status of each response to `request_id`.
+    fn answers(external: &[SendableMessage], request_id: u64) -> Vec<ResponseStatus> {
+        external
+            .iter()
+            .filter_map(|msg| match &msg.message {
+                Message::Response(response) if response.command_id.request_id == request_id => {
+                    Some(response.status)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn minority_partition_steps_down_turns_clients_away_and_reconciles_on_heal() {
+        let config = cluster_config(3, 2, 3);
+        let mut sim: Simulation = Simulation::new();
+        let address = |id: NodeId| config.get_address(&id).cloned().unwrap();
+        for id in config.acceptors.iter() {
+            let mut acceptor =
+                Acceptor::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            acceptor.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(acceptor));
+        }
+        for id in config.leaders.iter() {
+            let leader = Leader::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(leader));
+        }
+        let mut performed = HashMap::new();
+        for id in config.replicas.iter() {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            performed.insert(*id, log.clone());
+            let mut replica =
+                Replica::new(*id, config.clone(), Mailbox::new(), sim.clock()).unwrap();
+            replica.set_state_machine(Box::new(Performed(log)));
+            replica.start_periodic_checks().unwrap();
+            sim.add_node((*id).into(), &address((*id).into()), Box::new(replica));
+        }
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+        assert_eq!(run_workload(&mut sim, &config, 1, 5), 5);
+        let winner = active_ballots(sim.sent()).last().unwrap().clone();
+        let minority_replica = types::ReplicaId::new(201);
+        let majority_replica = address(types::ReplicaId::new(202).into());
+
+        // The active leader, one acceptor and one replica are cut off
+        sim.partition(&[
+            winner.leader.into(),
+            types::AcceptorId::new(1).into(),
+            minority_replica.into(),
+        ]);
+        // Taken in before anyone noticed: it waits for a quorum
+        sim.inject(request_to(&address(minority_replica.into()), 10));
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+        let expired = sim.sent().len();
+        sim.run_for(Duration::from_secs(5), Duration::from_millis(10));
+
+        // Past quorum_loss_timeout the cut-off leader no longer claims to
+        // lead, while the majority elected a leader of its own
+        let later = &sim.sent()[expired..];
+        assert!(later.iter().any(|msg| matches!(
+            &msg.message,
+            Message::Heartbeat(HeartbeatMessage { src, ballot: None, quorum_lost: true, .. })
+                if *src == NodeId::from(winner.leader)
+        )));
+        let leading: Vec<types::BallotNumber> = active_ballots(later);
+        assert!(leading.iter().all(|ballot| ballot.leader != winner.leader));
+        assert!(leading.iter().any(|ballot| *ballot > winner));
+
+        // The minority replica turns new requests away; the majority serves them
+        sim.inject(request_to(&address(minority_replica.into()), 11));
+        sim.inject(request_to(&majority_replica, 12));
+        sim.run_for(Duration::from_secs(2), Duration::from_millis(10));
+        let external = sim.take_external();
+        assert_eq!(answers(&external, 11), vec![ResponseStatus::Unavailable]);
+        assert!(answers(&external, 12).contains(&ResponseStatus::Performed));
+        assert!(answers(&external, 10).is_empty());
+
+        // Healed, the waiting request is decided, and the turned-away one
+        // goes through when the client sends it again once heartbeats have
+        // told the replica the cluster is whole
+        sim.heal();
+        let healed = sim.sent().len();
+        sim.run_for(Duration::from_secs(3), Duration::from_millis(10));
+        sim.inject(request_to(&address(minority_replica.into()), 11));
+        sim.run_for(Duration::from_secs(15), Duration::from_millis(10));
+        let external = sim.take_external();
+        assert!(answers(&external, 10).contains(&ResponseStatus::Performed));
+        assert!(answers(&external, 11).contains(&ResponseStatus::Performed));
+        // The old leader found the new one active and did not contest it
+        assert!(active_ballots(&sim.sent()[healed..])
+            .iter()
+            .all(|ballot| ballot.leader != winner.leader));
+
+        // Every replica performed every command exactly once, in the same order
+        let logs: Vec<Vec<types::CommandId>> = performed
+            .values()
+            .map(|log| log.lock().unwrap().clone())
+            .collect();
+        assert_eq!(logs[0].len(), 8);
+        let distinct: HashSet<&types::CommandId> = logs[0].iter().collect();
+        assert_eq!(distinct.len(), 8);
+        assert!(logs.iter().all(|log| *log == logs[0]));
+    }
+
+    /// Run ten requests through a cluster delivering by `delivery`,
+    /// returning the slot each replica answered each command with and every
+    /// message sent.
+    fn answers_under(
+        delivery: Delivery,
+    ) -> (BTreeMap<(u64, String), types::Slot>, Vec<SendableMessage>) {
+        let config = cluster_config(3, 1, 3);
+        let mut sim: Simulation = Simulation::new();
+        sim.set_delivery(delivery);
+        sim.add_cluster(&config).unwrap();
+        sim.run_for(Duration::from_secs(2), Duration::from_millis(10));
+
+        let client = types::Address::new("client".to_string(), 1);
+        let mut replicas: Vec<_> = config.replicas.iter().copied().collect();
+        replicas.sort_by_key(|id| NodeId::from(*id));
+        for request_id in 1..=10 {
+            let replica = replicas[request_id as usize % replicas.len()];
+            sim.inject(SendableMessage {
+                src: client.clone(),
+                dst: config.get_address(replica.as_ref()).cloned().unwrap(),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Request(RequestMessage {
+                    src: client.clone(),
+                    command: types::Command {
+                        client_id: NodeId::new(999),
+                        request_id,
+                        op: types::CommandType::Op(vec![request_id as u8]),
+                    },
+                    consistency: Consistency::Linearizable,
+                    proxied_by: None,
+                }),
+            });
+            sim.run_for(Duration::from_millis(10), Duration::from_millis(10));
+        }
+        sim.run_for(Duration::from_secs(10), Duration::from_millis(10));
+
+        let mut answers = BTreeMap::new();
+        for msg in sim.take_external() {
+            if let Message::Response(response) = msg.message {
+                let key = (response.command_id.request_id, msg.src.to_string());
+                assert_eq!(*answers.entry(key).or_insert(response.slot), response.slot);
+            }
+        }
+        (answers, sim.sent().to_vec())
+    }
+
+    #[test]
+    fn cluster_agrees_under_every_delivery_policy_and_seeds_replay() {
+        for delivery in [
+            Delivery::Fifo,
+            Delivery::Random { seed: 7 },
+            Delivery::Adversarial {
+                delayed: |msg| matches!(msg, Message::P2b(_) | Message::Decision(_)),
+                steps: 5,
+            },
+        ] {
+            let (answers, _) = answers_under(delivery);
+            // Every replica answered every command, all with the same slot
+            assert_eq!(answers.len(), 30);
+            for request_id in 1..=10 {
+                let slots: HashSet<_> = answers
+                    .iter()
+                    .filter(|((id, _), _)| *id == request_id)
+                    .map(|(_, slot)| *slot)
+                    .collect();
+                assert_eq!(slots.len(), 1);
+            }
+        }
+
+        let (_, first) = answers_under(Delivery::Random { seed: 7 });
+        let (_, again) = answers_under(Delivery::Random { seed: 7 });
+        let (_, other) = answers_under(Delivery::Random { seed: 8 });
+        // Nodes send to peers in hash set order, so compare what was sent
+        // rather than the order it was sent in, or the Lamport times that
+        // order stamped it with
+        let trace = |sent: &[SendableMessage]| {
+            let mut sent: Vec<_> = sent
+                .iter()
+                .map(|msg| {
+                    format!(
+                        "{:?}",
+                        SendableMessage {
+                            lamport: None,
+                            epoch: None,
+                            ..msg.clone()
+                        }
+                    )
+                })
+                .collect();
+            sent.sort();
+            sent
+        };
+        assert_eq!(trace(&first), trace(&again));
+        assert_ne!(trace(&first), trace(&other));
+    }
+}
@@ -0,0 +1,287 @@
+//! Rewinding and single-stepping simulator runs.
+//!
+//! A `TimeTravel` drives a `Simulation` and records every input to it: steps,
+//! time advancing, injected messages, crashes and partitions. Every `every`
+//! steps it takes a checkpoint. Nodes hold clocks, stores and state machines
+//! that cannot be copied, so a checkpoint is a position in the recorded run
+//! rather than a copy of it. Rewinding builds a fresh simulation and replays
+//! the inputs up to the checkpoint. Runs are deterministic, and a fingerprint
+//! of the messages sent by the checkpoint confirms that the replay got back
+//! to the same state.
+//!
+//! From a checkpoint, `step_message` delivers one message at a time and
+//! prints each node's status, to narrow down where a failed run goes wrong.
+//! Rewinding discards the inputs recorded after the checkpoint.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::messages::SendableMessage;
+use crate::nodes::clock::Ticks;
+use crate::sim::Simulation;
+use crate::time::Duration;
+use crate::types::{NodeId, Payload};
+
+#[derive(Clone, Debug)]
+enum Input<T> {
+    Step,
+    StepMessage,
+    Advance(Ticks),
+    Inject(Box<SendableMessage<T>>),
+    Crash(NodeId),
+    Disconnect(NodeId, NodeId),
+    Reconnect(NodeId, NodeId),
+}
+
+/// A point in a recorded run that `TimeTravel::rewind` can return to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Steps taken when the checkpoint was taken.
+    pub steps: u64,
+    /// Simulated time since the start of the run.
+    pub elapsed: Duration,
+    // Inputs recorded before the checkpoint
+    inputs: usize,
+    // Messages sent by then, and a hash of them independent of their order
+    fingerprint: (usize, u64),
+}
+
+type Build<T> = Box<dyn Fn() -> anyhow::Result<Simulation<T>>>;
+
+pub struct TimeTravel<T = Vec<u8>> {
+    build: Build<T>,
+    sim: Simulation<T>,
+    inputs: Vec<Input<T>>,
+    steps: u64,
+    every: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl<T: Payload + Send + 'static> TimeTravel<T> {
+    /// Record a run of the simulation `build` makes, checkpointing every
+    /// `every` steps. `build` is called again for each rewind, and must set
+    /// up the same nodes each time.
+    pub fn new(
+        build: impl Fn() -> anyhow::Result<Simulation<T>> + 'static,
+        every: u64,
+    ) -> anyhow::Result<TimeTravel<T>> {
+        let sim = build()?;
+        let mut travel = TimeTravel {
+            build: Box::new(build),
+            sim,
+            inputs: Vec::new(),
+            steps: 0,
+            every: every.max(1),
+            checkpoints: Vec::new(),
+        };
+        travel.checkpoint();
+        Ok(travel)
+    }
+
+    pub fn sim(&self) -> &Simulation<T> {
+        &self.sim
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Simulated time since the start of the run.
+    pub fn elapsed(&self) -> Duration {
+        self.sim.time.elapsed()
+    }
+
+    /// Checkpoints taken so far, oldest first; the first is the start of the run.
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    pub fn step(&mut self) {
+        self.record(Input::Step);
+        if self.steps.is_multiple_of(self.every) {
+            self.checkpoint();
+        }
+    }
+
+    /// Step repeatedly, advancing simulated time by `tick` after each step.
+    pub fn run_for(&mut self, duration: Duration, tick: Duration) {
+        let mut elapsed = Duration::ZERO;
+        while elapsed < duration {
+            self.step();
+            self.advance(tick);
+            elapsed += tick;
+        }
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.record(Input::Advance(self.sim.time.to_ticks(by)));
+    }
+
+    pub fn inject(&mut self, msg: SendableMessage<T>) {
+        self.record(Input::Inject(Box::new(msg)));
+    }
+
+    pub fn crash(&mut self, id: NodeId) {
+        self.record(Input::Crash(id));
+    }
+
+    pub fn disconnect(&mut self, a: NodeId, b: NodeId) {
+        self.record(Input::Disconnect(a, b));
+    }
+
+    pub fn reconnect(&mut self, a: NodeId, b: NodeId) {
+        self.record(Input::Reconnect(a, b));
+    }
+
+    /// Deliver the next in-flight message on its own, then print it and the
+    /// status of every node. Returns the message, if any was in flight.
+    pub fn step_message(&mut self) -> Option<SendableMessage<T>> {
+        let before = self.sim.sent().len();
+        let msg = self.sim.step_message()?;
+        self.inputs.push(Input::StepMessage);
+        println!("[step {}] {}", self.steps, msg);
+        for sent in &self.sim.sent()[before..] {
+            println!("  sent {}", sent);
+        }
+        for status in self.sim.statuses() {
+            println!("  {}", status);
+        }
+        Some(msg)
+    }
+
+    /// Go back to `checkpoints()[index]`, forgetting everything after it.
+    pub fn rewind(&mut self, index: usize) -> anyhow::Result<()> {
+        let checkpoint = *self
+            .checkpoints
+            .get(index)
+            .ok_or(anyhow::anyhow!("no checkpoint {}", index))?;
+        self.inputs.truncate(checkpoint.inputs);
+        self.checkpoints.truncate(index + 1);
+        self.sim = (self.build)()?;
+        self.steps = 0;
+        for input in self.inputs.clone() {
+            self.apply(input);
+        }
+        let replayed = fingerprint(&self.sim);
+        if replayed != checkpoint.fingerprint {
+            return Err(anyhow::anyhow!(
+                "replay to step {} diverged: {} messages sent, {} at the checkpoint",
+                checkpoint.steps,
+                replayed.0,
+                checkpoint.fingerprint.0
+            ));
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, input: Input<T>) {
+        self.inputs.push(input.clone());
+        self.apply(input);
+    }
+
+    fn apply(&mut self, input: Input<T>) {
+        match input {
+            Input::Step => {
+                self.sim.step();
+                self.steps += 1;
+            }
+            Input::StepMessage => {
+                self.sim.step_message();
+            }
+            Input::Advance(ticks) => self.sim.time.advance_ticks(ticks),
+            Input::Inject(msg) => self.sim.inject(*msg),
+            Input::Crash(id) => self.sim.crash(id),
+            Input::Disconnect(a, b) => self.sim.disconnect(a, b),
+            Input::Reconnect(a, b) => self.sim.reconnect(a, b),
+        }
+    }
+
+    fn checkpoint(&mut self) {
+        self.checkpoints.push(Checkpoint {
+            steps: self.steps,
+            elapsed: self.elapsed(),
+            inputs: self.inputs.len(),
+            fingerprint: fingerprint(&self.sim),
+        });
+    }
+}
+
+fn fingerprint<T: Payload + Send + 'static>(sim: &Simulation<T>) -> (usize, u64) {
+    let hash = sim.sent().iter().fold(0u64, |sum, msg| {
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", msg).hash(&mut hasher);
+        sum.wrapping_add(hasher.finish())
+    });
+    (sim.sent().len(), hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{Consistency, Message, RequestMessage};
+    use crate::sim::cluster_config;
+    use crate::types::{Address, Command, CommandType, ReplicaId};
+
+    #[test]
+    fn rewinding_replays_the_run_up_to_a_checkpoint() {
+        let config = cluster_config(3, 2, 2);
+        let build_config = config.clone();
+        let mut travel: TimeTravel = TimeTravel::new(
+            move || {
+                let mut sim = Simulation::new();
+                sim.add_cluster(&build_config)?;
+                Ok(sim)
+            },
+            50,
+        )
+        .unwrap();
+        travel.run_for(Duration::from_millis(990), Duration::from_millis(10));
+        let client = Address::new("client".to_string(), 1);
+        travel.inject(SendableMessage {
+            src: client.clone(),
+            dst: config
+                .get_address(ReplicaId::new(201).as_ref())
+                .cloned()
+                .unwrap(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Request(RequestMessage {
+                src: client,
+                command: Command {
+                    client_id: NodeId::new(999),
+                    request_id: 1,
+                    op: CommandType::Op(vec![1]),
+                },
+                consistency: Consistency::Linearizable,
+                proxied_by: None,
+            }),
+        });
+        travel.crash(NodeId::new(1));
+        travel.run_for(Duration::from_secs(1), Duration::from_millis(10));
+        assert_eq!(travel.steps(), 199);
+        assert_eq!(travel.checkpoints().len(), 4);
+
+        // Back to step 100, as the request is being proposed: the same
+        // messages had been sent as the first time
+        let checkpoint = travel.checkpoints()[2];
+        assert_eq!(checkpoint.steps, 100);
+        travel.rewind(2).unwrap();
+        assert_eq!(travel.steps(), 100);
+        assert_eq!(travel.elapsed(), checkpoint.elapsed);
+        assert_eq!(travel.checkpoints().len(), 3);
+        assert!(travel.sim().statuses()[0].crashed);
+
+        // Then one message at a time
+        let in_flight = travel.sim().in_flight().count();
+        let sent = travel.sim().sent().len();
+        assert!(in_flight > 0);
+        assert!(travel.step_message().is_some());
+        assert_eq!(travel.steps(), 100);
+        // Rewinding again drops the single steps with everything else after the checkpoint
+        travel.step();
+        travel.rewind(2).unwrap();
+        assert_eq!(travel.sim().sent().len(), sent);
+        assert_eq!(travel.sim().in_flight().count(), in_flight);
+        assert!(travel.rewind(9).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! The application state replicated by the cluster.
+//!
+//! Replicas hand every newly decided `CommandType::Op` to their
+//! `StateMachine` in slot order and send the result back to the client in a
+//! `ResponseMessage`. How a result is encoded is up to the application.
+//!
+//! Around each `apply` the replica calls `on_before_apply` and
+//! `on_after_apply` with the slot and command being performed, so triggers,
+//! metrics or secondary indexes can follow the log without a fork of the
+//! replica. A replica holding its memory to a window calls `on_snapshot`
+//! before it forgets performed decisions: from then on the state machine's
+//! own state is the only record of them.
+//!
+//! Applications with several kinds of command can send them as
+//! `CommandType::App { kind, payload }` instead of one `Op` type, and
+//! register a `CommandHandler` per kind in a `CommandRegistry`. Every
+//! replica must register the same kinds.
+//!
+//! A `CommandType::Batch` is applied one operation at a time, each with its
+//! own hooks, and answered with every result at once, packed by
+//! `encode_batch_results`.
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collections::BTreeMap;
+use crate::types::{Command, Slot};
+
+/// The decision a state machine is applying.
+#[derive(Clone, Copy, Debug)]
+pub struct ApplyContext<'a, T> {
+    pub slot: Slot,
+    pub command: &'a Command<T>,
+}
+
+pub trait StateMachine<T = Vec<u8>> {
+    /// Apply a decided operation and return the result for the client.
+    fn apply(&mut self, op: &T) -> Vec<u8>;
+
+    /// Answer `op` from the current state without applying it, if it is a
+    /// read. Reads below `Linearizable` consistency are served this way;
+    /// returning `None` sends the request through consensus instead.
+    fn query(&self, _op: &T) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called just before the operation in `ctx` is applied.
+    fn on_before_apply(&mut self, _ctx: &ApplyContext<'_, T>) {}
+
+    /// Called just after the operation in `ctx` was applied, with its result.
+    fn on_after_apply(&mut self, _ctx: &ApplyContext<'_, T>, _result: &[u8]) {}
+
+    /// Called before the replica forgets the decisions below `slot`, all of
+    /// which have been applied.
+    fn on_snapshot(&mut self, _slot: Slot) {}
+}
+
+/// Applies nothing and answers every command with an empty result.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullStateMachine;
+
+impl<T> StateMachine<T> for NullStateMachine {
+    fn apply(&mut self, _op: &T) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// Performs the `CommandType::App` commands of one kind.
+pub trait CommandHandler {
+    /// Apply a decided command's payload and return the result for the client.
+    fn apply(&mut self, payload: &[u8]) -> Vec<u8>;
+
+    /// Answer `payload` without applying it, if it is a read, as
+    /// `StateMachine::query` does.
+    fn query(&self, _payload: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Vec<u8>> CommandHandler for F {
+    fn apply(&mut self, payload: &[u8]) -> Vec<u8> {
+        self(payload)
+    }
+}
+
+/// Pack the results of a batch's operations into one response, each as its
+/// length, a little-endian `u32`, followed by its bytes.
+pub fn encode_batch_results(results: &[Vec<u8>]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(results.iter().map(|r| r.len() + 4).sum());
+    for result in results {
+        encoded.extend_from_slice(&(result.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(result);
+    }
+    encoded
+}
+
+/// The results `encode_batch_results` packed, or `None` if `encoded` was
+/// not packed by it.
+pub fn decode_batch_results(mut encoded: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut results = Vec::new();
+    while !encoded.is_empty() {
+        let (len, rest) = encoded.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let (result, rest) = rest.split_at(len);
+        results.push(result.to_vec());
+        encoded = rest;
+    }
+    Some(results)
+}
+
+/// The `CommandHandler` for each registered command kind.
+#[derive(Default)]
+pub struct CommandRegistry {
+    handlers: BTreeMap<u16, Box<dyn CommandHandler + Send>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry::default()
+    }
+
+    /// Have `handler` perform commands of `kind`. A kind can only be
+    /// registered once.
+    pub fn register(
+        &mut self,
+        kind: u16,
+        handler: Box<dyn CommandHandler + Send>,
+    ) -> anyhow::Result<()> {
+        if self.handlers.contains_key(&kind) {
+            anyhow::bail!("command kind {} is already registered", kind);
+        }
+        self.handlers.insert(kind, handler);
+        Ok(())
+    }
+
+    pub fn contains(&self, kind: u16) -> bool {
+        self.handlers.contains_key(&kind)
+    }
+
+    pub fn get(&self, kind: u16) -> Option<&(dyn CommandHandler + Send)> {
+        self.handlers.get(&kind).map(|handler| handler.as_ref())
+    }
+
+    pub fn get_mut(&mut self, kind: u16) -> Option<&mut (dyn CommandHandler + Send + 'static)> {
+        self.handlers.get_mut(&kind).map(|handler| handler.as_mut())
+    }
+
+    /// The registered kinds, in order.
+    pub fn kinds(&self) -> impl Iterator<Item = u16> + '_ {
+        self.handlers.keys().copied()
+    }
+}
+
+impl fmt::Debug for CommandRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.kinds()).finish()
+    }
+}
+
+/// Operations on a `KvStore`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvCommand {
+    Put { key: String, value: Vec<u8> },
+    Get { key: String },
+    Delete { key: String },
+}
+
+/// A replicated key-value map.
+///
+/// `Get` and `Delete` answer with the key's value (empty if it was absent);
+/// `Put` answers with an empty result.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KvStore {
+    data: BTreeMap<String, Vec<u8>>,
+}
+
+impl KvStore {
+    pub fn new() -> KvStore {
+        KvStore::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Vec<u8>> {
+        self.data.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl StateMachine<KvCommand> for KvStore {
+    fn apply(&mut self, op: &KvCommand) -> Vec<u8> {
+        match op {
+            KvCommand::Put { key, value } => {
+                self.data.insert(key.clone(), value.clone());
+                Vec::new()
+            }
+            KvCommand::Get { key } => self.data.get(key).cloned().unwrap_or_default(),
+            KvCommand::Delete { key } => self.data.remove(key).unwrap_or_default(),
+        }
+    }
+
+    fn query(&self, op: &KvCommand) -> Option<Vec<u8>> {
+        match op {
+            KvCommand::Get { key } => Some(self.data.get(key).cloned().unwrap_or_default()),
+            KvCommand::Put { .. } | KvCommand::Delete { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn kv_store_applies_commands() {
+        let mut kv = KvStore::new();
+        let put = KvCommand::Put {
+            key: "a".to_string(),
+            value: vec![1],
+        };
+        assert!(kv.apply(&put).is_empty());
+        assert_eq!(
+            kv.apply(&KvCommand::Get {
+                key: "a".to_string()
+            }),
+            vec![1]
+        );
+        assert_eq!(
+            kv.apply(&KvCommand::Delete {
+                key: "a".to_string()
+            }),
+            vec![1]
+        );
+        assert!(kv
+            .apply(&KvCommand::Get {
+                key: "a".to_string()
+            })
+            .is_empty());
+        assert!(kv.is_empty());
+        assert_eq!(
+            kv.query(&KvCommand::Get {
+                key: "a".to_string()
+            }),
+            Some(vec![])
+        );
+        assert_eq!(kv.query(&put), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! Cluster-wide status, collected from every node's admin server.
+//!
+//! A `StatusConfig` lists each node's id, role and admin address, usually
+//! from a `cluster.toml`:
+//!
+//! ```toml
+//! [[node]]
+//! id = 1
+//! role = "acceptor"
+//! admin = "10.0.0.1:9100"
+//! ```
+//!
+//! `ClusterStatus::collect` asks each of them for `GET /status` and puts the
+//! answers side by side: each node's ballot, its slot frontier and how far
+//! that lags behind the furthest node of the same role. The cluster is
+//! degraded when any node is unreachable or not `Ready`, or no leader leads,
+//! and a node whose transport reports a link down names it as a problem.
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::admin::StatusReport;
+use crate::nodes::combined::Role;
+use crate::nodes::health::Health;
+use crate::types::{BallotNumber, NodeId, Slot};
+
+/// Responses larger than this are refused.
+const MAX_RESPONSE_LEN: u64 = 64 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusConfig {
+    #[serde(rename = "node", default)]
+    pub nodes: Vec<AdminEndpoint>,
+}
+
+impl StatusConfig {
+    /// Read a status config from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<StatusConfig> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+/// Where to find one node's admin server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminEndpoint {
+    pub id: NodeId,
+    pub role: Role,
+    /// `host:port` of the node's `AdminServer`.
+    pub admin: String,
+}
+
+/// One row of the status table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeRow {
+    pub endpoint: AdminEndpoint,
+    /// The node's report, or why it could not be had.
+    pub report: Result<StatusReport, String>,
+    /// Slots the node's frontier is behind the furthest node of its role.
+    pub lag: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClusterStatus {
+    pub nodes: Vec<NodeRow>,
+}
+
+impl ClusterStatus {
+    /// Ask every node in `config` for its status, waiting at most `timeout` for each.
+    pub fn collect(config: &StatusConfig, timeout: Duration) -> ClusterStatus {
+        let rows = config
+            .nodes
+            .iter()
+            .map(|endpoint| NodeRow {
+                endpoint: endpoint.clone(),
+                report: fetch_status(&endpoint.admin, timeout).map_err(|e| e.to_string()),
+                lag: None,
+            })
+            .collect();
+        ClusterStatus::from_rows(rows)
+    }
+
+    /// Aggregate reports already collected, filling in each node's lag.
+    pub fn from_rows(mut nodes: Vec<NodeRow>) -> ClusterStatus {
+        let frontier = |row: &NodeRow| row.report.as_ref().ok()?.progress.frontier;
+        let furthest: Vec<(Role, Slot)> = nodes
+            .iter()
+            .filter_map(|row| Some((row.endpoint.role, frontier(row)?)))
+            .collect();
+        for row in nodes.iter_mut() {
+            let ahead = furthest
+                .iter()
+                .filter(|(role, _)| *role == row.endpoint.role)
+                .map(|(_, slot)| *slot)
+                .max();
+            row.lag = frontier(row).zip(ahead).map(|(slot, ahead)| ahead - slot);
+        }
+        ClusterStatus { nodes }
+    }
+
+    /// What is wrong with the cluster, if anything.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for row in &self.nodes {
+            match &row.report {
+                Err(e) => problems.push(format!("{} is unreachable: {}", row.endpoint.id, e)),
+                Ok(report) if report.health != Health::Ready => {
+                    problems.push(format!(
+                        "{} is {}",
+                        row.endpoint.id,
+                        health_label(&report.health)
+                    ));
+                }
+                Ok(_) => {}
+            }
+            let down = row.report.iter().flat_map(|report| &report.links);
+            for (dst, link) in down.filter(|(_, link)| !link.up) {
+                problems.push(format!(
+                    "{} cannot reach {}: {}",
+                    row.endpoint.id,
+                    dst,
+                    link.last_error.as_deref().unwrap_or("link down")
+                ));
+            }
+        }
+        let leading = self
+            .nodes
+            .iter()
+            .filter_map(|row| row.report.as_ref().ok())
+            .any(|report| report.progress.leading);
+        if !leading {
+            problems.push("no leader is leading".to_string());
+        }
+        problems
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        !self.problems().is_empty()
+    }
+}
+
+impl fmt::Display for ClusterStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut table = vec![[
+            "NODE", "ROLE", "ADMIN", "HEALTH", "BALLOT", "FRONTIER", "LAG",
+        ]
+        .map(String::from)];
+        for row in &self.nodes {
+            let (health, ballot, frontier) = match &row.report {
+                Ok(report) => (
+                    health_label(&report.health).to_string(),
+                    report
+                        .progress
+                        .ballot
+                        .as_ref()
+                        .map(|ballot| ballot_label(ballot, report.progress.leading))
+                        .unwrap_or_else(|| "-".to_string()),
+                    optional(report.progress.frontier),
+                ),
+                Err(_) => ("unreachable".to_string(), "-".to_string(), "-".to_string()),
+            };
+            table.push([
+                row.endpoint.id.to_string(),
+                row.endpoint.role.to_string(),
+                row.endpoint.admin.clone(),
+                health,
+                ballot,
+                frontier,
+                optional(row.lag),
+            ]);
+        }
+        let widths: Vec<usize> = (0..7)
+            .map(|column| table.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect();
+        for row in &table {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())?;
+        }
+        for problem in self.problems() {
+            writeln!(f, "degraded: {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+fn health_label(health: &Health) -> &'static str {
+    match health {
+        Health::Ready => "ready",
+        Health::Degraded { .. } => "degraded",
+        Health::NotReady { .. } => "not ready",
+    }
+}
+
+// round/leader/incarnation, as in `3/Node101/0`
+fn ballot_label(ballot: &BallotNumber, leading: bool) -> String {
+    format!(
+        "{}/{}/{}{}",
+        ballot.round,
+        ballot.leader.as_ref(),
+        ballot.incarnation,
+        if leading { " (leading)" } else { "" }
+    )
+}
+
+fn optional(value: Option<impl fmt::Display>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+/// `GET /status` from the admin server at `admin`.
+pub fn fetch_status(admin: &str, timeout: Duration) -> anyhow::Result<StatusReport> {
+    let addr: SocketAddr = admin
+        .to_socket_addrs()?
+        .next()
+        .ok_or(anyhow::anyhow!("{} does not resolve", admin))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "GET /status HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        admin
+    )?;
+    let mut response = String::new();
+    stream
+        .take(MAX_RESPONSE_LEN)
+        .read_to_string(&mut response)?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or(anyhow::anyhow!("malformed response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.starts_with("HTTP/1.1 200") {
+        anyhow::bail!("{}", status);
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::admin::AdminServer;
+    use crate::nodes::node::Progress;
+    use crate::types::{LeaderId, Round};
+
+    async fn serve(progress: Progress, health: Health) -> String {
+        let server = AdminServer::bind(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(move || health.clone()),
+        )
+        .await
+        .unwrap()
+        .with_status(Arc::new(move || progress.clone()));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+        addr.to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cluster_status_collects_every_node_and_spots_degradation() {
+        let ballot = BallotNumber {
+            round: Round(3),
+            leader: LeaderId::new(101),
+            incarnation: 0,
+        };
+        let leader = serve(
+            Progress {
+                ballot: Some(ballot.clone()),
+                leading: true,
+                frontier: Some(Slot(12)),
+                committed: Some(Slot(11)),
+            },
+            Health::Ready,
+        )
+        .await;
+        let replica = |frontier| Progress {
+            frontier: Some(Slot(frontier)),
+            ..Progress::default()
+        };
+        let ahead = serve(replica(12), Health::Ready).await;
+        let behind = serve(
+            replica(7),
+            Health::Degraded {
+                reasons: vec!["inbox backed up".to_string()],
+            },
+        )
+        .await;
+        let config: StatusConfig = toml::from_str(&format!(
+            r#"
+            [[node]]
+            id = 101
+            role = "leader"
+            admin = "{}"
+
+            [[node]]
+            id = 201
+            role = "replica"
+            admin = "{}"
+
+            [[node]]
+            id = 202
+            role = "replica"
+            admin = "{}"
+            "#,
+            leader, ahead, behind
+        ))
+        .unwrap();
+
+        let status = {
+            let config = config.clone();
+            tokio::task::spawn_blocking(move || {
+                ClusterStatus::collect(&config, Duration::from_secs(5))
+            })
+            .await
+            .unwrap()
+        };
+        let lags: Vec<Option<u64>> = status.nodes.iter().map(|row| row.lag).collect();
+        assert_eq!(lags, vec![Some(0), Some(0), Some(5)]);
+        assert_eq!(
+            status.nodes[0].report.as_ref().unwrap().progress.ballot,
+            Some(ballot)
+        );
+        assert_eq!(status.problems(), vec!["Node202 is degraded".to_string()]);
+        let table = status.to_string();
+        assert!(table.starts_with("NODE"));
+        assert!(table.contains("3/Node101/0 (leading)"));
+
+        // A node that does not answer degrades the cluster too
+        let mut config = config;
+        config.nodes.truncate(2);
+        config.nodes[0].admin = "127.0.0.1:1".to_string();
+        let status = tokio::task::spawn_blocking(move || {
+            ClusterStatus::collect(&config, Duration::from_secs(5))
+        })
+        .await
+        .unwrap();
+        assert!(status.nodes[0].report.is_err());
+        assert_eq!(
+            status.problems().len(),
+            2,
+            "unreachable leader, and so none leading"
+        );
+        assert!(status.is_degraded());
+    }
+}
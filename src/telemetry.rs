@@ -0,0 +1,104 @@
+//! OpenTelemetry export of the nodes' tracing output.
+//!
+//! The protocol core only emits `tracing` spans and events, so it stays
+//! sans-IO and `no_std`-friendly. This module bridges them to an OTLP
+//! collector: spans become OpenTelemetry spans, and events carrying a
+//! `monotonic_counter.*` or `histogram.*` field become counters or histograms.
+//!
+//! Spans and events use these attributes:
+//!
+//! | attribute            | meaning                                     |
+//! |----------------------|---------------------------------------------|
+//! | `paxos.node.role`    | `leader`, `acceptor` or `replica`           |
+//! | `paxos.node.id`      | the node's id, e.g. `Leader1`               |
+//! | `paxos.ballot.round` | the ballot round the node is acting under   |
+//! | `paxos.slot`         | the slot a counter event refers to          |
+//! | `paxos.reject.reason`| why a leader rejected a proposal            |
+//!
+//! and these counters:
+//!
+//! - `paxos.leader.adoptions`, `paxos.leader.preemptions`, `paxos.leader.throttled`
+//! - `paxos.leader.late_responses`
+//! - `paxos.decisions`, `paxos.proposals.rejected`
+//! - `paxos.acceptor.accepted`, `paxos.replica.performed`
+//! - `paxos.replica.result_cache.hits`, `paxos.replica.result_cache.evictions`
+//! - `paxos.replica.leaders_suspected`, `paxos.replica.decisions_shed`
+//! - `paxos.messages.malformed`, per `paxos.malformed.reason`
+//! - `paxos.messages.unroutable`, per `paxos.message.kind`
+//! - `paxos.messages.out_of_range`, per `paxos.message.kind`
+//! - `paxos.messages.misrouted`, per `paxos.node.role` of the node it reached
+//! - `paxos.messages.stale_epoch`, per `paxos.message.kind` an acceptor refused
+//! - `paxos.config.syncs_sent`
+//! - `paxos.combined.processed`, per `paxos.node.role` on a `CombinedNode`
+//!
+//! and these histograms, in milliseconds, from the file-backed stores:
+//!
+//! - `paxos.storage.write_ms`, `paxos.storage.fsync_ms`
+//!
+//! and, in slots, each replica's lag behind the highest decision it has
+//! seen, sampled at every slot progress check:
+//!
+//! - `paxos.replica.lag`
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use tracing_opentelemetry::{MetricsLayer, OpenTelemetryLayer};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Handle to the installed exporters; call `shutdown` before exiting to flush them.
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Telemetry {
+    /// Install a global subscriber that logs to stdout and exports to the
+    /// OTLP/gRPC collector at `endpoint` (e.g. `http://localhost:4317`).
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn init(service_name: &str, endpoint: &str) -> anyhow::Result<Telemetry> {
+        let resource = Resource::builder()
+            .with_service_name(service_name.to_string())
+            .build();
+
+        let span_exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(span_exporter)
+            .with_resource(resource.clone())
+            .build();
+
+        let metric_exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metric_exporter)
+            .with_resource(resource)
+            .build();
+
+        let tracer = tracer_provider.tracer("multifaustus");
+        tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer())
+            .with(OpenTelemetryLayer::new(tracer))
+            .with(MetricsLayer::new(meter_provider.clone()))
+            .try_init()?;
+
+        Ok(Telemetry {
+            tracer_provider,
+            meter_provider,
+        })
+    }
+
+    /// Flush pending spans and metrics and stop the exporters.
+    pub fn shutdown(self) -> anyhow::Result<()> {
+        self.tracer_provider.shutdown()?;
+        self.meter_provider.shutdown()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+//! Portable time types for the sans-IO core.
+//!
+//! `std::time::Instant::now()` panics on `wasm32-unknown-unknown`, so in the
+//! browser we use `web-time`, which has the same API backed by `performance.now()`.
+//! Without `std` there is no system clock at all, and `Instant` is an offset
+//! from an origin chosen by whichever `ClockProvider` the embedder supplies.
+//! Everything in `nodes` should take its time from a `ClockProvider` and only
+//! name these types.
+#[cfg(not(feature = "std"))]
+pub use core::time::Duration;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use std::time::{Duration, Instant};
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
+pub use web_time::{Duration, Instant};
+
+#[cfg(not(feature = "std"))]
+pub use self::no_std_instant::Instant;
+
+#[cfg(not(feature = "std"))]
+mod no_std_instant {
+    use core::ops::{Add, AddAssign, Sub};
+
+    use super::Duration;
+
+    /// A monotonic point in time, measured from the clock's origin.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        pub const fn from_origin(elapsed: Duration) -> Instant {
+            Instant(elapsed)
+        }
+
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+
+        pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_sub(duration).map(Instant)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, rhs: Duration) -> Instant {
+            Instant(self.0 + rhs)
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, rhs: Duration) {
+            self.0 += rhs;
+        }
+    }
+
+    impl Sub<Instant> for Instant {
+        type Output = Duration;
+
+        fn sub(self, rhs: Instant) -> Duration {
+            self.duration_since(rhs)
+        }
+    }
+}
@@ -0,0 +1,251 @@
+//! Authenticating the nodes messages claim to come from.
+//!
+//! Nodes name themselves in what they send, e.g. a P2a's `src` `LeaderId`,
+//! and nothing stops a peer from claiming to be another node. A `TcpServer`
+//! given a `Keyring` ties each connection to the nodes its sender can prove
+//! to be: the sender names a key id when it connects, the server challenges
+//! it with a fresh nonce, and the sender answers with the nonce's
+//! HMAC-SHA256 under the key's secret. A connection that proves a key is
+//! authenticated as the nodes the keyring lists for that key.
+//!
+//! `check_source` is the middleware the server runs every decoded message
+//! through: a message that claims a node as its sender is only let in on a
+//! connection authenticated as that node. Messages that claim none, such as
+//! clients' requests, are let in on any connection. A transport that
+//! authenticates peers some other way, e.g. by TLS client certificate, can
+//! run its messages through `check_source` with the nodes the certificate
+//! stands for.
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::messages::SendableMessage;
+use crate::types::NodeId;
+
+/// Bytes in the nonce a server challenges a connecting sender with.
+pub const NONCE_LEN: usize = 32;
+
+// Signed ahead of the key id and nonce, so a proof means nothing elsewhere
+const DOMAIN: &[u8] = b"multifaustus-auth-v1";
+
+// What a proof is the HMAC of
+fn signed(key_id: &str, nonce: &[u8]) -> Vec<u8> {
+    [DOMAIN, key_id.as_bytes(), nonce].concat()
+}
+
+/// A fresh nonce to challenge a sender with.
+pub(crate) fn nonce() -> anyhow::Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| anyhow::anyhow!("no randomness for a nonce"))?;
+    Ok(nonce)
+}
+
+/// The key a sender proves itself with: its id, and the secret shared with
+/// the servers it connects to.
+#[derive(Clone)]
+pub struct Credentials {
+    key_id: String,
+    key: hmac::Key,
+}
+
+impl Credentials {
+    pub fn new(key_id: impl Into<String>, secret: &[u8]) -> Credentials {
+        Credentials {
+            key_id: key_id.into(),
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// The answer to a server's challenge with `nonce`.
+    pub fn prove(&self, nonce: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.key, &signed(&self.key_id, nonce))
+            .as_ref()
+            .to_vec()
+    }
+}
+
+// Never print the secret
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone)]
+struct Key {
+    key: hmac::Key,
+    nodes: BTreeSet<NodeId>,
+}
+
+/// The keys a server accepts, by id, and the nodes each one speaks for.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: HashMap<String, Key>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    /// Accept `secret` under `key_id`, from senders speaking for `nodes`,
+    /// e.g. every node one process runs.
+    pub fn with_key(
+        mut self,
+        key_id: impl Into<String>,
+        secret: &[u8],
+        nodes: impl IntoIterator<Item = NodeId>,
+    ) -> Keyring {
+        let key = Key {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            nodes: nodes.into_iter().collect(),
+        };
+        self.keys.insert(key_id.into(), key);
+        self
+    }
+
+    /// The peer a sender claiming `key_id` is, if `proof` answers the
+    /// challenge with `nonce` under that key.
+    pub fn verify(&self, key_id: &str, nonce: &[u8], proof: &[u8]) -> Option<AuthenticatedPeer> {
+        let key = self.keys.get(key_id)?;
+        // Compared in constant time
+        hmac::verify(&key.key, &signed(key_id, nonce), proof).ok()?;
+        Some(AuthenticatedPeer {
+            key_id: key_id.to_string(),
+            nodes: key.nodes.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.keys.iter().map(|(id, key)| (id, &key.nodes)))
+            .finish()
+    }
+}
+
+/// The sender at the other end of a connection, as far as it proved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedPeer {
+    pub key_id: String,
+    /// The nodes the sender may claim to be.
+    pub nodes: BTreeSet<NodeId>,
+}
+
+/// Why `check_source` turned a message away.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SourceRejected {
+    /// The message claims a node, but arrived on a connection that
+    /// authenticated as no one.
+    Unauthenticated { claimed: NodeId },
+    /// The message claims a node the connection's key does not speak for.
+    Impersonation { claimed: NodeId, key_id: String },
+}
+
+impl fmt::Display for SourceRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceRejected::Unauthenticated { claimed } => {
+                write!(f, "claims to be {} without authenticating", claimed)
+            }
+            SourceRejected::Impersonation { claimed, key_id } => {
+                write!(f, "claims to be {}, which key {} is not", claimed, key_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SourceRejected {}
+
+/// Let `msg` in only if the node it claims to come from, if any, is one
+/// `peer` authenticated as.
+pub fn check_source<T>(
+    peer: Option<&AuthenticatedPeer>,
+    msg: &SendableMessage<T>,
+) -> Result<(), SourceRejected> {
+    let Some(claimed) = msg.message.sender() else {
+        return Ok(());
+    };
+    match peer {
+        Some(peer) if peer.nodes.contains(&claimed) => Ok(()),
+        Some(peer) => Err(SourceRejected::Impersonation {
+            claimed,
+            key_id: peer.key_id.clone(),
+        }),
+        None => Err(SourceRejected::Unauthenticated { claimed }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+
+    #[test]
+    fn messages_are_let_in_only_from_the_nodes_a_key_speaks_for() {
+        let keyring = Keyring::new().with_key("leaders", b"secret", [NodeId::new(1)]);
+        let nonce = nonce().unwrap();
+        let proof = Credentials::new("leaders", b"secret").prove(&nonce);
+        let peer = keyring.verify("leaders", &nonce, &proof).unwrap();
+        assert_eq!(peer.nodes, BTreeSet::from([NodeId::new(1)]));
+        // A wrong secret, a replayed proof or an unknown key proves nothing
+        let forged = Credentials::new("leaders", b"guess").prove(&nonce);
+        assert!(keyring.verify("leaders", &nonce, &forged).is_none());
+        assert!(keyring.verify("leaders", &[0; NONCE_LEN], &proof).is_none());
+        assert!(keyring.verify("others", &nonce, &proof).is_none());
+
+        let from = |leader: u64| SendableMessage::<Vec<u8>> {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: Address::new("127.0.0.1".to_string(), 2),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(leader),
+                ballot_number: BallotNumber::new(LeaderId::new(leader)),
+                decided_below: Slot(0),
+            }),
+        };
+        assert_eq!(check_source(Some(&peer), &from(1)), Ok(()));
+        assert_eq!(
+            check_source(Some(&peer), &from(2)),
+            Err(SourceRejected::Impersonation {
+                claimed: NodeId::new(2),
+                key_id: "leaders".to_string()
+            })
+        );
+        assert_eq!(
+            check_source(None, &from(1)),
+            Err(SourceRejected::Unauthenticated {
+                claimed: NodeId::new(1)
+            })
+        );
+        // Clients' requests claim no node
+        let request = SendableMessage {
+            message: Message::Request(RequestMessage {
+                src: Address::new("127.0.0.1".to_string(), 9),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![]),
+                },
+                consistency: Consistency::default(),
+                proxied_by: None,
+            }),
+            ..from(1)
+        };
+        assert_eq!(check_source(None, &request), Ok(()));
+    }
+}
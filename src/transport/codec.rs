@@ -0,0 +1,245 @@
+//! Encoding messages for the wire.
+//!
+//! `Codec`s turn messages into bytes and `Compression`s shrink those bytes.
+//! A `Codecs` registry holds the ones a transport can use, by name, and the
+//! `WireFormat`s it prefers, per peer: e.g. a compact codec and compression
+//! to peers in another datacenter, and plain JSON locally. Peers negotiate a
+//! format per connection from what the sender offers and the receiver
+//! supports, and fall back to plain JSON, which every peer reads.
+//!
+//! Plain JSON goes on the wire as it is. Anything else is tagged with the
+//! format it is in, so a receiver can decode each message on its own.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::messages;
+use crate::nodes::router::WireFormat;
+use crate::types::{Address, Payload};
+
+// Leads a message encoded in a format other than plain JSON, which never
+// starts with this byte, followed by the format's length and name
+const FORMAT_TAG: u8 = 0;
+
+/// Encodes and decodes messages for transports that put them on the wire.
+pub trait Codec<T = Vec<u8>> {
+    fn encode(&self, message: &messages::SendableMessage<T>) -> anyhow::Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> anyhow::Result<messages::SendableMessage<T>>;
+}
+
+/// JSON codec: human-readable and easy to consume from a browser.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl<T: Payload> Codec<T> for JsonCodec {
+    fn encode(&self, message: &messages::SendableMessage<T>) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> anyhow::Result<messages::SendableMessage<T>> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Compresses encoded messages, e.g. between datacenters.
+pub trait Compression {
+    fn compress(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>>;
+    fn decompress(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>>;
+}
+
+type SharedCodec<T> = Arc<dyn Codec<T> + Send + Sync>;
+type SharedCompression = Arc<dyn Compression + Send + Sync>;
+
+/// The codecs and compressions a transport can use, and the formats it
+/// prefers to send in.
+///
+/// Every registry has the `json` codec and the `none` compression.
+pub struct Codecs<T = Vec<u8>> {
+    codecs: BTreeMap<String, SharedCodec<T>>,
+    compressions: BTreeMap<String, SharedCompression>,
+    // Formats to offer, most preferred first, and overrides per peer
+    preferred: Vec<WireFormat>,
+    preferred_for: BTreeMap<String, Vec<WireFormat>>,
+}
+
+impl<T> Clone for Codecs<T> {
+    fn clone(&self) -> Self {
+        Codecs {
+            codecs: self.codecs.clone(),
+            compressions: self.compressions.clone(),
+            preferred: self.preferred.clone(),
+            preferred_for: self.preferred_for.clone(),
+        }
+    }
+}
+
+impl<T: Payload> Default for Codecs<T> {
+    fn default() -> Self {
+        Codecs {
+            codecs: BTreeMap::from([("json".to_string(), Arc::new(JsonCodec) as SharedCodec<T>)]),
+            compressions: BTreeMap::new(),
+            preferred: Vec::new(),
+            preferred_for: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Payload> Codecs<T> {
+    pub fn with_codec(mut self, name: &str, codec: impl Codec<T> + Send + Sync + 'static) -> Self {
+        self.codecs.insert(name.to_string(), Arc::new(codec));
+        self
+    }
+
+    pub fn with_compression(
+        mut self,
+        name: &str,
+        compression: impl Compression + Send + Sync + 'static,
+    ) -> Self {
+        self.compressions
+            .insert(name.to_string(), Arc::new(compression));
+        self
+    }
+
+    /// Offer `formats`, most preferred first, to peers without their own.
+    pub fn prefer(mut self, formats: Vec<WireFormat>) -> Self {
+        self.preferred = formats;
+        self
+    }
+
+    /// Offer `formats`, most preferred first, to the peer at `address`.
+    pub fn prefer_for(mut self, address: &Address, formats: Vec<WireFormat>) -> Self {
+        self.preferred_for.insert(address.to_string(), formats);
+        self
+    }
+
+    /// The formats to offer the peer at `address`, most preferred first.
+    pub fn offer(&self, address: &Address) -> Vec<WireFormat> {
+        self.preferred_for
+            .get(&address.to_string())
+            .unwrap_or(&self.preferred)
+            .iter()
+            .filter(|format| self.supports(format))
+            .cloned()
+            .collect()
+    }
+
+    pub fn supports(&self, format: &WireFormat) -> bool {
+        self.codecs.contains_key(&format.codec)
+            && (format.compression == "none" || self.compressions.contains_key(&format.compression))
+    }
+
+    /// The first of the formats a peer offered that this registry supports,
+    /// or plain JSON.
+    pub fn choose(&self, offer: &[WireFormat]) -> WireFormat {
+        offer
+            .iter()
+            .find(|format| self.supports(format))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn encode(
+        &self,
+        format: &WireFormat,
+        message: &messages::SendableMessage<T>,
+    ) -> anyhow::Result<Vec<u8>> {
+        if *format == WireFormat::default() {
+            return JsonCodec.encode(message);
+        }
+        let codec = self
+            .codecs
+            .get(&format.codec)
+            .ok_or_else(|| anyhow::anyhow!("no codec {}", format.codec))?;
+        let mut body = codec.encode(message)?;
+        if format.compression != "none" {
+            let compression = self
+                .compressions
+                .get(&format.compression)
+                .ok_or_else(|| anyhow::anyhow!("no compression {}", format.compression))?;
+            body = compression.compress(&body)?;
+        }
+        let name = format.to_string();
+        let name_len = u8::try_from(name.len())
+            .map_err(|_| anyhow::anyhow!("format name {} is too long", name))?;
+        let mut bytes = Vec::with_capacity(2 + name.len() + body.len());
+        bytes.push(FORMAT_TAG);
+        bytes.push(name_len);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    /// Decode a message in whichever format it is tagged with.
+    pub fn decode(&self, bytes: &[u8]) -> anyhow::Result<messages::SendableMessage<T>> {
+        let [FORMAT_TAG, name_len, rest @ ..] = bytes else {
+            return JsonCodec.decode(bytes);
+        };
+        let (name, body) = rest
+            .split_at_checked(usize::from(*name_len))
+            .ok_or_else(|| anyhow::anyhow!("truncated format name"))?;
+        let name = core::str::from_utf8(name)?;
+        let (codec, compression) = name
+            .split_once('+')
+            .ok_or_else(|| anyhow::anyhow!("malformed format name {}", name))?;
+        let codec = self
+            .codecs
+            .get(codec)
+            .ok_or_else(|| anyhow::anyhow!("no codec {}", codec))?;
+        if compression == "none" {
+            return codec.decode(body);
+        }
+        let compression = self
+            .compressions
+            .get(compression)
+            .ok_or_else(|| anyhow::anyhow!("no compression {}", compression))?;
+        codec.decode(&compression.decompress(body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+    use std::collections::{BTreeMap, HashSet};
+
+    #[test]
+    fn json_codec_round_trips_reconfig_decision() {
+        let rep = ReplicaId::new(1);
+        let config = Config::new(
+            HashSet::from([rep]),
+            HashSet::new(),
+            HashSet::new(),
+            BTreeMap::from([(rep.into(), Address::new("127.0.0.1".to_string(), 8080))]),
+            None,
+        );
+        let msg: SendableMessage = SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 8082),
+            dst: Address::new("127.0.0.1".to_string(), 8080),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(7),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Reconfig(config.clone()),
+                },
+                certificate: None,
+            }),
+        };
+
+        let bytes = JsonCodec.encode(&msg).unwrap();
+        let decoded: SendableMessage = JsonCodec.decode(&bytes).unwrap();
+
+        assert_eq!(decoded.dst, msg.dst);
+        match decoded.message {
+            Message::Decision(dec) => {
+                assert_eq!(dec.slot_number, Slot(7));
+                assert_eq!(dec.command.op, CommandType::Reconfig(config));
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+//! A transport wrapper that delays messages, for trying an application
+//! against WAN latency in staging.
+//!
+//! `DelayedTransport` holds each message for its destination's `Latency`,
+//! a fixed delay plus a random jitter, before handing it to the transport it
+//! wraps. Messages to one destination still go out in the order they were
+//! sent, as over a TCP connection: one drawn a shorter jitter than the
+//! message before it waits for that one.
+//!
+//! Sends are handed to a background task, so the wrapper must be spawned
+//! from within a tokio runtime, and what the wrapped transport refuses once
+//! the delay has passed is logged rather than returned.
+use std::cmp::Reverse;
+use std::collections::binary_heap::PeekMut;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::messages::SendableMessage;
+use crate::nodes::router::WireFormat;
+use crate::sim::Rng;
+use crate::transport::{Transport, TransportError};
+use crate::types::{Address, Payload};
+
+/// The latency added to messages for a destination.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Latency {
+    pub delay: Duration,
+    /// Up to this much more is added to each message, drawn uniformly.
+    pub jitter: Duration,
+}
+
+impl Latency {
+    pub fn new(delay: Duration, jitter: Duration) -> Latency {
+        Latency { delay, jitter }
+    }
+}
+
+/// Delays messages by their destination's `Latency` before handing them to
+/// the transport it wraps.
+pub struct DelayedTransport<T = Vec<u8>> {
+    outbound: mpsc::UnboundedSender<Delayed<T>>,
+    default_latency: Latency,
+    latencies: BTreeMap<String, Latency>,
+    state: Mutex<DelayState>,
+}
+
+struct DelayState {
+    rng: Rng,
+    // When the last message to each destination is due, to keep them in order
+    last_due: BTreeMap<String, Instant>,
+    sent: u64,
+}
+
+// A message waiting out its delay, ordered by when it is due and then by
+// when it was sent
+struct Delayed<T> {
+    due: Instant,
+    seq: u64,
+    message: SendableMessage<T>,
+    format: Option<WireFormat>,
+}
+
+impl<T> PartialEq for Delayed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl<T> Eq for Delayed<T> {}
+
+impl<T> PartialOrd for Delayed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Delayed<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+impl<T: Payload + Send + 'static> DelayedTransport<T> {
+    /// Wrap `inner`, delaying every message by `latency` unless its
+    /// destination has its own.
+    pub fn spawn(inner: Box<dyn Transport<T> + Send>, latency: Latency) -> DelayedTransport<T> {
+        let (outbound, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_delayed(inner, receiver));
+        DelayedTransport {
+            outbound,
+            default_latency: latency,
+            latencies: BTreeMap::new(),
+            state: Mutex::new(DelayState {
+                rng: Rng(0),
+                last_due: BTreeMap::new(),
+                sent: 0,
+            }),
+        }
+    }
+
+    /// Delay messages to `dst` by `latency`.
+    pub fn with_latency(mut self, dst: &Address, latency: Latency) -> DelayedTransport<T> {
+        self.latencies.insert(dst.to_string(), latency);
+        self
+    }
+
+    /// Draw jitter from `seed`, so runs can be repeated.
+    pub fn with_seed(self, seed: u64) -> DelayedTransport<T> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).rng = Rng(seed);
+        self
+    }
+
+    fn delay(
+        &self,
+        message: &SendableMessage<T>,
+        format: Option<WireFormat>,
+    ) -> Result<(), TransportError> {
+        let dst = message.dst.to_string();
+        let latency = self
+            .latencies
+            .get(&dst)
+            .copied()
+            .unwrap_or(self.default_latency);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let jitter = match latency.jitter.as_nanos() as u64 {
+            0 => Duration::ZERO,
+            nanos => Duration::from_nanos(state.rng.next() % (nanos + 1)),
+        };
+        let mut due = Instant::now() + latency.delay + jitter;
+        if let Some(last) = state.last_due.get(&dst) {
+            due = due.max(*last);
+        }
+        state.last_due.insert(dst, due);
+        state.sent += 1;
+        self.outbound
+            .send(Delayed {
+                due,
+                seq: state.sent,
+                message: message.clone(),
+                format,
+            })
+            .map_err(|_| TransportError::Closed)
+    }
+}
+
+impl<T: Payload + Send + 'static> Transport<T> for DelayedTransport<T> {
+    fn send(&self, message: &SendableMessage<T>) -> Result<(), TransportError> {
+        self.delay(message, None)
+    }
+
+    fn send_as(
+        &self,
+        message: &SendableMessage<T>,
+        format: &WireFormat,
+    ) -> Result<(), TransportError> {
+        self.delay(message, Some(format.clone()))
+    }
+}
+
+async fn run_delayed<T>(
+    inner: Box<dyn Transport<T> + Send>,
+    mut receiver: mpsc::UnboundedReceiver<Delayed<T>>,
+) {
+    let mut waiting: BinaryHeap<Reverse<Delayed<T>>> = BinaryHeap::new();
+    loop {
+        let next_due = waiting.peek().map(|Reverse(delayed)| delayed.due);
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some(delayed) => waiting.push(Reverse(delayed)),
+                // Nothing more will be sent; let what is waiting go out
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                let now = Instant::now();
+                while let Some(next) = waiting.peek_mut() {
+                    if next.0.due > now {
+                        break;
+                    }
+                    let Reverse(delayed) = PeekMut::pop(next);
+                    send(&*inner, delayed);
+                }
+            }
+        }
+    }
+    while let Some(Reverse(delayed)) = waiting.pop() {
+        tokio::time::sleep_until(delayed.due).await;
+        send(&*inner, delayed);
+    }
+}
+
+fn send<T>(inner: &(dyn Transport<T> + Send), delayed: Delayed<T>) {
+    let sent = match &delayed.format {
+        Some(format) => inner.send_as(&delayed.message, format),
+        None => inner.send(&delayed.message),
+    };
+    if let Err(e) = sent {
+        warn!("delay: dropping [{}]: {}", delayed.message, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+    use std::sync::Arc;
+
+    /// Records the slot of each decision it is given, and when.
+    struct Capture(Arc<Mutex<Vec<(u64, Instant)>>>);
+
+    impl Transport for Capture {
+        fn send(&self, message: &SendableMessage) -> Result<(), TransportError> {
+            if let Message::Decision(d) = &message.message {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((d.slot_number.0, Instant::now()));
+            }
+            Ok(())
+        }
+    }
+
+    fn decision(dst: &Address, slot: u64) -> SendableMessage {
+        SendableMessage {
+            src: Address::new("h".to_string(), 0),
+            dst: dst.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(slot),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: slot,
+                    op: CommandType::Op(vec![]),
+                },
+                certificate: None,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn delayed_transport_adds_latency_per_destination_in_order() {
+        let (far, near) = (
+            Address::new("far".to_string(), 1),
+            Address::new("near".to_string(), 1),
+        );
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let transport: DelayedTransport = DelayedTransport::spawn(
+            Box::new(Capture(delivered.clone())),
+            Latency::new(Duration::from_millis(40), Duration::from_millis(40)),
+        )
+        .with_latency(&near, Latency::default())
+        .with_seed(7);
+
+        let start = Instant::now();
+        for slot in 1..=5 {
+            transport.send(&decision(&far, slot)).unwrap();
+        }
+        transport.send(&decision(&near, 6)).unwrap();
+        drop(transport);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // The near destination is not held up by the far one, whose messages
+        // keep their order whatever jitter each drew
+        let delivered = delivered.lock().unwrap();
+        let slots: Vec<u64> = delivered.iter().map(|(slot, _)| *slot).collect();
+        assert_eq!(slots, vec![6, 1, 2, 3, 4, 5]);
+        assert!(delivered[1..]
+            .iter()
+            .all(|(_, at)| *at >= start + Duration::from_millis(40)));
+    }
+}
@@ -0,0 +1,246 @@
+//! Resolving addresses that name a host, rather than an IP.
+//!
+//! An `Address` may carry a hostname, e.g. a Kubernetes StatefulSet pod's
+//! stable DNS name, whose IP changes whenever the pod is rescheduled. A
+//! `DnsResolver` looks names up through a `Lookup` and caches each answer
+//! for its TTL, or `DEFAULT_DNS_TTL` when the lookup does not report one.
+//! Every `refresh_interval` the transport refreshes the names whose answers
+//! have expired, and reconnects to any whose addresses changed. IP literals
+//! are never looked up.
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::types::Address;
+
+/// How long an answer is cached when its lookup does not say.
+pub const DEFAULT_DNS_TTL: Duration = Duration::from_secs(30);
+
+/// How often expired answers are looked up again.
+pub const DNS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The addresses a name resolved to, and for how long they may be cached.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolved {
+    pub addrs: Vec<SocketAddr>,
+    pub ttl: Option<Duration>,
+}
+
+pub type LookupFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Resolved>> + Send + 'a>>;
+
+/// Looks a hostname up, e.g. in DNS.
+pub trait Lookup: Send + Sync {
+    fn lookup<'a>(&'a self, host: &'a str, port: u16) -> LookupFuture<'a>;
+}
+
+/// Looks names up with the operating system's resolver, which does not
+/// report TTLs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemLookup;
+
+impl Lookup for SystemLookup {
+    fn lookup<'a>(&'a self, host: &'a str, port: u16) -> LookupFuture<'a> {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, port)).await?.collect();
+            Ok(Resolved { addrs, ttl: None })
+        })
+    }
+}
+
+struct Cached {
+    addrs: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+/// Resolves addresses to socket addresses, caching each name's answer for
+/// its TTL.
+pub struct DnsResolver {
+    lookup: Arc<dyn Lookup>,
+    default_ttl: Duration,
+    refresh_interval: Duration,
+    // By the address's `host:port`
+    cache: HashMap<String, (Address, Cached)>,
+}
+
+impl DnsResolver {
+    pub fn new(lookup: Arc<dyn Lookup>) -> DnsResolver {
+        DnsResolver {
+            lookup,
+            default_ttl: DEFAULT_DNS_TTL,
+            refresh_interval: DNS_REFRESH_INTERVAL,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// A resolver using the operating system's.
+    pub fn system() -> DnsResolver {
+        DnsResolver::new(Arc::new(SystemLookup))
+    }
+
+    /// Cache answers whose lookup reports no TTL for `ttl`.
+    pub fn with_default_ttl(mut self, ttl: Duration) -> DnsResolver {
+        self.default_ttl = ttl;
+        self
+    }
+
+    /// Look expired answers up again every `interval`.
+    pub fn with_refresh_interval(mut self, interval: Duration) -> DnsResolver {
+        self.refresh_interval = interval;
+        self
+    }
+
+    pub fn refresh_interval(&self) -> Duration {
+        self.refresh_interval
+    }
+
+    /// The socket addresses `address` resolves to, from the cache while its
+    /// answer is fresh.
+    pub async fn resolve(&mut self, address: &Address) -> io::Result<Vec<SocketAddr>> {
+        let port = port_of(address)?;
+        if let Ok(ip) = address.host().parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let key = address.to_string();
+        let now = Instant::now();
+        if let Some((_, cached)) = self.cache.get(&key) {
+            if now < cached.expires {
+                return Ok(cached.addrs.clone());
+            }
+        }
+        let cached = self.look_up(address, port, now).await?;
+        let addrs = cached.addrs.clone();
+        self.cache.insert(key, (address.clone(), cached));
+        Ok(addrs)
+    }
+
+    /// Look up every name whose answer expired by `now` again. Returns the
+    /// addresses whose names now resolve differently, with what they
+    /// resolve to. A name that fails to resolve keeps its last answer and
+    /// is tried again on the next refresh.
+    pub async fn refresh(&mut self, now: Instant) -> Vec<(Address, Vec<SocketAddr>)> {
+        let expired: Vec<String> = self
+            .cache
+            .iter()
+            .filter(|(_, (_, cached))| cached.expires <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut changed = Vec::new();
+        for key in expired {
+            let address = self.cache[&key].0.clone();
+            let Ok(port) = port_of(&address) else {
+                continue;
+            };
+            match self.look_up(&address, port, now).await {
+                Ok(fresh) => {
+                    let (_, cached) = self.cache.get_mut(&key).expect("expired entry is cached");
+                    if fresh.addrs != cached.addrs {
+                        debug!("dns: {} moved to {:?}", address, fresh.addrs);
+                        changed.push((address, fresh.addrs.clone()));
+                    }
+                    *cached = fresh;
+                }
+                Err(e) => warn!("dns: failed to refresh {}: {}", address, e),
+            }
+        }
+        changed
+    }
+
+    async fn look_up(&self, address: &Address, port: u16, now: Instant) -> io::Result<Cached> {
+        let resolved = self.lookup.lookup(address.host(), port).await?;
+        if resolved.addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} resolved to no addresses", address.host()),
+            ));
+        }
+        Ok(Cached {
+            addrs: resolved.addrs,
+            expires: now + resolved.ttl.unwrap_or(self.default_ttl),
+        })
+    }
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        DnsResolver::system()
+    }
+}
+
+fn port_of(address: &Address) -> io::Result<u16> {
+    u16::try_from(address.port()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no valid port", address),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    /// Answers every name with `ip`, counting the lookups.
+    struct Fixed {
+        ip: Mutex<IpAddr>,
+        lookups: AtomicUsize,
+    }
+
+    impl Lookup for Fixed {
+        fn lookup<'a>(&'a self, _host: &'a str, port: u16) -> LookupFuture<'a> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            let ip = *self.ip.lock().unwrap();
+            Box::pin(async move {
+                Ok(Resolved {
+                    addrs: vec![SocketAddr::new(ip, port)],
+                    ttl: Some(Duration::from_secs(60)),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_are_cached_for_their_ttl_and_refreshed_after() {
+        let lookup = Arc::new(Fixed {
+            ip: Mutex::new("10.0.0.1".parse().unwrap()),
+            lookups: AtomicUsize::new(0),
+        });
+        let mut resolver = DnsResolver::new(lookup.clone());
+        let name = Address::new("paxos-0.paxos".to_string(), 7000);
+        let first: SocketAddr = "10.0.0.1:7000".parse().unwrap();
+
+        assert_eq!(resolver.resolve(&name).await.unwrap(), vec![first]);
+        assert_eq!(resolver.resolve(&name).await.unwrap(), vec![first]);
+        // IP literals are never looked up
+        let literal = Address::new("127.0.0.1".to_string(), 7001);
+        assert_eq!(
+            resolver.resolve(&literal).await.unwrap(),
+            vec!["127.0.0.1:7001".parse().unwrap()]
+        );
+        assert_eq!(lookup.lookups.load(Ordering::Relaxed), 1);
+
+        // Nothing has expired yet
+        *lookup.ip.lock().unwrap() = "10.0.0.2".parse().unwrap();
+        assert!(resolver.refresh(Instant::now()).await.is_empty());
+        let later = Instant::now() + Duration::from_secs(61);
+        let moved: SocketAddr = "10.0.0.2:7000".parse().unwrap();
+        assert_eq!(
+            resolver.refresh(later).await,
+            vec![(name.clone(), vec![moved])]
+        );
+        assert_eq!(resolver.resolve(&name).await.unwrap(), vec![moved]);
+        assert_eq!(lookup.lookups.load(Ordering::Relaxed), 2);
+        assert!(resolver
+            .resolve(&Address::new("paxos-0.paxos".to_string(), 70000))
+            .await
+            .is_err());
+    }
+}
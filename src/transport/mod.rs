@@ -1,6 +1,209 @@
+#[cfg(not(target_arch = "wasm32"))]
+pub mod auth;
+pub mod codec;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod delay;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dns;
 pub mod printer;
+pub mod pump;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tcp;
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+pub mod websocket;
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::sync::mpsc;
+
 use crate::messages;
+use crate::nodes::router::WireFormat;
+use crate::types::Address;
+
+/// Outbound half of a transport.
+pub trait Transport<T = Vec<u8>> {
+    /// Send `message` to `message.dst`.
+    ///
+    /// `Ok` means the transport has taken the message, not that the peer
+    /// received it: Paxos tolerates loss and retries at the protocol level.
+    fn send(&self, message: &messages::SendableMessage<T>) -> Result<(), TransportError>;
+
+    /// Send `message` encoded in `format`, the one negotiated for the link to
+    /// `message.dst`. Transports that do not negotiate formats just `send` it.
+    fn send_as(
+        &self,
+        message: &messages::SendableMessage<T>,
+        _format: &WireFormat,
+    ) -> Result<(), TransportError> {
+        self.send(message)
+    }
+}
+
+/// Inbound half of a transport, delivering messages addressed to this process.
+pub trait Receiver<T = Vec<u8>> {
+    /// The next inbound message, or `Ok(None)` if none is waiting.
+    ///
+    /// Returns `TransportError::Closed` once no more messages can arrive.
+    fn try_recv(&mut self) -> Result<Option<messages::SendableMessage<T>>, TransportError>;
+}
+
+/// Async counterpart of `Transport`, for transports that can only report
+/// the outcome of a send after awaiting I/O.
+#[cfg(feature = "async")]
+pub trait AsyncTransport<T = Vec<u8>> {
+    fn send(
+        &self,
+        message: &messages::SendableMessage<T>,
+    ) -> impl Future<Output = Result<(), TransportError>> + Send;
+}
+
+/// Async counterpart of `Receiver`.
+#[cfg(feature = "async")]
+pub trait AsyncReceiver<T = Vec<u8>> {
+    /// Wait for the next inbound message; `None` once the receiver is closed.
+    fn recv(&mut self) -> impl Future<Output = Option<messages::SendableMessage<T>>> + Send;
+}
+
+// The servers in this module hand out the receiving end of a channel
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Receiver<T> for mpsc::UnboundedReceiver<messages::SendableMessage<T>> {
+    fn try_recv(&mut self) -> Result<Option<messages::SendableMessage<T>>, TransportError> {
+        match mpsc::UnboundedReceiver::try_recv(self) {
+            Ok(msg) => Ok(Some(msg)),
+            Err(mpsc::error::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::error::TryRecvError::Disconnected) => Err(TransportError::Closed),
+        }
+    }
+}
+
+#[cfg(all(feature = "async", not(target_arch = "wasm32")))]
+impl<T: Send> AsyncReceiver<T> for mpsc::UnboundedReceiver<messages::SendableMessage<T>> {
+    fn recv(&mut self) -> impl Future<Output = Option<messages::SendableMessage<T>>> + Send {
+        mpsc::UnboundedReceiver::recv(self)
+    }
+}
+
+/// Why a transport could not send a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportError {
+    /// Worth retrying later, e.g. the peer is unreachable or a buffer is full.
+    Unavailable(String),
+    /// Retrying will not help, e.g. the message cannot be encoded.
+    Rejected(String),
+    /// The transport has shut down; nothing more will be sent or received.
+    Closed,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransportError::Unavailable(reason) => write!(f, "transport unavailable: {}", reason),
+            TransportError::Rejected(reason) => write!(f, "message rejected: {}", reason),
+            TransportError::Closed => write!(f, "transport closed"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// A change in a transport's connection to one destination, reported by
+/// transports that keep connections open (e.g. `TcpSender::take_link_events`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkEvent {
+    /// A connection to the destination was opened.
+    Up(Address),
+    /// Connecting to the destination failed. The transport drops what is
+    /// sent there until it has backed off and tries again.
+    Down { dst: Address, reason: String },
+    /// Writing to an open connection failed, and the connection was closed.
+    SendFailed { dst: Address, reason: String },
+    /// The peer agreed to receive messages in `format` on the new connection.
+    Negotiated { dst: Address, format: WireFormat },
+    /// The destination's hostname now resolves to `addrs`. A connection to
+    /// an address no longer among them was closed, and the next message
+    /// connects to the new ones without waiting out a backoff.
+    Moved {
+        dst: Address,
+        addrs: Vec<SocketAddr>,
+    },
+}
+
+impl LinkEvent {
+    pub fn dst(&self) -> &Address {
+        match self {
+            LinkEvent::Up(dst) => dst,
+            LinkEvent::Down { dst, .. }
+            | LinkEvent::SendFailed { dst, .. }
+            | LinkEvent::Negotiated { dst, .. } => dst,
+            LinkEvent::Moved { dst, .. } => dst,
+        }
+    }
+}
+
+/// The state of the link to one destination, as of the last `LinkEvent`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkHealth {
+    pub up: bool,
+    /// Failed connects and sends since the link was last up.
+    pub failures: u64,
+    pub last_error: Option<String>,
+    /// The format the link last negotiated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<WireFormat>,
+}
+
+impl LinkHealth {
+    pub fn record(&mut self, event: &LinkEvent) {
+        match event {
+            LinkEvent::Up(_) => {
+                self.up = true;
+                self.failures = 0;
+            }
+            LinkEvent::Down { reason, .. } | LinkEvent::SendFailed { reason, .. } => {
+                self.up = false;
+                self.failures += 1;
+                self.last_error = Some(reason.clone());
+            }
+            LinkEvent::Negotiated { format, .. } => self.format = Some(format.clone()),
+            // Whether it is up is told by connecting to the new addresses
+            LinkEvent::Moved { .. } => {}
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+
+    #[test]
+    fn channel_receiver_reports_empty_and_closed() {
+        let (tx, mut rx) = mpsc::unbounded_channel::<SendableMessage>();
+        assert!(matches!(Receiver::try_recv(&mut rx), Ok(None)));
 
-pub trait Transport {
-    fn send(&self, message: &messages::SendableMessage);
+        tx.send(SendableMessage {
+            src: Address::new("h".to_string(), 1),
+            dst: Address::new("h".to_string(), 2),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                decided_below: Slot(0),
+            }),
+        })
+        .unwrap();
+        drop(tx);
+        assert!(matches!(Receiver::try_recv(&mut rx), Ok(Some(_))));
+        assert!(matches!(
+            Receiver::try_recv(&mut rx),
+            Err(TransportError::Closed)
+        ));
+    }
 }
@@ -1,12 +1,13 @@
 use tracing::info;
 
 use crate::messages;
-use crate::transport::Transport;
+use crate::transport::{Transport, TransportError};
 
 pub struct Printer;
 
-impl Transport for Printer {
-    fn send(&self, message: &messages::SendableMessage) {
+impl<T> Transport<T> for Printer {
+    fn send(&self, message: &messages::SendableMessage<T>) -> Result<(), TransportError> {
         info!("sending message [{}]", message);
+        Ok(())
     }
 }
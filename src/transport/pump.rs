@@ -0,0 +1,366 @@
+//! Moves messages from a node's outbox onto a `Transport`.
+//!
+//! With an `OutboxJournal`, the messages a node must not lose are stored
+//! before the transport is given them, and trimmed from the store once it
+//! has taken them (or the pump gave up on them). A pump built again over the
+//! same store after a crash sends them first.
+//!
+//! Messages taken from a node are sent in the `WireFormat` its router has for
+//! their destination's link, if it has one.
+use std::collections::{BTreeMap, VecDeque};
+
+use tracing::{info, warn};
+
+use crate::messages::{Message, SendableMessage};
+use crate::nodes::combined::Role;
+use crate::nodes::node::Node;
+use crate::nodes::router::WireFormat;
+use crate::persistence::OutboxStore;
+use crate::transport::{Transport, TransportError};
+
+/// Attempts per message before the pump gives up on it.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Which messages a pump journals, and where.
+pub struct OutboxJournal<T = Vec<u8>> {
+    store: Box<dyn OutboxStore<T> + Send>,
+    durable: Selector<T>,
+}
+
+type Selector<T> = Box<dyn Fn(&Message<T>) -> bool + Send>;
+
+impl<T> OutboxJournal<T> {
+    /// Journal the messages for which `durable` holds in `store`.
+    pub fn new(
+        store: Box<dyn OutboxStore<T> + Send>,
+        durable: impl Fn(&Message<T>) -> bool + Send + 'static,
+    ) -> OutboxJournal<T> {
+        OutboxJournal {
+            store,
+            durable: Box::new(durable),
+        }
+    }
+
+    /// Journal what a node in `role` sends that is costly to lose: a
+    /// leader's Decisions, an acceptor's P2bs and a replica's Responses.
+    pub fn for_role(role: Role, store: Box<dyn OutboxStore<T> + Send>) -> OutboxJournal<T> {
+        OutboxJournal::new(store, move |message| {
+            matches!(
+                (role, message),
+                (Role::Leader, Message::Decision(_))
+                    | (Role::Acceptor, Message::P2b(_))
+                    | (Role::Replica, Message::Response(_))
+            )
+        })
+    }
+}
+
+/// Drains node outboxes into a `Transport`.
+///
+/// Messages are queued per destination. When a send fails with
+/// `TransportError::Unavailable`, that destination's queue stops and the same
+/// message is retried on the next `flush`, so messages to each destination
+/// always go out in the order the node sent them. Other destinations are not
+/// held up. A message is dropped once it has failed `max_attempts` times or
+/// was `Rejected`, or the transport is `Closed`; Paxos retries at the protocol level, so giving up is safe.
+pub struct OutboxPump<T = Vec<u8>> {
+    transport: Box<dyn Transport<T> + Send>,
+    // Queued messages per destination, with how often each head has failed
+    // and whether it is journaled
+    pending: BTreeMap<String, VecDeque<Queued<T>>>,
+    max_attempts: u32,
+    journal: Option<OutboxJournal<T>>,
+    // Journaled messages were queued or sent since the journal was last stored
+    journal_stale: bool,
+}
+
+struct Queued<T> {
+    msg: SendableMessage<T>,
+    attempts: u32,
+    durable: bool,
+    format: Option<WireFormat>,
+}
+
+impl<T: Clone> OutboxPump<T> {
+    pub fn new(transport: Box<dyn Transport<T> + Send>) -> OutboxPump<T> {
+        OutboxPump {
+            transport,
+            pending: BTreeMap::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            journal: None,
+            journal_stale: false,
+        }
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> OutboxPump<T> {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Journal messages in `journal`, first queueing the ones it already
+    /// holds, which a previous pump over the same store never sent.
+    pub fn set_journal(&mut self, mut journal: OutboxJournal<T>) -> anyhow::Result<()> {
+        let recovered = journal.store.load()?;
+        if !recovered.is_empty() {
+            info!("pump: recovered {} unsent messages", recovered.len());
+        }
+        for msg in recovered {
+            self.queue(msg, true, None);
+        }
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Take everything in `node`'s outbox and send as much as the transport accepts.
+    pub fn pump<N: Node<T> + ?Sized>(&mut self, node: &mut N) {
+        while let Some(msg) = node.deliver_sent() {
+            let format = node.wire_format(&msg.dst);
+            let durable = self.durable(&msg);
+            self.queue(msg, durable, format);
+        }
+        self.flush();
+    }
+
+    pub fn enqueue(&mut self, msg: SendableMessage<T>) {
+        let durable = self.durable(&msg);
+        self.queue(msg, durable, None);
+    }
+
+    fn durable(&self, msg: &SendableMessage<T>) -> bool {
+        self.journal
+            .as_ref()
+            .is_some_and(|journal| (journal.durable)(&msg.message))
+    }
+
+    fn queue(&mut self, msg: SendableMessage<T>, durable: bool, format: Option<WireFormat>) {
+        self.journal_stale |= durable;
+        self.pending
+            .entry(msg.dst.to_string())
+            .or_default()
+            .push_back(Queued {
+                msg,
+                attempts: 0,
+                durable,
+                format,
+            });
+    }
+
+    /// Send queued messages, stopping at the first transient failure per destination.
+    pub fn flush(&mut self) {
+        // Journal new messages before the transport sees them
+        self.store_journal();
+        let max_attempts = self.max_attempts;
+        for (dst, queue) in self.pending.iter_mut() {
+            while let Some(Queued {
+                msg,
+                attempts,
+                durable,
+                format,
+            }) = queue.front_mut()
+            {
+                let sent = match format {
+                    Some(format) => self.transport.send_as(msg, format),
+                    None => self.transport.send(msg),
+                };
+                match sent {
+                    Ok(()) => {}
+                    Err(TransportError::Unavailable(reason)) => {
+                        *attempts += 1;
+                        if *attempts < max_attempts {
+                            break;
+                        }
+                        warn!(
+                            "pump: dropping [{}] after {} attempts: {}",
+                            msg, attempts, reason
+                        );
+                    }
+                    Err(e @ (TransportError::Rejected(_) | TransportError::Closed)) => {
+                        warn!("pump: dropping [{}] for {}: {}", msg, dst, e);
+                    }
+                }
+                self.journal_stale |= *durable;
+                queue.pop_front();
+            }
+        }
+        self.pending.retain(|_, queue| !queue.is_empty());
+        // Trim what the transport took
+        self.store_journal();
+    }
+
+    fn store_journal(&mut self) {
+        let Some(journal) = self.journal.as_mut().filter(|_| self.journal_stale) else {
+            return;
+        };
+        let unsent: Vec<SendableMessage<T>> = self
+            .pending
+            .values()
+            .flatten()
+            .filter(|queued| queued.durable)
+            .map(|queued| queued.msg.clone())
+            .collect();
+        match journal.store.store(&unsent) {
+            Ok(()) => self.journal_stale = false,
+            // Sent anyway: a journal that cannot be written protects nothing
+            Err(e) => warn!("pump: failed to journal unsent messages: {}", e),
+        }
+    }
+
+    /// Messages still waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.pending.values().map(VecDeque::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Fails the first `failures` sends to port 1, delivers everything else.
+    struct Flaky {
+        failures: Mutex<u32>,
+        delivered: Arc<Mutex<Vec<(u64, u64)>>>,
+    }
+
+    impl Transport for Flaky {
+        fn send(&self, message: &SendableMessage) -> Result<(), TransportError> {
+            let port = if message.dst == Address::new("h".to_string(), 1) {
+                1
+            } else {
+                2
+            };
+            let mut failures = self.failures.lock().unwrap();
+            if port == 1 && *failures > 0 {
+                *failures -= 1;
+                return Err(TransportError::Unavailable(
+                    "connection refused".to_string(),
+                ));
+            }
+            if let Message::Decision(d) = &message.message {
+                self.delivered.lock().unwrap().push((port, d.slot_number.0));
+            }
+            Ok(())
+        }
+    }
+
+    fn decision(port: u64, slot: u64) -> SendableMessage {
+        SendableMessage {
+            src: Address::new("h".to_string(), 0),
+            dst: Address::new("h".to_string(), port),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(slot),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: slot,
+                    op: CommandType::Op(vec![]),
+                },
+                certificate: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn outbox_pump_retries_in_order_per_destination() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mut pump = OutboxPump::new(Box::new(Flaky {
+            failures: Mutex::new(2),
+            delivered: delivered.clone(),
+        }));
+        for slot in 1..=3 {
+            pump.enqueue(decision(1, slot));
+            pump.enqueue(decision(2, slot));
+        }
+
+        // The unavailable destination is held back; the other is not
+        pump.flush();
+        assert_eq!(*delivered.lock().unwrap(), vec![(2, 1), (2, 2), (2, 3)]);
+        assert_eq!(pump.pending(), 3);
+
+        pump.flush();
+        pump.flush();
+        assert_eq!(pump.pending(), 0);
+        assert_eq!(delivered.lock().unwrap()[3..], [(1, 1), (1, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn outbox_pump_gives_up_after_max_attempts() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mut pump = OutboxPump::new(Box::new(Flaky {
+            failures: Mutex::new(2),
+            delivered: delivered.clone(),
+        }))
+        .with_max_attempts(2);
+        pump.enqueue(decision(1, 1));
+        pump.enqueue(decision(1, 2));
+
+        pump.flush();
+        pump.flush();
+        assert_eq!(*delivered.lock().unwrap(), vec![(1, 2)]);
+        assert_eq!(pump.pending(), 0);
+    }
+
+    #[test]
+    fn outbox_pump_journals_messages_until_the_transport_takes_them() {
+        use crate::persistence::file::FileOutboxStore;
+
+        let path = std::env::temp_dir().join(format!(
+            "multifaustus-outbox-{}-{}.json",
+            std::process::id(),
+            line!()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let journal =
+            || OutboxJournal::for_role(Role::Leader, Box::new(FileOutboxStore::new(&path)));
+        let unsent = || -> Vec<SendableMessage> { FileOutboxStore::new(&path).load().unwrap() };
+        let heartbeat = SendableMessage {
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Heartbeat(HeartbeatMessage {
+                src: NodeId::new(1),
+                ballot: None,
+                quorum_lost: false,
+                promised: None,
+                lease: None,
+                commit_index: None,
+            }),
+            ..decision(1, 0)
+        };
+
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mut pump = OutboxPump::new(Box::new(Flaky {
+            failures: Mutex::new(u32::MAX),
+            delivered: delivered.clone(),
+        }));
+        pump.set_journal(journal()).unwrap();
+        pump.enqueue(decision(1, 1));
+        pump.enqueue(heartbeat);
+        pump.enqueue(decision(2, 2));
+        pump.flush();
+        // Only the Decision still waiting is kept; heartbeats are not journaled
+        assert_eq!(*delivered.lock().unwrap(), vec![(2, 2)]);
+        let kept = unsent();
+        assert_eq!(kept.len(), 1);
+        assert!(matches!(&kept[0].message, Message::Decision(d) if d.slot_number == Slot(1)));
+
+        // The node crashes; its next pump sends what was left
+        drop(pump);
+        let mut pump = OutboxPump::new(Box::new(Flaky {
+            failures: Mutex::new(0),
+            delivered: delivered.clone(),
+        }));
+        pump.set_journal(journal()).unwrap();
+        assert_eq!(pump.pending(), 1);
+        pump.flush();
+        assert_eq!(delivered.lock().unwrap()[1..], [(1, 1)]);
+        assert!(unsent().is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,938 @@
+//! TCP transport carrying length-prefixed frames.
+//!
+//! Each frame is a big-endian `u32` byte length followed by the message
+//! encoded with `JsonCodec`. Senders keep one connection open per destination.
+//!
+//! A message that encodes to more than `SizeLimits::max_frame_len` bytes is
+//! split across several frames, as long as it is within `max_message_len`.
+//! Every frame but the last has the top bit of its length set, and the
+//! receiver joins them back up before decoding. Both ends of a connection
+//! must agree on the limits: by default they are equal, so nothing is
+//! fragmented.
+//!
+//! A sender reports each destination's link going up or down as a
+//! `LinkEvent`. After a failed connect it drops what is sent to that
+//! destination until a backoff, doubling with each failure, has passed, so a
+//! dead peer costs one connect attempt per backoff rather than one per
+//! message.
+//!
+//! A sender whose `Codecs` prefer other formats than plain JSON for a
+//! destination offers them in a hello frame when it connects there, and the
+//! server answers with the first one it supports, or plain JSON. The sender
+//! reports the answer as `LinkEvent::Negotiated`, for the node's router, and
+//! the pump then hands it messages for that destination to send in it.
+//!
+//! Destinations may name a host rather than an IP. The sender resolves them
+//! with a `DnsResolver`, refreshing expired answers as it goes, and closes a
+//! connection whose destination moved to other addresses, reporting
+//! `LinkEvent::Moved`. The next message to it connects to the new ones.
+//!
+//! A server given a `Keyring` only lets in messages claiming a node from a
+//! connection authenticated as that node, see `transport::auth`. A sender
+//! with `Credentials` proves them on every connection it opens, before
+//! anything else; a server that refuses them closes the connection, and the
+//! sender reports the link down.
+use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::messages;
+use crate::nodes::router::WireFormat;
+use crate::transport::auth::{self, AuthenticatedPeer, Credentials, Keyring};
+use crate::transport::codec::Codecs;
+use crate::transport::dns::DnsResolver;
+#[cfg(feature = "async")]
+use crate::transport::AsyncTransport;
+use crate::transport::{LinkEvent, Transport, TransportError};
+use crate::types::{Address, Payload};
+
+/// Frames larger than this are treated as corrupt and close the connection.
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// How long a sender waits after a first failed connect before trying again.
+pub const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// The longest a sender waits between connect attempts to a dead destination.
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How long a sender waits for a server to answer the formats it offered
+/// before giving up on the connection.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Set in the length of every frame of a fragmented message but the last
+const MORE_FRAGMENTS: u32 = 1 << 31;
+
+// Leads a hello frame, followed by the formats offered as JSON. Messages
+// never start with it
+const HELLO_TAG: u8 = 1;
+
+// Leads an authentication frame, followed by an `AuthFrame` as JSON
+const AUTH_TAG: u8 = 2;
+
+// The longest answer to a hello a sender reads
+const MAX_HELLO_LEN: usize = 4096;
+
+/// A sender proving its `Credentials`: it claims a key, the server answers
+/// with a nonce, and it sends the proof, which the server answers with
+/// whether it was accepted.
+#[derive(Debug, Serialize, Deserialize)]
+enum AuthFrame {
+    Claim { key_id: String },
+    Proof { proof: Vec<u8> },
+}
+
+/// How large frames, and the messages carried in them, may be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// The largest frame sent or accepted. At most `MAX_FRAME_LEN`.
+    pub max_frame_len: usize,
+    /// The largest encoded message sent or accepted. Messages longer than
+    /// `max_frame_len` are fragmented.
+    pub max_message_len: usize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        SizeLimits {
+            max_frame_len: MAX_FRAME_LEN,
+            max_message_len: MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl SizeLimits {
+    /// Frame the encoded message `body`, fragmenting it if it needs more than one frame.
+    fn frame(&self, body: &[u8]) -> Result<Vec<u8>, TransportError> {
+        if body.len() > self.max_message_len {
+            return Err(TransportError::Rejected(format!(
+                "message of {} bytes exceeds the limit of {}",
+                body.len(),
+                self.max_message_len
+            )));
+        }
+        let chunk_len = self.max_frame_len.clamp(1, MAX_FRAME_LEN);
+        let chunks = body.len().div_ceil(chunk_len).max(1);
+        let mut frames = Vec::with_capacity(4 * chunks + body.len());
+        for (i, chunk) in body.chunks(chunk_len).enumerate() {
+            let more = if i + 1 < chunks { MORE_FRAGMENTS } else { 0 };
+            frames.extend_from_slice(&(chunk.len() as u32 | more).to_be_bytes());
+            frames.extend_from_slice(chunk);
+        }
+        if body.is_empty() {
+            frames.extend_from_slice(&0u32.to_be_bytes());
+        }
+        Ok(frames)
+    }
+}
+
+/// Accepts TCP connections and forwards every decoded message
+/// to a channel, from which the caller feeds node mailboxes.
+pub struct TcpServer<T = Vec<u8>> {
+    listener: TcpListener,
+    inbound: mpsc::UnboundedSender<messages::SendableMessage<T>>,
+    limits: SizeLimits,
+    codecs: Arc<Codecs<T>>,
+    keyring: Option<Arc<Keyring>>,
+}
+
+impl<T: Payload + Send + 'static> TcpServer<T> {
+    /// Bind the server, returning it along with the receiving end of its inbound channel.
+    pub async fn bind(
+        addr: SocketAddr,
+    ) -> anyhow::Result<(
+        TcpServer<T>,
+        mpsc::UnboundedReceiver<messages::SendableMessage<T>>,
+    )> {
+        let listener = TcpListener::bind(addr).await?;
+        let (inbound, receiver) = mpsc::unbounded_channel();
+        Ok((
+            TcpServer {
+                listener,
+                inbound,
+                limits: SizeLimits::default(),
+                codecs: Arc::new(Codecs::default()),
+                keyring: None,
+            },
+            receiver,
+        ))
+    }
+
+    /// Accept frames and reassembled messages up to `limits`.
+    pub fn with_limits(mut self, limits: SizeLimits) -> TcpServer<T> {
+        self.limits = limits;
+        self
+    }
+
+    /// Decode messages, and answer the formats senders offer, with `codecs`.
+    pub fn with_codecs(mut self, codecs: Codecs<T>) -> TcpServer<T> {
+        self.codecs = Arc::new(codecs);
+        self
+    }
+
+    /// Let in messages claiming a node only from connections that proved a
+    /// key in `keyring` speaking for it.
+    pub fn with_keyring(mut self, keyring: Keyring) -> TcpServer<T> {
+        self.keyring = Some(Arc::new(keyring));
+        self
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept connections until the inbound channel is closed.
+    pub async fn run(self) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            if self.inbound.is_closed() {
+                return Ok(());
+            }
+            debug!("tcp: accepted connection from {}", peer);
+            let inbound = self.inbound.clone();
+            let limits = self.limits;
+            let codecs = self.codecs.clone();
+            let keyring = self.keyring.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, inbound, limits, codecs, keyring).await {
+                    warn!("tcp: connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve_connection<T: Payload>(
+    mut stream: TcpStream,
+    inbound: mpsc::UnboundedSender<messages::SendableMessage<T>>,
+    limits: SizeLimits,
+    codecs: Arc<Codecs<T>>,
+    keyring: Option<Arc<Keyring>>,
+) -> anyhow::Result<()> {
+    // The fragments of the message being reassembled
+    let mut message = Vec::new();
+    // The key claimed and the nonce it was challenged with, then who proved it
+    let mut challenge: Option<(String, [u8; auth::NONCE_LEN])> = None;
+    let mut peer: Option<AuthenticatedPeer> = None;
+    loop {
+        let header = match stream.read_u32().await {
+            Ok(header) => header,
+            // The peer closed the connection between frames
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let len = (header & !MORE_FRAGMENTS) as usize;
+        if len > limits.max_frame_len.min(MAX_FRAME_LEN) {
+            anyhow::bail!("frame of {} bytes exceeds the limit", len);
+        }
+        if message.len() + len > limits.max_message_len {
+            anyhow::bail!(
+                "message of over {} bytes exceeds the limit",
+                message.len() + len
+            );
+        }
+        let start = message.len();
+        message.resize(start + len, 0);
+        stream.read_exact(&mut message[start..]).await?;
+        if header & MORE_FRAGMENTS != 0 {
+            continue;
+        }
+        let frame = core::mem::take(&mut message);
+        if let [HELLO_TAG, offer @ ..] = &frame[..] {
+            let offer: Vec<WireFormat> = serde_json::from_slice(offer)?;
+            let format = serde_json::to_vec(&codecs.choose(&offer))?;
+            stream.write_u32(format.len() as u32).await?;
+            stream.write_all(&format).await?;
+            continue;
+        }
+        if let [AUTH_TAG, frame @ ..] = &frame[..] {
+            match serde_json::from_slice(frame)? {
+                AuthFrame::Claim { key_id } => {
+                    let nonce = auth::nonce()?;
+                    stream.write_u32(nonce.len() as u32).await?;
+                    stream.write_all(&nonce).await?;
+                    challenge = Some((key_id, nonce));
+                }
+                AuthFrame::Proof { proof } => {
+                    let Some((key_id, nonce)) = challenge.take() else {
+                        anyhow::bail!("proof of no claimed key");
+                    };
+                    // Without a keyring nothing is checked, so any proof will do
+                    let verified = match keyring.as_ref() {
+                        Some(keyring) => keyring.verify(&key_id, &nonce, &proof),
+                        None => None,
+                    };
+                    let accepted = keyring.is_none() || verified.is_some();
+                    let answer = serde_json::to_vec(&accepted)?;
+                    stream.write_u32(answer.len() as u32).await?;
+                    stream.write_all(&answer).await?;
+                    if !accepted {
+                        anyhow::bail!("failed to prove key {}", key_id);
+                    }
+                    debug!("tcp: connection authenticated with key {}", key_id);
+                    peer = verified;
+                }
+            }
+            continue;
+        }
+        match codecs.decode(&frame) {
+            Ok(msg) => {
+                if keyring.is_some() {
+                    if let Err(e) = auth::check_source(peer.as_ref(), &msg) {
+                        warn!(
+                            monotonic_counter.paxos.transport.source_rejected = 1u64,
+                            "tcp: dropping a {} that {}",
+                            msg.message.kind(),
+                            e
+                        );
+                        continue;
+                    }
+                }
+                if inbound.send(msg).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => warn!("tcp: dropping undecodable frame: {}", e),
+        }
+    }
+}
+
+/// Sends messages to `{dst}` over TCP, connecting lazily and keeping one
+/// connection open per destination.
+///
+/// Messages are framed in `send`, so encoding failures are reported to the
+/// caller; writing is handed off to a background task, so `TcpSender::spawn`
+/// must be called from within a tokio runtime.
+pub struct TcpSender<T = Vec<u8>> {
+    // (destination, frames) pairs for the writer task
+    outbound: mpsc::UnboundedSender<(Address, Vec<u8>)>,
+    limits: SizeLimits,
+    link_events: Option<mpsc::UnboundedReceiver<LinkEvent>>,
+    codecs: Arc<Codecs<T>>,
+    // Shared with the writer task, which proves them on each connection
+    credentials: Arc<Mutex<Option<Credentials>>>,
+}
+
+impl<T: Payload + 'static> TcpSender<T> {
+    pub fn spawn() -> TcpSender<T> {
+        TcpSender::spawn_with_codecs(Codecs::default())
+    }
+
+    /// Spawn a sender that offers each destination the formats `codecs`
+    /// prefer for it, and encodes in them.
+    pub fn spawn_with_codecs(codecs: Codecs<T>) -> TcpSender<T> {
+        TcpSender::spawn_with_resolver(codecs, DnsResolver::system())
+    }
+
+    /// Spawn a sender that resolves hostnames with `resolver`.
+    pub fn spawn_with_resolver(codecs: Codecs<T>, resolver: DnsResolver) -> TcpSender<T> {
+        let codecs = Arc::new(codecs);
+        let (outbound, receiver) = mpsc::unbounded_channel();
+        let (events, link_events) = mpsc::unbounded_channel();
+        let credentials = Arc::new(Mutex::new(None));
+        tokio::spawn(run_sender(
+            receiver,
+            events,
+            codecs.clone(),
+            resolver,
+            credentials.clone(),
+        ));
+        TcpSender {
+            outbound,
+            limits: SizeLimits::default(),
+            link_events: Some(link_events),
+            codecs,
+            credentials,
+        }
+    }
+
+    /// Prove `credentials` on every connection opened from now on, e.g.
+    /// straight after spawning the sender.
+    pub fn with_credentials(self, credentials: Credentials) -> TcpSender<T> {
+        *self.credentials.lock().unwrap_or_else(|e| e.into_inner()) = Some(credentials);
+        self
+    }
+
+    /// The events reporting each destination's link going up or down, for
+    /// `NodeRunner::set_link_events`. Only the first call returns them.
+    pub fn take_link_events(&mut self) -> Option<mpsc::UnboundedReceiver<LinkEvent>> {
+        self.link_events.take()
+    }
+
+    /// Send frames and messages up to `limits`, fragmenting messages longer
+    /// than a frame.
+    pub fn with_limits(mut self, limits: SizeLimits) -> TcpSender<T> {
+        self.limits = limits;
+        self
+    }
+}
+
+impl<T: Payload> Transport<T> for TcpSender<T> {
+    fn send(&self, message: &messages::SendableMessage<T>) -> Result<(), TransportError> {
+        self.send_as(message, &WireFormat::default())
+    }
+
+    fn send_as(
+        &self,
+        message: &messages::SendableMessage<T>,
+        format: &WireFormat,
+    ) -> Result<(), TransportError> {
+        let body = self
+            .codecs
+            .encode(format, message)
+            .map_err(|e| TransportError::Rejected(e.to_string()))?;
+        let frames = self.limits.frame(&body)?;
+        self.outbound
+            .send((message.dst.clone(), frames))
+            .map_err(|_| TransportError::Closed)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: Payload> AsyncTransport<T> for TcpSender<T> {
+    // Handing a frame to the writer task never blocks
+    fn send(
+        &self,
+        message: &messages::SendableMessage<T>,
+    ) -> impl Future<Output = Result<(), TransportError>> + Send {
+        std::future::ready(Transport::send(self, message))
+    }
+}
+
+/// A destination the writer task has connected to, or failed to.
+#[derive(Default)]
+struct Link {
+    stream: Option<TcpStream>,
+    // The address the stream is connected to
+    peer: Option<SocketAddr>,
+    // Connects failed in a row, and when the next may be tried
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Link {
+    fn back_off(&mut self, now: Instant) {
+        self.failures += 1;
+        let backoff = MIN_RECONNECT_BACKOFF
+            .saturating_mul(1 << self.failures.min(16).saturating_sub(1))
+            .min(MAX_RECONNECT_BACKOFF);
+        self.retry_at = Some(now + backoff);
+    }
+
+    /// Its destination now resolves to `addrs`: drop a connection to any
+    /// other address, and connect again without waiting out a backoff.
+    fn moved(&mut self, addrs: &[SocketAddr]) {
+        if self.peer.is_some_and(|peer| !addrs.contains(&peer)) {
+            self.stream = None;
+            self.peer = None;
+        }
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
+
+/// Offer `offer` to the server at the other end of `stream`, and read back
+/// the format it chose.
+async fn handshake(stream: &mut TcpStream, offer: &[WireFormat]) -> anyhow::Result<WireFormat> {
+    let mut hello = vec![HELLO_TAG];
+    hello.extend(serde_json::to_vec(offer)?);
+    stream.write_u32(hello.len() as u32).await?;
+    stream.write_all(&hello).await?;
+    Ok(serde_json::from_slice(&read_answer(stream).await?)?)
+}
+
+// Read a length-prefixed answer of at most `MAX_HELLO_LEN` bytes
+async fn read_answer(stream: &mut TcpStream) -> anyhow::Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_HELLO_LEN {
+        anyhow::bail!("answer of {} bytes exceeds the limit", len);
+    }
+    let mut answer = vec![0; len];
+    stream.read_exact(&mut answer).await?;
+    Ok(answer)
+}
+
+async fn write_auth_frame(stream: &mut TcpStream, frame: &AuthFrame) -> anyhow::Result<()> {
+    let mut bytes = vec![AUTH_TAG];
+    bytes.extend(serde_json::to_vec(frame)?);
+    stream.write_u32(bytes.len() as u32).await?;
+    stream.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Prove `credentials` to the server at the other end of `stream`.
+async fn authenticate(stream: &mut TcpStream, credentials: &Credentials) -> anyhow::Result<()> {
+    let claim = AuthFrame::Claim {
+        key_id: credentials.key_id().to_string(),
+    };
+    write_auth_frame(stream, &claim).await?;
+    let nonce = read_answer(stream).await?;
+    let proof = AuthFrame::Proof {
+        proof: credentials.prove(&nonce),
+    };
+    write_auth_frame(stream, &proof).await?;
+    let accepted: bool = serde_json::from_slice(&read_answer(stream).await?)?;
+    if !accepted {
+        anyhow::bail!("key {} was refused", credentials.key_id());
+    }
+    Ok(())
+}
+
+/// Connect to `dst` at the first of `addrs` that answers, proving
+/// `credentials` if given any, and negotiating a format if `codecs` offer it
+/// any.
+async fn connect<T: Payload>(
+    dst: &Address,
+    addrs: &[SocketAddr],
+    codecs: &Codecs<T>,
+    credentials: Option<&Credentials>,
+) -> anyhow::Result<(TcpStream, Option<WireFormat>)> {
+    let mut stream = TcpStream::connect(addrs).await?;
+    stream.set_nodelay(true).ok();
+    if let Some(credentials) = credentials {
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, authenticate(&mut stream, credentials))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!("no answer to authentication within {:?}", HANDSHAKE_TIMEOUT)
+            })??;
+    }
+    let offer = codecs.offer(dst);
+    if offer.is_empty() {
+        return Ok((stream, None));
+    }
+    let format = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&mut stream, &offer))
+        .await
+        .map_err(|_| anyhow::anyhow!("no answer to hello within {:?}", HANDSHAKE_TIMEOUT))??;
+    Ok((stream, Some(format)))
+}
+
+async fn run_sender<T: Payload>(
+    mut receiver: mpsc::UnboundedReceiver<(Address, Vec<u8>)>,
+    events: mpsc::UnboundedSender<LinkEvent>,
+    codecs: Arc<Codecs<T>>,
+    mut resolver: DnsResolver,
+    credentials: Arc<Mutex<Option<Credentials>>>,
+) {
+    let mut links: HashMap<String, Link> = HashMap::new();
+    // Nobody listening for link events is not a reason to stop sending
+    let report = |event: LinkEvent| {
+        let _ = events.send(event);
+    };
+    let mut refresh = tokio::time::interval(resolver.refresh_interval());
+    refresh.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        let (dst, frame) = tokio::select! {
+            next = receiver.recv() => match next {
+                Some(next) => next,
+                None => break,
+            },
+            _ = refresh.tick() => {
+                for (dst, addrs) in resolver.refresh(Instant::now()).await {
+                    if let Some(link) = links.get_mut(&dst.to_string()) {
+                        link.moved(&addrs);
+                    }
+                    report(LinkEvent::Moved { dst, addrs });
+                }
+                continue;
+            }
+        };
+        let link = links.entry(dst.to_string()).or_default();
+        if link.stream.is_none() {
+            let now = Instant::now();
+            if link.retry_at.is_some_and(|retry_at| now < retry_at) {
+                // Paxos tolerates message loss; the protocol's retries will resend.
+                debug!("tcp: {} is down, dropping a message", dst);
+                continue;
+            }
+            let credentials = credentials
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone();
+            let connected = match resolver.resolve(&dst).await {
+                Ok(addrs) => connect(&dst, &addrs, &codecs, credentials.as_ref()).await,
+                Err(e) => Err(e.into()),
+            };
+            match connected {
+                Ok((stream, format)) => {
+                    link.peer = stream.peer_addr().ok();
+                    link.stream = Some(stream);
+                    link.failures = 0;
+                    link.retry_at = None;
+                    report(LinkEvent::Up(dst.clone()));
+                    if let Some(format) = format {
+                        debug!("tcp: sending to {} as {}", dst, format);
+                        report(LinkEvent::Negotiated {
+                            dst: dst.clone(),
+                            format,
+                        });
+                    }
+                }
+                Err(e) => {
+                    warn!("tcp: failed to connect to {}: {}", dst, e);
+                    link.back_off(now);
+                    report(LinkEvent::Down {
+                        dst,
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+            }
+        }
+        if let Some(stream) = link.stream.as_mut() {
+            if let Err(e) = stream.write_all(&frame).await {
+                warn!("tcp: send to {} failed: {}", dst, e);
+                // Reconnect on the next message, since the peer may only have restarted
+                link.stream = None;
+                link.peer = None;
+                report(LinkEvent::SendFailed {
+                    dst,
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::transport::codec::Compression;
+    use crate::transport::dns::{Lookup, LookupFuture, Resolved};
+    use crate::types::*;
+
+    #[tokio::test]
+    async fn tcp_sender_delivers_to_server() {
+        let (server, mut receiver): (TcpServer, _) =
+            TcpServer::bind("127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(server.run());
+
+        let sender: TcpSender = TcpSender::spawn();
+        for request_id in 0..3 {
+            let msg = SendableMessage {
+                src: Address::new("127.0.0.1".to_string(), 1),
+                dst: Address::new("127.0.0.1".to_string(), port as u64),
+                seq: None,
+                lamport: None,
+                epoch: None,
+                message: Message::Decision(DecisionMessage {
+                    src: LeaderId::new(1),
+                    slot_number: Slot(request_id + 1),
+                    command: Command {
+                        client_id: NodeId::new(9),
+                        request_id,
+                        op: CommandType::Op(vec![1, 2, 3]),
+                    },
+                    certificate: None,
+                }),
+            };
+            Transport::send(&sender, &msg).unwrap();
+        }
+
+        // Frames on one connection arrive in order
+        for slot in 1..=3 {
+            let received = receiver.recv().await.unwrap();
+            assert!(matches!(
+                received.message,
+                Message::Decision(DecisionMessage { slot_number, .. }) if slot_number == Slot(slot)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_sender_reports_links_and_backs_off_dead_destinations() {
+        let (server, _receiver): (TcpServer, _) = TcpServer::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let live = Address::new(
+            "127.0.0.1".to_string(),
+            server.local_addr().unwrap().port() as u64,
+        );
+        tokio::spawn(server.run());
+        // A port nothing listens on
+        let dead = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            Address::new(
+                "127.0.0.1".to_string(),
+                listener.local_addr().unwrap().port() as u64,
+            )
+        };
+
+        let mut sender: TcpSender = TcpSender::spawn();
+        let mut events = sender.take_link_events().unwrap();
+        assert!(sender.take_link_events().is_none());
+        let msg = |dst: &Address| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: dst.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                decided_below: Slot(0),
+            }),
+        };
+        for _ in 0..3 {
+            Transport::send(&sender, &msg(&dead)).unwrap();
+        }
+        Transport::send(&sender, &msg(&live)).unwrap();
+
+        // One connect attempt to the dead destination within the backoff
+        assert!(matches!(events.recv().await, Some(LinkEvent::Down { dst, .. }) if dst == dead));
+        assert_eq!(events.recv().await, Some(LinkEvent::Up(live)));
+    }
+
+    #[tokio::test]
+    async fn tcp_server_lets_in_only_messages_from_the_nodes_a_sender_proved() {
+        let keyring = Keyring::new().with_key("leader-1", b"secret", [NodeId::new(1)]);
+        let (server, mut receiver): (TcpServer, _) =
+            TcpServer::bind("127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+        let server = server.with_keyring(keyring);
+        let dst = Address::new(
+            "127.0.0.1".to_string(),
+            server.local_addr().unwrap().port() as u64,
+        );
+        tokio::spawn(server.run());
+        let p1a = |leader: u64| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: dst.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(leader),
+                ballot_number: BallotNumber::new(LeaderId::new(leader)),
+                decided_below: Slot(0),
+            }),
+        };
+        let request = SendableMessage {
+            message: Message::Request(RequestMessage {
+                src: Address::new("127.0.0.1".to_string(), 9),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(vec![]),
+                },
+                consistency: Consistency::default(),
+                proxied_by: None,
+            }),
+            ..p1a(1)
+        };
+
+        // Leader 1 is let in as itself, not as leader 2
+        let leader: TcpSender =
+            TcpSender::spawn().with_credentials(Credentials::new("leader-1", b"secret"));
+        Transport::send(&leader, &p1a(2)).unwrap();
+        Transport::send(&leader, &p1a(1)).unwrap();
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.message.sender(), Some(NodeId::new(1)));
+
+        // A client is let in with its request, not as a leader
+        let client: TcpSender = TcpSender::spawn();
+        Transport::send(&client, &p1a(1)).unwrap();
+        Transport::send(&client, &request).unwrap();
+        let received = receiver.recv().await.unwrap();
+        assert!(matches!(received.message, Message::Request(_)));
+
+        // A wrong secret is refused, and the link reported down
+        let mut forger: TcpSender =
+            TcpSender::spawn().with_credentials(Credentials::new("leader-1", b"guess"));
+        let mut events = forger.take_link_events().unwrap();
+        Transport::send(&forger, &p1a(1)).unwrap();
+        assert!(matches!(events.recv().await, Some(LinkEvent::Down { .. })));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    /// Resolves every name to whichever address it was last pointed at.
+    struct Pointed(std::sync::Mutex<SocketAddr>);
+
+    impl Lookup for Pointed {
+        fn lookup<'a>(&'a self, _host: &'a str, _port: u16) -> LookupFuture<'a> {
+            let addr = *self.0.lock().unwrap();
+            Box::pin(async move {
+                Ok(Resolved {
+                    addrs: vec![addr],
+                    ttl: Some(Duration::from_millis(10)),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_sender_reconnects_when_a_name_moves() {
+        let mut servers = Vec::new();
+        for _ in 0..2 {
+            let (server, receiver): (TcpServer, _) =
+                TcpServer::bind("127.0.0.1:0".parse().unwrap())
+                    .await
+                    .unwrap();
+            servers.push((server.local_addr().unwrap(), receiver));
+            tokio::spawn(server.run());
+        }
+        let lookup = Arc::new(Pointed(std::sync::Mutex::new(servers[0].0)));
+        let resolver =
+            DnsResolver::new(lookup.clone()).with_refresh_interval(Duration::from_millis(10));
+        let mut sender: TcpSender = TcpSender::spawn_with_resolver(Codecs::default(), resolver);
+        let mut events = sender.take_link_events().unwrap();
+        let name = Address::new("paxos-0.paxos".to_string(), 7000);
+        let msg = SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: name.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                decided_below: Slot(0),
+            }),
+        };
+
+        Transport::send(&sender, &msg).unwrap();
+        assert!(servers[0].1.recv().await.is_some());
+        assert_eq!(events.recv().await, Some(LinkEvent::Up(name.clone())));
+
+        // The pod is rescheduled at another address
+        *lookup.0.lock().unwrap() = servers[1].0;
+        assert_eq!(
+            events.recv().await,
+            Some(LinkEvent::Moved {
+                dst: name.clone(),
+                addrs: vec![servers[1].0],
+            })
+        );
+        Transport::send(&sender, &msg).unwrap();
+        assert!(servers[1].1.recv().await.is_some());
+        assert_eq!(events.recv().await, Some(LinkEvent::Up(name)));
+    }
+
+    /// Flips every bit, so a peer without it cannot read what it compressed.
+    struct Flip;
+
+    impl Compression for Flip {
+        fn compress(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+            Ok(bytes.iter().map(|b| !b).collect())
+        }
+
+        fn decompress(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+            self.compress(bytes)
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_sender_negotiates_a_format_per_destination() {
+        let flipped = WireFormat::new("json", "flip");
+        let codecs = || Codecs::default().with_compression("flip", Flip);
+        let (server, mut receiver): (TcpServer, _) =
+            TcpServer::bind("127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+        let server = server.with_codecs(codecs());
+        let dst = Address::new(
+            "127.0.0.1".to_string(),
+            server.local_addr().unwrap().port() as u64,
+        );
+        tokio::spawn(server.run());
+
+        // The sender offers a codec it lacks first, which it leaves out
+        let mut sender: TcpSender = TcpSender::spawn_with_codecs(
+            codecs().prefer_for(&dst, vec![WireFormat::new("cbor", "none"), flipped.clone()]),
+        );
+        let mut events = sender.take_link_events().unwrap();
+        let msg = |slot: u64| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: dst.clone(),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P2b(P2bMessage {
+                src: AcceptorId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                slot_number: Slot(slot),
+            }),
+        };
+        Transport::send(&sender, &msg(1)).unwrap();
+        assert_eq!(events.recv().await, Some(LinkEvent::Up(dst.clone())));
+        assert_eq!(
+            events.recv().await,
+            Some(LinkEvent::Negotiated {
+                dst: dst.clone(),
+                format: flipped.clone(),
+            })
+        );
+        sender.send_as(&msg(2), &flipped).unwrap();
+        for slot in 1..=2 {
+            let received = receiver.recv().await.unwrap();
+            assert!(matches!(received.message, Message::P2b(p2b) if p2b.slot_number == Slot(slot)));
+        }
+
+        // A peer without the compression settles for plain JSON
+        let plain: Codecs = Codecs::default();
+        assert_eq!(plain.choose(&[flipped]), WireFormat::default());
+    }
+
+    #[tokio::test]
+    async fn tcp_transport_fragments_messages_longer_than_a_frame() {
+        let limits = SizeLimits {
+            max_frame_len: 64,
+            max_message_len: 4096,
+        };
+        let (server, mut receiver): (TcpServer, _) =
+            TcpServer::bind("127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+        let server = server.with_limits(limits);
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(server.run());
+
+        let sender: TcpSender = TcpSender::spawn().with_limits(limits);
+        let msg = |op: Vec<u8>| SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: Address::new("127.0.0.1".to_string(), port as u64),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::Decision(DecisionMessage {
+                src: LeaderId::new(1),
+                slot_number: Slot(1),
+                command: Command {
+                    client_id: NodeId::new(9),
+                    request_id: 1,
+                    op: CommandType::Op(op),
+                },
+                certificate: None,
+            }),
+        };
+        // Too long even to fragment
+        assert!(matches!(
+            Transport::send(&sender, &msg(vec![7; 4096])),
+            Err(TransportError::Rejected(_))
+        ));
+        Transport::send(&sender, &msg(vec![7; 300])).unwrap();
+        Transport::send(&sender, &msg(vec![8])).unwrap();
+
+        for expected in [vec![7; 300], vec![8]] {
+            match receiver.recv().await.unwrap().message {
+                Message::Decision(decision) => {
+                    assert_eq!(decision.command.op, CommandType::Op(expected))
+                }
+                other => panic!("expected a decision, got {:?}", other),
+            }
+        }
+    }
+}
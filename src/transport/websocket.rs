@@ -0,0 +1,200 @@
+//! WebSocket transport, useful for bridging a cluster's message flow to a
+//! browser visualization or driving nodes from WASM clients.
+//!
+//! Messages are carried as JSON text frames (see `JsonCodec`) so that they
+//! can be inspected and produced directly from JavaScript.
+use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, warn};
+
+use crate::messages;
+use crate::transport::codec::{Codec, JsonCodec};
+#[cfg(feature = "async")]
+use crate::transport::AsyncTransport;
+use crate::transport::{Transport, TransportError};
+use crate::types::Payload;
+
+/// Accepts WebSocket connections and forwards every decoded message
+/// to a channel, from which the caller feeds node mailboxes.
+pub struct WebSocketServer<T = Vec<u8>> {
+    listener: TcpListener,
+    inbound: mpsc::UnboundedSender<messages::SendableMessage<T>>,
+}
+
+impl<T: Payload + Send + 'static> WebSocketServer<T> {
+    /// Bind the server, returning it along with the receiving end of its inbound channel.
+    pub async fn bind(
+        addr: SocketAddr,
+    ) -> anyhow::Result<(
+        WebSocketServer<T>,
+        mpsc::UnboundedReceiver<messages::SendableMessage<T>>,
+    )> {
+        let listener = TcpListener::bind(addr).await?;
+        let (inbound, receiver) = mpsc::unbounded_channel();
+        Ok((WebSocketServer { listener, inbound }, receiver))
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept connections until the inbound channel is closed.
+    pub async fn run(self) -> anyhow::Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept().await?;
+            if self.inbound.is_closed() {
+                return Ok(());
+            }
+            debug!("websocket: accepted connection from {}", peer);
+            let inbound = self.inbound.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, inbound).await {
+                    warn!("websocket: connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve_connection<T: Payload>(
+    stream: TcpStream,
+    inbound: mpsc::UnboundedSender<messages::SendableMessage<T>>,
+) -> anyhow::Result<()> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    while let Some(frame) = ws.next().await {
+        let decoded = match frame? {
+            WsMessage::Text(text) => JsonCodec.decode(text.as_bytes()),
+            WsMessage::Binary(bytes) => JsonCodec.decode(&bytes),
+            WsMessage::Close(_) => break,
+            _ => continue,
+        };
+        match decoded {
+            Ok(msg) => {
+                if inbound.send(msg).is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("websocket: dropping undecodable frame: {}", e),
+        }
+    }
+    Ok(())
+}
+
+/// Sends messages to `ws://{dst}`, connecting lazily and keeping one
+/// connection open per destination.
+///
+/// Messages are encoded in `send`, so encoding failures are reported to the
+/// caller; writing is handed off to a background task, so
+/// `WebSocketSender::spawn` must be called from within a tokio runtime.
+pub struct WebSocketSender<T = Vec<u8>> {
+    // (url, frame) pairs for the writer task
+    outbound: mpsc::UnboundedSender<(String, WsMessage)>,
+    _payload: PhantomData<fn(T)>,
+}
+
+impl<T: Payload> WebSocketSender<T> {
+    pub fn spawn() -> WebSocketSender<T> {
+        let (outbound, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_sender(receiver));
+        WebSocketSender {
+            outbound,
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<T: Payload> Transport<T> for WebSocketSender<T> {
+    fn send(&self, message: &messages::SendableMessage<T>) -> Result<(), TransportError> {
+        let bytes = JsonCodec
+            .encode(message)
+            .map_err(|e| TransportError::Rejected(e.to_string()))?;
+        let text = String::from_utf8(bytes).map_err(|e| {
+            TransportError::Rejected(format!("encoded message is not utf-8: {}", e))
+        })?;
+        self.outbound
+            .send((format!("ws://{}", message.dst), WsMessage::text(text)))
+            .map_err(|_| TransportError::Closed)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: Payload> AsyncTransport<T> for WebSocketSender<T> {
+    // Handing a frame to the writer task never blocks
+    fn send(
+        &self,
+        message: &messages::SendableMessage<T>,
+    ) -> impl Future<Output = Result<(), TransportError>> + Send {
+        std::future::ready(Transport::send(self, message))
+    }
+}
+
+type Connection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn run_sender(mut receiver: mpsc::UnboundedReceiver<(String, WsMessage)>) {
+    let mut connections: HashMap<String, Connection> = HashMap::new();
+    while let Some((url, frame)) = receiver.recv().await {
+        if !connections.contains_key(&url) {
+            match tokio_tungstenite::connect_async(url.as_str()).await {
+                Ok((ws, _)) => {
+                    connections.insert(url.clone(), ws);
+                }
+                Err(e) => {
+                    // Paxos tolerates message loss; the protocol's retries will resend.
+                    warn!("websocket: failed to connect to {}: {}", url, e);
+                    continue;
+                }
+            }
+        }
+        if let Some(ws) = connections.get_mut(&url) {
+            if let Err(e) = ws.send(frame).await {
+                warn!("websocket: send to {} failed: {}", url, e);
+                connections.remove(&url);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::*;
+    use crate::types::*;
+
+    #[tokio::test]
+    async fn websocket_sender_delivers_to_server() {
+        let (server, mut receiver): (WebSocketServer, _) =
+            WebSocketServer::bind("127.0.0.1:0".parse().unwrap())
+                .await
+                .unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(server.run());
+
+        let sender: WebSocketSender = WebSocketSender::spawn();
+        let msg = SendableMessage {
+            src: Address::new("127.0.0.1".to_string(), 1),
+            dst: Address::new("127.0.0.1".to_string(), port as u64),
+            seq: None,
+            lamport: None,
+            epoch: None,
+            message: Message::P1a(P1aMessage {
+                src: LeaderId::new(1),
+                ballot_number: BallotNumber::new(LeaderId::new(1)),
+                decided_below: Slot(0),
+            }),
+        };
+        Transport::send(&sender, &msg).unwrap();
+
+        let received = receiver.recv().await.unwrap();
+        assert_eq!(received.dst, msg.dst);
+        assert!(matches!(received.message, Message::P1a(_)));
+    }
+}
@@ -1,59 +1,363 @@
-use std::collections::{BTreeMap, HashSet};
-use std::fmt;
-use std::time::Duration;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Add, AddAssign, Sub};
+
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::collections::{BTreeMap, HashSet};
+use crate::time::Duration;
 
 /// A ballot number is a lexicographically ordered pair of an integer
 /// and the identifier of the ballot's leader.
-#[derive(Clone, Debug, Hash, Eq, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct BallotNumber {
-    pub round: u64,
+    pub round: Round,
     pub leader: LeaderId,
+    /// Which life of the leader made the ballot, counted across restarts, so
+    /// two lives of one `LeaderId` never make the same ballot.
+    #[serde(default)]
+    pub incarnation: u64,
 }
 
 impl BallotNumber {
     pub fn new(leader_id: LeaderId) -> Self {
         BallotNumber {
-            round: 0,
+            round: Round(0),
             leader: leader_id,
+            incarnation: 0,
+        }
+    }
+
+    /// The same leader's ballot in the round after `other`'s.
+    pub fn above(&self, other: &BallotNumber) -> BallotNumber {
+        BallotNumber {
+            round: other.round + 1,
+            leader: self.leader,
+            incarnation: self.incarnation,
+        }
+    }
+}
+
+/// A position in the log of commands, starting at 1.
+///
+/// Adding or subtracting a `u64` moves a number of slots along, and one slot
+/// less another is the number of slots between them.
+#[derive(
+    Clone, Copy, Debug, Default, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Slot(pub u64);
+
+impl Slot {
+    /// The first slot of every log.
+    pub const FIRST: Slot = Slot(1);
+
+    /// The slot after this one.
+    pub fn next(self) -> Slot {
+        Slot(self.0 + 1)
+    }
+
+    /// The slot `slots` before this one, or slot 0 if there is none.
+    pub fn saturating_sub(self, slots: u64) -> Slot {
+        Slot(self.0.saturating_sub(slots))
+    }
+
+    /// How many slots `earlier` is before this one, or 0 if it is not.
+    pub fn since(self, earlier: Slot) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// The slots from this one up to, but not including, `end`.
+    pub fn up_to(self, end: Slot) -> impl DoubleEndedIterator<Item = Slot> {
+        (self.0..end.0).map(Slot)
+    }
+}
+
+/// The highest slot that, with every slot before it, is decided: what an
+/// active leader has seen decided, `Slot(0)` before the first decision.
+#[derive(
+    Clone, Copy, Debug, Default, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct CommitIndex(pub Slot);
+
+impl CommitIndex {
+    /// Whether `slot` is decided, along with every slot before it.
+    pub fn covers(self, slot: Slot) -> bool {
+        slot <= self.0
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u64> for Slot {
+    fn from(slot: u64) -> Slot {
+        Slot(slot)
+    }
+}
+
+impl From<Slot> for u64 {
+    fn from(slot: Slot) -> u64 {
+        slot.0
+    }
+}
+
+impl Add<u64> for Slot {
+    type Output = Slot;
+
+    fn add(self, slots: u64) -> Slot {
+        Slot(self.0 + slots)
+    }
+}
+
+impl AddAssign<u64> for Slot {
+    fn add_assign(&mut self, slots: u64) {
+        self.0 += slots;
+    }
+}
+
+impl Sub<u64> for Slot {
+    type Output = Slot;
+
+    fn sub(self, slots: u64) -> Slot {
+        Slot(self.0 - slots)
+    }
+}
+
+impl Sub for Slot {
+    type Output = u64;
+
+    fn sub(self, other: Slot) -> u64 {
+        self.0 - other.0
+    }
+}
+
+/// The round of a ballot, which orders ballots before their leader does.
+#[derive(
+    Clone, Copy, Debug, Default, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Round(pub u64);
+
+impl Round {
+    /// The round after this one.
+    pub fn next(self) -> Round {
+        Round(self.0 + 1)
+    }
+}
+
+impl fmt::Display for Round {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u64> for Round {
+    fn from(round: u64) -> Round {
+        Round(round)
+    }
+}
+
+impl From<Round> for u64 {
+    fn from(round: Round) -> u64 {
+        round.0
+    }
+}
+
+impl Add<u64> for Round {
+    type Output = Round;
+
+    fn add(self, rounds: u64) -> Round {
+        Round(self.0 + rounds)
+    }
+}
+
+/// The part a node plays in the protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Acceptor,
+    Leader,
+    Replica,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Acceptor => write!(f, "acceptor"),
+            Role::Leader => write!(f, "leader"),
+            Role::Replica => write!(f, "replica"),
         }
     }
 }
 
 /// PValue is a triple consisting of a ballot number, a slot number, a command.
-#[derive(Clone, Debug, PartialEq)]
-pub struct PValue {
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PValue<T = Vec<u8>> {
     pub ballot_number: BallotNumber,
-    pub slot: u64,
-    pub command: Command,
+    pub slot: Slot,
+    pub command: Command<T>,
 }
 
 /// A command consists of the process identifier of the client
 // submitting the request, a client-local request identifier, and a command
-#[derive(Clone, Debug, PartialEq)]
-pub struct Command {
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Command<T = Vec<u8>> {
     pub client_id: NodeId,
     pub request_id: u64,
-    pub op: CommandType,
+    pub op: CommandType<T>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum CommandType {
+impl<T> Command<T> {
+    pub fn id(&self) -> CommandId {
+        CommandId {
+            client_id: self.client_id,
+            request_id: self.request_id,
+        }
+    }
+}
+
+/// Commands hash by identity only: hashing a large payload is wasted work
+/// when `(client_id, request_id)` already tells commands apart.
+impl<T> Hash for Command<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+/// The identity of a command: the submitting client and its request id.
+///
+/// Clients must not reuse a request id for a different operation, so
+/// comparing ids is a cheap stand-in for comparing whole commands.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CommandId {
+    pub client_id: NodeId,
+    pub request_id: u64,
+}
+
+impl fmt::Display for CommandId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.client_id, self.request_id)
+    }
+}
+
+// Reconfigurations are rare, so we don't box the config to shrink `Op`.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CommandType<T = Vec<u8>> {
     // An operation (which can be anything).
-    Op(Vec<u8>),
+    Op(T),
     // A ReconfigCommand is a command that changes the
     // configuration of the system
     Reconfig(Config),
+    // Adds or removes a single node, leaving the rest of the
+    // configuration as it is
+    Membership(MembershipChange),
+    // Membership and address changes merged into the current
+    // configuration, leaving its operational settings as they are
+    ConfigDelta(ConfigDelta),
+    // An application command of a registered kind, performed by the
+    // `CommandHandler` the replicas registered for `kind`
+    App { kind: u16, payload: Bytes },
+    // Decided by a leader in place of a command it refused, so that every
+    // replica answers the client with the refusal
+    Refused(RefusalReason),
+    // Operations a client sent together, applied in order in one slot and
+    // answered together, see `client::Batcher`
+    Batch(Vec<T>),
+}
+
+/// Why a leader refused a command, deciding a `CommandType::Refused` in
+/// its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RefusalReason {
+    /// A reconfiguration to acceptors with a quorum that need not intersect
+    /// every quorum of the current acceptors, so two leaders could have
+    /// values chosen in the same slot, one by each.
+    DisjointQuorums,
 }
 
+impl fmt::Display for RefusalReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefusalReason::DisjointQuorums => write!(
+                f,
+                "the new acceptors' quorums need not intersect the current ones'"
+            ),
+        }
+    }
+}
+
+/// A change to one role's membership, applied to whichever configuration is
+/// current when it takes effect. See `membership::MembershipManager`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MembershipChange {
+    AddReplica { id: ReplicaId, address: Address },
+    RemoveReplica { id: ReplicaId },
+    AddLeader { id: LeaderId, address: Address },
+    RemoveLeader { id: LeaderId },
+    AddAcceptor { id: AcceptorId, address: Address },
+    RemoveAcceptor { id: AcceptorId },
+}
+
+/// Membership and address changes to merge into whichever configuration is
+/// current when they take effect. Unlike a `Reconfig`, a delta leaves the
+/// timeouts and every other setting of that configuration as they are. See
+/// `membership::MembershipManager::merge`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDelta {
+    /// Nodes to add or remove, in order.
+    #[serde(default)]
+    pub membership: Vec<MembershipChange>,
+    /// New addresses for nodes that remain members.
+    #[serde(default)]
+    pub addresses: BTreeMap<NodeId, Address>,
+}
+
+/// The application payload carried by `CommandType::Op`.
+///
+/// Applications can replicate their own typed commands end-to-end rather
+/// than serializing them into bytes first; `Vec<u8>` is the default.
+pub trait Payload: Clone + fmt::Debug + Eq + Serialize + DeserializeOwned {}
+
+impl<T> Payload for T where T: Clone + fmt::Debug + Eq + Serialize + DeserializeOwned {}
+
 /// Used by leaders and acceptors to configure timeouts
 /// for various operations.
-#[derive(Clone, Debug, PartialEq)]
+///
+/// Fields missing when decoding take their defaults, so configs written by
+/// releases that had fewer timeouts still decode.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TimeoutConfig {
     // Backoff parameters
     pub min_timeout: Duration,
     pub max_timeout: Duration,
     pub timeout_multiplier: f32,
     pub timeout_decrease: Duration,
+    // How long a replica's slot_out may stall behind later decisions before it fetches the gap
+    pub slot_stall_timeout: Duration,
+    // How often acceptors and leaders announce themselves to peers
+    pub heartbeat_interval: Duration,
+    // How long a peer may stay silent before the failure detector suspects it
+    pub suspect_timeout: Duration,
+    // Phase 2 latency above which an active leader stops taking up new proposals
+    pub p2b_latency_slo: Duration,
+    // How long a leader goes without a reachable quorum of acceptors before
+    // it steps down and replicas turn new requests away as unavailable
+    pub quorum_loss_timeout: Duration,
+    // How long acceptors grant an active leader a lease for, if leases are used
+    pub lease: Option<Duration>,
+    // How far clocks may drift apart over a lease; holders take it to end this much early
+    pub max_clock_skew: Duration,
 }
 impl Default for TimeoutConfig {
     fn default() -> Self {
@@ -62,6 +366,13 @@ impl Default for TimeoutConfig {
             max_timeout: Duration::from_secs(10),
             timeout_multiplier: 1.5,
             timeout_decrease: Duration::from_millis(50),
+            slot_stall_timeout: Duration::from_secs(1),
+            heartbeat_interval: Duration::from_millis(500),
+            suspect_timeout: Duration::from_secs(2),
+            p2b_latency_slo: Duration::from_millis(500),
+            quorum_loss_timeout: Duration::from_secs(5),
+            lease: None,
+            max_clock_skew: Duration::from_millis(100),
         }
     }
 }
@@ -69,13 +380,26 @@ impl Default for TimeoutConfig {
 /// A configuration consists of a list of replicas, a list of
 /// acceptors and a list of leaders as well as a mapping of
 /// IDs to addresses.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub replicas: HashSet<ReplicaId>,
     pub acceptors: HashSet<AcceptorId>,
     pub leaders: HashSet<LeaderId>,
     pub id_address_map: BTreeMap<NodeId, Address>,
     pub timeout_config: TimeoutConfig,
+    /// Acceptors that vote but keep only ballots, not commands.
+    #[serde(default)]
+    pub witnesses: HashSet<AcceptorId>,
+    /// How many configuration changes led to this one. Replicas count one
+    /// for every change they apply, so the same configuration has the same
+    /// epoch everywhere, and nodes stamp it on the messages they send.
+    #[serde(default)]
+    pub epoch: u64,
+    /// Whether leaders send each decision with a `DecisionCertificate`, and
+    /// replicas perform only decisions whose certificate checks out, those
+    /// fetched from peers while catching up included.
+    #[serde(default)]
+    pub certify_decisions: bool,
 }
 
 impl Config {
@@ -92,15 +416,74 @@ impl Config {
             leaders,
             id_address_map,
             timeout_config: timeout_config.unwrap_or_default(),
+            witnesses: HashSet::new(),
+            epoch: 0,
+            certify_decisions: false,
+        }
+    }
+
+    /// Make `witnesses` witness acceptors: they count towards quorums but
+    /// store only the ballots they accept, not the commands.
+    pub fn with_witnesses<I>(mut self, witnesses: I) -> Config
+    where
+        I: IntoIterator<Item = AcceptorId>,
+    {
+        self.witnesses = witnesses.into_iter().collect();
+        self
+    }
+
+    /// Have leaders certify the decisions they send, and replicas check
+    /// the certificates before performing.
+    pub fn with_certified_decisions(mut self) -> Config {
+        self.certify_decisions = true;
+        self
+    }
+
+    pub fn is_witness(&self, acceptor: &AcceptorId) -> bool {
+        self.witnesses.contains(acceptor)
+    }
+
+    /// Check that every quorum of acceptors holds at least one that stores
+    /// commands, so an accepted command can always be recovered.
+    pub fn check_witnesses(&self) -> anyhow::Result<()> {
+        if let Some(stray) = self.witnesses.iter().find(|w| !self.acceptors.contains(*w)) {
+            anyhow::bail!("witness {} is not an acceptor", stray);
+        }
+        let quorum = (self.acceptors.len() / 2) + 1;
+        if self.witnesses.len() >= quorum {
+            anyhow::bail!(
+                "{} witnesses could form a quorum of {} acceptors on their own",
+                self.witnesses.len(),
+                self.acceptors.len()
+            );
         }
+        Ok(())
     }
 
     pub fn get_address(&self, id: &NodeId) -> Option<&Address> {
         self.id_address_map.get(id)
     }
+
+    /// Addresses the config gives to more than one node, with those nodes.
+    pub fn shared_addresses(&self) -> Vec<(Address, Vec<NodeId>)> {
+        let mut by_address: BTreeMap<String, (Address, Vec<NodeId>)> = BTreeMap::new();
+        for (id, address) in &self.id_address_map {
+            by_address
+                .entry(address.to_string())
+                .or_insert_with(|| (address.clone(), Vec::new()))
+                .1
+                .push(*id);
+        }
+        by_address
+            .into_values()
+            .filter(|(_, nodes)| nodes.len() > 1)
+            .collect()
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+/// Where a node is reached. The host is an IP or a hostname, which the TCP
+/// transport resolves; see `transport::dns`.
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Address {
     ip: String,
     port: u64,
@@ -110,16 +493,24 @@ impl Address {
     pub fn new(ip: String, port: u64) -> Address {
         Address { ip, port }
     }
+
+    pub fn host(&self) -> &str {
+        &self.ip
+    }
+
+    pub fn port(&self) -> u64 {
+        self.port
+    }
 }
 
-impl std::fmt::Display for Address {
+impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}:{}", self.ip, self.port)
     }
 }
 
 /// A ServerId is a unique identifier for a server in the system
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct NodeId(u64);
 
 impl NodeId {
@@ -128,7 +519,7 @@ impl NodeId {
     }
 }
 
-impl std::fmt::Display for NodeId {
+impl fmt::Display for NodeId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Node{}", self.0)
     }
@@ -136,7 +527,7 @@ impl std::fmt::Display for NodeId {
 
 /// Newtypes for the different kinds of servers in the system
 /// These protect their internal data.
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct AcceptorId(NodeId);
 
 impl AcceptorId {
@@ -144,7 +535,7 @@ impl AcceptorId {
         AcceptorId(NodeId::new(id))
     }
 }
-impl std::convert::AsRef<NodeId> for AcceptorId {
+impl AsRef<NodeId> for AcceptorId {
     fn as_ref(&self) -> &NodeId {
         &self.0
     }
@@ -156,15 +547,15 @@ impl From<AcceptorId> for NodeId {
     }
 }
 
-impl std::fmt::Display for AcceptorId {
+impl fmt::Display for AcceptorId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Acceptor{}", self.0)
     }
 }
 
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct LeaderId(NodeId);
-impl std::fmt::Display for LeaderId {
+impl fmt::Display for LeaderId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Leader{}", self.0)
     }
@@ -175,7 +566,7 @@ impl LeaderId {
         LeaderId(NodeId::new(id))
     }
 }
-impl std::convert::AsRef<NodeId> for LeaderId {
+impl AsRef<NodeId> for LeaderId {
     fn as_ref(&self) -> &NodeId {
         &self.0
     }
@@ -187,7 +578,7 @@ impl From<LeaderId> for NodeId {
     }
 }
 
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct ReplicaId(NodeId);
 
 impl ReplicaId {
@@ -195,7 +586,7 @@ impl ReplicaId {
         ReplicaId(NodeId::new(id))
     }
 }
-impl std::convert::AsRef<NodeId> for ReplicaId {
+impl AsRef<NodeId> for ReplicaId {
     fn as_ref(&self) -> &NodeId {
         &self.0
     }
@@ -207,7 +598,7 @@ impl From<ReplicaId> for NodeId {
     }
 }
 
-impl std::fmt::Display for ReplicaId {
+impl fmt::Display for ReplicaId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Replica{}", self.0)
     }
@@ -0,0 +1,592 @@
+//! Client traffic for benchmarks and soak tests.
+//!
+//! A `Workload` generates `KvCommand`s against a cluster's replicas: keys
+//! drawn uniformly or from a zipfian distribution, values of a fixed size,
+//! and a chosen fraction of `Get`s among the `Put`s. It is sans-IO like the
+//! nodes: `poll` hands out the requests due at a given time, `receive` takes
+//! the replicas' responses back, and `report` sums up throughput and latency.
+//!
+//! Requests arrive in one of two ways. An open loop sends at a fixed rate
+//! whether or not earlier requests were answered, and measures latency from
+//! when each request was due, so a cluster that falls behind shows it in the
+//! tail instead of slowing the load down. A closed loop keeps a fixed number
+//! of requests outstanding and sends the next one as each is answered.
+//!
+//! `run_simulated` drives a workload through a `sim::Simulation`, in
+//! simulated time; `run_network` drives one through a `Transport`, such as a
+//! `TcpSender`, in wall-clock time.
+use std::fmt;
+
+use anyhow::ensure;
+
+use crate::client::Client;
+use crate::collections::HashMap;
+use crate::messages::{Consistency, Message, ResponseMessage, ResponseStatus, SendableMessage};
+use crate::sim::{Rng, Simulation};
+use crate::state_machine::KvCommand;
+use crate::time::{Duration, Instant};
+use crate::types::{Address, CommandId};
+
+/// How keys are picked for each operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyDistribution {
+    /// Every key equally often.
+    Uniform,
+    /// The key ranked `i` about `1 / i^theta` as often as the first, with
+    /// `theta` in (0, 1): 0.99 is the usual skew of YCSB.
+    Zipfian { theta: f64 },
+}
+
+/// When requests are sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrival {
+    /// `per_second` requests a second, whether or not earlier ones were answered.
+    OpenLoop { per_second: f64 },
+    /// Up to `outstanding` requests awaiting a response at any time.
+    ClosedLoop { outstanding: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkloadConfig {
+    /// Requests to send in all.
+    pub operations: u64,
+    /// Keys to spread the operations over, named `key0`, `key1`, ...
+    pub keys: u64,
+    pub distribution: KeyDistribution,
+    /// Bytes in each value written.
+    pub value_size: usize,
+    /// The fraction of operations that are `Get`s, the rest being `Put`s.
+    pub read_fraction: f64,
+    /// Whether `Get`s are sent at the session's `Sequential` level, which
+    /// replicas answer locally, rather than through consensus.
+    pub sequential_reads: bool,
+    pub arrival: Arrival,
+    /// How long a request may wait for a response before it is counted as
+    /// timed out.
+    pub timeout: Duration,
+    /// Seeds the key, operation and value choices, so a run can be repeated.
+    pub seed: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> WorkloadConfig {
+        WorkloadConfig {
+            operations: 1000,
+            keys: 1000,
+            distribution: KeyDistribution::Uniform,
+            value_size: 64,
+            read_fraction: 0.5,
+            sequential_reads: false,
+            arrival: Arrival::ClosedLoop { outstanding: 8 },
+            timeout: Duration::from_secs(5),
+            seed: 0,
+        }
+    }
+}
+
+impl WorkloadConfig {
+    fn check(&self) -> anyhow::Result<()> {
+        ensure!(self.keys > 0, "a workload needs at least one key");
+        ensure!(
+            (0.0..=1.0).contains(&self.read_fraction),
+            "read fraction {} is not between 0 and 1",
+            self.read_fraction
+        );
+        if let KeyDistribution::Zipfian { theta } = self.distribution {
+            ensure!(
+                theta > 0.0 && theta < 1.0,
+                "zipfian theta {} is not between 0 and 1",
+                theta
+            );
+        }
+        match self.arrival {
+            Arrival::OpenLoop { per_second } => ensure!(
+                per_second > 0.0,
+                "an open loop needs a positive rate, not {}",
+                per_second
+            ),
+            Arrival::ClosedLoop { outstanding } => ensure!(
+                outstanding > 0,
+                "a closed loop needs at least one request outstanding"
+            ),
+        }
+        Ok(())
+    }
+}
+
+// Draws key ranks, 0 the most popular, after Gray et al., "Quickly
+// Generating Billion-Record Synthetic Databases", as YCSB does
+#[derive(Clone, Debug)]
+enum KeySampler {
+    Uniform {
+        keys: u64,
+    },
+    Zipfian {
+        keys: u64,
+        theta: f64,
+        alpha: f64,
+        zetan: f64,
+        eta: f64,
+    },
+}
+
+impl KeySampler {
+    fn new(keys: u64, distribution: KeyDistribution) -> KeySampler {
+        match distribution {
+            KeyDistribution::Uniform => KeySampler::Uniform { keys },
+            KeyDistribution::Zipfian { theta } => {
+                let zeta = |n: u64| (1..=n).map(|i| 1.0 / (i as f64).powf(theta)).sum::<f64>();
+                let zetan = zeta(keys);
+                KeySampler::Zipfian {
+                    keys,
+                    theta,
+                    alpha: 1.0 / (1.0 - theta),
+                    zetan,
+                    eta: (1.0 - (2.0 / keys as f64).powf(1.0 - theta))
+                        / (1.0 - zeta(2.min(keys)) / zetan),
+                }
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> u64 {
+        match *self {
+            KeySampler::Uniform { keys } => rng.next() % keys,
+            KeySampler::Zipfian {
+                keys,
+                theta,
+                alpha,
+                zetan,
+                eta,
+            } => {
+                let u = unit(rng);
+                let uz = u * zetan;
+                if uz < 1.0 {
+                    0
+                } else if uz < 1.0 + 0.5f64.powf(theta) {
+                    1.min(keys - 1)
+                } else {
+                    ((keys as f64 * (eta * u - eta + 1.0).powf(alpha)) as u64).min(keys - 1)
+                }
+            }
+        }
+    }
+}
+
+// Uniform in [0, 1)
+fn unit(rng: &mut Rng) -> f64 {
+    (rng.next() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OpKind {
+    Read,
+    Write,
+}
+
+/// Generates a workload's requests and measures the responses to them.
+pub struct Workload {
+    config: WorkloadConfig,
+    client: Client,
+    replicas: Vec<Address>,
+    keys: KeySampler,
+    rng: Rng,
+    issued: u64,
+    next_replica: usize,
+    started: Option<Instant>,
+    // When the next open-loop request is due
+    next_due: Option<Instant>,
+    // Requests awaiting a response, with when each was due
+    outstanding: HashMap<CommandId, (Instant, OpKind)>,
+    latencies: Vec<Duration>,
+    reads: u64,
+    writes: u64,
+    failed: u64,
+    timed_out: u64,
+    last_answer: Option<Instant>,
+}
+
+impl Workload {
+    /// A workload sent by `client` to `replicas` in turn.
+    pub fn new(
+        config: WorkloadConfig,
+        client: Client,
+        replicas: Vec<Address>,
+    ) -> anyhow::Result<Workload> {
+        config.check()?;
+        ensure!(
+            !replicas.is_empty(),
+            "a workload needs a replica to send to"
+        );
+        Ok(Workload {
+            keys: KeySampler::new(config.keys, config.distribution),
+            rng: Rng(config.seed),
+            config,
+            client,
+            replicas,
+            issued: 0,
+            next_replica: 0,
+            started: None,
+            next_due: None,
+            outstanding: HashMap::new(),
+            latencies: Vec::new(),
+            reads: 0,
+            writes: 0,
+            failed: 0,
+            timed_out: 0,
+            last_answer: None,
+        })
+    }
+
+    /// The requests due by `now`, with those outstanding past the timeout
+    /// given up on.
+    pub fn poll(&mut self, now: Instant) -> Vec<SendableMessage<KvCommand>> {
+        self.started.get_or_insert(now);
+        let timeout = self.config.timeout;
+        let before = self.outstanding.len();
+        self.outstanding
+            .retain(|_, (due, _)| now.saturating_duration_since(*due) < timeout);
+        self.timed_out += (before - self.outstanding.len()) as u64;
+
+        let mut requests = Vec::new();
+        while self.issued < self.config.operations {
+            let due = match self.config.arrival {
+                Arrival::OpenLoop { per_second } => {
+                    let due = *self.next_due.get_or_insert(now);
+                    if due > now {
+                        break;
+                    }
+                    self.next_due = Some(due + Duration::from_secs_f64(1.0 / per_second));
+                    due
+                }
+                Arrival::ClosedLoop { outstanding } => {
+                    if self.outstanding.len() >= outstanding {
+                        break;
+                    }
+                    now
+                }
+            };
+            requests.push(self.request(due));
+        }
+        requests
+    }
+
+    fn request(&mut self, due: Instant) -> SendableMessage<KvCommand> {
+        self.issued += 1;
+        let key = format!("key{}", self.keys.sample(&mut self.rng));
+        let (kind, op, consistency) = if unit(&mut self.rng) < self.config.read_fraction {
+            let consistency = if self.config.sequential_reads {
+                self.client.sequential()
+            } else {
+                Consistency::Linearizable
+            };
+            (OpKind::Read, KvCommand::Get { key }, consistency)
+        } else {
+            let value = (0..self.config.value_size)
+                .map(|_| self.rng.next() as u8)
+                .collect();
+            (
+                OpKind::Write,
+                KvCommand::Put { key, value },
+                Consistency::Linearizable,
+            )
+        };
+        let command = self.client.command(op);
+        self.outstanding.insert(command.id(), (due, kind));
+        let replica = &self.replicas[self.next_replica % self.replicas.len()];
+        self.next_replica += 1;
+        self.client.request(replica, &command, consistency)
+    }
+
+    /// Record a replica's response. Only the first answer to a request
+    /// counts, however many replicas send one.
+    pub fn receive(&mut self, response: &ResponseMessage, now: Instant) {
+        let Some((due, kind)) = self.outstanding.remove(&response.command_id) else {
+            return;
+        };
+        self.client.receive(response);
+        if response.status != ResponseStatus::Performed {
+            self.failed += 1;
+            return;
+        }
+        match kind {
+            OpKind::Read => self.reads += 1,
+            OpKind::Write => self.writes += 1,
+        }
+        self.latencies.push(now.saturating_duration_since(due));
+        self.last_answer = Some(now);
+    }
+
+    /// Whether every request has been sent and answered or given up on.
+    pub fn is_done(&self) -> bool {
+        self.issued >= self.config.operations && self.outstanding.is_empty()
+    }
+
+    /// How long until `poll` has something to do: the next open-loop request
+    /// falls due or the oldest outstanding one times out.
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let arrival = self
+            .next_due
+            .filter(|_| self.issued < self.config.operations);
+        let expiry = self
+            .outstanding
+            .values()
+            .map(|(due, _)| *due + self.config.timeout)
+            .min();
+        arrival
+            .into_iter()
+            .chain(expiry)
+            .min()
+            .map(|at| at.saturating_duration_since(now))
+    }
+
+    /// Throughput and latency of the responses so far.
+    pub fn report(&self) -> Report {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let percentile = |p: usize| {
+            latencies
+                .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+                .copied()
+        };
+        let elapsed = self
+            .started
+            .zip(self.last_answer)
+            .map_or(Duration::ZERO, |(started, last)| {
+                last.saturating_duration_since(started)
+            });
+        let completed = latencies.len() as u64;
+        Report {
+            issued: self.issued,
+            completed,
+            reads: self.reads,
+            writes: self.writes,
+            failed: self.failed,
+            timed_out: self.timed_out,
+            elapsed,
+            throughput: if elapsed.is_zero() {
+                0.0
+            } else {
+                completed as f64 / elapsed.as_secs_f64()
+            },
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies.last().copied(),
+        }
+    }
+}
+
+/// What a workload measured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// Requests sent.
+    pub issued: u64,
+    /// Requests performed, reads and writes.
+    pub completed: u64,
+    pub reads: u64,
+    pub writes: u64,
+    /// Requests a replica turned away.
+    pub failed: u64,
+    /// Requests no response came for within the timeout.
+    pub timed_out: u64,
+    /// From the first request to the last response.
+    pub elapsed: Duration,
+    /// Requests performed per second of `elapsed`.
+    pub throughput: f64,
+    pub p50: Option<Duration>,
+    pub p90: Option<Duration>,
+    pub p99: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let latency =
+            |d: Option<Duration>| d.map_or_else(|| "-".to_string(), |d| format!("{:?}", d));
+        writeln!(
+            f,
+            "{} of {} performed ({} reads, {} writes), {} failed, {} timed out",
+            self.completed, self.issued, self.reads, self.writes, self.failed, self.timed_out
+        )?;
+        writeln!(f, "{:.1} ops/s over {:?}", self.throughput, self.elapsed)?;
+        write!(
+            f,
+            "latency p50 {}  p90 {}  p99 {}  max {}",
+            latency(self.p50),
+            latency(self.p90),
+            latency(self.p99),
+            latency(self.max)
+        )
+    }
+}
+
+/// Run `workload` against a simulated cluster, stepping it `tick` at a time
+/// until every request is answered or given up on.
+pub fn run_simulated(
+    sim: &mut Simulation<KvCommand>,
+    workload: &mut Workload,
+    tick: Duration,
+) -> Report {
+    while !workload.is_done() {
+        for request in workload.poll(sim.now()) {
+            sim.inject(request);
+        }
+        sim.run_for(tick, tick);
+        for msg in sim.take_external() {
+            if let Message::Response(response) = msg.message {
+                workload.receive(&response, sim.now());
+            }
+        }
+    }
+    workload.report()
+}
+
+/// Run `workload` against a networked cluster, sending through `transport`
+/// and taking responses from `responses`, the inbound side of the client's
+/// `Address`, until every request is answered or given up on.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn run_network(
+    transport: &dyn crate::transport::Transport<KvCommand>,
+    responses: &mut tokio::sync::mpsc::UnboundedReceiver<SendableMessage<KvCommand>>,
+    workload: &mut Workload,
+) -> anyhow::Result<Report> {
+    while !workload.is_done() {
+        for request in workload.poll(Instant::now()) {
+            if let Err(e) = transport.send(&request) {
+                // Left to time out, as a lost request would be
+                tracing::warn!("workload: failed to send to {}: {}", request.dst, e);
+            }
+        }
+        let wait = workload
+            .next_timeout(Instant::now())
+            .unwrap_or(workload.config.timeout);
+        match tokio::time::timeout(wait, responses.recv()).await {
+            Ok(Some(msg)) => {
+                if let Message::Response(response) = msg.message {
+                    workload.receive(&response, Instant::now());
+                }
+            }
+            Ok(None) => anyhow::bail!("the client's inbound connection closed"),
+            Err(_) => {}
+        }
+    }
+    Ok(workload.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::RequestMessage;
+    use crate::sim::cluster_config;
+    use crate::types::NodeId;
+
+    fn client() -> Client {
+        Client::new(NodeId::new(900), Address::new("client".to_string(), 1))
+    }
+
+    #[test]
+    fn zipfian_keys_favour_the_first_ranks() {
+        let draw = |distribution| {
+            let sampler = KeySampler::new(1000, distribution);
+            let mut rng = Rng(7);
+            let mut counts = vec![0u32; 1000];
+            for _ in 0..20_000 {
+                counts[sampler.sample(&mut rng) as usize] += 1;
+            }
+            counts
+        };
+        let zipfian = draw(KeyDistribution::Zipfian { theta: 0.99 });
+        assert!(zipfian[0] > zipfian[1] && zipfian[1] > zipfian[10]);
+        // The ten most popular keys take a large share
+        assert!(zipfian[..10].iter().sum::<u32>() > 20_000 / 4);
+        let uniform = draw(KeyDistribution::Uniform);
+        assert!(uniform[..10].iter().sum::<u32>() < 20_000 / 50);
+    }
+
+    #[test]
+    fn closed_loop_keeps_its_requests_outstanding_and_open_loop_keeps_its_rate() {
+        let replicas = vec![Address::new("sim".to_string(), 1)];
+        let start = Instant::now();
+        let mut closed = Workload::new(
+            WorkloadConfig {
+                arrival: Arrival::ClosedLoop { outstanding: 3 },
+                read_fraction: 0.0,
+                ..WorkloadConfig::default()
+            },
+            client(),
+            replicas.clone(),
+        )
+        .unwrap();
+        let sent = closed.poll(start);
+        assert_eq!(sent.len(), 3);
+        assert!(closed.poll(start).is_empty());
+        let Message::Request(RequestMessage { command, .. }) = &sent[0].message else {
+            panic!("expected a request, got {}", sent[0]);
+        };
+        closed.receive(
+            &ResponseMessage {
+                src: crate::types::ReplicaId::new(201),
+                command_id: command.id(),
+                result: Vec::new(),
+                slot: crate::types::Slot(1),
+                status: ResponseStatus::Performed,
+            },
+            start + Duration::from_millis(4),
+        );
+        assert_eq!(closed.poll(start + Duration::from_millis(4)).len(), 1);
+        assert_eq!(closed.report().p50, Some(Duration::from_millis(4)));
+        // Unanswered, the rest time out
+        closed.poll(start + Duration::from_secs(10));
+        assert_eq!(closed.report().timed_out, 3);
+
+        let mut open = Workload::new(
+            WorkloadConfig {
+                arrival: Arrival::OpenLoop { per_second: 100.0 },
+                ..WorkloadConfig::default()
+            },
+            client(),
+            replicas,
+        )
+        .unwrap();
+        assert_eq!(open.poll(start).len(), 1);
+        // Sent on schedule whether or not anything was answered
+        assert_eq!(open.poll(start + Duration::from_millis(50)).len(), 5);
+        assert_eq!(
+            open.next_timeout(start + Duration::from_millis(50)),
+            Some(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn simulated_cluster_performs_a_workload() {
+        let config = cluster_config(3, 1, 2);
+        let mut sim: Simulation<KvCommand> = Simulation::new();
+        sim.add_cluster(&config).unwrap();
+        sim.run_for(Duration::from_secs(2), Duration::from_millis(1));
+        let mut replicas: Vec<_> = config.replicas.iter().copied().collect();
+        replicas.sort_by_key(|id| NodeId::from(*id));
+        let replicas = replicas
+            .iter()
+            .map(|id| config.get_address(id.as_ref()).cloned().unwrap())
+            .collect();
+        let mut workload = Workload::new(
+            WorkloadConfig {
+                operations: 50,
+                distribution: KeyDistribution::Zipfian { theta: 0.99 },
+                ..WorkloadConfig::default()
+            },
+            client(),
+            replicas,
+        )
+        .unwrap();
+
+        let report = run_simulated(&mut sim, &mut workload, Duration::from_millis(1));
+        assert_eq!(report.issued, 50);
+        assert_eq!(report.completed, 50);
+        assert_eq!(report.reads + report.writes, 50);
+        assert!(report.reads > 0 && report.writes > 0);
+        assert!(report.throughput > 0.0);
+        assert!(report.p50 <= report.p99 && report.p99 <= report.max);
+    }
+}
@@ -0,0 +1,790 @@
+//! Conformance with the pseudocode of "Paxos Made Moderately Complex" (van
+//! Renesse and Altinbuken).
+//!
+//! Each test takes one of the paper's processes, a table of cases for one of
+//! its rules, and drives the sans-IO nodes through their public message
+//! interface only, the way a transport would. Where the nodes depart from the
+//! paper on purpose, the case that shows it says how:
+//!
+//! - Acceptors do not answer a P1a or P2a below their promise. The paper
+//!   answers with the promise, to preempt the sender; here leaders learn of
+//!   higher ballots from acceptors' heartbeats instead.
+//! - Acceptors accept a P2a above their promise, and promise its ballot. The
+//!   paper only accepts at the promised ballot, but a P2a's ballot was
+//!   promised by a quorum, so it could not have lost phase 1.
+//! - A leader's commander ignores a P2b for another ballot rather than being
+//!   preempted by it. The ballot is still seen, and the next scout goes above
+//!   it.
+#![cfg(feature = "std")]
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::iter;
+use std::sync::{Arc, Mutex};
+
+use multifaustus::constants::WINDOW;
+use multifaustus::messages::*;
+use multifaustus::nodes::acceptor::Acceptor;
+use multifaustus::nodes::clock::{ClockAction, MockClock};
+use multifaustus::nodes::leader::Leader;
+use multifaustus::nodes::mailbox::Mailbox;
+use multifaustus::nodes::node::Node;
+use multifaustus::nodes::replica::Replica;
+use multifaustus::state_machine::StateMachine;
+use multifaustus::types::*;
+
+const ACCEPTORS: [u64; 3] = [1, 2, 3];
+const LEADER: u64 = 101;
+const RIVAL: u64 = 102;
+const REPLICA: u64 = 201;
+const CLIENT: u64 = 901;
+
+fn address(node: u64) -> Address {
+    Address::new("10.0.0.1".to_string(), 7000 + node)
+}
+
+fn config_with(leaders: &[u64]) -> Config {
+    let nodes = ACCEPTORS.iter().chain(&[LEADER, RIVAL, REPLICA]);
+    Config::new(
+        HashSet::from([ReplicaId::new(REPLICA)]),
+        ACCEPTORS.iter().map(|a| AcceptorId::new(*a)).collect(),
+        leaders.iter().map(|l| LeaderId::new(*l)).collect(),
+        nodes.map(|n| (NodeId::new(*n), address(*n))).collect(),
+        None,
+    )
+}
+
+fn config() -> Config {
+    config_with(&[LEADER, RIVAL])
+}
+
+fn ballot(round: u64, leader: u64) -> BallotNumber {
+    BallotNumber {
+        round: Round(round),
+        leader: LeaderId::new(leader),
+        incarnation: 0,
+    }
+}
+
+fn command(request_id: u64) -> Command {
+    Command {
+        client_id: NodeId::new(CLIENT),
+        request_id,
+        op: CommandType::Op(vec![request_id as u8]),
+    }
+}
+
+/// Hand `message` from `from` to `node`, let it work, and return everything
+/// it sent.
+fn deliver<N: Node>(node: &mut N, to: u64, from: u64, message: Message) -> Vec<SendableMessage> {
+    node.accept_message(SendableMessage {
+        src: address(from),
+        dst: address(to),
+        seq: None,
+        lamport: None,
+        epoch: None,
+        message,
+    });
+    while node.pending() > 0 {
+        node.work_on_message();
+    }
+    sent(node)
+}
+
+fn sent<N: Node>(node: &mut N) -> Vec<SendableMessage> {
+    iter::from_fn(|| node.deliver_sent()).collect()
+}
+
+fn p1a(round: u64) -> Message {
+    Message::P1a(P1aMessage {
+        src: LeaderId::new(LEADER),
+        ballot_number: ballot(round, LEADER),
+        decided_below: Slot::FIRST,
+    })
+}
+
+fn p1b(acceptor: u64, ballot_number: BallotNumber, accepted: Vec<PValue>) -> Message {
+    Message::P1b(P1bMessage {
+        src: AcceptorId::new(acceptor),
+        ballot_number,
+        accepted,
+        gc_below: Slot::default(),
+        witnessed: Vec::new(),
+        continues_from: None,
+        more_from: None,
+    })
+}
+
+fn p2a(round: u64, slot: u64) -> Message {
+    Message::P2a(P2aMessage {
+        src: LeaderId::new(LEADER),
+        ballot_number: ballot(round, LEADER),
+        slot_number: Slot(slot),
+        command: command(slot),
+        gc_below: Slot::default(),
+    })
+}
+
+fn p2b(acceptor: u64, ballot_number: BallotNumber, slot: u64) -> Message {
+    Message::P2b(P2bMessage {
+        src: AcceptorId::new(acceptor),
+        ballot_number,
+        slot_number: Slot(slot),
+    })
+}
+
+fn propose(slot: u64, request_id: u64) -> Message {
+    Message::Propose(ProposeMessage {
+        src: ReplicaId::new(REPLICA),
+        slot_number: Slot(slot),
+        command: command(request_id),
+    })
+}
+
+fn decision(slot: u64, command: Command) -> Message {
+    Message::Decision(DecisionMessage {
+        src: LeaderId::new(LEADER),
+        slot_number: Slot(slot),
+        command,
+        certificate: None,
+    })
+}
+
+fn request(request_id: u64) -> Message {
+    Message::Request(RequestMessage {
+        src: address(CLIENT),
+        command: command(request_id),
+        consistency: Consistency::default(),
+        proxied_by: None,
+    })
+}
+
+fn acceptor() -> Acceptor {
+    Acceptor::new(
+        AcceptorId::new(ACCEPTORS[0]),
+        config(),
+        Mailbox::new(),
+        Box::new(MockClock::new()),
+    )
+    .unwrap()
+}
+
+/// A leader scouting one round above a rival's preempting ballot at
+/// `round`, with its P1as taken, and the ballot it is scouting with.
+fn scouting_leader(round: u64) -> (Leader, BallotNumber) {
+    let mut leader = Leader::new(
+        LeaderId::new(LEADER),
+        config(),
+        Mailbox::new(),
+        Box::new(MockClock::new()),
+    )
+    .unwrap();
+    let first = BallotNumber::new(LeaderId::new(LEADER));
+    sent(&mut leader);
+    let preempted = Message::Preempted(PreemptedMessage {
+        src: LeaderId::new(RIVAL),
+        ballot_number: ballot(round, RIVAL),
+    });
+    deliver(&mut leader, LEADER, RIVAL, preempted);
+    leader
+        .handle_timer(ClockAction::SendScout { ballot: first })
+        .unwrap();
+    let scouting = sent(&mut leader)
+        .iter()
+        .find_map(|m| match &m.message {
+            Message::P1a(p1a) => Some(p1a.ballot_number.clone()),
+            _ => None,
+        })
+        .expect("a scout");
+    (leader, scouting)
+}
+
+/// A leader adopted at the returned ballot, having accepted nothing.
+fn active_leader() -> (Leader, BallotNumber) {
+    let (mut leader, ballot) = scouting_leader(5);
+    for acceptor in ACCEPTORS {
+        deliver(
+            &mut leader,
+            LEADER,
+            acceptor,
+            p1b(acceptor, ballot.clone(), vec![]),
+        );
+    }
+    assert!(leader.progress().leading);
+    (leader, ballot)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Ask {
+    P1a(u64),
+    P2a(u64),
+}
+
+/// Acceptor: a P1a at or above the promise is promised and answered with
+/// everything accepted; a P2a at or above it is accepted.
+#[test]
+fn acceptors_promise_and_accept_at_or_above_their_ballot() {
+    struct Case {
+        name: &'static str,
+        promised: Option<u64>,
+        ask: Ask,
+        // The round of the P1b or P2b sent back, if any
+        reply: Option<u64>,
+        accepted: bool,
+    }
+    let cases = [
+        Case {
+            name: "a fresh acceptor promises the first ballot it is asked",
+            promised: None,
+            ask: Ask::P1a(1),
+            reply: Some(1),
+            accepted: false,
+        },
+        Case {
+            name: "p1a adopts a higher ballot",
+            promised: Some(1),
+            ask: Ask::P1a(2),
+            reply: Some(2),
+            accepted: false,
+        },
+        Case {
+            name: "p1a at the promised ballot is answered again",
+            promised: Some(2),
+            ask: Ask::P1a(2),
+            reply: Some(2),
+            accepted: false,
+        },
+        Case {
+            name: "p1a below the promise goes unanswered",
+            promised: Some(3),
+            ask: Ask::P1a(2),
+            reply: None,
+            accepted: false,
+        },
+        Case {
+            name: "p2a at the promised ballot is accepted",
+            promised: Some(2),
+            ask: Ask::P2a(2),
+            reply: Some(2),
+            accepted: true,
+        },
+        Case {
+            name: "p2a above the promise is accepted and promised",
+            promised: Some(1),
+            ask: Ask::P2a(3),
+            reply: Some(3),
+            accepted: true,
+        },
+        Case {
+            name: "p2a below the promise is refused",
+            promised: Some(3),
+            ask: Ask::P2a(2),
+            reply: None,
+            accepted: false,
+        },
+    ];
+    let me = ACCEPTORS[0];
+    for case in cases {
+        let mut acceptor = acceptor();
+        if let Some(round) = case.promised {
+            deliver(&mut acceptor, me, LEADER, p1a(round));
+        }
+        let message = match case.ask {
+            Ask::P1a(round) => p1a(round),
+            Ask::P2a(round) => p2a(round, 1),
+        };
+        let replies = deliver(&mut acceptor, me, LEADER, message);
+        let reply = replies.iter().find_map(|m| match (&m.message, case.ask) {
+            (Message::P1b(p1b), Ask::P1a(_)) => Some(p1b.ballot_number.round.0),
+            (Message::P2b(p2b), Ask::P2a(_)) => Some(p2b.ballot_number.round.0),
+            _ => None,
+        });
+        assert_eq!(reply, case.reply, "{}", case.name);
+        if let Some(round) = case.reply {
+            assert!(
+                replies.iter().all(|m| m.dst == address(LEADER)),
+                "{}",
+                case.name
+            );
+            // Promised: a lower P1a is no longer answered
+            let lower = deliver(&mut acceptor, me, LEADER, p1a(round.saturating_sub(1)));
+            assert!(round == 0 || lower.is_empty(), "{}", case.name);
+        }
+
+        // What was accepted is reported to the next scout
+        let replies = deliver(&mut acceptor, me, LEADER, p1a(100));
+        let accepted = replies.iter().any(|m| match &m.message {
+            Message::P1b(p1b) => p1b.accepted.iter().any(|pv| pv.slot == Slot(1)),
+            _ => false,
+        });
+        assert_eq!(accepted, case.accepted, "{}", case.name);
+    }
+}
+
+// A pvalue an acceptor reports: its slot, round, leader and request id
+type Accepted = (u64, u64, u64, u64);
+
+/// Scout and pmax: once a majority of acceptors adopt its ballot, the leader
+/// proposes, in each slot any of them accepted a value in, the value
+/// accepted at the highest ballot, over its own proposal.
+#[test]
+fn adopted_leaders_propose_the_highest_ballots_value_in_each_slot() {
+    struct Case {
+        name: &'static str,
+        // A proposal the leader has before it is adopted
+        proposed: Option<(u64, u64)>,
+        // Each acceptor's P1b: whether it is for the leader's ballot, and
+        // what it accepted
+        reports: Vec<(u64, bool, Vec<Accepted>)>,
+        // The (slot, request id) of each P2a sent, if adopted
+        adopted: Option<Vec<(u64, u64)>>,
+    }
+    let cases = [
+        Case {
+            name: "one P1b is not a majority",
+            proposed: None,
+            reports: vec![(1, true, vec![(1, 2, LEADER, 7)])],
+            adopted: None,
+        },
+        Case {
+            name: "a majority with nothing accepted adopts with nothing to propose",
+            proposed: None,
+            reports: vec![(1, true, vec![]), (2, true, vec![])],
+            adopted: Some(vec![]),
+        },
+        Case {
+            name: "pmax picks the highest round's value",
+            proposed: None,
+            reports: vec![
+                (1, true, vec![(1, 2, LEADER, 7)]),
+                (2, true, vec![(1, 4, RIVAL, 8)]),
+            ],
+            adopted: Some(vec![(1, 8)]),
+        },
+        Case {
+            name: "pmax breaks a round's tie by leader",
+            proposed: None,
+            reports: vec![
+                (1, true, vec![(1, 3, RIVAL, 8)]),
+                (2, true, vec![(1, 3, LEADER, 7)]),
+            ],
+            adopted: Some(vec![(1, 8)]),
+        },
+        Case {
+            name: "a value one acceptor of the majority accepted is proposed",
+            proposed: None,
+            reports: vec![(1, true, vec![(2, 1, RIVAL, 8)]), (2, true, vec![])],
+            adopted: Some(vec![(2, 8)]),
+        },
+        Case {
+            name: "an accepted value overrides the leader's own proposal",
+            proposed: Some((1, 9)),
+            reports: vec![(1, true, vec![(1, 2, RIVAL, 8)]), (2, true, vec![])],
+            adopted: Some(vec![(1, 8)]),
+        },
+        Case {
+            name: "the leader's own proposals fill the other slots",
+            proposed: Some((3, 9)),
+            reports: vec![(1, true, vec![(1, 2, RIVAL, 8)]), (2, true, vec![])],
+            adopted: Some(vec![(1, 8), (3, 9)]),
+        },
+        Case {
+            name: "a P1b for another ballot does not count",
+            proposed: None,
+            reports: vec![(1, true, vec![]), (2, false, vec![])],
+            adopted: None,
+        },
+    ];
+    for case in cases {
+        let (mut leader, scouting) = scouting_leader(5);
+        if let Some((slot, request_id)) = case.proposed {
+            deliver(&mut leader, LEADER, REPLICA, propose(slot, request_id));
+        }
+        let mut p2as = Vec::new();
+        for (acceptor, current, accepted) in &case.reports {
+            let ballot_number = if *current {
+                scouting.clone()
+            } else {
+                ballot(2, LEADER)
+            };
+            let accepted = accepted
+                .iter()
+                .map(|(slot, round, leader, request_id)| PValue {
+                    ballot_number: ballot(*round, *leader),
+                    slot: Slot(*slot),
+                    command: command(*request_id),
+                })
+                .collect();
+            let p1b = p1b(*acceptor, ballot_number, accepted);
+            p2as.extend(deliver(&mut leader, LEADER, *acceptor, p1b));
+        }
+        let p2as: Vec<&P2aMessage> = p2as
+            .iter()
+            .filter_map(|m| P2aMessage::of(&m.message))
+            .collect();
+        assert!(
+            p2as.iter().all(|p2a| p2a.ballot_number == scouting),
+            "{}",
+            case.name
+        );
+        let proposed: BTreeSet<(u64, u64)> = p2as
+            .iter()
+            .map(|p2a| (p2a.slot_number.0, p2a.command.request_id))
+            .collect();
+        match case.adopted {
+            Some(expected) => {
+                assert!(leader.progress().leading, "{}", case.name);
+                assert_eq!(proposed, expected.into_iter().collect(), "{}", case.name);
+            }
+            None => {
+                assert!(!leader.progress().leading, "{}", case.name);
+                assert!(proposed.is_empty(), "{}", case.name);
+            }
+        }
+    }
+}
+
+/// Commander: a value is decided, and sent to every replica, once a majority
+/// of acceptors accept it at the leader's ballot.
+#[test]
+fn commanders_decide_on_a_majority_of_p2bs_for_their_ballot() {
+    struct Case {
+        name: &'static str,
+        // Each acceptor's P2b, and whether it is for the leader's ballot
+        p2bs: Vec<(u64, bool)>,
+        decided: bool,
+    }
+    let cases = [
+        Case {
+            name: "one P2b is not a majority",
+            p2bs: vec![(1, true)],
+            decided: false,
+        },
+        Case {
+            name: "a majority decides",
+            p2bs: vec![(1, true), (3, true)],
+            decided: true,
+        },
+        Case {
+            name: "an acceptor counts once however often it answers",
+            p2bs: vec![(2, true), (2, true)],
+            decided: false,
+        },
+        Case {
+            name: "a P2b for another ballot does not count",
+            p2bs: vec![(1, true), (2, false)],
+            decided: false,
+        },
+    ];
+    for case in cases {
+        let (mut leader, active) = active_leader();
+        let p2as = deliver(&mut leader, LEADER, REPLICA, propose(1, 7));
+        let to: BTreeSet<String> = p2as
+            .iter()
+            .filter(|m| matches!(m.message, Message::P2a(_)))
+            .map(|m| m.dst.to_string())
+            .collect();
+        assert_eq!(to.len(), ACCEPTORS.len(), "{}", case.name);
+
+        let mut decisions = Vec::new();
+        for (acceptor, current) in &case.p2bs {
+            let ballot_number = if *current {
+                active.clone()
+            } else {
+                ballot(active.round.0 + 1, RIVAL)
+            };
+            let p2b = p2b(*acceptor, ballot_number, 1);
+            decisions.extend(deliver(&mut leader, LEADER, *acceptor, p2b));
+        }
+        let decisions: Vec<&SendableMessage> = decisions
+            .iter()
+            .filter(|m| matches!(&m.message, Message::Decision(d) if d.slot_number == Slot(1)))
+            .collect();
+        if case.decided {
+            assert_eq!(decisions.len(), 1, "{}", case.name);
+            assert_eq!(decisions[0].dst, address(REPLICA), "{}", case.name);
+        } else {
+            assert!(decisions.is_empty(), "{}", case.name);
+        }
+    }
+}
+
+/// Preemption: a leader preempted by a higher ballot stops leading and
+/// scouts again above it; a lower one changes nothing.
+#[test]
+fn leaders_preempted_by_a_higher_ballot_give_way_and_go_above_it() {
+    struct Case {
+        name: &'static str,
+        // The preempting ballot's round, relative to the active one's
+        by: i64,
+        leader: u64,
+        leading: bool,
+        // The leader's ballot's round after, relative to the active one's
+        round: i64,
+    }
+    let cases = [
+        Case {
+            name: "a lower ballot changes nothing",
+            by: -1,
+            leader: RIVAL,
+            leading: true,
+            round: 0,
+        },
+        Case {
+            name: "the same round by a lower leader changes nothing",
+            by: 0,
+            leader: 100,
+            leading: true,
+            round: 0,
+        },
+        Case {
+            name: "the same round by a higher leader preempts",
+            by: 0,
+            leader: RIVAL,
+            leading: false,
+            round: 1,
+        },
+        Case {
+            name: "a higher round preempts and is outbid",
+            by: 2,
+            leader: RIVAL,
+            leading: false,
+            round: 3,
+        },
+    ];
+    for case in cases {
+        let (mut leader, active) = active_leader();
+        let round = (active.round.0 as i64 + case.by) as u64;
+        let preempted = Message::Preempted(PreemptedMessage {
+            src: LeaderId::new(case.leader),
+            ballot_number: ballot(round, case.leader),
+        });
+        deliver(&mut leader, LEADER, RIVAL, preempted);
+        let progress = leader.progress();
+        assert_eq!(progress.leading, case.leading, "{}", case.name);
+        let ballot_number = progress.ballot.expect("leaders have a ballot");
+        assert_eq!(
+            ballot_number.round.0 as i64 - active.round.0 as i64,
+            case.round,
+            "{}",
+            case.name
+        );
+        assert_eq!(ballot_number.leader, LeaderId::new(LEADER), "{}", case.name);
+    }
+}
+
+/// Applies every operation, recording each.
+struct Recording(Arc<Mutex<Vec<u8>>>);
+
+impl StateMachine for Recording {
+    fn apply(&mut self, op: &Vec<u8>) -> Vec<u8> {
+        self.0.lock().unwrap().extend(op);
+        op.clone()
+    }
+}
+
+fn replica() -> (Replica, Arc<Mutex<Vec<u8>>>) {
+    let mut replica = Replica::new(
+        ReplicaId::new(REPLICA),
+        config(),
+        Mailbox::new(),
+        Box::new(MockClock::new()),
+    )
+    .unwrap();
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    replica.set_state_machine(Box::new(Recording(applied.clone())));
+    sent(&mut replica);
+    (replica, applied)
+}
+
+// The slots proposed in `sent`, and the leaders each went to
+fn proposals(sent: &[SendableMessage]) -> BTreeMap<u64, BTreeSet<String>> {
+    let mut proposals: BTreeMap<u64, BTreeSet<String>> = BTreeMap::new();
+    for message in sent {
+        if let Message::Propose(propose) = &message.message {
+            proposals
+                .entry(propose.slot_number.0)
+                .or_default()
+                .insert(message.dst.to_string());
+        }
+    }
+    proposals
+}
+
+/// Replica: at most WINDOW slots are proposed beyond the last performed, and
+/// the window moves on as slots are decided.
+#[test]
+fn replicas_propose_at_most_window_slots_ahead() {
+    struct Case {
+        name: &'static str,
+        requests: u64,
+        // Slots decided with the request of the same number
+        decided: Vec<u64>,
+        proposed: Vec<u64>,
+    }
+    let window = WINDOW;
+    let cases = [
+        Case {
+            name: "fewer requests than the window are all proposed",
+            requests: window - 1,
+            decided: vec![],
+            proposed: (1..window).collect(),
+        },
+        Case {
+            name: "requests beyond the window wait",
+            requests: window + 2,
+            decided: vec![],
+            proposed: (1..=window).collect(),
+        },
+        Case {
+            name: "performing the first slot opens the next",
+            requests: window + 2,
+            decided: vec![1],
+            proposed: (1..=window + 1).collect(),
+        },
+        Case {
+            name: "a later decision does not open the window",
+            requests: window + 2,
+            decided: vec![2],
+            proposed: (1..=window).collect(),
+        },
+    ];
+    for case in cases {
+        let (mut replica, _) = replica();
+        let mut out = Vec::new();
+        for request_id in 1..=case.requests {
+            out.extend(deliver(&mut replica, REPLICA, CLIENT, request(request_id)));
+        }
+        for slot in case.decided {
+            out.extend(deliver(
+                &mut replica,
+                REPLICA,
+                LEADER,
+                decision(slot, command(slot)),
+            ));
+        }
+        let proposals = proposals(&out);
+        assert_eq!(
+            proposals.keys().copied().collect::<Vec<_>>(),
+            case.proposed,
+            "{}",
+            case.name
+        );
+        let leaders = BTreeSet::from([address(LEADER).to_string(), address(RIVAL).to_string()]);
+        assert!(proposals.values().all(|to| *to == leaders), "{}", case.name);
+    }
+}
+
+/// Replica: decisions are performed in slot order, whatever order they
+/// arrive in, and a command decided in more than one slot is performed once.
+#[test]
+fn replicas_perform_decisions_in_slot_order_and_commands_once() {
+    struct Case {
+        name: &'static str,
+        // (slot, request id) in the order decided
+        decisions: Vec<(u64, u64)>,
+        // Request ids in the order performed
+        performed: Vec<u8>,
+    }
+    let cases = [
+        Case {
+            name: "in order",
+            decisions: vec![(1, 1), (2, 2), (3, 3)],
+            performed: vec![1, 2, 3],
+        },
+        Case {
+            name: "out of order",
+            decisions: vec![(3, 3), (1, 1), (2, 2)],
+            performed: vec![1, 2, 3],
+        },
+        Case {
+            name: "behind a gap",
+            decisions: vec![(2, 2), (3, 3)],
+            performed: vec![],
+        },
+        Case {
+            name: "decided twice",
+            decisions: vec![(1, 1), (2, 1), (3, 2)],
+            performed: vec![1, 2],
+        },
+    ];
+    for case in cases {
+        let (mut replica, applied) = replica();
+        for request_id in 1..=3 {
+            deliver(&mut replica, REPLICA, CLIENT, request(request_id));
+        }
+        let mut responses = Vec::new();
+        for (slot, request_id) in &case.decisions {
+            let decision = decision(*slot, command(*request_id));
+            responses.extend(deliver(&mut replica, REPLICA, LEADER, decision));
+        }
+        assert_eq!(*applied.lock().unwrap(), case.performed, "{}", case.name);
+        // Each performed command is answered, in the order performed
+        let answered: Vec<u8> = responses
+            .iter()
+            .filter(|m| m.dst == address(CLIENT))
+            .filter_map(|m| ResponseMessage::of(&m.message))
+            .map(|r| r.command_id.request_id as u8)
+            .collect();
+        let mut unique = answered.clone();
+        unique.dedup();
+        assert_eq!(unique, case.performed, "{}", case.name);
+    }
+}
+
+/// Replica: a reconfiguration decided in slot s governs proposals from slot
+/// s + WINDOW on, so no slot in flight changes configuration under it.
+#[test]
+fn reconfigurations_take_effect_window_slots_later() {
+    struct Case {
+        name: &'static str,
+        slot: u64,
+    }
+    let cases = [
+        Case {
+            name: "decided in the first slot",
+            slot: 1,
+        },
+        Case {
+            name: "decided in a later slot",
+            slot: 2,
+        },
+    ];
+    let window = WINDOW;
+    for case in cases {
+        let (mut replica, _) = replica();
+        let reconfig = Command {
+            client_id: NodeId::new(CLIENT),
+            request_id: 100,
+            op: CommandType::Reconfig(config_with(&[RIVAL])),
+        };
+        for slot in 1..case.slot {
+            deliver(
+                &mut replica,
+                REPLICA,
+                LEADER,
+                decision(slot, command(50 + slot)),
+            );
+        }
+        deliver(&mut replica, REPLICA, LEADER, decision(case.slot, reconfig));
+        let mut out = Vec::new();
+        for request_id in 1..=window + 1 {
+            out.extend(deliver(&mut replica, REPLICA, CLIENT, request(request_id)));
+        }
+        let effective = case.slot + window;
+        let proposals = proposals(&out);
+        assert_eq!(
+            proposals.keys().copied().collect::<Vec<_>>(),
+            (case.slot + 1..=effective).collect::<Vec<_>>(),
+            "{}",
+            case.name
+        );
+        for (slot, to) in proposals {
+            let expected = if slot < effective {
+                BTreeSet::from([address(LEADER).to_string(), address(RIVAL).to_string()])
+            } else {
+                BTreeSet::from([address(RIVAL).to_string()])
+            };
+            assert_eq!(to, expected, "{}: slot {}", case.name, slot);
+        }
+    }
+}
@@ -1,6 +1,5 @@
 #[cfg(test)]
 mod tests {
-    use multifaustus::nodes::replica::Replica;
     use quickcheck::quickcheck;
 
     quickcheck! {
@@ -0,0 +1,342 @@
+//! Golden-file wire compatibility tests.
+//!
+//! `tests/golden/<codec>/<version>/` holds one encoded sample of every message
+//! variant. Every fixture of every version must still decode, so nodes keep
+//! understanding peers that run an older release, and the fixtures in
+//! `unreleased/` must match today's encoding byte for byte.
+//!
+//! A deliberate change to the encoding rewrites `unreleased/` in place with
+//! `UPDATE_GOLDEN=1 cargo test --test wire_compat`. Only a release copies it
+//! to a new versioned directory, whose fixtures are never edited again.
+#![cfg(feature = "std")]
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use multifaustus::messages::*;
+use multifaustus::transport::codec::{Codec, JsonCodec};
+use multifaustus::types::*;
+
+/// The directory holding the encoding as it stands, not yet released.
+const UNRELEASED: &str = "unreleased";
+
+fn golden_dir(codec: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(codec)
+}
+
+/// The fixture name for a message: its variant. Matching exhaustively means a
+/// new variant does not compile here until it has a sample.
+fn variant(message: &Message) -> &'static str {
+    match message {
+        Message::P1a(_) => "P1a",
+        Message::P1b(_) => "P1b",
+        Message::P2a(_) => "P2a",
+        Message::P2b(_) => "P2b",
+        Message::Preempted(_) => "Preempted",
+        Message::Decision(_) => "Decision",
+        Message::Request(_) => "Request",
+        Message::Propose(_) => "Propose",
+        Message::ProposeRejected(_) => "ProposeRejected",
+        Message::ProposeAccepted(_) => "ProposeAccepted",
+        Message::DecisionFetch(_) => "DecisionFetch",
+        Message::DecisionFetchReply(_) => "DecisionFetchReply",
+        Message::Response(_) => "Response",
+        Message::Heartbeat(_) => "Heartbeat",
+        Message::PreP1a(_) => "PreP1a",
+        Message::PreP1b(_) => "PreP1b",
+        Message::TakeOver(_) => "TakeOver",
+        Message::P1bMore(_) => "P1bMore",
+        Message::QueryAccepted(_) => "QueryAccepted",
+        Message::AcceptedReply(_) => "AcceptedReply",
+        Message::Misrouted(_) => "Misrouted",
+        Message::ConfigSync(_) => "ConfigSync",
+        Message::Gossip(_) => "Gossip",
+        Message::Barrier(_) => "Barrier",
+        Message::Committed(_) => "Committed",
+    }
+}
+
+/// One message of every variant, using every optional field.
+fn samples() -> Vec<SendableMessage> {
+    let leader = LeaderId::new(101);
+    let acceptor = AcceptorId::new(1);
+    let replica = ReplicaId::new(201);
+    let ballot = BallotNumber {
+        round: Round(3),
+        leader,
+        incarnation: 2,
+    };
+    let command = Command {
+        client_id: NodeId::new(900),
+        request_id: 7,
+        op: CommandType::Op(vec![1, 2, 3]),
+    };
+    // One member per role: sets have no stable order on the wire
+    let config = Config::new(
+        HashSet::from([replica]),
+        HashSet::from([acceptor]),
+        HashSet::from([leader]),
+        BTreeMap::from([
+            (acceptor.into(), Address::new("10.0.0.1".to_string(), 7001)),
+            (leader.into(), Address::new("10.0.0.2".to_string(), 7101)),
+            (replica.into(), Address::new("10.0.0.3".to_string(), 7201)),
+        ]),
+        Some(TimeoutConfig {
+            min_timeout: Duration::from_millis(150),
+            lease: Some(Duration::from_secs(3)),
+            ..TimeoutConfig::default()
+        }),
+    )
+    .with_certified_decisions();
+    let mut synced = config.clone();
+    synced.epoch = 4;
+    let reconfig = Command {
+        client_id: NodeId::new(900),
+        request_id: 8,
+        op: CommandType::Reconfig(config),
+    };
+    let messages = vec![
+        Message::P1a(P1aMessage {
+            src: leader,
+            ballot_number: ballot.clone(),
+            decided_below: Slot(2),
+        }),
+        Message::P1b(P1bMessage {
+            src: acceptor,
+            ballot_number: ballot.clone(),
+            accepted: vec![PValue {
+                ballot_number: ballot.clone(),
+                slot: Slot(4),
+                command: command.clone(),
+            }],
+            gc_below: Slot(2),
+            witnessed: vec![(Slot(5), ballot.clone())],
+            continues_from: Some(Slot(4)),
+            more_from: Some(Slot(6)),
+        }),
+        Message::P2a(P2aMessage {
+            src: leader,
+            ballot_number: ballot.clone(),
+            slot_number: Slot(4),
+            command: command.clone(),
+            gc_below: Slot(2),
+        }),
+        Message::P2b(P2bMessage {
+            src: acceptor,
+            ballot_number: ballot.clone(),
+            slot_number: Slot(4),
+        }),
+        Message::Preempted(PreemptedMessage {
+            src: leader,
+            ballot_number: ballot.clone(),
+        }),
+        Message::Decision(DecisionMessage {
+            src: leader,
+            slot_number: Slot(5),
+            command: reconfig,
+            certificate: Some(DecisionCertificate {
+                ballot_number: ballot.clone(),
+                acceptors: vec![acceptor],
+            }),
+        }),
+        Message::Request(RequestMessage {
+            src: Address::new("10.0.0.9".to_string(), 9000),
+            command: command.clone(),
+            consistency: Consistency::Sequential {
+                after_slot: Slot(4),
+            },
+            proxied_by: Some(replica),
+        }),
+        Message::Propose(ProposeMessage {
+            src: replica,
+            slot_number: Slot(4),
+            command: command.clone(),
+        }),
+        Message::ProposeRejected(ProposeRejectedMessage {
+            src: leader,
+            slot_number: Slot(4),
+            command_id: command.id(),
+            reason: RejectReason::SlotOccupied,
+            free_slot: Some(Slot(6)),
+        }),
+        Message::ProposeAccepted(ProposeAcceptedMessage {
+            src: leader,
+            slot_number: Slot(4),
+            command_id: command.id(),
+        }),
+        Message::DecisionFetch(DecisionFetchMessage {
+            src: replica,
+            slots: vec![Slot(2), Slot(3)],
+        }),
+        Message::DecisionFetchReply(DecisionFetchReplyMessage {
+            src: replica,
+            decisions: vec![(Slot(2), command.clone())],
+            certificates: vec![(
+                Slot(2),
+                DecisionCertificate {
+                    ballot_number: ballot.clone(),
+                    acceptors: vec![acceptor],
+                },
+            )],
+        }),
+        Message::Response(ResponseMessage {
+            src: replica,
+            command_id: command.id(),
+            result: vec![9],
+            slot: Slot(4),
+            status: ResponseStatus::Unavailable,
+        }),
+        Message::Heartbeat(HeartbeatMessage {
+            src: leader.into(),
+            ballot: Some(ballot.clone()),
+            quorum_lost: true,
+            promised: Some(ballot.clone()),
+            lease: Some(Lease {
+                ballot: ballot.clone(),
+                id: 11,
+                duration: Duration::from_millis(2500),
+            }),
+            commit_index: Some(CommitIndex(Slot(5))),
+        }),
+        Message::PreP1a(PreP1aMessage {
+            src: leader,
+            ballot_number: ballot.clone(),
+        }),
+        Message::PreP1b(PreP1bMessage {
+            src: acceptor,
+            ballot_number: ballot.clone(),
+            granted: true,
+        }),
+        Message::TakeOver(TakeOverMessage {
+            src: leader,
+            ballot_hint: ballot.clone(),
+        }),
+        Message::P1bMore(P1bMoreMessage {
+            src: leader,
+            ballot_number: ballot.clone(),
+            from_slot: Slot(6),
+        }),
+        Message::QueryAccepted(QueryAcceptedMessage {
+            src: Address::new("10.0.0.2".to_string(), 7101),
+            slots: vec![Slot(6), Slot(7)],
+        }),
+        Message::AcceptedReply(AcceptedReplyMessage {
+            src: acceptor,
+            accepted: vec![PValue {
+                ballot_number: ballot.clone(),
+                slot: Slot(6),
+                command: command.clone(),
+            }],
+            witnessed: vec![(Slot(7), ballot)],
+            gc_below: Slot(3),
+        }),
+        Message::Misrouted(MisroutedMessage {
+            src: acceptor.into(),
+            role: Role::Acceptor,
+            kind: "Decision".to_string(),
+        }),
+        Message::ConfigSync(ConfigSyncMessage {
+            src: leader.into(),
+            config: synced.clone(),
+        }),
+        Message::Gossip(GossipMessage {
+            src: acceptor.into(),
+            config: synced,
+            peers: vec![
+                (
+                    acceptor.into(),
+                    Address::new("10.0.0.2".to_string(), 7101),
+                    2,
+                ),
+                (leader.into(), Address::new("10.0.0.3".to_string(), 7201), 0),
+            ],
+        }),
+        Message::Barrier(BarrierMessage {
+            src: Address::new("10.0.0.9".to_string(), 9000),
+            id: 12,
+            slot: Slot(6),
+        }),
+        Message::Committed(CommittedMessage {
+            src: replica,
+            id: 12,
+            commit_index: CommitIndex(Slot(6)),
+        }),
+    ];
+    messages
+        .into_iter()
+        .map(|message| SendableMessage {
+            src: Address::new("10.0.0.2".to_string(), 7101),
+            dst: Address::new("10.0.0.3".to_string(), 7201),
+            seq: Some(42),
+            lamport: Some(7),
+            epoch: Some(3),
+            message,
+        })
+        .collect()
+}
+
+fn check_current_encoding(codec: &dyn Codec, name: &str, extension: &str) {
+    let dir = golden_dir(name).join(UNRELEASED);
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    if update {
+        fs::create_dir_all(&dir).unwrap();
+    }
+    for sample in samples() {
+        let path = dir.join(format!("{}.{}", variant(&sample.message), extension));
+        let encoded = codec.encode(&sample).unwrap();
+        if update {
+            fs::write(&path, &encoded).unwrap();
+            continue;
+        }
+        let golden = fs::read(&path).unwrap_or_else(|e| {
+            panic!(
+                "{}: {} (run with UPDATE_GOLDEN=1 to create it)",
+                path.display(),
+                e
+            )
+        });
+        assert!(
+            encoded == golden,
+            "{} no longer matches the {} encoding; run with UPDATE_GOLDEN=1 for a deliberate change\n  golden: {}\n encoded: {}",
+            path.display(),
+            name,
+            String::from_utf8_lossy(&golden),
+            String::from_utf8_lossy(&encoded)
+        );
+    }
+}
+
+fn check_every_version_decodes(codec: &dyn Codec, name: &str) {
+    let mut decoded = 0;
+    for version in fs::read_dir(golden_dir(name)).unwrap() {
+        for fixture in fs::read_dir(version.unwrap().path()).unwrap() {
+            let path = fixture.unwrap().path();
+            let message = codec
+                .decode(&fs::read(&path).unwrap())
+                .unwrap_or_else(|e| panic!("{} no longer decodes: {}", path.display(), e));
+            assert_eq!(
+                Some(variant(&message.message)),
+                path.file_stem().and_then(|stem| stem.to_str()),
+                "{} decoded as the wrong message",
+                path.display()
+            );
+            decoded += 1;
+        }
+    }
+    assert!(decoded >= samples().len());
+}
+
+#[test]
+fn json_encoding_matches_golden_files() {
+    check_current_encoding(&JsonCodec, "json", "json");
+}
+
+#[test]
+fn json_golden_files_of_every_version_decode() {
+    check_every_version_decodes(&JsonCodec, "json");
+}